@@ -0,0 +1,278 @@
+//! Animation of the camera and spheres along paths of keyframes, so a sequence of frames can fly
+//! through a scene.
+
+use crate::{
+    camera::{Motion, ThinLensCamera},
+    scene::Scene,
+    Vec3f,
+};
+use std::f32::consts::PI;
+
+/// How the movement from one keyframe to the next speeds up and slows down.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Easing {
+    /// Steady speed.
+    Linear,
+    /// Starting slowly and speeding up.
+    EaseIn,
+    /// Starting quickly and slowing down.
+    EaseOut,
+    /// Starting and finishing slowly.
+    EaseInOut,
+}
+
+impl Easing {
+    /// Fraction of the way to the next keyframe at fraction `t` of the time to it.
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => t * (2.0 - t),
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+/// Where the camera is, and what it looks at, at one moment of an animation.
+pub struct Keyframe {
+    /// Time of the keyframe, in seconds.
+    pub time: f32,
+    pub eye: Vec3f,
+    pub target: Vec3f,
+    /// Easing of the movement from this keyframe to the next.
+    pub easing: Easing,
+}
+
+impl Keyframe {
+    pub fn new(time: f32, eye: Vec3f, target: Vec3f) -> Self {
+        Keyframe {
+            time,
+            eye,
+            target,
+            easing: Easing::EaseInOut,
+        }
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Self {
+        self.easing = easing;
+        self
+    }
+}
+
+/// A camera moving through a series of keyframes. The path curves smoothly through each
+/// keyframe, and holds still before the first and after the last.
+pub struct CameraPath {
+    /// The camera's settings other than where it is.
+    camera: ThinLensCamera,
+    /// Direction kept towards the top of the image as the camera moves.
+    up: Vec3f,
+    /// Keyframes in order of time.
+    keyframes: Vec<Keyframe>,
+}
+
+impl CameraPath {
+    /// A path for `camera`, which gives the settings other than where it is, such as its field
+    /// of view and lens.
+    pub fn new(camera: ThinLensCamera, up: Vec3f) -> Self {
+        CameraPath {
+            camera,
+            up,
+            keyframes: Vec::new(),
+        }
+    }
+
+    /// Use the settings of `camera` other than where it is, such as its field of view and lens.
+    pub fn with_camera(mut self, camera: ThinLensCamera) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    pub fn with_keyframe(mut self, keyframe: Keyframe) -> Self {
+        let index = self
+            .keyframes
+            .partition_point(|other| other.time <= keyframe.time);
+        self.keyframes.insert(index, keyframe);
+        self
+    }
+
+    /// Time of the last keyframe, when the camera stops moving.
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    /// The camera where it is at `time`.
+    pub fn camera_at(&self, time: f32) -> ThinLensCamera {
+        match self.position_at(time) {
+            Some((eye, target)) => self.camera.clone().with_position(eye, target, self.up),
+            None => self.camera.clone(),
+        }
+    }
+
+    /// The camera for a frame exposed from `open` to `close`, blurred along its movement in
+    /// between.
+    pub fn camera_during(&self, open: f32, close: f32) -> ThinLensCamera {
+        let start = self.camera_at(open);
+        let end = self.camera_at(close);
+        let motion = Motion {
+            eye: end.eye,
+            forward: end.forward,
+            up: end.up,
+            shutter_open: 0.0,
+            shutter_close: 1.0,
+        };
+        ThinLensCamera {
+            motion: Some(motion),
+            ..start
+        }
+    }
+
+    /// Eye and target at `time`, or `None` without any keyframes.
+    fn position_at(&self, time: f32) -> Option<(Vec3f, Vec3f)> {
+        let keyframes = &self.keyframes;
+        let ([before, from, to, after], t) =
+            span(keyframes.len(), |index| keyframes[index].time, time)?;
+        let (before, from, to, after) = (
+            &keyframes[before],
+            &keyframes[from],
+            &keyframes[to],
+            &keyframes[after],
+        );
+        let t = from.easing.apply(t);
+        Some((
+            catmull_rom([before.eye, from.eye, to.eye, after.eye], t),
+            catmull_rom([before.target, from.target, to.target, after.target], t),
+        ))
+    }
+}
+
+/// A sphere moving through a series of keyframes, each where its center is at a time in seconds.
+/// Like the camera, it curves smoothly through each keyframe, and holds still before the first
+/// and after the last.
+pub struct SpherePath {
+    /// Index of the sphere in the scene.
+    pub sphere: usize,
+    /// Times and centers, in order of time.
+    keyframes: Vec<(f32, Vec3f)>,
+}
+
+impl SpherePath {
+    pub fn new(sphere: usize) -> Self {
+        SpherePath {
+            sphere,
+            keyframes: Vec::new(),
+        }
+    }
+
+    pub fn with_keyframe(mut self, time: f32, center: Vec3f) -> Self {
+        let index = self.keyframes.partition_point(|&(other, _)| other <= time);
+        self.keyframes.insert(index, (time, center));
+        self
+    }
+
+    /// Center of the sphere at `time`, or `None` without any keyframes.
+    pub fn center_at(&self, time: f32) -> Option<Vec3f> {
+        let keyframes = &self.keyframes;
+        let (indices, t) = span(keyframes.len(), |index| keyframes[index].0, time)?;
+        Some(catmull_rom(indices.map(|index| keyframes[index].1), t))
+    }
+}
+
+/// Everything which moves in an animated scene.
+pub struct Animation {
+    /// Path the camera follows, if it moves.
+    pub camera: Option<CameraPath>,
+    /// Paths of the spheres which move. Spheres which emit light are sampled where they were
+    /// added to the scene, so shouldn't be moved.
+    pub spheres: Vec<SpherePath>,
+    /// Fraction of each frame the shutter is open for, blurring the camera's movement over it.
+    pub shutter: f32,
+}
+
+impl Default for Animation {
+    fn default() -> Self {
+        Animation {
+            camera: None,
+            spheres: Vec::new(),
+            shutter: 0.0,
+        }
+    }
+}
+
+impl Animation {
+    /// The animation of the built in scenes, a few seconds long: the camera swings around the
+    /// spheres and back while the gold sphere bounces.
+    pub fn built_in() -> Self {
+        let target = Vec3f::new(0.0, 0.0, -20.0);
+        let eye = |degrees: f32| {
+            let angle = degrees * PI / 180.0;
+            target + Vec3f::new(angle.sin(), 0.0, angle.cos()) * 20.0
+        };
+        let camera = CameraPath::new(ThinLensCamera::default(), Vec3f::new(0.0, 1.0, 0.0))
+            .with_keyframe(Keyframe::new(0.0, eye(0.0), target))
+            .with_keyframe(Keyframe::new(2.0, eye(-35.0), target))
+            .with_keyframe(Keyframe::new(4.0, eye(0.0), target));
+        let (rest, top) = (Vec3f::new(5.0, -1.0, -15.0), Vec3f::new(5.0, 3.0, -15.0));
+        let bounce = SpherePath::new(2)
+            .with_keyframe(0.0, rest)
+            .with_keyframe(1.0, top)
+            .with_keyframe(2.0, rest)
+            .with_keyframe(3.0, top)
+            .with_keyframe(4.0, rest);
+        Animation {
+            camera: Some(camera),
+            spheres: vec![bounce],
+            ..Animation::default()
+        }
+    }
+
+    pub fn with_shutter(mut self, shutter: f32) -> Self {
+        self.shutter = shutter;
+        self
+    }
+
+    /// Move the camera and spheres of `scene` to where they are in the frame starting at `time`,
+    /// which lasts `frame_duration`, both in seconds.
+    pub fn apply(&self, scene: &mut Scene, time: f32, frame_duration: f32) {
+        if let Some(path) = &self.camera {
+            scene.camera = if self.shutter > 0.0 {
+                Box::new(path.camera_during(time, time + self.shutter * frame_duration))
+            } else {
+                Box::new(path.camera_at(time))
+            };
+        }
+        for path in &self.spheres {
+            if let Some(center) = path.center_at(time) {
+                scene.spheres[path.sphere].center = center;
+            }
+        }
+    }
+}
+
+/// Where `time` falls among `count` keyframes, whose times in order `time_of` gives: the indices
+/// of the four keyframes shaping the curve there, and the fraction of the time from the second
+/// to the third. Before the first keyframe and after the last, all four are that keyframe, so the
+/// curve holds still. `None` without any keyframes.
+fn span(count: usize, time_of: impl Fn(usize) -> f32, time: f32) -> Option<([usize; 4], f32)> {
+    let last = count.checked_sub(1)?;
+    let next = (0..count)
+        .take_while(|&index| time_of(index) <= time)
+        .count();
+    if next == 0 || next > last {
+        return Some(([next.min(last); 4], 0.0));
+    }
+    let (from, to) = (next - 1, next);
+    let t = (time - time_of(from)) / (time_of(to) - time_of(from));
+    // The keyframes either side shape the curve, so it passes smoothly through each one
+    Some(([next.saturating_sub(2), from, to, (next + 1).min(last)], t))
+}
+
+/// Point at `t` along the Catmull-Rom spline from `p1` to `p2`, shaped by `p0` and `p3`.
+fn catmull_rom([p0, p1, p2, p3]: [Vec3f; 4], t: f32) -> Vec3f {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    (p1 * 2.0
+        + (p2 - p0) * t
+        + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+        + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+        * 0.5
+}
@@ -0,0 +1,199 @@
+//! Auxiliary output variables, or passes: images of what the camera sees besides the light
+//! arriving, such as the normals and distance of the surfaces hit, written alongside the render
+//! for compositing and denoising. Passes are averaged over each pixel's samples with the same
+//! weights as the render, so their edges line up with it.
+
+use crate::{
+    integrator::{DirectLighting, Integrator},
+    material::Media,
+    output::Format,
+    rng::Rng,
+    scene::Scene,
+    tracer::{NonFinite, Surface},
+    Ray, Vec3f,
+};
+use std::path::{Path, PathBuf};
+
+/// A pass which can be written alongside the render.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Aov {
+    /// World space shading normal of the first surface hit, mapped from `[-1, 1]` to `[0, 1]`,
+    /// or black where nothing was hit.
+    Normal,
+    /// Distance along the camera ray to the first surface hit, nearest of the pixel's samples,
+    /// and infinite where nothing was hit.
+    Depth,
+    /// Base color of the first surface hit, or black where nothing was hit.
+    Albedo,
+    /// Light reaching the camera straight from the lights and background, or reflected from the
+    /// first surface hit straight from them.
+    Direct,
+    /// The rest of the light, found after more than one bounce, which is the render less the
+    /// direct pass.
+    Indirect,
+    /// Fraction of the pixel's camera rays which hit something, in every channel.
+    Alpha,
+    /// Index of the sphere nearest the camera in the pixel, plus one, or zero where nothing was
+    /// hit, in every channel.
+    ObjectId,
+    /// Index of the material of the sphere nearest the camera in the pixel, plus one, or zero
+    /// where nothing was hit, in every channel.
+    MaterialId,
+}
+
+impl Aov {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "normal" => Some(Aov::Normal),
+            "depth" => Some(Aov::Depth),
+            "albedo" => Some(Aov::Albedo),
+            "direct" => Some(Aov::Direct),
+            "indirect" => Some(Aov::Indirect),
+            "alpha" => Some(Aov::Alpha),
+            "object-id" => Some(Aov::ObjectId),
+            "material-id" => Some(Aov::MaterialId),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::Albedo => "albedo",
+            Aov::Direct => "direct",
+            Aov::Indirect => "indirect",
+            Aov::Alpha => "alpha",
+            Aov::ObjectId => "object-id",
+            Aov::MaterialId => "material-id",
+        }
+    }
+
+    /// Whether the pass holds data rather than light or color, so it's written as it is, without
+    /// the exposure, tone mapping, LUT or sRGB curve the render is written with.
+    pub fn is_data(self) -> bool {
+        matches!(
+            self,
+            Aov::Normal | Aov::Depth | Aov::Alpha | Aov::ObjectId | Aov::MaterialId
+        )
+    }
+
+    /// Whether the pass can be written in `format`. Depth reaches beyond `[0, 1]`, so needs
+    /// floats, and IDs need them exactly, which the 8 bit mantissas of RGBE aren't.
+    pub fn supports(self, format: Format) -> bool {
+        match self {
+            Aov::Depth => matches!(format, Format::Exr | Format::Hdr),
+            Aov::ObjectId | Aov::MaterialId => format == Format::Exr,
+            _ => true,
+        }
+    }
+
+    /// Where the pass of a render written to `path` goes, which is beside it with the pass's name
+    /// before the extension, such as `raytraced.normal.ppm`.
+    pub fn path(self, path: &Path) -> PathBuf {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        path.with_extension(match extension {
+            Some(extension) => format!("{}.{extension}", self.name()),
+            None => self.name().to_string(),
+        })
+    }
+}
+
+/// Sums of the passes over the samples of a pixel.
+pub struct PassSums<'a> {
+    aovs: &'a [Aov],
+    sums: Vec<Vec3f>,
+    /// Distance to the nearest surface any sample hit, which depth and IDs are taken from.
+    nearest: f32,
+}
+
+impl<'a> PassSums<'a> {
+    /// Sums of `aovs`, with no samples added yet.
+    pub fn new(aovs: &'a [Aov]) -> Self {
+        PassSums {
+            aovs,
+            sums: vec![Vec3f::default(); aovs.len()],
+            nearest: f32::INFINITY,
+        }
+    }
+
+    /// Add the passes of the sample along camera `ray`, if it had one, which brought back
+    /// `radiance`, with the weight the filter gave it. Lighting is sampled with `rng`, which
+    /// should be apart from the sample's own numbers, so asking for passes doesn't change the
+    /// render.
+    pub fn add(
+        &mut self,
+        ray: Option<&Ray>,
+        radiance: Vec3f,
+        weight: f32,
+        scene: &Scene,
+        rng: &mut Rng,
+    ) -> Result<(), NonFinite> {
+        let hit = ray.and_then(|ray| Some((ray, scene.intersect(ray)?)));
+        let surface =
+            hit.map(|(ray, hit)| (Surface::new(ray, &hit, scene, &Media::default()), hit));
+        let nearest = surface.as_ref().filter(|(_, hit)| hit.t < self.nearest);
+        let needs_direct = self
+            .aovs
+            .iter()
+            .any(|&aov| matches!(aov, Aov::Direct | Aov::Indirect));
+        let direct = match ray {
+            Some(&ray) if needs_direct => DirectLighting.trace(ray, scene, rng)?,
+            _ => Vec3f::default(),
+        };
+
+        for (&aov, sum) in self.aovs.iter().zip(&mut self.sums) {
+            let value = match aov {
+                Aov::Normal => match &surface {
+                    Some((surface, _)) => {
+                        (surface.interaction.normal + Vec3f::new_uniform(1.0)) * 0.5
+                    }
+                    None => Vec3f::default(),
+                },
+                // Depth and IDs aren't blended, they're those of the nearest surface
+                Aov::Depth | Aov::ObjectId | Aov::MaterialId => {
+                    if let Some((surface, hit)) = nearest {
+                        let sphere = &scene.spheres[surface.sphere];
+                        *sum = Vec3f::new_uniform(match aov {
+                            Aov::Depth => hit.t,
+                            Aov::ObjectId => (surface.sphere + 1) as f32,
+                            _ => (sphere.material.0 + 1) as f32,
+                        });
+                    }
+                    continue;
+                }
+                Aov::Albedo => match &surface {
+                    Some((surface, _)) => {
+                        let sphere = &scene.spheres[surface.sphere];
+                        scene.material(sphere.material).pbr().base_color
+                    }
+                    None => Vec3f::default(),
+                },
+                Aov::Direct => direct,
+                Aov::Indirect => radiance - direct,
+                Aov::Alpha => Vec3f::new_uniform(if surface.is_some() { 1.0 } else { 0.0 }),
+            };
+            *sum += value * weight;
+        }
+        if let Some((_, hit)) = nearest {
+            self.nearest = hit.t;
+        }
+        Ok(())
+    }
+
+    /// The passes of the pixel, once all of its samples are added, whose weights sum to
+    /// `total_weight`, in the order of the `aovs` they were made with.
+    pub fn average(&self, total_weight: f32) -> impl Iterator<Item = Vec3f> + '_ {
+        self.aovs.iter().zip(&self.sums).map(move |(&aov, &sum)| {
+            if aov == Aov::Depth && self.nearest == f32::INFINITY {
+                Vec3f::new_uniform(f32::INFINITY)
+            } else if matches!(aov, Aov::Depth | Aov::ObjectId | Aov::MaterialId) {
+                sum
+            } else if total_weight > 0.0 {
+                sum * (1.0 / total_weight)
+            } else {
+                Vec3f::default()
+            }
+        })
+    }
+}
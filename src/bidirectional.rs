@@ -0,0 +1,533 @@
+//! Bidirectional path tracing, which follows paths of light out from the lights as well as back
+//! from the camera, and joins every point on one to every point on the other. Light which camera
+//! paths rarely find by themselves, like light bouncing into shadowed corners, or focused by
+//! glass onto a surface which is itself only seen in a reflection, is found from the lights.
+//!
+//! Each way of joining the paths is weighed against the others which could have made the same
+//! path of light, with multiple importance sampling. Light paths aren't joined to the camera
+//! itself, which would need every projection to be inverted, so caustics seen directly are still
+//! left to camera paths finding the lights. Glass blocks the paths being joined, rather than
+//! tinting them like it tints shadows, as the light it lets through is found by following it.
+//! Media only absorb the light travelling through them.
+
+use crate::{
+    material::{Material, Media},
+    rng::Rng,
+    scene::Scene,
+    tracer::{self, power_heuristic, NonFinite, SampledLights, Surface},
+    Ray, Vec3f,
+};
+use std::f32::consts::PI;
+
+/// Most surfaces on each of the paths from the camera and from the light.
+pub const MAX_SUBPATH_VERTICES: usize = 8;
+
+/// Bounces after which paths may be ended at random by Russian roulette.
+const ROULETTE_DEPTH: usize = 3;
+
+/// A point on a path from the camera or from a light.
+struct Vertex<'a> {
+    point: Vec3f,
+    /// Normal of the surface, or `None` for lights emitting from a single point.
+    normal: Option<Vec3f>,
+    /// Weight of the light carried along the path as far as the vertex.
+    beta: Vec3f,
+    /// Probability density per unit area of the path reaching the vertex from the one before.
+    pdf_forward: f32,
+    /// Probability density per unit area of the vertex being reached from the one after it, were
+    /// the path followed the other way.
+    pdf_reverse: f32,
+    /// Whether the surface scatters light in a few sharp directions, like a mirror, so paths
+    /// can't be joined there.
+    delta: bool,
+    kind: Kind<'a>,
+}
+
+enum Kind<'a> {
+    /// On the light with the given index.
+    Light(usize),
+    Surface {
+        surface: Surface,
+        material: &'a dyn Material,
+        /// Direction the path arrived in.
+        arriving: Vec3f,
+        /// Whether the material is otherwise only lit directly, so paths leave it in cosine
+        /// weighted directions.
+        gathers: bool,
+    },
+}
+
+impl Vertex<'_> {
+    /// Fraction of the light arriving from `direction` which the surface reflects back along the
+    /// path, including the cosine term. Zero for directions below the surface.
+    fn reflectance(&self, direction: Vec3f) -> Vec3f {
+        let Kind::Surface {
+            surface,
+            material,
+            arriving,
+            ..
+        } = &self.kind
+        else {
+            return Vec3f::new_uniform(0.0);
+        };
+        let cos = surface.interaction.normal.dot_product(direction);
+        if cos <= 0.0 {
+            return Vec3f::new_uniform(0.0);
+        }
+        let ray = Ray {
+            origin: self.point - *arriving,
+            direction: *arriving,
+        };
+        // eval is scaled by pi, relative to the BRDF
+        material.eval(&ray, &surface.interaction, direction) * (cos / PI)
+    }
+
+    /// Probability density per unit solid angle of the path leaving in `direction`, having
+    /// arrived in `arriving`.
+    fn scatter_pdf(&self, arriving: Vec3f, direction: Vec3f) -> f32 {
+        match &self.kind {
+            _ if self.delta => 0.0,
+            Kind::Light(_) => 0.0,
+            Kind::Surface {
+                surface,
+                material,
+                gathers,
+                ..
+            } => {
+                let interaction = &surface.interaction;
+                if *gathers {
+                    return interaction.normal.dot_product(direction).max(0.0) / PI;
+                }
+                let ray = Ray {
+                    origin: self.point - arriving,
+                    direction: arriving,
+                };
+                material.pdf(&ray, interaction, direction)
+            }
+        }
+    }
+
+    /// Probability density per unit solid angle of the path leaving in `direction`, having
+    /// arrived as it did.
+    fn leaving_pdf(&self, direction: Vec3f) -> f32 {
+        match &self.kind {
+            Kind::Light(_) => 0.0,
+            Kind::Surface { arriving, .. } => self.scatter_pdf(*arriving, direction),
+        }
+    }
+
+    /// Probability density per unit area of a path arriving from `previous`, or as this path
+    /// arrived if `None`, continuing from here to `next`.
+    fn pdf(&self, scene: &Scene, previous: Option<&Vertex>, next: &Vertex) -> f32 {
+        let offset = next.point - self.point;
+        let sqr_distance = offset.sqr_magnitude();
+        if sqr_distance <= 0.0 {
+            return 0.0;
+        }
+        let direction = offset * (1.0 / sqr_distance.sqrt());
+        let pdf = match &self.kind {
+            Kind::Light(index) => scene.lights()[*index].emit_pdf(self.point, direction).1,
+            Kind::Surface { arriving, .. } => {
+                let arriving = previous.map_or(*arriving, |previous| {
+                    (self.point - previous.point).normalized()
+                });
+                self.scatter_pdf(arriving, direction)
+            }
+        };
+        pdf * next.cos(direction) / sqr_distance
+    }
+
+    /// Cosine of the surface normal to `direction`, converting densities per unit solid angle
+    /// into densities per unit area. One for lights emitting from a single point.
+    fn cos(&self, direction: Vec3f) -> f32 {
+        self.normal
+            .map_or(1.0, |normal| normal.dot_product(direction).abs())
+    }
+}
+
+/// Light arriving along the camera ray, or what produced a NaN or infinite value along the way.
+pub fn trace(ray: Ray, scene: &Scene, rng: &mut Rng) -> Result<Vec3f, NonFinite> {
+    let mut camera = Vec::with_capacity(MAX_SUBPATH_VERTICES);
+    let escaped = random_walk(scene, ray, Vec3f::new_uniform(1.0), 0.0, &mut camera, rng);
+    let light = light_path(scene, rng);
+
+    let mut radiance = Vec3f::new_uniform(0.0);
+    if let Some((ray, beta)) = escaped {
+        // The background is only sampled directly where paths can be joined
+        let sampled_lights =
+            camera
+                .last()
+                .filter(|vertex| !vertex.delta)
+                .map(|vertex| SampledLights {
+                    point: vertex.point,
+                    pdf: vertex.leaving_pdf(ray.direction),
+                });
+        radiance += tracer::escaped(&ray, scene, sampled_lights) * beta;
+    }
+    for depth in 0..camera.len() {
+        let path = &camera[..=depth];
+        let vertex = &camera[depth];
+        let mut contribution = emitted(scene, path);
+        if !vertex.delta {
+            contribution += sample_background(scene, vertex, rng);
+            contribution += sample_light(scene, path, rng);
+            for length in 2..=light.len() {
+                contribution += connect(scene, path, &light[..length]);
+            }
+        }
+        if !contribution.is_finite() {
+            let Kind::Surface {
+                surface, material, ..
+            } = &vertex.kind
+            else {
+                continue;
+            };
+            return Err(NonFinite {
+                sphere: surface.sphere,
+                material: material.name(),
+                depth,
+                quantity: "radiance",
+            });
+        }
+        radiance += contribution;
+    }
+    Ok(radiance)
+}
+
+/// Follow a path of light out from a light, chosen in proportion to its power.
+fn light_path<'a>(scene: &'a Scene, rng: &mut Rng) -> Vec<Vertex<'a>> {
+    let mut vertices = Vec::with_capacity(MAX_SUBPATH_VERTICES + 1);
+    let Some((index, probability)) = scene.choose_light(rng) else {
+        return vertices;
+    };
+    let Some(emission) = scene.lights()[index].emit(rng) else {
+        return vertices;
+    };
+    let pdf_position = probability * emission.pdf_position;
+    let cos = emission
+        .normal
+        .map_or(1.0, |normal| normal.dot_product(emission.ray.direction));
+    vertices.push(Vertex {
+        point: emission.ray.origin,
+        normal: emission.normal,
+        beta: emission.radiance * (1.0 / pdf_position),
+        pdf_forward: pdf_position,
+        pdf_reverse: 0.0,
+        delta: false,
+        kind: Kind::Light(index),
+    });
+    let beta = emission.radiance * (cos / (pdf_position * emission.pdf_direction));
+    random_walk(
+        scene,
+        emission.ray,
+        beta,
+        emission.pdf_direction,
+        &mut vertices,
+        rng,
+    );
+    vertices
+}
+
+/// Follow a path from `ray`, carrying `beta` and chosen with probability density `pdf` per unit
+/// solid angle, adding each surface it scatters off to `vertices`. Returns the ray which escaped
+/// the scene, if the path ended that way, with the weight of the light it brings back.
+fn random_walk<'a>(
+    scene: &'a Scene,
+    mut ray: Ray,
+    mut beta: Vec3f,
+    mut pdf: f32,
+    vertices: &mut Vec<Vertex<'a>>,
+    rng: &mut Rng,
+) -> Option<(Ray, Vec3f)> {
+    let mut media = Media::default();
+    for depth in 0..MAX_SUBPATH_VERTICES {
+        let Some(hit) = scene.intersect(&ray) else {
+            return Some((ray, beta));
+        };
+        beta *= tracer::transmittance(scene, &media, &ray, hit.t);
+        let material = scene.material(scene.spheres[hit.sphere].material);
+        let surface = Surface::new(&ray, &hit, scene, &media);
+        let interaction = surface.interaction;
+        let sqr_distance = (interaction.point - ray.origin).sqr_magnitude();
+
+        // Surfaces which split the light, like glass reflecting and refracting it, continue the
+        // path along one of the rays, chosen in proportion to the light it carries. Surfaces
+        // otherwise only lit directly scatter in cosine weighted directions.
+        let scattered = material.scatter(&ray, &interaction, rng);
+        let gathers = scattered.is_none();
+        let next = match scattered {
+            Some(mut secondary) => {
+                let total: f32 = secondary.iter().map(|(_, weight)| weight.average()).sum();
+                let mut target = rng.next_f32() * total;
+                let index = secondary
+                    .iter()
+                    .position(|(_, weight)| {
+                        target -= weight.average();
+                        target < 0.0
+                    })
+                    .unwrap_or(secondary.len().saturating_sub(1));
+                (total > 0.0)
+                    .then(|| secondary.swap_remove(index))
+                    .map(|(next, weight)| (next, weight * (total / weight.average())))
+            }
+            None => {
+                let direction = interaction.normal + rng.unit_vector();
+                (direction.magnitude() > 1e-6).then(|| {
+                    let direction = direction.normalized();
+                    let weight = material.eval(&ray, &interaction, direction);
+                    (interaction.spawn_ray(direction), weight)
+                })
+            }
+        };
+        let vertex = Vertex {
+            point: interaction.point,
+            normal: Some(surface.normal),
+            beta,
+            pdf_forward: pdf * surface.normal.dot_product(ray.direction).abs() / sqr_distance,
+            pdf_reverse: 0.0,
+            delta: !gathers && !material.samples_lights(),
+            kind: Kind::Surface {
+                surface,
+                material,
+                arriving: ray.direction,
+                gathers,
+            },
+        };
+        let Some((next, weight)) = next.filter(|(_, weight)| weight.is_positive()) else {
+            vertices.push(vertex);
+            return None;
+        };
+
+        pdf = vertex.scatter_pdf(ray.direction, next.direction);
+        // Density of the path going the other way, from the next vertex back to the one before
+        let reverse = vertex.scatter_pdf(-next.direction, -ray.direction);
+        if let Some(previous) = vertices.last_mut() {
+            previous.pdf_reverse = reverse * previous.cos(ray.direction) / sqr_distance;
+        }
+        if let Kind::Surface {
+            surface, material, ..
+        } = &vertex.kind
+        {
+            media = surface.media_towards(next.direction, *material, &media);
+        }
+        vertices.push(vertex);
+        beta *= weight;
+
+        if depth + 1 >= ROULETTE_DEPTH {
+            // Paths carrying little light are ended early, and the survivors make up for them
+            let survival = beta.max_component().min(0.95);
+            if rng.next_f32() >= survival {
+                return None;
+            }
+            beta = beta * (1.0 / survival);
+        }
+        ray = next;
+    }
+    None
+}
+
+/// Light emitted towards the camera by the surface at the end of the camera path, where the path
+/// found a light by itself.
+fn emitted(scene: &Scene, camera: &[Vertex]) -> Vec3f {
+    let vertex = &camera[camera.len() - 1];
+    let Kind::Surface { material, .. } = &vertex.kind else {
+        return Vec3f::new_uniform(0.0);
+    };
+    let emitted = material.emitted();
+    if !emitted.is_positive() {
+        return Vec3f::new_uniform(0.0);
+    }
+    vertex.beta * emitted * mis_weight(scene, camera, &[])
+}
+
+/// Light from the sun or an environment map reaching a vertex of the camera path directly.
+fn sample_background(scene: &Scene, vertex: &Vertex, rng: &mut Rng) -> Vec3f {
+    let Kind::Surface {
+        surface,
+        material,
+        arriving,
+        ..
+    } = &vertex.kind
+    else {
+        return Vec3f::new_uniform(0.0);
+    };
+    let ray = Ray {
+        origin: vertex.point - *arriving,
+        direction: *arriving,
+    };
+    let direct_weight =
+        |pdf: f32, direction: Vec3f| power_heuristic(pdf, vertex.leaving_pdf(direction));
+    let radiance = tracer::sample_background(
+        &ray,
+        &surface.interaction,
+        *material,
+        scene,
+        direct_weight,
+        true,
+        rng,
+    );
+    vertex.beta * radiance
+}
+
+/// Light reaching the end of the camera path straight from a point sampled on a light, which is
+/// a light path of a single vertex.
+fn sample_light(scene: &Scene, camera: &[Vertex], rng: &mut Rng) -> Vec3f {
+    let vertex = &camera[camera.len() - 1];
+    let Kind::Surface { surface, .. } = &vertex.kind else {
+        return Vec3f::new_uniform(0.0);
+    };
+    let Some((index, probability)) = scene.choose_light(rng) else {
+        return Vec3f::new_uniform(0.0);
+    };
+    let light = &scene.lights()[index];
+    let Some(sample) = light.sample(vertex.point, rng) else {
+        return Vec3f::new_uniform(0.0);
+    };
+    let reflectance = vertex.reflectance(sample.direction);
+    if !reflectance.is_positive() || !sample.radiance.is_positive() {
+        return Vec3f::new_uniform(0.0);
+    }
+    let shadow_ray = surface.interaction.spawn_ray(sample.direction);
+    let occluded = scene
+        .intersect(&shadow_ray)
+        .is_some_and(|hit| hit.t < sample.distance && light.sphere() != Some(hit.sphere));
+    if occluded {
+        return Vec3f::new_uniform(0.0);
+    }
+
+    // The light's end of the path, as if it had been chosen to start a light path
+    let point = vertex.point + sample.direction * sample.distance;
+    let (pdf_position, _) = light.emit_pdf(point, -sample.direction);
+    let light_vertex = Vertex {
+        point,
+        normal: light
+            .sphere()
+            .map(|sphere| (point - scene.spheres[sphere].center).normalized()),
+        beta: Vec3f::new_uniform(0.0),
+        pdf_forward: probability * pdf_position,
+        pdf_reverse: 0.0,
+        delta: false,
+        kind: Kind::Light(index),
+    };
+    let weight = mis_weight(scene, camera, &[light_vertex]);
+    vertex.beta * reflectance * sample.radiance * (weight / (probability * sample.pdf))
+}
+
+/// Light carried along the light path and on from its end to the end of the camera path, if
+/// nothing lies between them.
+fn connect(scene: &Scene, camera: &[Vertex], light: &[Vertex]) -> Vec3f {
+    let (pt, qs) = (&camera[camera.len() - 1], &light[light.len() - 1]);
+    if qs.delta {
+        return Vec3f::new_uniform(0.0);
+    }
+    let offset = qs.point - pt.point;
+    let sqr_distance = offset.sqr_magnitude();
+    let direction = offset * (1.0 / sqr_distance.sqrt());
+    let contribution = pt.beta
+        * pt.reflectance(direction)
+        * qs.reflectance(-direction)
+        * qs.beta
+        * (1.0 / sqr_distance);
+    if !contribution.is_positive() || !visible(scene, pt, qs) {
+        return Vec3f::new_uniform(0.0);
+    }
+    contribution * mis_weight(scene, camera, light)
+}
+
+/// Whether nothing lies between the surfaces at two vertices.
+fn visible(scene: &Scene, from: &Vertex, to: &Vertex) -> bool {
+    let (Kind::Surface { surface: a, .. }, Kind::Surface { surface: b, .. }) =
+        (&from.kind, &to.kind)
+    else {
+        return false;
+    };
+    let direction = (to.point - from.point).normalized();
+    let ray = a.interaction.spawn_ray(direction);
+    let distance = (to.point - ray.origin).magnitude();
+    // The surface faces the ray, so the first hit on its sphere is the vertex itself, even
+    // where the hit is too imprecise to tell how far along the ray it is
+    scene
+        .intersect(&ray)
+        .is_none_or(|hit| hit.t >= distance || hit.sphere == b.sphere)
+}
+
+/// Weight of the path made by joining the end of `camera` to the end of `light`, against every
+/// other way of joining a camera path and a light path which could have made it, by the balance
+/// heuristic. An empty light path is a camera path which found a light by itself.
+fn mis_weight(scene: &Scene, camera: &[Vertex], light: &[Vertex]) -> f32 {
+    // Forward and reverse densities, and whether paths can be joined at each vertex. The
+    // vertices being joined have their reverse densities replaced, now the path continues past
+    // them.
+    let densities = |path: &[Vertex]| -> Vec<(f32, f32, bool)> {
+        path.iter()
+            .map(|vertex| (vertex.pdf_forward, vertex.pdf_reverse, !vertex.delta))
+            .collect()
+    };
+    let (mut camera_pdfs, mut light_pdfs) = (densities(camera), densities(light));
+    let (t, s) = (camera.len(), light.len());
+    let pt = &camera[t - 1];
+    let pt_minus = t.checked_sub(2).map(|index| &camera[index]);
+    camera_pdfs[t - 1].2 = true;
+    if s == 0 {
+        let Kind::Surface {
+            surface, arriving, ..
+        } = &pt.kind
+        else {
+            return 1.0;
+        };
+        // Only spheres which are lights could have started a light path
+        let Some(index) = scene
+            .lights()
+            .iter()
+            .position(|light| light.sphere() == Some(surface.sphere))
+        else {
+            return 1.0;
+        };
+        let (pdf_position, pdf_direction) = scene.lights()[index].emit_pdf(pt.point, -*arriving);
+        camera_pdfs[t - 1].1 = scene.power_fraction(index) * pdf_position;
+        if let Some(pt_minus) = pt_minus {
+            let sqr_distance = (pt.point - pt_minus.point).sqr_magnitude();
+            camera_pdfs[t - 2].1 = pdf_direction * pt_minus.cos(*arriving) / sqr_distance;
+        }
+    } else {
+        let qs = &light[s - 1];
+        let qs_minus = s.checked_sub(2).map(|index| &light[index]);
+        light_pdfs[s - 1].2 = true;
+        camera_pdfs[t - 1].1 = qs.pdf(scene, qs_minus, pt);
+        if let Some(pt_minus) = pt_minus {
+            camera_pdfs[t - 2].1 = pt.pdf(scene, Some(qs), pt_minus);
+        }
+        light_pdfs[s - 1].1 = pt.pdf(scene, pt_minus, qs);
+        if let Some(qs_minus) = qs_minus {
+            light_pdfs[s - 2].1 = qs.pdf(scene, Some(pt), qs_minus);
+        }
+    }
+
+    // Ratios of the density of each other way of making the path to this one's, walking the
+    // join point along the path. Delta densities are left as one, as they cancel out.
+    let remap = |pdf: f32| if pdf == 0.0 { 1.0 } else { pdf };
+    let mut sum = 0.0;
+    let mut ratio = 1.0;
+    // The camera itself can't be joined to, so camera paths keep at least one vertex
+    for i in (1..t).rev() {
+        ratio *= remap(camera_pdfs[i].1) / remap(camera_pdfs[i].0);
+        if camera_pdfs[i].2 && camera_pdfs[i - 1].2 {
+            sum += ratio;
+        }
+    }
+    ratio = 1.0;
+    for i in (0..s).rev() {
+        ratio *= remap(light_pdfs[i].1) / remap(light_pdfs[i].0);
+        // Camera paths can only find lights which are spheres
+        let before = match i {
+            0 => {
+                matches!(light[0].kind, Kind::Light(index) if scene.lights()[index].sphere().is_some())
+            }
+            _ => light_pdfs[i - 1].2,
+        };
+        if light_pdfs[i].2 && before {
+            sum += ratio;
+        }
+    }
+    1.0 / (1.0 + sum)
+}
@@ -0,0 +1,130 @@
+//! A tileable blue noise mask: a value for every pixel of a square, which are spread so that
+//! similar values are never near each other. Offsetting each pixel's random numbers by the mask
+//! leaves the noise in an image at high frequencies only, which looks finer to the eye and is
+//! easier for a denoiser to remove than clumps of white noise.
+//!
+//! The mask is made once, the first time it's used, by Ulichney's void-and-cluster method.
+
+use crate::rng::Rng;
+use std::sync::OnceLock;
+
+/// Width and height of the mask, which repeats across the image.
+pub const MASK_SIZE: usize = 64;
+
+/// Spread of the filter which finds clusters and voids in the mask, in pixels.
+const SIGMA: f32 = 1.5;
+
+/// Fraction of the pixels set in the initial pattern.
+const INITIAL_DENSITY: f32 = 0.1;
+
+/// Value of the mask at pixel (`x`, `y`), in `[0, 1)`, repeating every `MASK_SIZE` pixels.
+pub fn value(x: usize, y: usize) -> f32 {
+    static MASK: OnceLock<Vec<u16>> = OnceLock::new();
+    let mask = MASK.get_or_init(void_and_cluster);
+    let rank = mask[(y % MASK_SIZE) * MASK_SIZE + x % MASK_SIZE];
+    (f32::from(rank) + 0.5) / (MASK_SIZE * MASK_SIZE) as f32
+}
+
+/// Pixels set in a binary pattern, with the energy of the filter around them at every pixel.
+#[derive(Clone)]
+struct Pattern {
+    set: Vec<bool>,
+    energy: Vec<f32>,
+    /// Filter weight at each offset, wrapping around the mask.
+    filter: Vec<f32>,
+}
+
+impl Pattern {
+    fn new() -> Self {
+        let filter = (0..MASK_SIZE * MASK_SIZE)
+            .map(|index| {
+                let distance = |d: usize| d.min(MASK_SIZE - d) as f32;
+                let (dx, dy) = (distance(index % MASK_SIZE), distance(index / MASK_SIZE));
+                (-(dx * dx + dy * dy) / (2.0 * SIGMA * SIGMA)).exp()
+            })
+            .collect();
+        Pattern {
+            set: vec![false; MASK_SIZE * MASK_SIZE],
+            energy: vec![0.0; MASK_SIZE * MASK_SIZE],
+            filter,
+        }
+    }
+
+    fn toggle(&mut self, pixel: usize) {
+        self.set[pixel] = !self.set[pixel];
+        let sign = if self.set[pixel] { 1.0 } else { -1.0 };
+        let (px, py) = (pixel % MASK_SIZE, pixel / MASK_SIZE);
+        for (index, energy) in self.energy.iter_mut().enumerate() {
+            let dx = (index % MASK_SIZE + MASK_SIZE - px) % MASK_SIZE;
+            let dy = (index / MASK_SIZE + MASK_SIZE - py) % MASK_SIZE;
+            *energy += sign * self.filter[dy * MASK_SIZE + dx];
+        }
+    }
+
+    /// The set pixel with the most set pixels around it.
+    fn tightest_cluster(&self) -> usize {
+        self.extreme(true, |a, b| a > b)
+    }
+
+    /// The unset pixel with the fewest set pixels around it.
+    fn largest_void(&self) -> usize {
+        self.extreme(false, |a, b| a < b)
+    }
+
+    fn extreme(&self, set: bool, better: impl Fn(f32, f32) -> bool) -> usize {
+        let mut best = None;
+        for (pixel, &energy) in self.energy.iter().enumerate() {
+            if self.set[pixel] != set {
+                continue;
+            }
+            if best.is_none_or(|(_, best_energy)| better(energy, best_energy)) {
+                best = Some((pixel, energy));
+            }
+        }
+        best.map_or(0, |(pixel, _)| pixel)
+    }
+}
+
+/// Rank every pixel of the mask, from the order they're added to a pattern which is kept as even
+/// as it can be at every step.
+fn void_and_cluster() -> Vec<u16> {
+    let pixels = MASK_SIZE * MASK_SIZE;
+    let mut ranks = vec![0; pixels];
+
+    // A random initial pattern, evened out by moving pixels from the tightest cluster to the
+    // largest void until the move would go straight back, or it's taking too long
+    let mut initial = Pattern::new();
+    let mut rng = Rng::new(0);
+    let count = (pixels as f32 * INITIAL_DENSITY) as usize;
+    while initial.set.iter().filter(|&&set| set).count() < count {
+        let pixel = (rng.next_u64() % pixels as u64) as usize;
+        if !initial.set[pixel] {
+            initial.toggle(pixel);
+        }
+    }
+    for _ in 0..pixels {
+        let cluster = initial.tightest_cluster();
+        initial.toggle(cluster);
+        let void = initial.largest_void();
+        initial.toggle(void);
+        if void == cluster {
+            break;
+        }
+    }
+
+    // The initial pixels are ranked below the count, taking out the tightest cluster each time
+    let mut pattern = initial.clone();
+    for rank in (0..count).rev() {
+        let cluster = pattern.tightest_cluster();
+        pattern.toggle(cluster);
+        ranks[cluster] = rank as u16;
+    }
+    // and the rest above it, filling in the largest void each time
+    let mut pattern = initial;
+    for rank in count..pixels {
+        let void = pattern.largest_void();
+        pattern.toggle(void);
+        ranks[void] = rank as u16;
+    }
+    ranks
+}
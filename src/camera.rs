@@ -0,0 +1,591 @@
+//! Cameras, which decide where the rays for each pixel start and which way they go.
+
+use crate::{rng::Rng, Ray, Vec3f};
+use std::f32::consts::PI;
+
+/// Vertical field of view of the default camera, in degrees.
+pub const DEFAULT_FOV: f32 = 30.0;
+
+/// Generates the rays which the image is rendered from.
+pub trait Camera: Send + Sync {
+    /// The ray through the point (`px`, `py`) of the image, each from 0 to 1 across it and down
+    /// it, for an image `aspect_ratio` times wider than it is tall. Cameras which vary their rays,
+    /// such as for depth of field, draw the random numbers for this sample from `rng`. Returns
+    /// `None` for points which the camera doesn't cover.
+    fn generate_ray(&self, px: f32, py: f32, aspect_ratio: f32, rng: &mut Rng) -> Option<Ray>;
+
+    /// Closest equivalent of the camera as a fixed view, for exporting.
+    fn view(&self) -> View;
+
+    /// The ray through the center of pixel (`x`, `y`) of an image `width` by `height` pixels,
+    /// counting rows from the top.
+    fn pixel_ray(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+        rng: &mut Rng,
+    ) -> Option<Ray> {
+        self.pixel_sample_ray(x, y, (0.5, 0.5), width, height, rng)
+    }
+
+    /// The ray through the point `offset` within pixel (`x`, `y`), from (0, 0) at the pixel's top
+    /// left corner to (1, 1) at its bottom right.
+    fn pixel_sample_ray(
+        &self,
+        x: usize,
+        y: usize,
+        (dx, dy): (f32, f32),
+        width: usize,
+        height: usize,
+        rng: &mut Rng,
+    ) -> Option<Ray> {
+        let px = (x as f32 + dx) * (1.0 / width as f32);
+        let py = (y as f32 + dy) * (1.0 / height as f32);
+        self.generate_ray(px, py, width as f32 / height as f32, rng)
+    }
+}
+
+/// Where a camera is, which way it faces, and how it projects the scene.
+pub struct View {
+    pub eye: Vec3f,
+    pub right: Vec3f,
+    pub up: Vec3f,
+    pub forward: Vec3f,
+    /// Vertical field of view, in degrees, for perspective projection.
+    pub fov: f32,
+    pub projection: Projection,
+}
+
+/// How the camera maps pixels to rays.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Projection {
+    /// Rays fan out from the eye across the field of view, as in a photograph.
+    Perspective,
+    /// Parallel rays along the view direction, across a view `height` units tall, so that
+    /// objects appear the same size at any distance.
+    Orthographic { height: f32 },
+    /// A fisheye lens covering `fov` degrees, which may be 180 or more, across a circle fitting
+    /// the height of the image. Pixels outside the circle are left black.
+    Fisheye { fov: f32, mapping: FisheyeMapping },
+    /// Every direction around the camera, with longitude across the image and latitude down it,
+    /// in the layout of [`EnvironmentMap`](crate::environment::EnvironmentMap). Meant for images
+    /// twice as wide as they are tall.
+    Equirectangular,
+}
+
+/// How the angle of a ray from the view direction maps to its distance from the center of a
+/// fisheye image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FisheyeMapping {
+    /// Distance in proportion to the angle, keeping angles even across the image.
+    Equidistant,
+    /// Equal areas of the image cover equal solid angles, as in most real fisheye lenses.
+    Equisolid,
+}
+
+/// A pair of views, one for each eye, rendered into the two halves of the image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Stereo {
+    /// Distance between the eyes, which sit either side of the camera's position.
+    pub interocular: f32,
+    /// Distance at which the eyes' views meet, so objects there appear at the depth of the
+    /// screen, or `None` for parallel views which meet at infinity.
+    pub convergence: Option<f32>,
+    pub layout: StereoLayout,
+}
+
+/// Where each eye's view goes in a stereo image.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum StereoLayout {
+    /// The left eye in the left half, and the right eye in the right half.
+    SideBySide,
+    /// The left eye in the top half, and the right eye in the bottom half.
+    OverUnder,
+}
+
+/// Where the camera moves to over the course of a frame, for motion blur. The camera moves
+/// steadily from its own position at time 0 to this one at time 1, and is seen while the shutter
+/// is open.
+#[derive(Copy, Clone, PartialEq)]
+pub struct Motion {
+    pub eye: Vec3f,
+    pub forward: Vec3f,
+    pub up: Vec3f,
+    /// Times, from 0 to 1, when the shutter opens and closes.
+    pub shutter_open: f32,
+    pub shutter_close: f32,
+}
+
+/// A camera positioned by the point it looks from and the point it looks at, which focuses
+/// through a thin lens. It's a pinhole camera, with everything in focus, unless given an aperture
+/// for depth of field.
+#[derive(Clone)]
+pub struct ThinLensCamera {
+    pub eye: Vec3f,
+    /// Unit vectors of the camera's frame, pointing to the right of the image, to the top, and
+    /// in the direction the camera looks.
+    pub right: Vec3f,
+    pub up: Vec3f,
+    pub forward: Vec3f,
+    /// Vertical field of view, in degrees, for perspective projection.
+    pub fov: f32,
+    pub projection: Projection,
+    /// Radius of the lens. Rays start from random points across it, blurring whatever is away
+    /// from the focus distance.
+    pub aperture: f32,
+    /// Distance in front of the camera to the plane which is in perfect focus.
+    pub focus_distance: f32,
+    /// Movement of the lens across the sensor, as fractions of the image's width and height, to
+    /// the right and up. Shifting up keeps the verticals of a tall building parallel while
+    /// framing its top.
+    pub shift_x: f32,
+    pub shift_y: f32,
+    /// Angles of the lens, in degrees, which tilt the plane of focus instead of keeping it
+    /// square to the view. Tilt brings the bottom of the plane nearer, and swing the right.
+    pub tilt: f32,
+    pub swing: f32,
+    /// Renders a view for each eye, when set.
+    pub stereo: Option<Stereo>,
+    /// Blurs the image along the camera's movement, when set.
+    pub motion: Option<Motion>,
+}
+
+impl ThinLensCamera {
+    /// A camera at `eye` looking towards `target`, turned so that `up` points towards the top of
+    /// the image.
+    pub fn look_at(eye: Vec3f, target: Vec3f, up: Vec3f, fov: f32) -> Self {
+        let forward = (target - eye).normalized();
+        let right = forward.cross_product(up).normalized();
+        ThinLensCamera {
+            eye,
+            right,
+            up: right.cross_product(forward),
+            forward,
+            fov,
+            projection: Projection::Perspective,
+            aperture: 0.0,
+            focus_distance: (target - eye).magnitude(),
+            shift_x: 0.0,
+            shift_y: 0.0,
+            tilt: 0.0,
+            swing: 0.0,
+            stereo: None,
+            motion: None,
+        }
+    }
+
+    /// Start building a camera, which is at the origin looking down -Z like the default one until
+    /// moved.
+    pub fn builder() -> CameraBuilder {
+        CameraBuilder {
+            eye: Vec3f::new_uniform(0.0),
+            target: Vec3f::new(0.0, 0.0, -1.0),
+            up: Vec3f::new(0.0, 1.0, 0.0),
+            fov: DEFAULT_FOV,
+            projection: Projection::Perspective,
+            depth_of_field: None,
+            shift: (0.0, 0.0),
+            tilt: (0.0, 0.0),
+            stereo: None,
+            motion: None,
+        }
+    }
+
+    /// Move the camera to `eye`, looking towards `target` with `up` towards the top of the
+    /// image, keeping its other settings.
+    pub fn with_position(mut self, eye: Vec3f, target: Vec3f, up: Vec3f) -> Self {
+        self.eye = eye;
+        self.forward = (target - eye).normalized();
+        self.right = self.forward.cross_product(up).normalized();
+        self.up = self.right.cross_product(self.forward);
+        self
+    }
+
+    pub fn with_projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    /// Give the camera a lens of radius `aperture`, focused at `focus_distance`.
+    pub fn with_depth_of_field(mut self, aperture: f32, focus_distance: f32) -> Self {
+        self.aperture = aperture.max(0.0);
+        self.focus_distance = focus_distance;
+        self
+    }
+
+    pub fn with_shift(mut self, shift_x: f32, shift_y: f32) -> Self {
+        self.shift_x = shift_x;
+        self.shift_y = shift_y;
+        self
+    }
+
+    /// Tilt the plane of focus, following the Scheimpflug principle of a lens tilted against the
+    /// sensor. With a wide aperture and strong tilt, only a band of the image is sharp, making
+    /// scenes look like miniatures.
+    pub fn with_tilt(mut self, tilt: f32, swing: f32) -> Self {
+        self.tilt = tilt.clamp(-89.0, 89.0);
+        self.swing = swing.clamp(-89.0, 89.0);
+        self
+    }
+
+    pub fn with_stereo(mut self, stereo: Stereo) -> Self {
+        self.stereo = Some(stereo);
+        self
+    }
+
+    /// Move the camera during the frame to look from `eye` towards `target`, seen between the
+    /// times the shutter opens and closes.
+    pub fn with_motion(
+        mut self,
+        eye: Vec3f,
+        target: Vec3f,
+        shutter_open: f32,
+        shutter_close: f32,
+    ) -> Self {
+        self.motion = Some(Motion {
+            eye,
+            forward: (target - eye).normalized(),
+            up: self.up,
+            shutter_open,
+            shutter_close,
+        });
+        self
+    }
+
+    /// The camera without motion, where it is at `time` through its movement.
+    fn at_time(&self, time: f32) -> ThinLensCamera {
+        let mut camera = ThinLensCamera {
+            motion: None,
+            ..self.clone()
+        };
+        if let Some(motion) = &self.motion {
+            let lerp = |from: Vec3f, to: Vec3f| from * (1.0 - time) + to * time;
+            camera.eye = lerp(self.eye, motion.eye);
+            camera.forward = lerp(self.forward, motion.forward).normalized();
+            camera.right = camera
+                .forward
+                .cross_product(lerp(self.up, motion.up))
+                .normalized();
+            camera.up = camera.right.cross_product(camera.forward);
+        }
+        camera
+    }
+
+    /// Distance along a ray in `direction` to the surface `distance` in front of the camera. For
+    /// flat projections, it's a plane facing along `normal`, but it can't be for those looking
+    /// behind themselves.
+    fn distance_along(&self, direction: Vec3f, distance: f32, normal: Vec3f) -> f32 {
+        match self.projection {
+            Projection::Fisheye { .. } | Projection::Equirectangular => distance,
+            _ => distance * self.forward.dot_product(normal) / direction.dot_product(normal),
+        }
+    }
+}
+
+impl Camera for ThinLensCamera {
+    /// With an aperture, the ray starts from a random point on the lens, and with motion, from
+    /// where the camera is at a random time while the shutter is open.
+    fn generate_ray(&self, px: f32, py: f32, aspect_ratio: f32, rng: &mut Rng) -> Option<Ray> {
+        if let Some(motion) = &self.motion {
+            let time =
+                motion.shutter_open + (motion.shutter_close - motion.shutter_open) * rng.next_f32();
+            return self.at_time(time).generate_ray(px, py, aspect_ratio, rng);
+        }
+        // Each eye sees half of the image, from its side of the camera
+        let (px, py, aspect_ratio, eye_offset) = match self.stereo {
+            None => (px, py, aspect_ratio, 0.0),
+            Some(stereo) => {
+                let (px, py, aspect_ratio, left) = match stereo.layout {
+                    StereoLayout::SideBySide => {
+                        let left = px < 0.5;
+                        let px = if left { px * 2.0 } else { px * 2.0 - 1.0 };
+                        (px, py, aspect_ratio * 0.5, left)
+                    }
+                    StereoLayout::OverUnder => {
+                        let left = py < 0.5;
+                        let py = if left { py * 2.0 } else { py * 2.0 - 1.0 };
+                        (px, py, aspect_ratio * 2.0, left)
+                    }
+                };
+                let offset = stereo.interocular * 0.5;
+                (px, py, aspect_ratio, if left { -offset } else { offset })
+            }
+        };
+        // Position of the point across the image, from -1 to 1 each way before any shift
+        let px = 2.0 * px - 1.0 + 2.0 * self.shift_x;
+        let py = 1.0 - 2.0 * py + 2.0 * self.shift_y;
+
+        let (eye, direction) = match self.projection {
+            Projection::Perspective => {
+                let angle = f32::tan(PI * 0.5 * self.fov / 180.0);
+                let xx = px * angle * aspect_ratio;
+                let yy = py * angle;
+                let direction = self.right * xx + self.up * yy + self.forward;
+                (self.eye, direction.normalized())
+            }
+            Projection::Orthographic { height } => {
+                let xx = px * aspect_ratio * height * 0.5;
+                let yy = py * height * 0.5;
+                (self.eye + self.right * xx + self.up * yy, self.forward)
+            }
+            Projection::Fisheye { fov, mapping } => {
+                let xx = px * aspect_ratio;
+                let radius = (xx * xx + py * py).sqrt();
+                if radius > 1.0 {
+                    return None;
+                }
+                let half_fov = (fov * 0.5).to_radians();
+                let theta = match mapping {
+                    FisheyeMapping::Equidistant => radius * half_fov,
+                    FisheyeMapping::Equisolid => 2.0 * (radius * (half_fov * 0.5).sin()).asin(),
+                };
+                // Turn away from the view direction by theta, towards the pixel
+                let (cos_phi, sin_phi) = if radius > 0.0 {
+                    (xx / radius, py / radius)
+                } else {
+                    (1.0, 0.0)
+                };
+                let direction = (self.right * cos_phi + self.up * sin_phi) * theta.sin()
+                    + self.forward * theta.cos();
+                (self.eye, direction)
+            }
+            Projection::Equirectangular => {
+                let longitude = px * PI;
+                let latitude = py * PI * 0.5;
+                let direction = self.right * (longitude.sin() * latitude.cos())
+                    + self.up * latitude.sin()
+                    + self.forward * (longitude.cos() * latitude.cos());
+                (self.eye, direction)
+            }
+        };
+        let (eye, direction) = match self.stereo.and_then(|stereo| stereo.convergence) {
+            _ if eye_offset == 0.0 => (eye, direction),
+            None => (eye + self.right * eye_offset, direction),
+            Some(convergence) => {
+                let t = self.distance_along(direction, convergence, self.forward);
+                let point = eye + direction * t;
+                let eye = eye + self.right * eye_offset;
+                (eye, (point - eye).normalized())
+            }
+        };
+        if self.aperture <= 0.0 {
+            return Some(Ray {
+                origin: eye,
+                direction,
+            });
+        }
+        // Every ray through the pixel meets at the same point in focus
+        let focus_normal = (self.forward - self.up * self.tilt.to_radians().tan()
+            + self.right * self.swing.to_radians().tan())
+        .normalized();
+        let focus =
+            eye + direction * self.distance_along(direction, self.focus_distance, focus_normal);
+        let r = self.aperture * rng.next_f32().sqrt();
+        let phi = 2.0 * PI * rng.next_f32();
+        let origin = eye + self.right * (r * phi.cos()) + self.up * (r * phi.sin());
+        Some(Ray {
+            origin,
+            direction: (focus - origin).normalized(),
+        })
+    }
+
+    fn view(&self) -> View {
+        View {
+            eye: self.eye,
+            right: self.right,
+            up: self.up,
+            forward: self.forward,
+            fov: self.fov,
+            projection: self.projection,
+        }
+    }
+}
+
+impl Default for ThinLensCamera {
+    /// At the origin looking down -Z.
+    fn default() -> Self {
+        ThinLensCamera::look_at(
+            Vec3f::new_uniform(0.0),
+            Vec3f::new(0.0, 0.0, -1.0),
+            Vec3f::new(0.0, 1.0, 0.0),
+            DEFAULT_FOV,
+        )
+    }
+}
+
+/// Builds a camera one setting at a time, checking they make sense once it is finished.
+pub struct CameraBuilder {
+    eye: Vec3f,
+    target: Vec3f,
+    up: Vec3f,
+    fov: f32,
+    projection: Projection,
+    depth_of_field: Option<(f32, f32)>,
+    shift: (f32, f32),
+    tilt: (f32, f32),
+    stereo: Option<Stereo>,
+    /// Where the camera moves to look from and towards, and when the shutter opens and closes.
+    motion: Option<(Vec3f, Vec3f, f32, f32)>,
+}
+
+impl CameraBuilder {
+    /// Place the camera at `eye`, looking towards `target`.
+    pub fn look_at(mut self, eye: Vec3f, target: Vec3f) -> Self {
+        self.eye = eye;
+        self.target = target;
+        self
+    }
+
+    /// Turn the camera so that `up` points towards the top of the image.
+    pub fn up(mut self, up: Vec3f) -> Self {
+        self.up = up;
+        self
+    }
+
+    /// Vertical field of view, in degrees, for perspective projection.
+    pub fn fov(mut self, fov: f32) -> Self {
+        self.fov = fov;
+        self
+    }
+
+    pub fn projection(mut self, projection: Projection) -> Self {
+        self.projection = projection;
+        self
+    }
+
+    /// Give the camera a lens of radius `aperture`, focused at `focus_distance`.
+    pub fn depth_of_field(mut self, aperture: f32, focus_distance: f32) -> Self {
+        self.depth_of_field = Some((aperture, focus_distance));
+        self
+    }
+
+    pub fn shift(mut self, shift_x: f32, shift_y: f32) -> Self {
+        self.shift = (shift_x, shift_y);
+        self
+    }
+
+    /// Tilt the plane of focus, see [`ThinLensCamera::with_tilt`].
+    pub fn tilt(mut self, tilt: f32, swing: f32) -> Self {
+        self.tilt = (tilt, swing);
+        self
+    }
+
+    pub fn stereo(mut self, stereo: Stereo) -> Self {
+        self.stereo = Some(stereo);
+        self
+    }
+
+    /// Move the camera during the frame, see [`ThinLensCamera::with_motion`].
+    pub fn motion(
+        mut self,
+        eye: Vec3f,
+        target: Vec3f,
+        shutter_open: f32,
+        shutter_close: f32,
+    ) -> Self {
+        self.motion = Some((eye, target, shutter_open, shutter_close));
+        self
+    }
+
+    /// Finish the camera, or describe the first problem with it.
+    pub fn build(self) -> Result<ThinLensCamera, String> {
+        if !(self.eye.is_finite() && self.target.is_finite() && self.up.is_finite()) {
+            return Err("camera position, target and up vector must be finite".to_string());
+        }
+        let forward = self.target - self.eye;
+        if forward.magnitude() <= 0.0 {
+            return Err("camera must look at a point away from its position".to_string());
+        }
+        if forward.normalized().cross_product(self.up).magnitude() <= 1e-6 {
+            return Err(
+                "camera up vector must not be zero or along the direction it looks".to_string(),
+            );
+        }
+        if !(self.fov > 0.0 && self.fov < 180.0) {
+            return Err(format!(
+                "camera field of view is {} degrees, which must be above 0 and below 180",
+                self.fov
+            ));
+        }
+        match self.projection {
+            Projection::Orthographic { height } if !(height > 0.0 && height.is_finite()) => {
+                return Err(format!(
+                    "orthographic view is {height} tall, which must be positive"
+                ));
+            }
+            Projection::Fisheye { fov, .. } if !(fov > 0.0 && fov <= 360.0) => {
+                return Err(format!(
+                    "fisheye covers {fov} degrees, which must be above 0 and at most 360"
+                ));
+            }
+            _ => {}
+        }
+        if let Some((aperture, focus_distance)) = self.depth_of_field {
+            if !(aperture >= 0.0 && aperture.is_finite()) {
+                return Err(format!(
+                    "aperture is {aperture}, which must not be negative"
+                ));
+            }
+            if !(focus_distance > 0.0 && focus_distance.is_finite()) {
+                return Err(format!(
+                    "focus distance is {focus_distance}, which must be positive"
+                ));
+            }
+        }
+        if !(self.shift.0.is_finite() && self.shift.1.is_finite()) {
+            return Err("lens shift must be finite".to_string());
+        }
+        let (tilt, swing) = self.tilt;
+        if !(tilt.abs() < 90.0 && swing.abs() < 90.0) {
+            return Err("lens tilt and swing must be less than 90 degrees".to_string());
+        }
+        if let Some(stereo) = self.stereo {
+            if !(stereo.interocular >= 0.0 && stereo.interocular.is_finite()) {
+                return Err("distance between the eyes must not be negative".to_string());
+            }
+            if stereo
+                .convergence
+                .is_some_and(|c| !(c > 0.0 && c.is_finite()))
+            {
+                return Err("convergence distance must be positive".to_string());
+            }
+        }
+        if let Some((eye, target, shutter_open, shutter_close)) = self.motion {
+            if !(eye.is_finite() && target.is_finite()) {
+                return Err("camera motion's position and target must be finite".to_string());
+            }
+            let forward = target - eye;
+            if forward.magnitude() <= 0.0 {
+                return Err(
+                    "camera must move to look at a point away from its position".to_string()
+                );
+            }
+            if forward.normalized().cross_product(self.up).magnitude() <= 1e-6 {
+                return Err("camera must not move to look along its up vector".to_string());
+            }
+            if !(0.0 <= shutter_open && shutter_open <= shutter_close && shutter_close <= 1.0) {
+                return Err(format!(
+                    "shutter is open from {shutter_open} to {shutter_close}, which must be in \
+                     order from 0 to 1"
+                ));
+            }
+        }
+
+        let mut camera = ThinLensCamera::look_at(self.eye, self.target, self.up, self.fov)
+            .with_projection(self.projection)
+            .with_shift(self.shift.0, self.shift.1)
+            .with_tilt(tilt, swing);
+        if let Some((aperture, focus_distance)) = self.depth_of_field {
+            camera = camera.with_depth_of_field(aperture, focus_distance);
+        }
+        if let Some(stereo) = self.stereo {
+            camera = camera.with_stereo(stereo);
+        }
+        if let Some((eye, target, shutter_open, shutter_close)) = self.motion {
+            camera = camera.with_motion(eye, target, shutter_open, shutter_close);
+        }
+        Ok(camera)
+    }
+}
@@ -0,0 +1,134 @@
+//! Ray-marched volumetric clouds.
+
+use crate::{noise, sky::Sky, Ray, Vec3f};
+use std::f32::consts::PI;
+
+/// A layer of clouds between two altitudes, with density driven by noise.
+pub struct CloudLayer {
+    /// Altitude of the base of the clouds.
+    pub bottom: f32,
+    /// Altitude of the tops of the clouds.
+    pub top: f32,
+    /// Fraction of the sky covered by clouds, from 0 to 1.
+    pub coverage: f32,
+    /// Extinction per unit distance, at full density.
+    pub density: f32,
+    /// Size of the largest cloud features, in world units.
+    pub scale: f32,
+}
+
+impl CloudLayer {
+    pub fn new(bottom: f32, top: f32) -> Self {
+        CloudLayer {
+            bottom,
+            top,
+            coverage: 0.45,
+            density: 0.04,
+            scale: (top - bottom) * 1.5,
+        }
+    }
+
+    /// Density of the clouds at a point, from 0 to 1. Fewer `octaves` of noise give a cheaper,
+    /// blurrier result.
+    fn density_at(&self, point: Vec3f, octaves: u32) -> f32 {
+        let height = (point.y - self.bottom) / (self.top - self.bottom);
+        if !(0.0..=1.0).contains(&height) {
+            return 0.0;
+        }
+        // Round off the bottoms and tops of the clouds
+        let profile = 4.0 * height * (1.0 - height);
+        let noise = noise::fbm(point * (1.0 / self.scale), octaves) * 0.5 + 0.5;
+        ((noise - (1.0 - self.coverage)) / self.coverage.max(1e-3)).max(0.0) * profile
+    }
+
+    /// Distances along the ray between which it lies within the layer, limited to at most
+    /// `max_distance` through the layer.
+    fn span(&self, ray: &Ray, max_distance: f32) -> Option<(f32, f32)> {
+        let (start, end) = if ray.direction.y.abs() < 1e-6 {
+            if !(self.bottom..=self.top).contains(&ray.origin.y) {
+                return None;
+            }
+            (0.0, f32::INFINITY)
+        } else {
+            let t_bottom = (self.bottom - ray.origin.y) / ray.direction.y;
+            let t_top = (self.top - ray.origin.y) / ray.direction.y;
+            (t_bottom.min(t_top).max(0.0), t_bottom.max(t_top))
+        };
+        (end > start).then(|| (start, end.min(start + max_distance)))
+    }
+
+    /// Fraction of light which passes through the clouds from `point` in `direction`.
+    pub fn transmittance(&self, point: Vec3f, direction: Vec3f) -> f32 {
+        // Detail is lost in shadows anyway, so use a coarser density
+        const STEPS: usize = 6;
+        const OCTAVES: u32 = 3;
+        let ray = Ray {
+            origin: point,
+            direction,
+        };
+        let Some((start, end)) = self.span(&ray, (self.top - self.bottom) * 4.0) else {
+            return 1.0;
+        };
+        let step = (end - start) / STEPS as f32;
+        let optical_depth: f32 = (0..STEPS)
+            .map(|i| {
+                self.density_at(
+                    point + direction * (start + (i as f32 + 0.5) * step),
+                    OCTAVES,
+                )
+            })
+            .sum::<f32>()
+            * self.density
+            * step;
+        (-optical_depth).exp()
+    }
+
+    /// March the ray through the layer, giving the light scattered towards the ray origin and the
+    /// fraction of the light from behind the clouds which passes through.
+    pub fn march(&self, ray: &Ray, sky: &Sky) -> (Vec3f, f32) {
+        const STEPS: usize = 48;
+        const OCTAVES: u32 = 5;
+        // Clouds near the horizon are seen through a long stretch of the layer. Past this,
+        // they're too far away to make out.
+        let max_distance = (self.top - self.bottom) * 20.0;
+        let Some((start, end)) = self.span(ray, max_distance) else {
+            return (Vec3f::new_uniform(0.0), 1.0);
+        };
+
+        let cos_sun = ray.direction.dot_product(sky.sun_direction);
+        // Mix of strong forward scattering for the silver lining, and some back scattering
+        let phase = 0.7 * henyey_greenstein(cos_sun, 0.6) + 0.3 * henyey_greenstein(cos_sun, -0.3);
+        let ambient = sky.ambient(Vec3f::new(0.0, 1.0, 0.0));
+
+        let step = (end - start) / STEPS as f32;
+        let mut in_scattered = Vec3f::new_uniform(0.0);
+        let mut transmittance = 1.0;
+        for i in 0..STEPS {
+            let point = ray.origin + ray.direction * (start + (i as f32 + 0.5) * step);
+            let density = self.density_at(point, OCTAVES);
+            if density <= 0.0 {
+                continue;
+            }
+            let extinction = density * self.density;
+            // Multiple scattering is not simulated, so the sun's contribution is boosted to make up
+            // for the light which would otherwise bounce around inside the cloud.
+            let sun = sky.sun_color
+                * (self.transmittance(point, sky.sun_direction) * phase * MULTIPLE_SCATTERING);
+            // Clouds barely absorb, so all of the extinguished light is scattered
+            in_scattered += (sun + ambient) * (transmittance * extinction * step);
+            transmittance *= (-extinction * step).exp();
+            if transmittance < 0.01 {
+                break;
+            }
+        }
+        (in_scattered, transmittance)
+    }
+}
+
+const MULTIPLE_SCATTERING: f32 = 4.0 * PI;
+
+/// Henyey–Greenstein phase function, for scattering through an angle with cosine `cos_theta`.
+fn henyey_greenstein(cos_theta: f32, g: f32) -> f32 {
+    let denom = 1.0 + g * g - 2.0 * g * cos_theta;
+    (1.0 - g * g) / (4.0 * PI * denom * denom.sqrt())
+}
@@ -0,0 +1,391 @@
+//! Measuring how much two renders differ, to quantify noise and regressions between integrators
+//! and versions.
+//!
+//! Alongside plain per-channel errors, images are compared with LDR FLIP (Andersson et al.,
+//! "FLIP: A Difference Evaluator for Alternating Images", 2020), which estimates how different
+//! they look to someone flipping between them. Both are filtered as the eye blurs them at a
+//! typical viewing distance, then compared in a perceptually uniform color space, with the error
+//! raised where edges and points differ, as the eye is drawn to those.
+
+use crate::{texture::ImageTexture, Vec3f};
+
+/// Pixels per degree of the viewer's field of view, for a 0.7 m wide 4K monitor seen from 0.7 m,
+/// as FLIP assumes by default.
+const PIXELS_PER_DEGREE: f32 = 0.7 * 3840.0 / 0.7 * std::f32::consts::PI / 180.0;
+
+/// Exponents compressing the color and feature differences.
+const COLOR_EXPONENT: f32 = 0.7;
+const FEATURE_EXPONENT: f32 = 0.5;
+
+/// Fraction of the largest color difference, and the error it's mapped to, below which errors
+/// are spread over most of the range, as small differences matter most.
+const COLOR_CUTOFF: f32 = 0.4;
+const ERROR_CUTOFF: f32 = 0.95;
+
+/// Gaussian weights and scales of the contrast sensitivity of the eye to luminance, red-green
+/// and blue-yellow, each the sum of two Gaussians.
+const SENSITIVITIES: [[(f32, f32); 2]; 3] = [
+    [(1.0, 0.0047), (0.0, 1e-5)],
+    [(1.0, 0.0053), (0.0, 1e-5)],
+    [(34.1, 0.04), (13.5, 0.025)],
+];
+
+/// Distance between the peak and trough of the eye's edge detection, in degrees.
+const FEATURE_WIDTH: f32 = 0.082;
+
+/// The linear sRGB primaries in CIE XYZ, and back.
+const RGB_TO_XYZ: [[f32; 3]; 3] = [
+    [0.412_386_56, 0.357_591_5, 0.180_450_5],
+    [0.212_636_82, 0.715_183, 0.072_180_2],
+    [0.019_330_62, 0.119_197_16, 0.950_372_6],
+];
+const XYZ_TO_RGB: [[f32; 3]; 3] = [
+    [3.240_97, -1.537_383_2, -0.498_610_76],
+    [-0.969_243_65, 1.875_967_5, 0.041_555_06],
+    [0.055_630_08, -0.203_976_96, 1.056_971_5],
+];
+
+/// How much two images differ.
+#[derive(Copy, Clone, Debug)]
+pub struct Differences {
+    /// Root mean square difference of each channel.
+    pub rmse: f32,
+    /// Mean absolute difference of each channel.
+    pub mae: f32,
+    /// Mean LDR FLIP error, from 0 for images which look the same to 1.
+    pub flip: f32,
+}
+
+/// How much `test` differs from `reference`, in linear light. FLIP compares colors clamped to
+/// `[0, 1]`, as displayed, so high dynamic range images are best tone mapped first.
+pub fn compare(reference: &ImageTexture, test: &ImageTexture) -> Result<Differences, String> {
+    let (width, height) = (reference.width(), reference.height());
+    if (test.width(), test.height()) != (width, height) {
+        return Err(format!(
+            "images differ in size, {width}x{height} and {}x{}",
+            test.width(),
+            test.height()
+        ));
+    }
+    let pixels = |image: &ImageTexture| -> Vec<Vec3f> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| image.pixel(x, y))
+            .collect()
+    };
+    let (reference, test) = (pixels(reference), pixels(test));
+
+    let (mut squares, mut absolute) = (0.0_f64, 0.0_f64);
+    for (a, b) in reference.iter().zip(&test) {
+        let difference = *a - *b;
+        for channel in [difference.x, difference.y, difference.z] {
+            squares += f64::from(channel * channel);
+            absolute += f64::from(channel.abs());
+        }
+    }
+    let values = (3 * width * height) as f64;
+    let flip = flip(&reference, &test, width, height);
+    Ok(Differences {
+        rmse: (squares / values).sqrt() as f32,
+        mae: (absolute / values) as f32,
+        flip: (flip.iter().map(|&error| f64::from(error)).sum::<f64>() / flip.len() as f64) as f32,
+    })
+}
+
+/// FLIP error of each pixel of `test` compared to `reference`, both `width` by `height` pixels
+/// of linear sRGB.
+pub fn flip(reference: &[Vec3f], test: &[Vec3f], width: usize, height: usize) -> Vec<f32> {
+    let white = transform(&RGB_TO_XYZ, Vec3f::new_uniform(1.0));
+    let prepare = |image: &[Vec3f]| {
+        let opponent: Vec<Vec3f> = image
+            .iter()
+            .map(|&color| {
+                let xyz = transform(&RGB_TO_XYZ, clamp(color)) / white;
+                Vec3f::new(
+                    116.0 * xyz.y - 16.0,
+                    500.0 * (xyz.x - xyz.y),
+                    200.0 * (xyz.y - xyz.z),
+                )
+            })
+            .collect();
+        // Blur as the eye would, in opponent colors, then compare in L*a*b*
+        let filtered = blur_opponent(&opponent, width, height);
+        let lab: Vec<Vec3f> = filtered
+            .into_iter()
+            .map(|opponent| {
+                let y = (opponent.x + 16.0) / 116.0;
+                let xyz = Vec3f::new(opponent.y / 500.0 + y, y, y - opponent.z / 200.0) * white;
+                hunt(lab(clamp(transform(&XYZ_TO_RGB, xyz)), white))
+            })
+            .collect();
+        let luminance: Vec<f32> = opponent.iter().map(|o| (o.x + 16.0) / 116.0).collect();
+        (lab, features(&luminance, width, height))
+    };
+    let (reference_lab, reference_features) = prepare(reference);
+    let (test_lab, test_features) = prepare(test);
+
+    let green = hunt(lab(Vec3f::new(0.0, 1.0, 0.0), white));
+    let blue = hunt(lab(Vec3f::new(0.0, 0.0, 1.0), white));
+    let max_color = hyab(green, blue).powf(COLOR_EXPONENT);
+    let cutoff = COLOR_CUTOFF * max_color;
+
+    (0..width * height)
+        .map(|i| {
+            let color = hyab(reference_lab[i], test_lab[i]).powf(COLOR_EXPONENT);
+            let color = if color < cutoff {
+                ERROR_CUTOFF / cutoff * color
+            } else {
+                ERROR_CUTOFF + (color - cutoff) / (max_color - cutoff) * (1.0 - ERROR_CUTOFF)
+            };
+            let ([edge_a, point_a], [edge_b, point_b]) = (reference_features[i], test_features[i]);
+            let feature = (edge_a - edge_b).abs().max((point_a - point_b).abs());
+            let feature = (feature / std::f32::consts::SQRT_2).powf(FEATURE_EXPONENT);
+            color.powf(1.0 - feature)
+        })
+        .collect()
+}
+
+fn clamp(color: Vec3f) -> Vec3f {
+    Vec3f::new(
+        color.x.clamp(0.0, 1.0),
+        color.y.clamp(0.0, 1.0),
+        color.z.clamp(0.0, 1.0),
+    )
+}
+
+fn transform(matrix: &[[f32; 3]; 3], color: Vec3f) -> Vec3f {
+    let row = |[a, b, c]: [f32; 3]| a * color.x + b * color.y + c * color.z;
+    Vec3f::new(row(matrix[0]), row(matrix[1]), row(matrix[2]))
+}
+
+/// Linear sRGB `color` in CIE L*a*b*, relative to `white` in XYZ.
+fn lab(color: Vec3f, white: Vec3f) -> Vec3f {
+    let xyz = transform(&RGB_TO_XYZ, color) / white;
+    let delta: f32 = 6.0 / 29.0;
+    let f = |t: f32| {
+        if t > delta.powi(3) {
+            t.cbrt()
+        } else {
+            t / (3.0 * delta * delta) + 4.0 / 29.0
+        }
+    };
+    let (x, y, z) = (f(xyz.x), f(xyz.y), f(xyz.z));
+    Vec3f::new(116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z))
+}
+
+/// Scale chroma with lightness, as colors look less colorful the darker they are.
+fn hunt(lab: Vec3f) -> Vec3f {
+    Vec3f::new(lab.x, 0.01 * lab.x * lab.y, 0.01 * lab.x * lab.z)
+}
+
+/// Distance between two colors in L*a*b*, better suited to large differences than Euclidean.
+fn hyab(a: Vec3f, b: Vec3f) -> f32 {
+    let difference = a - b;
+    difference.x.abs() + (difference.y * difference.y + difference.z * difference.z).sqrt()
+}
+
+/// Opponent colors blurred by the eye's contrast sensitivity to each, which is a sum of two
+/// Gaussians, each blurred across then down.
+fn blur_opponent(opponent: &[Vec3f], width: usize, height: usize) -> Vec<Vec3f> {
+    let radius = SENSITIVITIES
+        .iter()
+        .flatten()
+        .map(|&(_, scale)| scale)
+        .fold(0.0, f32::max);
+    let radius =
+        (3.0 * (radius / (2.0 * std::f32::consts::PI.powi(2))).sqrt() * PIXELS_PER_DEGREE).ceil();
+    let radius = radius as isize;
+    let step = 1.0 / PIXELS_PER_DEGREE;
+
+    let mut blurred = vec![Vec3f::default(); opponent.len()];
+    for (channel, gaussians) in SENSITIVITIES.iter().enumerate() {
+        let values: Vec<f32> = opponent.iter().map(|o| component(*o, channel)).collect();
+        // The weights of each Gaussian's 1D kernel, and its scale in 2D
+        let kernels: Vec<(Vec<f32>, f32)> = gaussians
+            .iter()
+            .filter(|&&(weight, _)| weight > 0.0)
+            .map(|&(weight, scale)| {
+                let kernel: Vec<f32> = (-radius..=radius)
+                    .map(|x| {
+                        let distance = x as f32 * step;
+                        (-std::f32::consts::PI.powi(2) * distance * distance / scale).exp()
+                    })
+                    .collect();
+                (kernel, weight * (std::f32::consts::PI / scale).sqrt())
+            })
+            .collect();
+        let total: f32 = kernels
+            .iter()
+            .map(|(kernel, weight)| weight * kernel.iter().sum::<f32>().powi(2))
+            .sum();
+        for (kernel, weight) in &kernels {
+            let result = convolve(&values, width, height, kernel, kernel);
+            for (blurred, value) in blurred.iter_mut().zip(result) {
+                add_component(blurred, channel, weight / total * value);
+            }
+        }
+    }
+    blurred
+}
+
+/// Magnitudes of the edges and points the eye detects in `luminance`, from Gaussian first and
+/// second derivatives across and down.
+fn features(luminance: &[f32], width: usize, height: usize) -> Vec<[f32; 2]> {
+    let deviation = 0.5 * FEATURE_WIDTH * PIXELS_PER_DEGREE;
+    let radius = (3.0 * deviation).ceil() as isize;
+    let gaussian: Vec<f32> = (-radius..=radius)
+        .map(|x| (-((x * x) as f32) / (2.0 * deviation * deviation)).exp())
+        .collect();
+    let gaussian_sum: f32 = gaussian.iter().sum();
+    let smooth: Vec<f32> = gaussian.iter().map(|g| g / gaussian_sum).collect();
+    // Positive and negative weights each sum to one, across the whole 2D kernel
+    let normalize = |kernel: Vec<f32>| {
+        let positive: f32 = kernel.iter().filter(|&&k| k > 0.0).sum();
+        let negative: f32 = -kernel.iter().filter(|&&k| k < 0.0).sum::<f32>();
+        kernel
+            .into_iter()
+            .map(|k| if k < 0.0 { k / negative } else { k / positive })
+            .collect::<Vec<f32>>()
+    };
+    let edge = normalize(
+        (-radius..=radius)
+            .zip(&gaussian)
+            .map(|(x, g)| -(x as f32) * g)
+            .collect(),
+    );
+    let point = normalize(
+        (-radius..=radius)
+            .zip(&gaussian)
+            .map(|(x, g)| ((x * x) as f32 / (deviation * deviation) - 1.0) * g)
+            .collect(),
+    );
+    let magnitude = |kernel: &[f32]| {
+        let across = convolve(luminance, width, height, kernel, &smooth);
+        let down = convolve(luminance, width, height, &smooth, kernel);
+        across
+            .iter()
+            .zip(&down)
+            .map(|(a, d)| (a * a + d * d).sqrt())
+            .collect::<Vec<f32>>()
+    };
+    let (edges, points) = (magnitude(&edge), magnitude(&point));
+    edges.into_iter().zip(points).map(|(e, p)| [e, p]).collect()
+}
+
+/// `values` convolved with `across` along rows then `down` along columns, both centred, with the
+/// edge pixels repeated beyond the image.
+fn convolve(values: &[f32], width: usize, height: usize, across: &[f32], down: &[f32]) -> Vec<f32> {
+    let pass = |values: &[f32], kernel: &[f32], horizontal: bool| {
+        let radius = (kernel.len() / 2) as isize;
+        let mut result = vec![0.0; values.len()];
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = 0.0;
+                for (offset, weight) in (-radius..=radius).zip(kernel) {
+                    let (sx, sy) = if horizontal {
+                        (
+                            (x as isize + offset).clamp(0, width as isize - 1) as usize,
+                            y,
+                        )
+                    } else {
+                        (
+                            x,
+                            (y as isize + offset).clamp(0, height as isize - 1) as usize,
+                        )
+                    };
+                    sum += weight * values[sy * width + sx];
+                }
+                result[y * width + x] = sum;
+            }
+        }
+        result
+    };
+    pass(&pass(values, across, true), down, false)
+}
+
+fn component(color: Vec3f, channel: usize) -> f32 {
+    match channel {
+        0 => color.x,
+        1 => color.y,
+        _ => color.z,
+    }
+}
+
+fn add_component(color: &mut Vec3f, channel: usize, value: f32) {
+    match channel {
+        0 => color.x += value,
+        1 => color.y += value,
+        _ => color.z += value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uniform(color: Vec3f) -> ImageTexture {
+        ImageTexture::new(16, 16, vec![color; 16 * 16])
+    }
+
+    #[test]
+    fn identical_images_do_not_differ() {
+        let mut pixels = vec![Vec3f::new(0.2, 0.5, 0.8); 16 * 16];
+        pixels[5 * 16 + 7] = Vec3f::new(1.0, 0.0, 0.0);
+        let image = ImageTexture::new(16, 16, pixels.clone());
+        let differences = compare(&image, &image).unwrap();
+        assert_eq!((differences.rmse, differences.mae), (0.0, 0.0));
+        assert!(differences.flip.abs() < 1e-6, "{}", differences.flip);
+        assert!(flip(&pixels, &pixels, 16, 16)
+            .iter()
+            .all(|&error| error.abs() < 1e-6));
+    }
+
+    #[test]
+    fn per_channel_errors() {
+        let differences = compare(
+            &uniform(Vec3f::new(0.0, 0.0, 0.0)),
+            &uniform(Vec3f::new(0.3, 0.0, 0.0)),
+        )
+        .unwrap();
+        assert!((differences.mae - 0.1).abs() < 1e-6);
+        assert!((differences.rmse - 0.03_f32.sqrt()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn flip_grows_with_the_difference() {
+        let gray = uniform(Vec3f::new_uniform(0.5));
+        let flip = |color: Vec3f| compare(&gray, &uniform(color)).unwrap().flip;
+        let (slight, large) = (
+            flip(Vec3f::new_uniform(0.55)),
+            flip(Vec3f::new_uniform(1.0)),
+        );
+        assert!(
+            0.0 < slight && slight < large && large <= 1.0,
+            "{slight} {large}"
+        );
+
+        let black_and_white = compare(
+            &uniform(Vec3f::default()),
+            &uniform(Vec3f::new_uniform(1.0)),
+        );
+        assert!(black_and_white.unwrap().flip > 0.9);
+    }
+
+    #[test]
+    fn flip_is_highest_where_the_images_differ() {
+        let reference = vec![Vec3f::new_uniform(0.5); 16 * 16];
+        let mut test = reference.clone();
+        test[8 * 16 + 8] = Vec3f::new(1.0, 0.0, 0.0);
+        let errors = flip(&reference, &test, 16, 16);
+        let peak = errors[8 * 16 + 8];
+        assert!(errors.iter().all(|&error| error <= peak));
+        assert!(errors[0] < peak * 0.1, "{} {peak}", errors[0]);
+    }
+
+    #[test]
+    fn rejects_images_of_different_sizes() {
+        let small = ImageTexture::new(1, 1, vec![Vec3f::default()]);
+        let error = compare(&uniform(Vec3f::default()), &small).unwrap_err();
+        assert_eq!(error, "images differ in size, 16x16 and 1x1");
+    }
+}
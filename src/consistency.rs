@@ -0,0 +1,376 @@
+//! Runtime consistency checking of primitive intersections.
+//!
+//! Random rays are fired at each primitive, and the analytic intersection is cross-validated
+//! against the intersection with a tessellated reference mesh of the same primitive. This catches
+//! NaNs, missed hits and precision problems in intersection code, which otherwise only show up as
+//! speckles in a render.
+
+use crate::{rng::Rng, sphere::Sphere, Ray, Vec3f};
+use std::f32::consts::PI;
+
+/// A primitive which can be validated against a tessellated approximation of itself.
+pub trait Tessellate {
+    /// Name of the primitive type, used when reporting.
+    fn kind(&self) -> &'static str;
+
+    /// Distance along the ray to the nearest intersection in front of the ray origin.
+    fn nearest_hit(&self, ray: &Ray) -> Option<f32>;
+
+    /// Surface normal at a point on the primitive.
+    fn normal_at(&self, point: Vec3f) -> Vec3f;
+
+    /// A sphere enclosing the whole primitive, as `(center, radius)`.
+    fn bounding_sphere(&self) -> (Vec3f, f32);
+
+    /// A triangle mesh approximating the primitive, along with the maximum distance between the
+    /// mesh and the true surface.
+    fn tessellate(&self) -> (Vec<Triangle>, f32);
+}
+
+impl Tessellate for Sphere {
+    fn kind(&self) -> &'static str {
+        "sphere"
+    }
+
+    fn nearest_hit(&self, ray: &Ray) -> Option<f32> {
+        let (t0, t1) = self.intersect(ray)?;
+        if t0 >= 0.0 {
+            Some(t0)
+        } else if t1 >= 0.0 {
+            Some(t1)
+        } else {
+            None
+        }
+    }
+
+    fn normal_at(&self, point: Vec3f) -> Vec3f {
+        (point - self.center).normalized()
+    }
+
+    fn bounding_sphere(&self) -> (Vec3f, f32) {
+        (self.center, self.radius)
+    }
+
+    fn tessellate(&self) -> (Vec<Triangle>, f32) {
+        const STACKS: usize = 32;
+        const SLICES: usize = 64;
+        let point = |stack: usize, slice: usize| {
+            let theta = PI * stack as f32 / STACKS as f32;
+            let phi = 2.0 * PI * slice as f32 / SLICES as f32;
+            self.center
+                + Vec3f::new(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                ) * self.radius
+        };
+
+        let mut triangles = Vec::with_capacity(STACKS * SLICES * 2);
+        for stack in 0..STACKS {
+            for slice in 0..SLICES {
+                let a = point(stack, slice);
+                let b = point(stack + 1, slice);
+                let c = point(stack + 1, slice + 1);
+                let d = point(stack, slice + 1);
+                // The triangles touching the poles are degenerate, skip them.
+                if stack != 0 {
+                    triangles.push(Triangle::new(a, b, d));
+                }
+                if stack != STACKS - 1 {
+                    triangles.push(Triangle::new(b, c, d));
+                }
+            }
+        }
+
+        // The mesh deviates furthest from the surface in the middle of each quad.
+        let half_stack = 0.5 * PI / STACKS as f32;
+        let half_slice = PI / SLICES as f32;
+        let chord_error = self.radius * (1.0 - half_stack.cos() * half_slice.cos());
+        (triangles, chord_error)
+    }
+}
+
+type Vec3d = crate::vec::Vec3<f64>;
+
+/// Triangle of a reference mesh. The reference is intersected in double precision, so that its own
+/// precision problems don't get blamed on the primitive being checked.
+pub struct Triangle([Vec3d; 3]);
+
+impl Triangle {
+    pub fn new(v0: Vec3f, v1: Vec3f, v2: Vec3f) -> Self {
+        Triangle([to_f64(v0), to_f64(v1), to_f64(v2)])
+    }
+
+    /// Möller–Trumbore ray-triangle intersection, giving distance along the ray.
+    fn intersect(&self, origin: Vec3d, direction: Vec3d) -> Option<f64> {
+        // Allow a small tolerance on the barycentric coordinates, so rays don't slip through the
+        // cracks between adjacent triangles.
+        const EDGE_EPSILON: f64 = 1e-9;
+        let [v0, v1, v2] = self.0;
+        let edge1 = v1 - v0;
+        let edge2 = v2 - v0;
+        let p = direction.cross_product(edge2);
+        let det = edge1.dot_product(p);
+        // Ray is parallel to the triangle
+        if det.abs() < f64::EPSILON {
+            return None;
+        }
+        let inv_det = 1.0 / det;
+        let s = origin - v0;
+        let u = s.dot_product(p) * inv_det;
+        if !(-EDGE_EPSILON..=1.0 + EDGE_EPSILON).contains(&u) {
+            return None;
+        }
+        let q = s.cross_product(edge1);
+        let v = direction.dot_product(q) * inv_det;
+        if v < -EDGE_EPSILON || u + v > 1.0 + EDGE_EPSILON {
+            return None;
+        }
+        let t = edge2.dot_product(q) * inv_det;
+        (t >= 0.0).then_some(t)
+    }
+
+    fn normal(&self) -> Vec3f {
+        let [v0, v1, v2] = self.0;
+        let normal = (v1 - v0).cross_product(v2 - v0).normalized();
+        Vec3f::new(normal.x as f32, normal.y as f32, normal.z as f32)
+    }
+}
+
+fn to_f64(v: Vec3f) -> Vec3d {
+    Vec3d::new(v.x as f64, v.y as f64, v.z as f64)
+}
+
+/// Which way the rays of a check are generated.
+#[derive(Copy, Clone, Debug)]
+enum RayKind {
+    /// Rays from just outside the primitive aimed at its bounds.
+    Outside,
+    /// Rays from random points within the bounds, in random directions.
+    Within,
+    /// Rays from very far away aimed at its bounds, to stress precision.
+    Distant,
+}
+
+enum Failure {
+    /// The analytic intersection produced NaN or infinity.
+    NonFinite { t: f32 },
+    /// The reference mesh was hit, but the analytic primitive was not.
+    Missed { reference: f32 },
+    /// The analytic primitive was hit, but the reference mesh was not.
+    Spurious { t: f32 },
+    /// Both were hit, but at different distances.
+    Distance { t: f32, reference: f32 },
+}
+
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::NonFinite { t } => write!(f, "non-finite intersection at {t}"),
+            Failure::Missed { reference } => {
+                write!(f, "missed, reference was hit at {reference}")
+            }
+            Failure::Spurious { t } => write!(f, "hit at {t}, reference was missed"),
+            Failure::Distance { t, reference } => {
+                write!(f, "hit at {t}, reference was hit at {reference}")
+            }
+        }
+    }
+}
+
+struct Example {
+    kind: RayKind,
+    origin: Vec3f,
+    direction: Vec3f,
+    failure: Failure,
+}
+
+/// Results of checking a single primitive.
+pub struct PrimitiveReport {
+    index: usize,
+    kind: &'static str,
+    rays: usize,
+    non_finite: usize,
+    missed: usize,
+    spurious: usize,
+    distance: usize,
+    examples: Vec<Example>,
+}
+
+impl PrimitiveReport {
+    fn failures(&self) -> usize {
+        self.non_finite + self.missed + self.spurious + self.distance
+    }
+}
+
+pub struct Report {
+    primitives: Vec<PrimitiveReport>,
+}
+
+impl Report {
+    pub fn is_ok(&self) -> bool {
+        self.primitives.iter().all(|p| p.failures() == 0)
+    }
+
+    pub fn print(&self) {
+        for p in &self.primitives {
+            let status = if p.failures() == 0 { "ok" } else { "FAILED" };
+            println!(
+                "{} #{}: {} ({} rays, {} non-finite, {} missed, {} spurious, {} distance mismatches)",
+                p.kind,
+                p.index,
+                status,
+                p.rays,
+                p.non_finite,
+                p.missed,
+                p.spurious,
+                p.distance,
+            );
+            for example in &p.examples {
+                println!(
+                    "    {:?} ray from ({}, {}, {}) towards ({}, {}, {}): {}",
+                    example.kind,
+                    example.origin.x,
+                    example.origin.y,
+                    example.origin.z,
+                    example.direction.x,
+                    example.direction.y,
+                    example.direction.z,
+                    example.failure,
+                );
+            }
+        }
+    }
+}
+
+/// Fire `rays_per_kind` rays of each kind at every primitive, comparing the analytic
+/// intersections to those with a tessellated reference.
+pub fn check_primitives<P: Tessellate>(primitives: &[P], rays_per_kind: usize) -> Report {
+    const MAX_EXAMPLES: usize = 5;
+    let mut rng = Rng::new(0x5eed);
+
+    let primitives = primitives
+        .iter()
+        .enumerate()
+        .map(|(index, primitive)| {
+            let (mesh, chord_error) = primitive.tessellate();
+            let (center, radius) = primitive.bounding_sphere();
+            let mut report = PrimitiveReport {
+                index,
+                kind: primitive.kind(),
+                rays: 0,
+                non_finite: 0,
+                missed: 0,
+                spurious: 0,
+                distance: 0,
+                examples: Vec::new(),
+            };
+
+            for kind in [RayKind::Outside, RayKind::Within, RayKind::Distant] {
+                for _ in 0..rays_per_kind {
+                    let ray = match kind {
+                        RayKind::Outside => {
+                            let origin = center + rng.unit_vector() * (radius * 2.0);
+                            let target = center + rng.unit_vector() * (radius * rng.next_f32());
+                            Ray {
+                                origin,
+                                direction: (target - origin).normalized(),
+                            }
+                        }
+                        RayKind::Within => Ray {
+                            origin: center + rng.unit_vector() * (radius * rng.next_f32()),
+                            direction: rng.unit_vector(),
+                        },
+                        RayKind::Distant => {
+                            let origin = center + rng.unit_vector() * (radius * 1000.0);
+                            let target = center + rng.unit_vector() * (radius * rng.next_f32());
+                            Ray {
+                                origin,
+                                direction: (target - origin).normalized(),
+                            }
+                        }
+                    };
+                    report.rays += 1;
+                    if let Some(failure) = check_ray(primitive, &mesh, chord_error, radius, &ray) {
+                        match failure {
+                            Failure::NonFinite { .. } => report.non_finite += 1,
+                            Failure::Missed { .. } => report.missed += 1,
+                            Failure::Spurious { .. } => report.spurious += 1,
+                            Failure::Distance { .. } => report.distance += 1,
+                        }
+                        if report.examples.len() < MAX_EXAMPLES {
+                            report.examples.push(Example {
+                                kind,
+                                origin: ray.origin,
+                                direction: ray.direction,
+                                failure,
+                            });
+                        }
+                    }
+                }
+            }
+            report
+        })
+        .collect();
+
+    Report { primitives }
+}
+
+fn check_ray<P: Tessellate>(
+    primitive: &P,
+    mesh: &[Triangle],
+    chord_error: f32,
+    radius: f32,
+    ray: &Ray,
+) -> Option<Failure> {
+    // Relative precision we expect from f32 intersection code
+    const RELATIVE_EPSILON: f32 = 1e-4;
+    // Rays which meet the surface at a shallower angle than this may legitimately hit only one of
+    // the primitive and its reference, as they are within `chord_error` of the silhouette.
+    let grazing_cos = 2.0 * (2.0 * chord_error / radius).sqrt();
+
+    let analytic = primitive.nearest_hit(ray);
+    if let Some(t) = analytic {
+        if !t.is_finite() {
+            return Some(Failure::NonFinite { t });
+        }
+    }
+
+    // Rays starting within `chord_error` of the surface may start on the other side of the mesh
+    // than of the true surface, so only check rays which start clear of it.
+    let near_origin = |t: f32, cos: f32| t <= chord_error / cos.max(grazing_cos);
+
+    let (origin, direction) = (to_f64(ray.origin), to_f64(ray.direction));
+    let reference = mesh
+        .iter()
+        .filter_map(|triangle| {
+            let t = triangle.intersect(origin, direction)?;
+            Some((t as f32, triangle))
+        })
+        .min_by(|a, b| a.0.total_cmp(&b.0));
+
+    match (analytic, reference) {
+        (None, None) => None,
+        (None, Some((reference, triangle))) => {
+            let cos = ray.direction.dot_product(triangle.normal()).abs();
+            (cos > grazing_cos && !near_origin(reference, cos))
+                .then_some(Failure::Missed { reference })
+        }
+        (Some(t), None) => {
+            let normal = primitive.normal_at(ray.origin + ray.direction * t);
+            let cos = ray.direction.dot_product(normal).abs();
+            (cos > grazing_cos && !near_origin(t, cos)).then_some(Failure::Spurious { t })
+        }
+        (Some(t), Some((reference, triangle))) => {
+            let cos = ray.direction.dot_product(triangle.normal()).abs();
+            let tolerance =
+                chord_error / cos.max(grazing_cos) + RELATIVE_EPSILON * t.abs().max(1.0);
+            // A grazing ray may pass through the mesh near the silhouette and hit the far side
+            // of the primitive, or vice versa.
+            ((t - reference).abs() > tolerance
+                && cos > grazing_cos
+                && !near_origin(t.min(reference), cos))
+            .then_some(Failure::Distance { t, reference })
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Generation of synthetic datasets for machine learning.
+//!
+//! Renders randomized scenes, each along with ground truth images of what the camera sees, and
+//! writes a JSON manifest describing every sample.
+
+use crate::{
+    json,
+    material::{Dielectric, Diffuse, Emissive, Metal, Microfacet, Specular},
+    render,
+    rng::Rng,
+    scene::{Background, Scene},
+    settings::RenderSettings,
+    sky::Sky,
+    Sphere, Vec3f,
+};
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Render `count` random scenes into `out_dir`, with a `manifest.json` describing them.
+pub fn generate(
+    out_dir: &Path,
+    count: usize,
+    seed: u64,
+    settings: &RenderSettings,
+) -> io::Result<()> {
+    fs::create_dir_all(out_dir)?;
+    let mut rng = Rng::new(seed);
+
+    let mut samples = Vec::with_capacity(count);
+    for index in 0..count {
+        let scene = random_scene(&mut rng);
+        let name = |pass: &str, extension: &str| format!("{index:05}_{pass}.{extension}");
+        let sample = Sample {
+            beauty: name("beauty", "ppm"),
+            normal: name("normal", "ppm"),
+            depth: name("depth", "pfm"),
+            segmentation: name("segmentation", "pgm"),
+        };
+
+        render::render(&scene, settings, &out_dir.join(&sample.beauty))?;
+        write_ground_truth(&scene, out_dir, &sample, settings)?;
+        println!("Rendered sample {} of {count}", index + 1);
+        samples.push((sample, scene));
+    }
+
+    fs::write(
+        out_dir.join("manifest.json"),
+        manifest(seed, &samples, settings).as_bytes(),
+    )
+}
+
+/// File names of the images of one sample.
+struct Sample {
+    beauty: String,
+    /// World space normals, mapped from `[-1, 1]` to `[0, 255]`.
+    normal: String,
+    /// Distance along the camera ray to the first hit, infinite where nothing was hit.
+    depth: String,
+    /// Segmentation IDs, which are the index of the sphere hit plus one, or zero for nothing.
+    segmentation: String,
+}
+
+/// A scene of spheres of random sizes and materials resting on the ground, lit by either a
+/// spherical light or the sun.
+fn random_scene(rng: &mut Rng) -> Scene {
+    let mut scene = if rng.next_f32() < 0.5 {
+        let mut sun = rng.unit_vector();
+        sun.y = sun.y.abs().max(0.2);
+        Scene::new(Background::Sky(Sky::new(sun)))
+    } else {
+        Scene::new(Background::Uniform(Vec3f::new_uniform(rng.range(0.5, 2.0))))
+    };
+
+    let ground_color = Vec3f::new_uniform(rng.range(0.1, 0.6));
+    let ground = scene.add_material(Diffuse::new(ground_color));
+    scene.spheres.push(Sphere::new(
+        Vec3f::new(0.0, -10004.0, -20.0),
+        10000.0,
+        ground,
+    ));
+
+    let count = 3 + (rng.next_u64() % 6) as usize;
+    let mut attempts = 0;
+    while scene.spheres.len() <= count && attempts < 100 {
+        attempts += 1;
+        let radius = rng.range(0.5, 3.0);
+        let center = Vec3f::new(rng.range(-8.0, 8.0), -4.0 + radius, rng.range(-30.0, -12.0));
+        let overlaps = scene.spheres[1..].iter().any(|other| {
+            let distance = (other.center - center).magnitude();
+            distance < other.radius + radius
+        });
+        if overlaps {
+            continue;
+        }
+
+        let color = Vec3f::new(rng.next_f32(), rng.next_f32(), rng.next_f32());
+        let kind = rng.next_f32();
+        let material = if kind < 0.4 {
+            scene.add_material(Diffuse::new(color))
+        } else if kind < 0.55 {
+            scene.add_material(Specular::new(color))
+        } else if kind < 0.7 {
+            scene.add_material(Metal::new(color, rng.range(0.0, 0.6)))
+        } else if kind < 0.85 {
+            scene.add_material(Microfacet::new(color, rng.range(0.1, 0.7)))
+        } else {
+            let iors = [Dielectric::WATER, Dielectric::GLASS, Dielectric::DIAMOND];
+            let ior = iors[(rng.next_u64() % iors.len() as u64) as usize];
+            scene.add_material(Dielectric::new(color, ior))
+        };
+        scene.spheres.push(Sphere::new(center, radius, material));
+    }
+
+    if let Background::Uniform(_) = scene.background {
+        let light = scene.add_material(Emissive::new(Vec3f::new_uniform(rng.range(100.0, 300.0))));
+        let center = Vec3f::new(rng.range(-15.0, 15.0), rng.range(10.0, 25.0), -20.0);
+        scene.add_sphere(Sphere::new(center, 3.0, light));
+    }
+
+    scene
+}
+
+fn write_ground_truth(
+    scene: &Scene,
+    out_dir: &Path,
+    sample: &Sample,
+    settings: &RenderSettings,
+) -> io::Result<()> {
+    let (width, height) = (settings.width, settings.height);
+    let mut normals = Vec::with_capacity(width * height * 3);
+    let mut depths = Vec::with_capacity(width * height);
+    let mut ids = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let mut rng = Rng::new((y * width + x) as u64);
+            let ray = scene.camera.pixel_ray(x, y, width, height, &mut rng);
+            match ray.and_then(|ray| Some((scene.intersect(&ray)?, ray))) {
+                Some((hit, ray)) => {
+                    let sphere = &scene.spheres[hit.sphere];
+                    let point = ray.origin + ray.direction * hit.t;
+                    let normal = (point - sphere.center).normalized();
+                    normals.extend(
+                        [normal.x, normal.y, normal.z]
+                            .map(|n| ((n * 0.5 + 0.5) * 255.0).round() as u8),
+                    );
+                    depths.push(hit.t);
+                    ids.push(hit.sphere as u16 + 1);
+                }
+                None => {
+                    normals.extend([0, 0, 0]);
+                    depths.push(f32::INFINITY);
+                    ids.push(0);
+                }
+            }
+        }
+    }
+
+    let mut normal_file = BufWriter::new(File::create(out_dir.join(&sample.normal))?);
+    write!(normal_file, "P6\n{width} {height}\n255\n")?;
+    normal_file.write_all(&normals)?;
+    normal_file.flush()?;
+
+    // PFM stores rows bottom to top, and a negative scale means little endian
+    let mut depth_file = BufWriter::new(File::create(out_dir.join(&sample.depth))?);
+    write!(depth_file, "Pf\n{width} {height}\n-1.0\n")?;
+    for row in depths.chunks(width).rev() {
+        for depth in row {
+            depth_file.write_all(&depth.to_le_bytes())?;
+        }
+    }
+    depth_file.flush()?;
+
+    // 16 bit PGM is big endian
+    let mut id_file = BufWriter::new(File::create(out_dir.join(&sample.segmentation))?);
+    write!(id_file, "P5\n{width} {height}\n65535\n")?;
+    for id in ids {
+        id_file.write_all(&id.to_be_bytes())?;
+    }
+    id_file.flush()
+}
+
+fn manifest(seed: u64, samples: &[(Sample, Scene)], settings: &RenderSettings) -> String {
+    let mut json = String::new();
+    let _ = write!(
+        json,
+        "{{\n  \"seed\": {seed},\n  \"width\": {},\n  \"height\": {},\n  \"samples\": [",
+        settings.width, settings.height
+    );
+    for (i, (sample, scene)) in samples.iter().enumerate() {
+        let separator = if i == 0 { "" } else { "," };
+        let _ = write!(
+            json,
+            "{separator}\n    {{\n      \"beauty\": {},\n      \"normal\": {},\n      \"depth\": {},\n      \"segmentation\": {},\n      \"objects\": [",
+            json::string(&sample.beauty),
+            json::string(&sample.normal),
+            json::string(&sample.depth),
+            json::string(&sample.segmentation),
+        );
+        for (index, sphere) in scene.spheres.iter().enumerate() {
+            let separator = if index == 0 { "" } else { "," };
+            let c = sphere.center;
+            let _ = write!(
+                json,
+                "{separator}\n        {{ \"id\": {}, \"center\": [{}, {}, {}], \"radius\": {}, \"material\": {} }}",
+                index + 1,
+                c.x,
+                c.y,
+                c.z,
+                sphere.radius,
+                json::string(scene.material(sphere.material).name()),
+            );
+        }
+        json.push_str("\n      ]\n    }");
+    }
+    json.push_str("\n  ]\n}\n");
+    json
+}
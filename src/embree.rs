@@ -0,0 +1,204 @@
+//! Intersection backend using Intel Embree 3.
+//!
+//! Only geometry intersection is delegated to Embree, shading stays with rayox. Bindings to the
+//! parts of the C API which are needed are declared here, so nothing beyond the Embree library
+//! itself is required.
+
+use crate::{
+    scene::{Hit, Intersector},
+    Ray, Sphere,
+};
+use std::{
+    ffi::{c_char, c_uint, c_void},
+    ptr,
+};
+
+#[allow(non_camel_case_types)]
+mod ffi {
+    use super::*;
+
+    pub type RTCDevice = *mut c_void;
+    pub type RTCScene = *mut c_void;
+    pub type RTCGeometry = *mut c_void;
+
+    pub const RTC_GEOMETRY_TYPE_SPHERE_POINT: c_uint = 50;
+    pub const RTC_BUFFER_TYPE_VERTEX: c_uint = 1;
+    pub const RTC_FORMAT_FLOAT4: c_uint = 0x9004;
+    pub const RTC_INVALID_GEOMETRY_ID: c_uint = c_uint::MAX;
+    pub const RTC_INTERSECT_CONTEXT_FLAG_INCOHERENT: c_uint = 0;
+
+    #[repr(C)]
+    pub struct RTCIntersectContext {
+        pub flags: c_uint,
+        pub filter: *const c_void,
+        pub inst_id: [c_uint; 1],
+    }
+
+    #[repr(C, align(16))]
+    pub struct RTCRay {
+        pub org_x: f32,
+        pub org_y: f32,
+        pub org_z: f32,
+        pub tnear: f32,
+        pub dir_x: f32,
+        pub dir_y: f32,
+        pub dir_z: f32,
+        pub time: f32,
+        pub tfar: f32,
+        pub mask: c_uint,
+        pub id: c_uint,
+        pub flags: c_uint,
+    }
+
+    #[repr(C, align(16))]
+    pub struct RTCHit {
+        pub ng_x: f32,
+        pub ng_y: f32,
+        pub ng_z: f32,
+        pub u: f32,
+        pub v: f32,
+        pub prim_id: c_uint,
+        pub geom_id: c_uint,
+        pub inst_id: [c_uint; 1],
+    }
+
+    #[repr(C, align(16))]
+    pub struct RTCRayHit {
+        pub ray: RTCRay,
+        pub hit: RTCHit,
+    }
+
+    #[link(name = "embree3")]
+    extern "C" {
+        pub fn rtcNewDevice(config: *const c_char) -> RTCDevice;
+        pub fn rtcReleaseDevice(device: RTCDevice);
+        pub fn rtcGetDeviceError(device: RTCDevice) -> c_uint;
+        pub fn rtcNewScene(device: RTCDevice) -> RTCScene;
+        pub fn rtcCommitScene(scene: RTCScene);
+        pub fn rtcReleaseScene(scene: RTCScene);
+        pub fn rtcNewGeometry(device: RTCDevice, geometry_type: c_uint) -> RTCGeometry;
+        pub fn rtcSetNewGeometryBuffer(
+            geometry: RTCGeometry,
+            buffer_type: c_uint,
+            slot: c_uint,
+            format: c_uint,
+            byte_stride: usize,
+            item_count: usize,
+        ) -> *mut c_void;
+        pub fn rtcCommitGeometry(geometry: RTCGeometry);
+        pub fn rtcAttachGeometry(scene: RTCScene, geometry: RTCGeometry) -> c_uint;
+        pub fn rtcReleaseGeometry(geometry: RTCGeometry);
+        pub fn rtcIntersect1(
+            scene: RTCScene,
+            context: *mut RTCIntersectContext,
+            rayhit: *mut RTCRayHit,
+        );
+    }
+}
+
+/// The scene's spheres, built into an Embree scene.
+pub struct EmbreeScene {
+    device: ffi::RTCDevice,
+    scene: ffi::RTCScene,
+}
+
+// Embree scenes are safe to intersect from many threads once committed.
+unsafe impl Send for EmbreeScene {}
+unsafe impl Sync for EmbreeScene {}
+
+impl EmbreeScene {
+    pub fn new(spheres: &[Sphere]) -> Result<Self, String> {
+        unsafe {
+            let device = ffi::rtcNewDevice(ptr::null());
+            if device.is_null() {
+                return Err("Failed to create Embree device".to_string());
+            }
+            let scene = ffi::rtcNewScene(device);
+            let embree = EmbreeScene { device, scene };
+
+            // All spheres go in one geometry, so the primitive ID is the sphere index.
+            let geometry = ffi::rtcNewGeometry(device, ffi::RTC_GEOMETRY_TYPE_SPHERE_POINT);
+            let vertices = ffi::rtcSetNewGeometryBuffer(
+                geometry,
+                ffi::RTC_BUFFER_TYPE_VERTEX,
+                0,
+                ffi::RTC_FORMAT_FLOAT4,
+                4 * std::mem::size_of::<f32>(),
+                spheres.len(),
+            ) as *mut [f32; 4];
+            if vertices.is_null() {
+                ffi::rtcReleaseGeometry(geometry);
+                return Err(embree.error("allocate the sphere buffer"));
+            }
+            for (i, sphere) in spheres.iter().enumerate() {
+                let c = sphere.center;
+                vertices.add(i).write([c.x, c.y, c.z, sphere.radius]);
+            }
+            ffi::rtcCommitGeometry(geometry);
+            ffi::rtcAttachGeometry(scene, geometry);
+            ffi::rtcReleaseGeometry(geometry);
+            ffi::rtcCommitScene(scene);
+
+            match ffi::rtcGetDeviceError(device) {
+                0 => Ok(embree),
+                _ => Err(embree.error("build the scene")),
+            }
+        }
+    }
+
+    fn error(&self, action: &str) -> String {
+        let code = unsafe { ffi::rtcGetDeviceError(self.device) };
+        format!("Embree failed to {action} (error {code})")
+    }
+}
+
+impl Intersector for EmbreeScene {
+    fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        let mut context = ffi::RTCIntersectContext {
+            flags: ffi::RTC_INTERSECT_CONTEXT_FLAG_INCOHERENT,
+            filter: ptr::null(),
+            inst_id: [ffi::RTC_INVALID_GEOMETRY_ID],
+        };
+        let mut rayhit = ffi::RTCRayHit {
+            ray: ffi::RTCRay {
+                org_x: ray.origin.x,
+                org_y: ray.origin.y,
+                org_z: ray.origin.z,
+                tnear: 0.0,
+                dir_x: ray.direction.x,
+                dir_y: ray.direction.y,
+                dir_z: ray.direction.z,
+                time: 0.0,
+                tfar: f32::INFINITY,
+                mask: u32::MAX,
+                id: 0,
+                flags: 0,
+            },
+            hit: ffi::RTCHit {
+                ng_x: 0.0,
+                ng_y: 0.0,
+                ng_z: 0.0,
+                u: 0.0,
+                v: 0.0,
+                prim_id: ffi::RTC_INVALID_GEOMETRY_ID,
+                geom_id: ffi::RTC_INVALID_GEOMETRY_ID,
+                inst_id: [ffi::RTC_INVALID_GEOMETRY_ID],
+            },
+        };
+        unsafe { ffi::rtcIntersect1(self.scene, &mut context, &mut rayhit) };
+
+        (rayhit.hit.geom_id != ffi::RTC_INVALID_GEOMETRY_ID).then_some(Hit {
+            t: rayhit.ray.tfar,
+            sphere: rayhit.hit.prim_id as usize,
+        })
+    }
+}
+
+impl Drop for EmbreeScene {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::rtcReleaseScene(self.scene);
+            ffi::rtcReleaseDevice(self.device);
+        }
+    }
+}
@@ -0,0 +1,250 @@
+//! Environment maps, which surround the scene with a photographed or rendered panorama that both
+//! fills the background and lights the scene.
+
+use crate::{rng::Rng, texture::ImageTexture, texture::Texture, Vec3f};
+use std::{f32::consts::PI, path::Path};
+
+/// An equirectangular panorama at infinite distance, with -Z at its center and +Y at the top.
+pub struct EnvironmentMap {
+    image: ImageTexture,
+    /// Scale applied to the radiance of the image.
+    pub intensity: f32,
+    /// For sampling directions towards the bright parts of the image, or `None` if it's black.
+    distribution: Option<Distribution>,
+    /// Openings which all of the environment's light reaching the scene passes through, if any.
+    pub portals: Vec<Portal>,
+}
+
+impl EnvironmentMap {
+    pub fn new(image: ImageTexture) -> Self {
+        EnvironmentMap {
+            distribution: Distribution::new(&image),
+            image,
+            intensity: 1.0,
+            portals: Vec::new(),
+        }
+    }
+
+    /// Load the panorama from an image file, preferably high dynamic range.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        ImageTexture::load(path).map(EnvironmentMap::new)
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    /// Add an opening which the environment's light enters the scene through. Once there are
+    /// any, light is only sampled through them, so they must cover every opening.
+    pub fn with_portal(mut self, portal: Portal) -> Self {
+        self.portals.push(portal);
+        self
+    }
+
+    /// Radiance arriving from `direction`.
+    pub fn radiance(&self, direction: Vec3f) -> Vec3f {
+        self.image.value(direction_uv(direction), direction) * self.intensity
+    }
+
+    /// Pick a direction to sample the environment's light arriving at `point`, returning it with
+    /// its probability density over solid angle. Directions go through a portal, if there are
+    /// any, and otherwise follow the brightness of the panorama.
+    pub fn sample(&self, point: Vec3f, rng: &mut Rng) -> Option<(Vec3f, f32)> {
+        if self.portals.is_empty() {
+            return self.sample_image(rng);
+        }
+        // Portals are chosen in proportion to their area, which makes the density the same as
+        // for sampling one portal the size of them all
+        let total_area: f32 = self.portals.iter().map(Portal::area).sum();
+        let mut target = rng.next_f32() * total_area;
+        let portal = self
+            .portals
+            .iter()
+            .find(|portal| {
+                target -= portal.area();
+                target < 0.0
+            })
+            .unwrap_or(&self.portals[self.portals.len() - 1]);
+        let direction = (portal.sample_point(rng) - point).normalized();
+        let pdf = self.pdf(point, direction);
+        (pdf > 0.0).then_some((direction, pdf))
+    }
+
+    /// Probability density per unit solid angle of [`EnvironmentMap::sample`] choosing
+    /// `direction` from `point`.
+    pub fn pdf(&self, point: Vec3f, direction: Vec3f) -> f32 {
+        if self.portals.is_empty() {
+            return self.pdf_image(direction);
+        }
+        let total_area: f32 = self.portals.iter().map(Portal::area).sum();
+        self.portals
+            .iter()
+            .filter_map(|portal| portal.intersect(point, direction))
+            .map(|(distance, cos)| distance * distance / (cos * total_area))
+            .sum()
+    }
+
+    /// Pick a direction in proportion to the light arriving from it, so that small, bright parts
+    /// of the panorama like the sun are found by few samples. Returns the direction with its
+    /// probability density over solid angle.
+    fn sample_image(&self, rng: &mut Rng) -> Option<(Vec3f, f32)> {
+        let distribution = self.distribution.as_ref()?;
+        let (width, height) = (self.image.width(), self.image.height());
+        let (r1, r2) = (rng.next_f32(), rng.next_f32());
+        let row = distribution.rows.partition_point(|&c| c <= r1);
+        let row = row.min(height - 1);
+        let columns = &distribution.columns[row * width..(row + 1) * width];
+        let column = columns.partition_point(|&c| c <= r2).min(width - 1);
+
+        let u = (column as f32 + rng.next_f32()) / width as f32;
+        let v = 1.0 - (row as f32 + rng.next_f32()) / height as f32;
+        let latitude = (v - 0.5) * PI;
+        let longitude = (u - 0.5) * 2.0 * PI;
+        let direction = Vec3f::new(
+            longitude.sin() * latitude.cos(),
+            latitude.sin(),
+            -longitude.cos() * latitude.cos(),
+        );
+        // The image is stretched over a band of the sphere which narrows towards the poles
+        let stretch = 2.0 * PI * PI * latitude.cos();
+        if stretch <= 0.0 {
+            return None;
+        }
+        Some((
+            direction,
+            distribution.density[column + row * width] / stretch,
+        ))
+    }
+
+    /// Probability density per unit solid angle of [`EnvironmentMap::sample_image`] choosing
+    /// `direction`.
+    fn pdf_image(&self, direction: Vec3f) -> f32 {
+        let Some(distribution) = &self.distribution else {
+            return 0.0;
+        };
+        let (width, height) = (self.image.width(), self.image.height());
+        let (u, v) = direction_uv(direction);
+        let column = ((u * width as f32) as usize).min(width - 1);
+        let row = (((1.0 - v) * height as f32) as usize).min(height - 1);
+        let stretch = 2.0 * PI * PI * ((v - 0.5) * PI).cos();
+        if stretch <= 0.0 {
+            return 0.0;
+        }
+        distribution.density[column + row * width] / stretch
+    }
+}
+
+/// A rectangular opening, such as a window into a room, which the light of an environment map
+/// passes through. Sampling the environment through the openings of an enclosed scene, rather
+/// than over the whole panorama where most directions are blocked, removes most of the noise.
+pub struct Portal {
+    pub corner: Vec3f,
+    /// Edges from the corner, spanning a parallelogram which is usually a rectangle.
+    pub edge_u: Vec3f,
+    pub edge_v: Vec3f,
+}
+
+impl Portal {
+    pub fn new(corner: Vec3f, edge_u: Vec3f, edge_v: Vec3f) -> Self {
+        Portal {
+            corner,
+            edge_u,
+            edge_v,
+        }
+    }
+
+    pub fn area(&self) -> f32 {
+        self.edge_u.cross_product(self.edge_v).magnitude()
+    }
+
+    /// A point on the portal, chosen uniformly over its area.
+    fn sample_point(&self, rng: &mut Rng) -> Vec3f {
+        self.corner + self.edge_u * rng.next_f32() + self.edge_v * rng.next_f32()
+    }
+
+    /// Where the ray from `point` along `direction` passes through the portal, as the distance
+    /// and the cosine of the angle it makes with the portal's normal.
+    fn intersect(&self, point: Vec3f, direction: Vec3f) -> Option<(f32, f32)> {
+        let cross = self.edge_u.cross_product(self.edge_v);
+        let normal = cross.normalized();
+        let cos = direction.dot_product(normal);
+        if cos.abs() < 1e-6 {
+            return None;
+        }
+        let distance = (self.corner - point).dot_product(normal) / cos;
+        if distance <= 0.0 {
+            return None;
+        }
+        // Coordinates of the crossing along each edge
+        let offset = point + direction * distance - self.corner;
+        let scale = 1.0 / cross.sqr_magnitude();
+        let u = offset.cross_product(self.edge_v).dot_product(cross) * scale;
+        let v = self.edge_u.cross_product(offset).dot_product(cross) * scale;
+        ((0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v)).then_some((distance, cos.abs()))
+    }
+}
+
+/// Distribution of directions over the pixels of a panorama, in proportion to their luminance and
+/// the solid angle they cover.
+struct Distribution {
+    /// Cumulative probability of the rows, from the top.
+    rows: Vec<f32>,
+    /// Cumulative probability of the pixels along each row.
+    columns: Vec<f32>,
+    /// Probability density of each pixel over texture coordinates.
+    density: Vec<f32>,
+}
+
+impl Distribution {
+    fn new(image: &ImageTexture) -> Option<Self> {
+        let (width, height) = (image.width(), image.height());
+        let mut rows = Vec::with_capacity(height);
+        let mut columns = Vec::with_capacity(width * height);
+        let mut density = Vec::with_capacity(width * height);
+        let mut total = 0.0;
+        for y in 0..height {
+            // Rows near the poles cover less of the sphere
+            let latitude = (0.5 - (y as f32 + 0.5) / height as f32) * PI;
+            let row_start = columns.len();
+            let mut row_total = 0.0;
+            for x in 0..width {
+                let weight = image.pixel(x, y).luminance().max(0.0) * latitude.cos();
+                row_total += weight;
+                columns.push(row_total);
+                density.push(weight);
+            }
+            for cumulative in &mut columns[row_start..] {
+                *cumulative = if row_total > 0.0 {
+                    *cumulative / row_total
+                } else {
+                    1.0
+                };
+            }
+            total += row_total;
+            rows.push(total);
+        }
+        if !(total > 0.0 && total.is_finite()) {
+            return None;
+        }
+        for cumulative in &mut rows {
+            *cumulative /= total;
+        }
+        let scale = (width * height) as f32 / total;
+        for weight in &mut density {
+            *weight *= scale;
+        }
+        Some(Distribution {
+            rows,
+            columns,
+            density,
+        })
+    }
+}
+
+/// Texture coordinates of the panorama in `direction`.
+pub fn direction_uv(direction: Vec3f) -> (f32, f32) {
+    let u = 0.5 + direction.x.atan2(-direction.z) / (2.0 * PI);
+    let v = 0.5 + direction.y.clamp(-1.0, 1.0).asin() / PI;
+    (u, v)
+}
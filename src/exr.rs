@@ -0,0 +1,407 @@
+//! OpenEXR encoding. The image's light is written as it was rendered, in full or half precision
+//! floats, with no tone mapping, LUT or clamping, so it can be graded and composited later. Rows
+//! are left uncompressed, one to a chunk, so where each will go is known up front and they can be
+//! written as they're rendered.
+//!
+//! Images stored the same way can be decoded too, for comparing renders and loading textures.
+
+use crate::{
+    framebuffer::{f16_to_f32, f32_to_f16, Framebuffer},
+    metadata::Metadata,
+    output::ImageWriter,
+    settings::RenderSettings,
+    texture::ImageTexture,
+    Vec3f,
+};
+use std::{
+    io::{self, Seek, SeekFrom, Write},
+    time::Duration,
+};
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+
+/// Version 2, as a single part image stored in scanlines.
+const VERSION: [u8; 4] = [2, 0, 0, 0];
+
+/// Writes an image as an OpenEXR file, in rows from the top, which can be written a few rows at a
+/// time as they're rendered.
+pub struct ExrWriter<W: Write + Seek> {
+    writer: W,
+    /// Write half precision floats rather than full.
+    half: bool,
+    /// Write an alpha channel, which the color is multiplied by, as it's rendered.
+    alpha: bool,
+    /// Index of the next row to write.
+    next_row: usize,
+    /// Where the value of the render time attribute is, which is only known once the image is
+    /// finished and is written over it then.
+    render_time_position: u64,
+}
+
+impl<W: Write + Seek> ExrWriter<W> {
+    /// Start writing an image `width` by `height` pixels to `writer`, in half precision floats if
+    /// `half`, otherwise full, with an alpha channel if `alpha`, keeping `metadata` as string
+    /// attributes.
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        half: bool,
+        alpha: bool,
+        metadata: &Metadata,
+    ) -> io::Result<Self> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "image too large for EXR");
+        let max_x = i32::try_from(width).map_err(|_| too_large())? - 1;
+        let max_y = i32::try_from(height).map_err(|_| too_large())? - 1;
+
+        let mut header = Vec::new();
+        header.extend(MAGIC);
+        header.extend(VERSION);
+        // Channels are listed in alphabetical order, each with its type and sampling
+        let names: &[&[u8]] = if alpha {
+            &[b"A", b"B", b"G", b"R"]
+        } else {
+            &[b"B", b"G", b"R"]
+        };
+        let mut channels = Vec::new();
+        for &name in names {
+            channels.extend(name);
+            channels.push(0);
+            let pixel_type: i32 = if half { 1 } else { 2 };
+            channels.extend(pixel_type.to_le_bytes());
+            // Not perceptually linear, and three reserved bytes
+            channels.extend([0; 4]);
+            channels.extend(1_i32.to_le_bytes());
+            channels.extend(1_i32.to_le_bytes());
+        }
+        channels.push(0);
+        attribute(&mut header, "channels", "chlist", &channels);
+        attribute(&mut header, "compression", "compression", &[0]);
+        let window: Vec<u8> = [0, 0, max_x, max_y]
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        attribute(&mut header, "dataWindow", "box2i", &window);
+        attribute(&mut header, "displayWindow", "box2i", &window);
+        attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+        let one = 1.0_f32.to_le_bytes();
+        attribute(&mut header, "pixelAspectRatio", "float", &one);
+        attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+        attribute(&mut header, "screenWindowWidth", "float", &one);
+        for (name, value) in metadata.entries() {
+            attribute(&mut header, name, "string", value.as_bytes());
+        }
+        // In seconds, filled in once the image is finished
+        attribute(&mut header, "renderTime", "float", &[0; 4]);
+        let render_time_position = writer.stream_position()? + header.len() as u64 - 4;
+        header.push(0);
+
+        // The offset of each row's chunk, which follow the header and this table
+        let bytes_per_channel = if half { 2 } else { 4 };
+        let chunk_size = 8 + names.len() * width * bytes_per_channel;
+        let first_chunk = header.len() + 8 * height;
+        for row in 0..height {
+            header.extend(((first_chunk + row * chunk_size) as u64).to_le_bytes());
+        }
+        writer.write_all(&header)?;
+        Ok(ExrWriter {
+            writer,
+            half,
+            alpha,
+            next_row: 0,
+            render_time_position,
+        })
+    }
+}
+
+impl<W: Write + Seek> ImageWriter for ExrWriter<W> {
+    fn write_rows(
+        &mut self,
+        framebuffer: &Framebuffer,
+        _settings: &RenderSettings,
+    ) -> io::Result<()> {
+        let bytes_per_channel = if self.half { 2 } else { 4 };
+        let channels = if self.alpha { 4 } else { 3 };
+        let size = channels * framebuffer.width * bytes_per_channel;
+        let mut chunk = Vec::with_capacity(8 + size);
+        let mut row = Vec::with_capacity(framebuffer.width);
+        let mut alpha = Vec::with_capacity(framebuffer.width);
+        for y in 0..framebuffer.height {
+            chunk.clear();
+            chunk.extend((self.next_row as i32).to_le_bytes());
+            chunk.extend((size as i32).to_le_bytes());
+            // Each channel's values for the whole row, in the channels' order
+            row.clear();
+            row.extend((0..framebuffer.width).map(|x| framebuffer.get(x, y)));
+            alpha.clear();
+            if self.alpha {
+                alpha.extend((0..framebuffer.width).map(|x| framebuffer.alpha(x, y)));
+            }
+            let blue = row.iter().map(|pixel| pixel.z);
+            let green = row.iter().map(|pixel| pixel.y);
+            let red = row.iter().map(|pixel| pixel.x);
+            for value in alpha.iter().copied().chain(blue).chain(green).chain(red) {
+                if self.half {
+                    chunk.extend(f32_to_f16(value).to_le_bytes());
+                } else {
+                    chunk.extend(value.to_le_bytes());
+                }
+            }
+            self.writer.write_all(&chunk)?;
+            self.next_row += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, render_time: Duration) -> io::Result<()> {
+        let end = self.writer.stream_position()?;
+        self.writer
+            .seek(SeekFrom::Start(self.render_time_position))?;
+        self.writer
+            .write_all(&render_time.as_secs_f32().to_le_bytes())?;
+        self.writer.seek(SeekFrom::Start(end))?;
+        self.writer.flush()
+    }
+}
+
+/// Append an attribute to the header.
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend(name.as_bytes());
+    header.push(0);
+    header.extend(kind.as_bytes());
+    header.push(0);
+    header.extend((value.len() as i32).to_le_bytes());
+    header.extend(value);
+}
+
+/// Decode a single part OpenEXR image stored in uncompressed scanlines, as the renderer writes
+/// them, from its R, G and B channels. The light is left as it is, multiplied by any alpha.
+pub fn decode(bytes: &[u8]) -> Result<ImageTexture, String> {
+    let mut position = 0;
+    if take(bytes, &mut position, 4)? != MAGIC {
+        return Err("expected an OpenEXR signature".to_string());
+    }
+    let version = take(bytes, &mut position, 4)?;
+    if version[0] != 2 || version[1] & !0x04 != 0 {
+        return Err("only single part scanline images are supported".to_string());
+    }
+
+    let mut channels = Vec::new();
+    let mut window = None;
+    loop {
+        let name = null_terminated(bytes, &mut position)?;
+        if name.is_empty() {
+            break;
+        }
+        let kind = null_terminated(bytes, &mut position)?;
+        let size = i32::from_le_bytes(take(bytes, &mut position, 4)?.try_into().unwrap());
+        let size = usize::try_from(size).map_err(|_| "invalid attribute size")?;
+        let value = take(bytes, &mut position, size)?;
+        match (name, kind) {
+            (b"channels", b"chlist") => {
+                let mut at = 0;
+                loop {
+                    let name = null_terminated(value, &mut at)?;
+                    if name.is_empty() {
+                        break;
+                    }
+                    let fields = value.get(at..at + 16).ok_or("truncated channel list")?;
+                    at += 16;
+                    let field = |i: usize| {
+                        i32::from_le_bytes([fields[i], fields[i + 1], fields[i + 2], fields[i + 3]])
+                    };
+                    if field(8) != 1 || field(12) != 1 {
+                        return Err("subsampled channels aren't supported".to_string());
+                    }
+                    channels.push((name, field(0)));
+                }
+            }
+            (b"compression", b"compression") if value != [0] => {
+                return Err("only uncompressed images are supported".to_string());
+            }
+            (b"dataWindow", b"box2i") if value.len() == 16 => {
+                let field = |i: usize| {
+                    i32::from_le_bytes([value[i], value[i + 1], value[i + 2], value[i + 3]])
+                };
+                window = Some([field(0), field(4), field(8), field(12)]);
+            }
+            _ => {}
+        }
+    }
+    let [min_x, min_y, max_x, max_y] = window.ok_or("missing data window")?;
+    let (width, height) = (
+        usize::try_from(i64::from(max_x) - i64::from(min_x) + 1).unwrap_or(0),
+        usize::try_from(i64::from(max_y) - i64::from(min_y) + 1).unwrap_or(0),
+    );
+    if width == 0 || height == 0 {
+        return Err("invalid data window".to_string());
+    }
+    let index = |name: &[u8]| {
+        channels
+            .iter()
+            .position(|&(channel, _)| channel == name)
+            .ok_or_else(|| "expected R, G and B channels".to_string())
+    };
+    let (red, green, blue) = (index(b"R")?, index(b"G")?, index(b"B")?);
+
+    // Each row is a chunk, found through the table of offsets after the header
+    let mut pixels = vec![Vec3f::default(); width * height];
+    let mut values = vec![0.0; channels.len() * width];
+    for _ in 0..height {
+        let offset = u64::from_le_bytes(take(bytes, &mut position, 8)?.try_into().unwrap());
+        let mut at = usize::try_from(offset).map_err(|_| "invalid offset")?;
+        let chunk = bytes.get(at..at + 8).ok_or("truncated chunk")?;
+        at += 8;
+        let y = i32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        let row = usize::try_from(i64::from(y) - i64::from(min_y))
+            .ok()
+            .filter(|&row| row < height)
+            .ok_or("chunk outside the data window")?;
+        // Each channel's values for the whole row, in the channels' order
+        for (&(_, pixel_type), values) in channels.iter().zip(values.chunks_exact_mut(width)) {
+            let size = if pixel_type == 1 { 2 } else { 4 };
+            let data = bytes.get(at..at + size * width).ok_or("truncated chunk")?;
+            at += size * width;
+            for (value, bytes) in values.iter_mut().zip(data.chunks_exact(size)) {
+                *value = match pixel_type {
+                    0 => u32::from_le_bytes(bytes.try_into().unwrap()) as f32,
+                    1 => f16_to_f32(u16::from_le_bytes([bytes[0], bytes[1]])),
+                    _ => f32::from_le_bytes(bytes.try_into().unwrap()),
+                };
+            }
+        }
+        for (x, pixel) in pixels[row * width..(row + 1) * width]
+            .iter_mut()
+            .enumerate()
+        {
+            let value = |channel: usize| values[channel * width + x];
+            *pixel = Vec3f::new(value(red), value(green), value(blue));
+        }
+    }
+    Ok(ImageTexture::new(width, height, pixels))
+}
+
+/// The `count` bytes from `position`, moving `position` past them.
+fn take<'a>(bytes: &'a [u8], position: &mut usize, count: usize) -> Result<&'a [u8], String> {
+    let taken = bytes
+        .get(*position..*position + count)
+        .ok_or("truncated file")?;
+    *position += count;
+    Ok(taken)
+}
+
+/// The bytes from `position` up to the next zero, moving `position` past it.
+fn null_terminated<'a>(bytes: &'a [u8], position: &mut usize) -> Result<&'a [u8], String> {
+    let rest = bytes.get(*position..).unwrap_or_default();
+    let length = rest
+        .iter()
+        .position(|&byte| byte == 0)
+        .ok_or("unterminated string")?;
+    *position += length + 1;
+    Ok(&rest[..length])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::PixelFormat;
+    use std::io::Cursor;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            width: 5,
+            height: 3,
+            samples_per_pixel: 1,
+            integrator: "path".to_string(),
+            seed: 7,
+            scene_hash: 0,
+        }
+    }
+
+    /// Light well outside `[0, 1]`, as the format keeps it.
+    fn color(x: usize, y: usize) -> Vec3f {
+        Vec3f::new(
+            x as f32 * 10.5,
+            y as f32 * 0.001,
+            0.25 + x as f32 - y as f32,
+        )
+    }
+
+    fn write(half: bool, alpha: bool) -> Vec<u8> {
+        let mut bytes = Cursor::new(Vec::new());
+        let mut writer =
+            Box::new(ExrWriter::new(&mut bytes, 5, 3, half, alpha, &metadata()).unwrap());
+        // Written a row at a time, as strips of the image are rendered
+        for y in 0..3 {
+            let mut framebuffer = Framebuffer::new(5, 1, PixelFormat::F32);
+            if alpha {
+                framebuffer = framebuffer.with_alpha();
+            }
+            for x in 0..5 {
+                framebuffer.set(x, 0, color(x, y));
+                if alpha {
+                    framebuffer.set_alpha(x, 0, 0.5);
+                }
+            }
+            writer
+                .write_rows(&framebuffer, &RenderSettings::default())
+                .unwrap();
+        }
+        writer.finish(Duration::from_millis(1500)).unwrap();
+        bytes.into_inner()
+    }
+
+    fn contains(bytes: &[u8], part: &[u8]) -> bool {
+        bytes.windows(part.len()).any(|window| window == part)
+    }
+
+    #[test]
+    fn round_trips_full_floats_exactly() {
+        let image = decode(&write(false, false)).unwrap();
+        assert_eq!((image.width(), image.height()), (5, 3));
+        for (x, y) in (0..3).flat_map(|y| (0..5).map(move |x| (x, y))) {
+            let (a, b) = (image.pixel(x, y), color(x, y));
+            assert_eq!([a.x, a.y, a.z], [b.x, b.y, b.z], "at ({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn round_trips_half_floats_to_their_precision() {
+        let image = decode(&write(true, true)).unwrap();
+        for (x, y) in (0..3).flat_map(|y| (0..5).map(move |x| (x, y))) {
+            let (a, b) = (image.pixel(x, y), color(x, y));
+            for (a, b) in [(a.x, b.x), (a.y, b.y), (a.z, b.z)] {
+                assert!(
+                    (a - b).abs() <= b.abs() / 1024.0,
+                    "at ({x}, {y}) {a} is not {b}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn writes_attributes() {
+        let bytes = write(false, true);
+        assert!(bytes.starts_with(&MAGIC));
+        assert!(contains(&bytes, b"channels\0chlist\0"));
+        assert!(contains(&bytes, b"A\0\x02\0\0\0"));
+        assert!(contains(&bytes, b"integrator\0string\0\x04\0\0\0path"));
+        let mut render_time = b"renderTime\0float\0\x04\0\0\0".to_vec();
+        render_time.extend(1.5_f32.to_le_bytes());
+        assert!(contains(&bytes, &render_time));
+    }
+
+    #[test]
+    fn rejects_compressed_images() {
+        let mut bytes = write(false, false);
+        let attribute = b"compression\0compression\0\x01\0\0\0";
+        let at = bytes
+            .windows(attribute.len())
+            .position(|window| window == attribute)
+            .unwrap();
+        // Zip compression
+        bytes[at + attribute.len()] = 3;
+        assert!(decode(&bytes).is_err());
+        assert!(decode(&bytes[..20]).is_err());
+    }
+}
@@ -0,0 +1,118 @@
+//! Reconstruction filters, which weigh the samples around each pixel to make its color. Samples
+//! are spread over the filter's footprint, which may reach into the neighbouring pixels, in
+//! proportion to its weight, so the pixel is the filtered image there. Wider filters blur the
+//! image slightly in exchange for smoother edges and less aliasing.
+
+/// A filter, centred on the middle of a pixel, separable into the same curve across and down.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// Every sample within the pixel counts equally.
+    Box,
+    /// Samples count less the further they are from the middle of the pixel, falling to zero at
+    /// the middle of the neighbouring pixels.
+    Tent,
+    /// A Gaussian, falling smoothly to zero a pixel and a half away.
+    Gaussian,
+    /// The Mitchell-Netravali filter with B = C = 1/3, reaching two pixels away, which is sharper
+    /// than the Gaussian, as it takes away a little of the light around edges.
+    Mitchell,
+}
+
+/// Standard deviation of the Gaussian filter, in pixels.
+const GAUSSIAN_SIGMA: f32 = 0.5;
+
+/// Pieces the footprint is split into across and down, to place samples in proportion to the
+/// filter's weight.
+const SEGMENTS: usize = 64;
+
+/// The Mitchell-Netravali filter's parameters.
+const MITCHELL_B: f32 = 1.0 / 3.0;
+const MITCHELL_C: f32 = 1.0 / 3.0;
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "box" => Some(Filter::Box),
+            "tent" => Some(Filter::Tent),
+            "gaussian" => Some(Filter::Gaussian),
+            "mitchell" => Some(Filter::Mitchell),
+            _ => None,
+        }
+    }
+
+    /// Furthest from the middle of the pixel a sample is weighed, across or down, in pixels.
+    pub fn radius(self) -> f32 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    /// Where a sample goes, in pixels from the middle of the pixel, placed by the numbers `u` and
+    /// `v` in `[0, 1)` in proportion to the filter's magnitude, along with its weight. Negative
+    /// parts of the filter give their samples a weight of minus one, the rest plus one, so the
+    /// weighted average of the samples is the filtered image.
+    pub fn sample(self, (u, v): (f32, f32)) -> ((f32, f32), f32) {
+        let (dx, dy) = (self.sample_1d(u), self.sample_1d(v));
+        let weight = if self.weight(dx, dy) < 0.0 { -1.0 } else { 1.0 };
+        ((dx, dy), weight)
+    }
+
+    fn sample_1d(self, u: f32) -> f32 {
+        if self == Filter::Box {
+            return u - 0.5;
+        }
+        let radius = self.radius();
+        let step = 2.0 * radius / SEGMENTS as f32;
+        let magnitudes: [f32; SEGMENTS] =
+            std::array::from_fn(|i| self.weight_1d(-radius + (i as f32 + 0.5) * step).abs());
+        let mut target = u * magnitudes.iter().sum::<f32>();
+        for (i, &magnitude) in magnitudes.iter().enumerate() {
+            if target < magnitude {
+                return -radius + (i as f32 + target / magnitude) * step;
+            }
+            target -= magnitude;
+        }
+        // Rounding may leave the number just past the last piece
+        radius
+    }
+
+    /// Weight of a sample (`dx`, `dy`) pixels from the middle of the pixel, which may be negative
+    /// for filters which sharpen.
+    pub fn weight(self, dx: f32, dy: f32) -> f32 {
+        self.weight_1d(dx) * self.weight_1d(dy)
+    }
+
+    fn weight_1d(self, d: f32) -> f32 {
+        let d = d.abs();
+        if d > self.radius() {
+            return 0.0;
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => 1.0 - d,
+            Filter::Gaussian => {
+                // Shifted down so the filter reaches zero at its radius rather than stopping short
+                let gaussian = |d: f32| (-d * d / (2.0 * GAUSSIAN_SIGMA * GAUSSIAN_SIGMA)).exp();
+                (gaussian(d) - gaussian(self.radius())).max(0.0)
+            }
+            Filter::Mitchell => {
+                let (b, c) = (MITCHELL_B, MITCHELL_C);
+                let (d2, d3) = (d * d, d * d * d);
+                let weight = if d < 1.0 {
+                    (12.0 - 9.0 * b - 6.0 * c) * d3
+                        + (-18.0 + 12.0 * b + 6.0 * c) * d2
+                        + (6.0 - 2.0 * b)
+                } else {
+                    (-b - 6.0 * c) * d3
+                        + (6.0 * b + 30.0 * c) * d2
+                        + (-12.0 * b - 48.0 * c) * d
+                        + (8.0 * b + 24.0 * c)
+                };
+                weight / 6.0
+            }
+        }
+    }
+}
@@ -0,0 +1,306 @@
+use crate::Vec3f;
+
+/// How pixels are stored in a [`Framebuffer`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PixelFormat {
+    F32,
+    /// Half precision floats, taking half the memory at the cost of precision.
+    F16,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::F32 => 12,
+            PixelFormat::F16 => 6,
+        }
+    }
+}
+
+enum Storage {
+    F32(Vec<Vec3f>),
+    F16(Vec<[u16; 3]>),
+}
+
+pub struct Framebuffer {
+    pub width: usize,
+    pub height: usize,
+    storage: Storage,
+    /// Coverage of each pixel, if the image has an alpha channel.
+    alpha: Option<Vec<f32>>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize, format: PixelFormat) -> Self {
+        let storage = match format {
+            PixelFormat::F32 => Storage::F32(vec![Vec3f::default(); width * height]),
+            PixelFormat::F16 => Storage::F16(vec![[0; 3]; width * height]),
+        };
+        Framebuffer {
+            width,
+            height,
+            storage,
+            alpha: None,
+        }
+    }
+
+    /// Give the image an alpha channel, with every pixel transparent.
+    pub fn with_alpha(mut self) -> Self {
+        self.alpha = Some(vec![0.0; self.width * self.height]);
+        self
+    }
+
+    pub fn has_alpha(&self) -> bool {
+        self.alpha.is_some()
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Vec3f {
+        let index = x + y * self.width;
+        match &self.storage {
+            Storage::F32(pixels) => pixels[index],
+            Storage::F16(pixels) => {
+                let [r, g, b] = pixels[index];
+                Vec3f::new(f16_to_f32(r), f16_to_f32(g), f16_to_f32(b))
+            }
+        }
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, color: Vec3f) {
+        let index = x + y * self.width;
+        match &mut self.storage {
+            Storage::F32(pixels) => pixels[index] = color,
+            Storage::F16(pixels) => {
+                pixels[index] = [
+                    f32_to_f16(color.x),
+                    f32_to_f16(color.y),
+                    f32_to_f16(color.z),
+                ]
+            }
+        }
+    }
+
+    /// Alpha of a pixel, which is opaque if the image has no alpha channel.
+    pub fn alpha(&self, x: usize, y: usize) -> f32 {
+        match &self.alpha {
+            Some(alpha) => alpha[x + y * self.width],
+            None => 1.0,
+        }
+    }
+
+    /// Set the alpha of a pixel, if the image has an alpha channel.
+    pub fn set_alpha(&mut self, x: usize, y: usize, value: f32) {
+        if let Some(alpha) = &mut self.alpha {
+            alpha[x + y * self.width] = value;
+        }
+    }
+
+    /// Iterate over the pixels in rows, top to bottom.
+    pub fn pixels(&self) -> impl Iterator<Item = Vec3f> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| self.get(x, y)))
+    }
+}
+
+/// Convert to IEEE 754 half precision, rounding to nearest even.
+pub(crate) fn f32_to_f16(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32;
+    let mantissa = bits & 0x7f_ffff;
+
+    if exponent == 0xff {
+        // Infinity or NaN, keeping NaNs as NaN
+        let nan = if mantissa != 0 { 0x200 } else { 0 };
+        return sign | 0x7c00 | nan;
+    }
+    let exponent = exponent - 127 + 15;
+    if exponent >= 0x1f {
+        // Too large, becomes infinity
+        return sign | 0x7c00;
+    }
+    if exponent <= 0 {
+        // Subnormal or too small, which become zero
+        if exponent < -10 {
+            return sign;
+        }
+        let mantissa = mantissa | 0x80_0000;
+        let shift = (14 - exponent) as u32;
+        let half = mantissa >> shift;
+        let remainder = mantissa & ((1 << shift) - 1);
+        let halfway = 1 << (shift - 1);
+        let round = remainder > halfway || (remainder == halfway && half & 1 == 1);
+        return sign | (half + round as u32) as u16;
+    }
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    let remainder = mantissa & 0x1fff;
+    let round = remainder > 0x1000 || (remainder == 0x1000 && half & 1 == 1);
+    // Rounding may carry into the exponent, which correctly gives infinity at the top end
+    sign | (half + round as u32) as u16
+}
+
+pub(crate) fn f16_to_f32(value: u16) -> f32 {
+    let sign = ((value & 0x8000) as u32) << 16;
+    let exponent = ((value >> 10) & 0x1f) as u32;
+    let mantissa = (value & 0x3ff) as u32;
+
+    let bits = match exponent {
+        0 if mantissa == 0 => sign,
+        0 => {
+            // Subnormal, normalize it for f32
+            let shift = mantissa.leading_zeros() - 21;
+            let mantissa = (mantissa << shift) & 0x3ff;
+            sign | ((127 - 15 + 1 - shift) << 23) | (mantissa << 13)
+        }
+        0x1f => sign | 0x7f80_0000 | (mantissa << 13),
+        _ => sign | ((exponent + 127 - 15) << 23) | (mantissa << 13),
+    };
+    f32::from_bits(bits)
+}
+
+/// How the render is laid out in memory, to fit within a memory budget.
+#[derive(Debug)]
+pub struct MemoryPlan {
+    pub format: PixelFormat,
+    /// Number of rows rendered at once. When less than the image height, the image is rendered
+    /// and written out in strips, never holding all of it in memory.
+    pub rows_per_strip: usize,
+}
+
+impl MemoryPlan {
+    /// Choose the layout with the best quality which fits `images` images in `budget` bytes,
+    /// alongside `overhead` bytes used by the rest of the renderer. First falls back to half
+    /// precision pixels, then to streaming strips of the images.
+    pub fn new(
+        width: usize,
+        height: usize,
+        images: usize,
+        budget: Option<usize>,
+        overhead: usize,
+    ) -> Result<Self, String> {
+        let full = |format| MemoryPlan {
+            format,
+            rows_per_strip: height,
+        };
+        let Some(budget) = budget else {
+            return Ok(full(PixelFormat::F32));
+        };
+        let available = budget.saturating_sub(overhead);
+        let row_bytes = |format: PixelFormat| images * width * format.bytes_per_pixel();
+
+        if row_bytes(PixelFormat::F32) * height <= available {
+            return Ok(full(PixelFormat::F32));
+        }
+        if row_bytes(PixelFormat::F16) * height <= available {
+            return Ok(full(PixelFormat::F16));
+        }
+        // Full precision strips are preferred, as long as they're not so thin that the
+        // per-strip overhead dominates.
+        const MIN_STRIP_ROWS: usize = 16;
+        for format in [PixelFormat::F32, PixelFormat::F16] {
+            let rows = available / row_bytes(format);
+            if rows >= MIN_STRIP_ROWS.min(height) {
+                return Ok(MemoryPlan {
+                    format,
+                    rows_per_strip: rows,
+                });
+            }
+        }
+        let rows = available / row_bytes(PixelFormat::F16);
+        if rows > 0 {
+            return Ok(MemoryPlan {
+                format: PixelFormat::F16,
+                rows_per_strip: rows,
+            });
+        }
+        Err(format!(
+            "A memory budget of {budget} bytes is too small, at least {} bytes are needed",
+            overhead + row_bytes(PixelFormat::F16)
+        ))
+    }
+
+    pub fn is_degraded(&self, height: usize) -> bool {
+        self.format != PixelFormat::F32 || self.rows_per_strip < height
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_half_round_trips() {
+        for half in 0..=u16::MAX {
+            let value = f16_to_f32(half);
+            if value.is_nan() {
+                assert!(f16_to_f32(f32_to_f16(value)).is_nan());
+            } else {
+                assert_eq!(f32_to_f16(value), half, "{half:#06x} became {value}");
+            }
+        }
+    }
+
+    #[test]
+    fn halves_round_to_nearest_even() {
+        assert_eq!(f32_to_f16(1.0), 0x3c00);
+        // Exactly between 1 and the next half, which is odd, so rounds down to 1
+        assert_eq!(f32_to_f16(1.0 + 2.0_f32.powi(-11)), 0x3c00);
+        // Exactly between the next half and the one after, rounding up to the even one
+        assert_eq!(f32_to_f16(1.0 + 3.0 * 2.0_f32.powi(-11)), 0x3c02);
+        assert_eq!(f32_to_f16(2.0_f32.powi(-24)), 0x0001);
+        assert_eq!(f32_to_f16(2.0_f32.powi(-26)), 0x0000);
+        assert_eq!(f32_to_f16(-2.5), 0xc100);
+    }
+
+    #[test]
+    fn halves_overflow_to_infinity() {
+        assert_eq!(f32_to_f16(65504.0), 0x7bff);
+        assert_eq!(f32_to_f16(65520.0), 0x7c00);
+        assert_eq!(f32_to_f16(1e10), 0x7c00);
+        assert_eq!(f32_to_f16(f32::NEG_INFINITY), 0xfc00);
+        assert!(f16_to_f32(f32_to_f16(f32::NAN)).is_nan());
+    }
+
+    #[test]
+    fn memory_plan_without_budget_is_full_precision() {
+        let plan = MemoryPlan::new(100, 100, 2, None, 0).unwrap();
+        assert_eq!(plan.format, PixelFormat::F32);
+        assert_eq!(plan.rows_per_strip, 100);
+        assert!(!plan.is_degraded(100));
+    }
+
+    #[test]
+    fn memory_plan_falls_back_to_half_then_strips() {
+        // Each row is 1200 bytes at full precision and 600 at half, after 1000 bytes of overhead
+        let plan = |budget: usize| MemoryPlan::new(100, 100, 1, Some(budget + 1000), 1000);
+
+        let full = plan(120_000).unwrap();
+        assert_eq!((full.format, full.rows_per_strip), (PixelFormat::F32, 100));
+
+        let half = plan(119_999).unwrap();
+        assert_eq!((half.format, half.rows_per_strip), (PixelFormat::F16, 100));
+        assert!(half.is_degraded(100));
+
+        let strips = plan(59_999).unwrap();
+        assert_eq!(
+            (strips.format, strips.rows_per_strip),
+            (PixelFormat::F32, 49)
+        );
+
+        // Too few full precision rows fit, so half precision strips are taller
+        let half_strips = plan(12_000).unwrap();
+        assert_eq!(
+            (half_strips.format, half_strips.rows_per_strip),
+            (PixelFormat::F16, 20)
+        );
+
+        // Thin strips are still better than nothing
+        let thin = plan(3_000).unwrap();
+        assert_eq!((thin.format, thin.rows_per_strip), (PixelFormat::F16, 5));
+    }
+
+    #[test]
+    fn memory_plan_rejects_too_small_a_budget() {
+        let error = MemoryPlan::new(100, 100, 1, Some(1500), 1000).unwrap_err();
+        assert!(error.contains("at least 1600 bytes"), "{error}");
+        assert!(MemoryPlan::new(100, 100, 1, Some(10), 1000).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! Export of scenes to glTF 2.0, to move them into modelling tools.
+//!
+//! glTF has no spheres, so every sphere is an instance of one tessellated unit sphere, scaled and
+//! moved into place by its node. Materials are exported as their closest metallic-roughness
+//! equivalent. The background has no equivalent and isn't exported.
+
+use crate::{camera::Projection, json, scene::Scene, Vec3f};
+use std::{f32::consts::PI, fmt::Write as _, path::Path};
+
+const STACKS: usize = 24;
+const SLICES: usize = 48;
+
+/// Write the scene and camera to `path` as a `.gltf` file, with the geometry embedded. The
+/// camera is framed for images `aspect_ratio` times wider than they are tall.
+pub fn export(scene: &Scene, aspect_ratio: f32, path: &Path) -> std::io::Result<()> {
+    std::fs::write(path, to_gltf(scene, aspect_ratio))
+}
+
+fn to_gltf(scene: &Scene, aspect_ratio: f32) -> String {
+    let (positions, indices) = unit_sphere();
+    let mut buffer = Vec::with_capacity(positions.len() * 12 + indices.len() * 2);
+    for position in &positions {
+        for component in [position.x, position.y, position.z] {
+            buffer.extend(component.to_le_bytes());
+        }
+    }
+    let indices_offset = buffer.len();
+    for index in &indices {
+        buffer.extend(index.to_le_bytes());
+    }
+
+    let mut json = String::new();
+    let _ = write!(
+        json,
+        "{{\n  \"asset\": {{ \"version\": \"2.0\", \"generator\": \"rayox\" }},\n  \"extensionsUsed\": [\"KHR_materials_clearcoat\", \"KHR_materials_emissive_strength\", \"KHR_materials_ior\", \"KHR_materials_transmission\"],\n  \"scene\": 0,\n  \"scenes\": [{{ \"nodes\": [{}] }}],\n  \"nodes\": [",
+        (0..=scene.spheres.len())
+            .map(|node| node.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    for (index, sphere) in scene.spheres.iter().enumerate() {
+        let c = sphere.center;
+        let r = sphere.radius;
+        let _ = write!(
+            json,
+            "\n    {{ \"name\": \"sphere {index}\", \"mesh\": {}, \"translation\": [{}, {}, {}], \"scale\": [{r}, {r}, {r}] }},",
+            sphere.material.0, c.x, c.y, c.z
+        );
+    }
+    // glTF cameras look down their -Z axis, so the camera's frame maps straight onto the node's
+    // transform, given in columns.
+    let camera = scene.camera.view();
+    let back = -camera.forward;
+    let matrix = [
+        camera.right.x,
+        camera.right.y,
+        camera.right.z,
+        0.0,
+        camera.up.x,
+        camera.up.y,
+        camera.up.z,
+        0.0,
+        back.x,
+        back.y,
+        back.z,
+        0.0,
+        camera.eye.x,
+        camera.eye.y,
+        camera.eye.z,
+        1.0,
+    ]
+    .map(|value| value.to_string())
+    .join(", ");
+    let projection = match camera.projection {
+        // glTF has no fisheye or panoramic cameras, so they fall back to the perspective field of
+        // view
+        Projection::Perspective | Projection::Fisheye { .. } | Projection::Equirectangular => format!(
+            "\"type\": \"perspective\", \"perspective\": {{ \"aspectRatio\": {aspect_ratio}, \"yfov\": {}, \"znear\": 0.01 }}",
+            camera.fov.to_radians()
+        ),
+        // Magnifications are half the width and height of the view
+        Projection::Orthographic { height } => format!(
+            "\"type\": \"orthographic\", \"orthographic\": {{ \"xmag\": {}, \"ymag\": {}, \"zfar\": 1000, \"znear\": 0 }}",
+            height * 0.5 * aspect_ratio,
+            height * 0.5
+        ),
+    };
+    let _ = write!(
+        json,
+        "\n    {{ \"name\": \"camera\", \"camera\": 0, \"matrix\": [{matrix}] }}\n  ],\n  \"cameras\": [{{ {projection} }}],\n  \"meshes\": [",
+    );
+    // Primitives hold the material, so there is a mesh for each material sharing the geometry.
+    for index in 0..scene.materials.len() {
+        let separator = if index == 0 { "" } else { "," };
+        let _ = write!(
+            json,
+            "{separator}\n    {{ \"primitives\": [{{ \"attributes\": {{ \"POSITION\": 0, \"NORMAL\": 0 }}, \"indices\": 1, \"material\": {index} }}] }}"
+        );
+    }
+    json.push_str("\n  ],\n  \"materials\": [");
+    for (index, material) in scene.materials.iter().enumerate() {
+        let separator = if index == 0 { "" } else { "," };
+        let pbr = material.pbr();
+        let b = pbr.base_color;
+        // Emission is stored as a color of at most one, scaled by a separate strength.
+        let strength = pbr
+            .emission
+            .x
+            .max(pbr.emission.y)
+            .max(pbr.emission.z)
+            .max(1.0);
+        let e = pbr.emission * (1.0 / strength);
+        let _ = write!(
+            json,
+            "{separator}\n    {{ \"name\": {}, \"pbrMetallicRoughness\": {{ \"baseColorFactor\": [{}, {}, {}, 1], \"metallicFactor\": {}, \"roughnessFactor\": {} }}, \"emissiveFactor\": [{}, {}, {}], \"extensions\": {{ \"KHR_materials_clearcoat\": {{ \"clearcoatFactor\": {}, \"clearcoatRoughnessFactor\": {} }}, \"KHR_materials_emissive_strength\": {{ \"emissiveStrength\": {strength} }}, \"KHR_materials_ior\": {{ \"ior\": {} }}, \"KHR_materials_transmission\": {{ \"transmissionFactor\": {} }} }} }}",
+            json::string(&format!("{} {index}", material.name())),
+            b.x.clamp(0.0, 1.0),
+            b.y.clamp(0.0, 1.0),
+            b.z.clamp(0.0, 1.0),
+            pbr.metallic,
+            pbr.roughness,
+            e.x,
+            e.y,
+            e.z,
+            pbr.clearcoat,
+            pbr.clearcoat_roughness,
+            pbr.ior,
+            pbr.transmission,
+        );
+    }
+    let _ = write!(
+        json,
+        "\n  ],\n  \"accessors\": [\n    {{ \"bufferView\": 0, \"componentType\": 5126, \"count\": {}, \"type\": \"VEC3\", \"min\": [-1, -1, -1], \"max\": [1, 1, 1] }},\n    {{ \"bufferView\": 1, \"componentType\": 5123, \"count\": {}, \"type\": \"SCALAR\" }}\n  ],\n  \"bufferViews\": [\n    {{ \"buffer\": 0, \"byteOffset\": 0, \"byteLength\": {indices_offset}, \"target\": 34962 }},\n    {{ \"buffer\": 0, \"byteOffset\": {indices_offset}, \"byteLength\": {}, \"target\": 34963 }}\n  ],\n  \"buffers\": [{{ \"byteLength\": {}, \"uri\": \"data:application/octet-stream;base64,{}\" }}]\n}}\n",
+        positions.len(),
+        indices.len(),
+        buffer.len() - indices_offset,
+        buffer.len(),
+        base64(&buffer),
+    );
+    json
+}
+
+/// Vertices and triangle indices of a UV sphere of radius one. On a unit sphere the positions
+/// double as the normals.
+fn unit_sphere() -> (Vec<Vec3f>, Vec<u16>) {
+    let mut positions = Vec::with_capacity((STACKS + 1) * (SLICES + 1));
+    for stack in 0..=STACKS {
+        let theta = PI * stack as f32 / STACKS as f32;
+        for slice in 0..=SLICES {
+            let phi = 2.0 * PI * slice as f32 / SLICES as f32;
+            positions.push(Vec3f::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            ));
+        }
+    }
+    let mut indices = Vec::with_capacity(STACKS * SLICES * 6);
+    for stack in 0..STACKS {
+        for slice in 0..SLICES {
+            let a = (stack * (SLICES + 1) + slice) as u16;
+            let b = a + SLICES as u16 + 1;
+            // Counter-clockwise seen from outside
+            indices.extend([a, a + 1, b, a + 1, b + 1, b]);
+        }
+    }
+    (positions, indices)
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
@@ -0,0 +1,117 @@
+//! Density grids, which give media such as smoke and clouds their shape, for rendering
+//! simulations made elsewhere. Grids are stored in a simple raw format: a text header line
+//! `rayox-grid WIDTH HEIGHT DEPTH`, followed by the density of every cell as a little-endian 32 bit
+//! float, with x changing fastest, then y, then z.
+
+use crate::Vec3f;
+use std::path::Path;
+
+const MAGIC: &str = "rayox-grid";
+
+/// A box of densities, from zero upwards, interpolated between the centers of its cells.
+pub struct DensityGrid {
+    /// Number of cells along x, y and z.
+    size: [usize; 3],
+    cells: Vec<f32>,
+    /// Greatest density of any cell.
+    max: f32,
+}
+
+impl DensityGrid {
+    /// A grid `size` cells along x, y and z, with x changing fastest in `cells`.
+    pub fn new(size: [usize; 3], cells: Vec<f32>) -> Result<Self, String> {
+        if size.contains(&0) {
+            return Err("grid must have at least one cell along each axis".to_string());
+        }
+        let count = size[0] * size[1] * size[2];
+        if cells.len() != count {
+            return Err(format!(
+                "grid of {}x{}x{} needs {count} cells, but has {}",
+                size[0],
+                size[1],
+                size[2],
+                cells.len()
+            ));
+        }
+        if cells
+            .iter()
+            .any(|density| !density.is_finite() || *density < 0.0)
+        {
+            return Err("densities must be finite and not negative".to_string());
+        }
+        let max = cells.iter().copied().fold(0.0, f32::max);
+        Ok(DensityGrid { size, cells, max })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let bytes = std::fs::read(path)
+            .map_err(|err| format!("Failed to read density grid `{}`: {err}", path.display()))?;
+        DensityGrid::parse(&bytes)
+            .map_err(|err| format!("Invalid density grid `{}`: {err}", path.display()))
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        let header_end = bytes
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .ok_or("missing header")?;
+        let header = std::str::from_utf8(&bytes[..header_end]).map_err(|_| "invalid header")?;
+        let mut words = header.split_whitespace();
+        if words.next() != Some(MAGIC) {
+            return Err(format!("header must start with `{MAGIC}`"));
+        }
+        let mut dimension = || {
+            words
+                .next()
+                .and_then(|word| word.parse::<usize>().ok())
+                .ok_or("header must give the width, height and depth")
+        };
+        let size = [dimension()?, dimension()?, dimension()?];
+        let cells = bytes[header_end + 1..]
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+            .collect();
+        DensityGrid::new(size, cells)
+    }
+
+    /// Greatest density anywhere in the grid.
+    pub fn max(&self) -> f32 {
+        self.max
+    }
+
+    /// Density at `position`, from 0 to 1 across the grid along each axis, interpolated
+    /// trilinearly between the centers of the cells. Zero outside the grid.
+    pub fn density(&self, position: Vec3f) -> f32 {
+        let inside = |p: f32| (0.0..=1.0).contains(&p);
+        if !(inside(position.x) && inside(position.y) && inside(position.z)) {
+            return 0.0;
+        }
+        let [width, height, depth] = self.size;
+        let (x, tx) = split(position.x, width);
+        let (y, ty) = split(position.y, height);
+        let (z, tz) = split(position.z, depth);
+        let at = |x: usize, y: usize, z: usize| self.cells[x + (y + z * height) * width];
+        let next = |i: usize, size: usize| (i + 1).min(size - 1);
+        let lerp = |a: f32, b: f32, t: f32| a * (1.0 - t) + b * t;
+        let plane = |z: usize| {
+            lerp(
+                lerp(at(x, y, z), at(next(x, width), y, z), tx),
+                lerp(
+                    at(x, next(y, height), z),
+                    at(next(x, width), next(y, height), z),
+                    tx,
+                ),
+                ty,
+            )
+        };
+        lerp(plane(z), plane(next(z, depth)), tz)
+    }
+}
+
+/// Split a position from 0 to 1 across `size` cells into the index of the cell center below it
+/// and the fraction towards the next, holding the value of the outer cells out to the edges.
+fn split(position: f32, size: usize) -> (usize, f32) {
+    let position = (position * size as f32 - 0.5).clamp(0.0, (size - 1) as f32);
+    let index = position as usize;
+    (index, position - index as f32)
+}
@@ -0,0 +1,122 @@
+//! Radiance RGBE encoding. Each pixel's light is stored as three 8 bit mantissas sharing an
+//! exponent, so the full range rendered survives in a quarter of the space of floats, without
+//! tone mapping, LUT or clamping. Rows are run length encoded, as readers expect of images as
+//! wide as most renders.
+
+use crate::{framebuffer::Framebuffer, output::ImageWriter, settings::RenderSettings, Vec3f};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+/// Widths rows can be run length encoded at; others are written flat.
+const ENCODED_WIDTHS: std::ops::Range<usize> = 8..0x8000;
+
+/// Shortest run of one value worth encoding as a run, as shorter runs take no less space as
+/// literals, and the longest run and longest span of literal values.
+const MIN_RUN: usize = 4;
+const MAX_RUN: usize = 127;
+const MAX_LITERALS: usize = 128;
+
+/// Writes an image as a Radiance `.hdr` file, in rows from the top, which can be written a few
+/// rows at a time as they're rendered.
+pub struct HdrWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> HdrWriter<W> {
+    /// Start writing an image `width` by `height` pixels to `writer`.
+    pub fn new(mut writer: W, width: usize, height: usize) -> io::Result<Self> {
+        write!(
+            writer,
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {height} +X {width}\n"
+        )?;
+        Ok(HdrWriter { writer })
+    }
+}
+
+impl<W: Write> ImageWriter for HdrWriter<W> {
+    fn write_rows(
+        &mut self,
+        framebuffer: &Framebuffer,
+        _settings: &RenderSettings,
+    ) -> io::Result<()> {
+        let width = framebuffer.width;
+        let mut row = Vec::with_capacity(width);
+        let mut encoded = Vec::with_capacity(4 * width + 4);
+        for y in 0..framebuffer.height {
+            row.clear();
+            row.extend((0..width).map(|x| rgbe(framebuffer.get(x, y))));
+            encoded.clear();
+            if ENCODED_WIDTHS.contains(&width) {
+                encoded.extend([2, 2, (width >> 8) as u8, width as u8]);
+                for channel in 0..4 {
+                    let values: Vec<u8> = row.iter().map(|pixel| pixel[channel]).collect();
+                    run_length_encode(&values, &mut encoded);
+                }
+            } else {
+                encoded.extend(row.iter().flatten());
+            }
+            self.writer.write_all(&encoded)?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, _render_time: Duration) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A color as mantissas for each channel, sharing the brightest channel's exponent. Colors too
+/// dark to represent are black, and negative channels are clamped to zero.
+fn rgbe(color: Vec3f) -> [u8; 4] {
+    let max = color.max_component();
+    if max.is_nan() || max < 1e-32 {
+        return [0; 4];
+    }
+    // The largest exponent which fits
+    let max = max.min(1e38);
+    // The exponent which puts the brightest channel's mantissa in [0.5, 1)
+    let exponent = ((max.to_bits() >> 23) & 0xff) as i32 - 126;
+    let scale = 2_f32.powi(8 - exponent);
+    let mantissa = |channel: f32| (channel.clamp(0.0, max) * scale).min(255.0) as u8;
+    [
+        mantissa(color.x),
+        mantissa(color.y),
+        mantissa(color.z),
+        (exponent + 128) as u8,
+    ]
+}
+
+/// Append one channel of a row, as runs of a repeated value and spans of literal values.
+fn run_length_encode(values: &[u8], encoded: &mut Vec<u8>) {
+    let mut start = 0;
+    while start < values.len() {
+        let run = values[start..]
+            .iter()
+            .take(MAX_RUN)
+            .take_while(|&&value| value == values[start])
+            .count();
+        if run >= MIN_RUN {
+            encoded.extend([128 + run as u8, values[start]]);
+            start += run;
+            continue;
+        }
+        // Literals up to the next run worth encoding
+        let mut end = start + 1;
+        while end < values.len() && end - start < MAX_LITERALS {
+            let next_run = values[end..]
+                .iter()
+                .take(MIN_RUN)
+                .take_while(|&&value| value == values[end])
+                .count();
+            if next_run >= MIN_RUN {
+                break;
+            }
+            end += 1;
+        }
+        encoded.push((end - start) as u8);
+        encoded.extend(&values[start..end]);
+        start = end;
+    }
+}
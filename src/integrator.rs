@@ -0,0 +1,222 @@
+//! Integrators, which find the light arriving along each camera ray. They're chosen when
+//! rendering rather than built in to the render loop, so they can be compared on the same scene.
+
+use crate::{
+    bidirectional,
+    irradiance_cache::{self, IrradianceCache},
+    material::Media,
+    occlusion, path_tracer,
+    photon_map::{self, PhotonMaps},
+    rng::{stream_seed, Rng},
+    scene::Scene,
+    settings::RenderSettings,
+    tracer::{self, Bounces, NonFinite},
+    Ray, Vec3f,
+};
+
+/// Finds the light arriving along camera rays.
+pub trait Integrator: Send + Sync {
+    /// Light arriving along `ray`, or what produced a NaN or infinite value along the way.
+    fn trace(&self, ray: Ray, scene: &Scene, rng: &mut Rng) -> Result<Vec3f, NonFinite>;
+}
+
+/// Ray tracing of reflections and refractions up to `max_depth` deep, with surfaces lit
+/// directly.
+pub struct Whitted {
+    pub max_depth: usize,
+}
+
+impl Integrator for Whitted {
+    fn trace(&self, ray: Ray, scene: &Scene, rng: &mut Rng) -> Result<Vec3f, NonFinite> {
+        tracer::trace(ray, scene, self.max_depth, rng)
+    }
+}
+
+/// Only the light reaching the first surface hit straight from the lights and the background,
+/// without any reflections, refractions or bounces.
+pub struct DirectLighting;
+
+impl Integrator for DirectLighting {
+    fn trace(&self, ray: Ray, scene: &Scene, rng: &mut Rng) -> Result<Vec3f, NonFinite> {
+        let media = Media::default();
+        let hit = scene.intersect(&ray);
+        let walked = tracer::walk_medium(ray, hit, &media, None, scene, rng);
+        let Some(hit) = walked.hit else {
+            return Ok(tracer::escaped(&walked.ray, scene, walked.sampled_lights)
+                * walked.throughput
+                + walked.in_scattered);
+        };
+        // Surfaces stop scattering rays straight away
+        let bounces = Bounces::whitted(0, 0);
+        let shaded = tracer::shade(
+            &walked.ray,
+            &hit,
+            scene,
+            bounces,
+            walked.sampled_lights,
+            &media,
+            rng,
+        );
+        shaded.check(&hit, scene, 0)?;
+        Ok(shaded.radiance * walked.throughput + walked.in_scattered)
+    }
+}
+
+/// The path tracer, see [`path_tracer`].
+pub struct PathTracer {
+    /// Brightest light found after the first bounce may be, if clamped.
+    pub max_indirect: Option<f32>,
+}
+
+impl Integrator for PathTracer {
+    fn trace(&self, ray: Ray, scene: &Scene, rng: &mut Rng) -> Result<Vec3f, NonFinite> {
+        path_tracer::trace(ray, scene, self.max_indirect, rng)
+    }
+}
+
+/// The bidirectional path tracer, see [`bidirectional`].
+pub struct Bidirectional;
+
+impl Integrator for Bidirectional {
+    fn trace(&self, ray: Ray, scene: &Scene, rng: &mut Rng) -> Result<Vec3f, NonFinite> {
+        bidirectional::trace(ray, scene, rng)
+    }
+}
+
+/// Photon mapping, see [`photon_map`], with photons already traced through the scene.
+pub struct PhotonMapping {
+    pub photons: PhotonMaps,
+}
+
+impl Integrator for PhotonMapping {
+    fn trace(&self, ray: Ray, scene: &Scene, rng: &mut Rng) -> Result<Vec3f, NonFinite> {
+        photon_map::trace(ray, scene, &self.photons, rng)
+    }
+}
+
+/// Whitted ray tracing of reflections and refractions up to `max_depth` deep, with matte
+/// surfaces lit indirectly too, see [`irradiance_cache`]. The cache is filled before rendering.
+pub struct IrradianceCaching {
+    pub cache: IrradianceCache,
+    pub max_depth: usize,
+    /// Brightest light found after the first bounce of the rays gathering indirect light may
+    /// be, if clamped.
+    pub max_indirect: Option<f32>,
+}
+
+impl Integrator for IrradianceCaching {
+    fn trace(&self, ray: Ray, scene: &Scene, rng: &mut Rng) -> Result<Vec3f, NonFinite> {
+        irradiance_cache::trace(
+            ray,
+            scene,
+            &self.cache,
+            self.max_depth,
+            self.max_indirect,
+            rng,
+        )
+    }
+}
+
+/// Ambient occlusion within `distance` of each surface, see [`occlusion`].
+pub struct AmbientOcclusion {
+    pub distance: f32,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn trace(&self, ray: Ray, scene: &Scene, rng: &mut Rng) -> Result<Vec3f, NonFinite> {
+        Ok(occlusion::trace(ray, scene, self.distance, rng))
+    }
+}
+
+/// Which integrator to render with, and how it's set up.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum IntegratorKind {
+    Whitted,
+    DirectLighting,
+    /// Follow paths of light through every bounce, for global illumination.
+    PathTracing,
+    /// Join paths followed out from the lights to paths from the camera, for light which paths
+    /// from the camera rarely find by themselves.
+    Bidirectional,
+    /// Light matte surfaces indirectly from a cache of the light bouncing onto them, gathered
+    /// sparsely and interpolated.
+    IrradianceCaching,
+    /// First follow this many photons out from the lights, for caustics of light focused through
+    /// glass and off mirrors.
+    PhotonMapping {
+        photons: usize,
+    },
+    /// Make this many mutations of the path tracer's paths per pixel on average, for light
+    /// which is hard to find but worth exploring once found. The whole image is held in memory
+    /// at once.
+    Metropolis {
+        mutations: usize,
+    },
+    /// Render ambient occlusion within this distance of each surface, instead of lighting.
+    AmbientOcclusion {
+        distance: f32,
+    },
+}
+
+impl IntegratorKind {
+    /// Integrators which take no settings, by name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "whitted" => Some(IntegratorKind::Whitted),
+            "direct" => Some(IntegratorKind::DirectLighting),
+            "path" => Some(IntegratorKind::PathTracing),
+            "bidirectional" => Some(IntegratorKind::Bidirectional),
+            "irradiance-cache" => Some(IntegratorKind::IrradianceCaching),
+            _ => None,
+        }
+    }
+
+    /// Name of the integrator as `--integrator` takes it, or for those with settings, as the
+    /// option choosing them is named.
+    pub fn name(self) -> &'static str {
+        match self {
+            IntegratorKind::Whitted => "whitted",
+            IntegratorKind::DirectLighting => "direct",
+            IntegratorKind::PathTracing => "path",
+            IntegratorKind::Bidirectional => "bidirectional",
+            IntegratorKind::IrradianceCaching => "irradiance-cache",
+            IntegratorKind::PhotonMapping { .. } => "photon-map",
+            IntegratorKind::Metropolis { .. } => "metropolis",
+            IntegratorKind::AmbientOcclusion { .. } => "ambient-occlusion",
+        }
+    }
+
+    /// The integrator, made ready to trace rays through `scene` as `settings` describe, with any
+    /// random numbers used in preparing it made from the render's seed. Metropolis light
+    /// transport mutates the path tracer's paths, so that's the integrator it traces them with.
+    pub fn create(self, scene: &Scene, settings: &RenderSettings) -> Box<dyn Integrator> {
+        match self {
+            IntegratorKind::Whitted => Box::new(Whitted {
+                max_depth: settings.max_depth,
+            }),
+            IntegratorKind::DirectLighting => Box::new(DirectLighting),
+            IntegratorKind::PathTracing | IntegratorKind::Metropolis { .. } => {
+                Box::new(PathTracer {
+                    max_indirect: settings.max_indirect,
+                })
+            }
+            IntegratorKind::Bidirectional => Box::new(Bidirectional),
+            IntegratorKind::IrradianceCaching => Box::new(IrradianceCaching {
+                cache: IrradianceCache::fill(scene, settings),
+                max_depth: settings.max_depth,
+                max_indirect: settings.max_indirect,
+            }),
+            // Photons are traced with their own random numbers, apart from any pixel's
+            IntegratorKind::PhotonMapping { photons } => Box::new(PhotonMapping {
+                photons: PhotonMaps::trace(
+                    scene,
+                    photons,
+                    &mut Rng::new(stream_seed(settings.seed, u64::MAX)),
+                ),
+            }),
+            IntegratorKind::AmbientOcclusion { distance } => {
+                Box::new(AmbientOcclusion { distance })
+            }
+        }
+    }
+}
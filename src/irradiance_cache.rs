@@ -0,0 +1,331 @@
+//! Irradiance caching, after Ward et al. The light bouncing onto matte surfaces changes slowly
+//! across them, so rather than gathering it afresh at every point the camera sees, it's gathered
+//! with the path tracer at a sparse set of points, cached, and interpolated between them
+//! everywhere else. The cache is filled before rendering, by tracing a ray through each pixel,
+//! so it's the same however many threads then render the image and in whatever order.
+//!
+//! Only the indirect light is cached. Lights are still sampled directly at every point, as the
+//! tracer does, so shadows stay sharp.
+
+use crate::{
+    material::{Interaction, Media},
+    path_tracer,
+    rng::{stream_seed, Rng},
+    scene::{Background, Scene},
+    settings::RenderSettings,
+    tile::{self, Tile, TileOrder},
+    tracer::{self, Bounces, NonFinite, Surface},
+    Ray, Vec3f,
+};
+use std::{
+    collections::HashMap,
+    f32::consts::PI,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+};
+
+/// Rays cast over the hemisphere above a point to gather the light bouncing onto it.
+pub const IRRADIANCE_RAYS: usize = 64;
+
+/// Rays cast to gather the light at points the cache doesn't cover while rendering. The light
+/// gathered there isn't kept, so fewer rays are cast, leaving the pixel's samples to average out
+/// the noise.
+const UNCACHED_RAYS: usize = 4;
+
+/// Width and height of the tiles the cache is filled over, in pixels.
+const FILL_TILE_SIZE: usize = 32;
+
+/// Largest error allowed when interpolating between cached points, as a fraction. Smaller values
+/// place the cached points closer together.
+const ACCURACY: f32 = 0.3;
+
+/// Nearest and furthest a cached point may be used from, however near or far the surfaces around
+/// it are.
+const MIN_SPACING: f32 = 0.05;
+const MAX_SPACING: f32 = 1.0;
+
+/// Indirect light arriving at a point on a surface.
+struct Record {
+    point: Vec3f,
+    normal: Vec3f,
+    irradiance: Vec3f,
+    /// Distance within which the record is used.
+    radius: f32,
+}
+
+/// Indirect light gathered at points across the scene's surfaces, shared by every pixel.
+#[derive(Default)]
+pub struct IrradianceCache {
+    records: Vec<Record>,
+    /// Indices of the records which may be used within each cell of a grid `MAX_SPACING` wide.
+    cells: HashMap<[i32; 3], Vec<usize>>,
+}
+
+impl IrradianceCache {
+    /// Fill a cache for the image the settings describe of what the scene's camera sees, by
+    /// tracing a ray through each pixel and caching the light gathered wherever it can't yet be
+    /// interpolated. Tiles of the image are filled on the settings' threads, each into a cache of
+    /// its own with random numbers of its own, then their records are merged in the order of the
+    /// tiles, leaving out those which the records before them cover, so the cache is the same
+    /// however many threads fill it. Rays which produce a NaN or infinite value add nothing more.
+    pub fn fill(scene: &Scene, settings: &RenderSettings) -> Self {
+        let tiles = tile::tiles(
+            settings.width,
+            0..settings.height,
+            FILL_TILE_SIZE,
+            TileOrder::Scanline,
+        );
+        let mut filled: Vec<Vec<Record>> = tiles.iter().map(|_| Vec::new()).collect();
+        let next = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel();
+        thread::scope(|scope| {
+            for _ in 0..settings.threads.min(tiles.len()) {
+                let (next, sender, tiles) = (&next, sender.clone(), &tiles);
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(&tile) = tiles.get(index) else {
+                        break;
+                    };
+                    let records = fill_tile(scene, settings, tile, index);
+                    if sender.send((index, records)).is_err() {
+                        break;
+                    }
+                });
+            }
+            // Filled tiles stop arriving once every thread has run out of them
+            drop(sender);
+            for (done, (index, records)) in receiver.into_iter().enumerate() {
+                filled[index] = records;
+                if settings.report_progress {
+                    eprint!(
+                        "\rFilled the irradiance cache over {} of {} tiles ({:.0}%)",
+                        done + 1,
+                        tiles.len(),
+                        100.0 * (done + 1) as f32 / tiles.len() as f32
+                    );
+                }
+            }
+        });
+        if settings.report_progress && !tiles.is_empty() {
+            eprintln!();
+        }
+
+        let mut cache = IrradianceCache::default();
+        for record in filled.into_iter().flatten() {
+            if cache.interpolate(record.point, record.normal).is_none() {
+                cache.insert(record);
+            }
+        }
+        cache
+    }
+
+    /// Indirect light arriving at the interaction, interpolated from the cached records nearby,
+    /// or gathered there with fewer rays if there are none close enough, as it isn't kept. The
+    /// path traced rays gathering it have the light they find after their first bounce clamped
+    /// to `max_indirect`, if given.
+    pub fn irradiance(
+        &self,
+        interaction: &Interaction,
+        scene: &Scene,
+        max_indirect: Option<f32>,
+        rng: &mut Rng,
+    ) -> Result<Vec3f, NonFinite> {
+        match self.interpolate(interaction.point, interaction.normal) {
+            Some(irradiance) => Ok(irradiance),
+            None => Ok(gather(interaction, scene, max_indirect, UNCACHED_RAYS, rng)?.irradiance),
+        }
+    }
+
+    /// Irradiance at `point` interpolated from the records whose estimated error there is within
+    /// the accuracy, weighted by how small it is.
+    fn interpolate(&self, point: Vec3f, normal: Vec3f) -> Option<Vec3f> {
+        let mut sum = Vec3f::new_uniform(0.0);
+        let mut total_weight = 0.0;
+        for &index in self.cells.get(&cell(point))? {
+            let record = &self.records[index];
+            let offset = point - record.point;
+            // Records in front of the point see light which it may not
+            if offset.dot_product(normal + record.normal) < -1e-3 {
+                continue;
+            }
+            let error = offset.magnitude() / record.radius
+                + (1.0 - normal.dot_product(record.normal)).max(0.0).sqrt() / ACCURACY;
+            if error >= 1.0 {
+                continue;
+            }
+            let weight = 1.0 / error.max(1e-3) - 1.0;
+            sum += record.irradiance * weight;
+            total_weight += weight;
+        }
+        (total_weight > 0.0).then(|| sum * (1.0 / total_weight))
+    }
+
+    fn insert(&mut self, record: Record) {
+        let index = self.records.len();
+        let (min, max) = (
+            cell(record.point - Vec3f::new_uniform(record.radius)),
+            cell(record.point + Vec3f::new_uniform(record.radius)),
+        );
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    self.cells.entry([x, y, z]).or_default().push(index);
+                }
+            }
+        }
+        self.records.push(record);
+    }
+}
+
+/// The records a cache filled over `tile` alone would hold, see [`IrradianceCache::fill`]. The
+/// tile is the one at `index` in the order they're merged, which its random numbers come from.
+fn fill_tile(scene: &Scene, settings: &RenderSettings, tile: Tile, index: usize) -> Vec<Record> {
+    let (width, height) = (settings.width, settings.height);
+    // Each tile has its own stream of random numbers, apart from every pixel's and the photons'
+    let mut rng = Rng::new(stream_seed(settings.seed, u64::MAX - 1 - index as u64));
+    let mut cache = IrradianceCache::default();
+    for (x, y) in tile.pixels() {
+        let Some(ray) = scene.camera.pixel_ray(x, y, width, height, &mut rng) else {
+            continue;
+        };
+        let _ = walk(
+            ray,
+            scene,
+            settings.max_depth,
+            &mut rng,
+            |interaction, rng| {
+                let (point, normal) = (interaction.point, interaction.normal);
+                if let Some(irradiance) = cache.interpolate(point, normal) {
+                    return Ok(irradiance);
+                }
+                let record = gather(
+                    interaction,
+                    scene,
+                    settings.max_indirect,
+                    IRRADIANCE_RAYS,
+                    rng,
+                )?;
+                let irradiance = record.irradiance;
+                cache.insert(record);
+                Ok(irradiance)
+            },
+        );
+    }
+    cache.records
+}
+
+/// Gather the indirect light arriving at the interaction with `rays` path traced rays, as a
+/// record to cache.
+fn gather(
+    interaction: &Interaction,
+    scene: &Scene,
+    max_indirect: Option<f32>,
+    rays: usize,
+    rng: &mut Rng,
+) -> Result<Record, NonFinite> {
+    let (point, normal) = (interaction.point, interaction.normal);
+    // Light arriving straight from the lights is left to direct sampling, as is the light from
+    // the sun and environment maps, while the sky's is approximated by its ambient light. Only a
+    // uniform background isn't lit by otherwise.
+    let mut gathered = Vec3f::new_uniform(0.0);
+    let mut inverse_distance = 0.0;
+    for _ in 0..rays {
+        let direction = normal + rng.unit_vector();
+        if direction.magnitude() <= 1e-6 {
+            continue;
+        }
+        let ray = interaction.spawn_ray(direction.normalized());
+        let Some(hit) = scene.intersect(&ray) else {
+            if let Background::Uniform(color) = scene.background {
+                gathered += color;
+            }
+            continue;
+        };
+        inverse_distance += 1.0 / hit.t;
+        let direct = tracer::emitted(&ray, &hit, scene, None)
+            * tracer::transmittance(scene, &Media::default(), &ray, hit.t);
+        gathered += path_tracer::trace(ray, scene, max_indirect, rng)? - direct;
+    }
+    // The rays are cosine weighted, which cancels out the cosine term but for pi
+    let irradiance = gathered * (PI / rays as f32);
+    // Records are used over a distance in proportion to how far away the surfaces around them
+    // are, as light changes fastest near other surfaces
+    let mean_distance = rays as f32 / inverse_distance;
+    let radius = (ACCURACY * mean_distance).clamp(MIN_SPACING, MAX_SPACING);
+    Ok(Record {
+        point,
+        normal,
+        irradiance,
+        radius,
+    })
+}
+
+/// Cell of the grid containing `point`.
+fn cell(point: Vec3f) -> [i32; 3] {
+    let index = |p: f32| (p / MAX_SPACING).floor() as i32;
+    [index(point.x), index(point.y), index(point.z)]
+}
+
+/// Light arriving along the camera ray, or what produced a NaN or infinite value along the way.
+/// Rays are followed off mirrors and through glass up to `max_depth` deep as the tracer does,
+/// and matte surfaces which are only lit directly are lit indirectly from the cache too.
+pub fn trace(
+    ray: Ray,
+    scene: &Scene,
+    cache: &IrradianceCache,
+    max_depth: usize,
+    max_indirect: Option<f32>,
+    rng: &mut Rng,
+) -> Result<Vec3f, NonFinite> {
+    walk(ray, scene, max_depth, rng, |interaction, rng| {
+        cache.irradiance(interaction, scene, max_indirect, rng)
+    })
+}
+
+/// Light arriving along the camera ray as [`trace`] finds it, with the indirect light arriving
+/// at matte surfaces found by `indirect`.
+fn walk(
+    ray: Ray,
+    scene: &Scene,
+    max_depth: usize,
+    rng: &mut Rng,
+    mut indirect: impl FnMut(&Interaction, &mut Rng) -> Result<Vec3f, NonFinite>,
+) -> Result<Vec3f, NonFinite> {
+    let mut radiance = Vec3f::new_uniform(0.0);
+    let mut pending = vec![(ray, Vec3f::new_uniform(1.0), 0, Media::default(), None)];
+    while let Some((ray, throughput, depth, media, sampled_lights)) = pending.pop() {
+        let hit = scene.intersect(&ray);
+        let walked = tracer::walk_medium(ray, hit, &media, sampled_lights, scene, rng);
+        radiance += walked.in_scattered * throughput;
+        let throughput = throughput * walked.throughput;
+        let (ray, sampled_lights) = (walked.ray, walked.sampled_lights);
+        let Some(hit) = walked.hit else {
+            radiance += tracer::escaped(&ray, scene, sampled_lights) * throughput;
+            continue;
+        };
+
+        let bounces = Bounces::whitted(depth, max_depth);
+        let shaded = tracer::shade(&ray, &hit, scene, bounces, sampled_lights, &media, rng);
+        shaded.check(&hit, scene, depth)?;
+        radiance += shaded.radiance * throughput;
+        if shaded.secondary.is_empty() {
+            let material = scene.material(scene.spheres[hit.sphere].material);
+            let interaction = Surface::new(&ray, &hit, scene, &media).interaction;
+            // eval is scaled by pi, relative to the BRDF
+            let reflectance = material.eval(&ray, &interaction, interaction.normal);
+            if reflectance.is_positive() {
+                let irradiance = indirect(&interaction, rng)?;
+                radiance += reflectance * irradiance * throughput * (1.0 / PI);
+            }
+        }
+        pending.extend(shaded.secondary.into_iter().rev().map(
+            |(ray, weight, media, sampled_lights)| {
+                (ray, throughput * weight, depth + 1, media, sampled_lights)
+            },
+        ));
+    }
+    Ok(radiance)
+}
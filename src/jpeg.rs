@@ -0,0 +1,372 @@
+//! Baseline JPEG encoding, for small previews. Colors are converted to YCbCr and each channel is
+//! compressed in 8 by 8 blocks, by a discrete cosine transform quantized with the example tables
+//! of the JPEG specification, scaled by the quality, and coded with its example Huffman tables.
+//! Chroma is kept at full resolution, as renders often have fine colored detail.
+
+use crate::{
+    framebuffer::Framebuffer,
+    output::{self, ImageWriter},
+    settings::RenderSettings,
+};
+use std::{
+    f32::consts::PI,
+    io::{self, Write},
+    time::Duration,
+};
+
+/// Width and height of the blocks compressed.
+const BLOCK: usize = 8;
+
+/// Order the coefficients of a block are stored in, from lowest to highest frequency.
+const ZIGZAG: [usize; 64] = [
+    0, 1, 8, 16, 9, 2, 3, 10, 17, 24, 32, 25, 18, 11, 4, 5, 12, 19, 26, 33, 40, 48, 41, 34, 27, 20,
+    13, 6, 7, 14, 21, 28, 35, 42, 49, 56, 57, 50, 43, 36, 29, 22, 15, 23, 30, 37, 44, 51, 58, 59,
+    52, 45, 38, 31, 39, 46, 53, 60, 61, 54, 47, 55, 62, 63,
+];
+
+/// The specification's quantization tables at a quality of 50, in rows.
+const LUMA_QUANTIZATION: [u16; 64] = [
+    16, 11, 10, 16, 24, 40, 51, 61, 12, 12, 14, 19, 26, 58, 60, 55, 14, 13, 16, 24, 40, 57, 69, 56,
+    14, 17, 22, 29, 51, 87, 80, 62, 18, 22, 37, 56, 68, 109, 103, 77, 24, 35, 55, 64, 81, 104, 113,
+    92, 49, 64, 78, 87, 103, 121, 120, 101, 72, 92, 95, 98, 112, 100, 103, 99,
+];
+const CHROMA_QUANTIZATION: [u16; 64] = [
+    17, 18, 24, 47, 99, 99, 99, 99, 18, 21, 26, 66, 99, 99, 99, 99, 24, 26, 56, 99, 99, 99, 99, 99,
+    47, 66, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+    99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99, 99,
+];
+
+/// The specification's Huffman tables, as the number of codes of each length from 1 to 16 bits
+/// and the values they code, shortest first.
+const LUMA_DC_LENGTHS: [u8; 16] = [0, 1, 5, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0];
+const CHROMA_DC_LENGTHS: [u8; 16] = [0, 3, 1, 1, 1, 1, 1, 1, 1, 1, 1, 0, 0, 0, 0, 0];
+const DC_VALUES: [u8; 12] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11];
+const LUMA_AC_LENGTHS: [u8; 16] = [0, 2, 1, 3, 3, 2, 4, 3, 5, 5, 4, 4, 0, 0, 1, 0x7d];
+const LUMA_AC_VALUES: [u8; 162] = [
+    0x01, 0x02, 0x03, 0x00, 0x04, 0x11, 0x05, 0x12, 0x21, 0x31, 0x41, 0x06, 0x13, 0x51, 0x61, 0x07,
+    0x22, 0x71, 0x14, 0x32, 0x81, 0x91, 0xa1, 0x08, 0x23, 0x42, 0xb1, 0xc1, 0x15, 0x52, 0xd1, 0xf0,
+    0x24, 0x33, 0x62, 0x72, 0x82, 0x09, 0x0a, 0x16, 0x17, 0x18, 0x19, 0x1a, 0x25, 0x26, 0x27, 0x28,
+    0x29, 0x2a, 0x34, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49,
+    0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68, 0x69,
+    0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x83, 0x84, 0x85, 0x86, 0x87, 0x88, 0x89,
+    0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5, 0xa6, 0xa7,
+    0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3, 0xc4, 0xc5,
+    0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda, 0xe1, 0xe2,
+    0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf1, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+const CHROMA_AC_LENGTHS: [u8; 16] = [0, 2, 1, 2, 4, 4, 3, 4, 7, 5, 4, 4, 0, 1, 2, 0x77];
+const CHROMA_AC_VALUES: [u8; 162] = [
+    0x00, 0x01, 0x02, 0x03, 0x11, 0x04, 0x05, 0x21, 0x31, 0x06, 0x12, 0x41, 0x51, 0x07, 0x61, 0x71,
+    0x13, 0x22, 0x32, 0x81, 0x08, 0x14, 0x42, 0x91, 0xa1, 0xb1, 0xc1, 0x09, 0x23, 0x33, 0x52, 0xf0,
+    0x15, 0x62, 0x72, 0xd1, 0x0a, 0x16, 0x24, 0x34, 0xe1, 0x25, 0xf1, 0x17, 0x18, 0x19, 0x1a, 0x26,
+    0x27, 0x28, 0x29, 0x2a, 0x35, 0x36, 0x37, 0x38, 0x39, 0x3a, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48,
+    0x49, 0x4a, 0x53, 0x54, 0x55, 0x56, 0x57, 0x58, 0x59, 0x5a, 0x63, 0x64, 0x65, 0x66, 0x67, 0x68,
+    0x69, 0x6a, 0x73, 0x74, 0x75, 0x76, 0x77, 0x78, 0x79, 0x7a, 0x82, 0x83, 0x84, 0x85, 0x86, 0x87,
+    0x88, 0x89, 0x8a, 0x92, 0x93, 0x94, 0x95, 0x96, 0x97, 0x98, 0x99, 0x9a, 0xa2, 0xa3, 0xa4, 0xa5,
+    0xa6, 0xa7, 0xa8, 0xa9, 0xaa, 0xb2, 0xb3, 0xb4, 0xb5, 0xb6, 0xb7, 0xb8, 0xb9, 0xba, 0xc2, 0xc3,
+    0xc4, 0xc5, 0xc6, 0xc7, 0xc8, 0xc9, 0xca, 0xd2, 0xd3, 0xd4, 0xd5, 0xd6, 0xd7, 0xd8, 0xd9, 0xda,
+    0xe2, 0xe3, 0xe4, 0xe5, 0xe6, 0xe7, 0xe8, 0xe9, 0xea, 0xf2, 0xf3, 0xf4, 0xf5, 0xf6, 0xf7, 0xf8,
+    0xf9, 0xfa,
+];
+
+/// Codes of a Huffman table, and their lengths, for each value.
+struct HuffmanTable {
+    codes: [(u16, u8); 256],
+}
+
+impl HuffmanTable {
+    /// The canonical codes for values with the numbers of codes of each length in `lengths`.
+    fn new(lengths: &[u8; 16], values: &[u8]) -> Self {
+        let mut codes = [(0, 0); 256];
+        let mut values = values.iter();
+        let mut code = 0_u16;
+        for (length, &count) in (1..).zip(lengths) {
+            for &value in values.by_ref().take(usize::from(count)) {
+                codes[usize::from(value)] = (code, length);
+                code += 1;
+            }
+            code <<= 1;
+        }
+        HuffmanTable { codes }
+    }
+}
+
+/// A channel's quantization and Huffman tables.
+struct Channel {
+    /// Quantization step of each coefficient, in rows.
+    quantization: [u16; 64],
+    dc: HuffmanTable,
+    ac: HuffmanTable,
+}
+
+/// Writes an image as a JPEG, in rows from the top, which can be written a few rows at a time as
+/// they're rendered. Rows are held back until there are enough to fill a row of blocks.
+pub struct JpegWriter<W: Write> {
+    writer: W,
+    width: usize,
+    /// Rows to the bottom of the image which haven't been written yet.
+    rows_left: usize,
+    luma: Channel,
+    chroma: Channel,
+    /// Rows of YCbCr colors not yet compressed.
+    pending: Vec<[f32; 3]>,
+    /// DC coefficient of the last block of each channel, which the next is coded relative to.
+    previous_dc: [i32; 3],
+    bits: BitWriter,
+}
+
+impl<W: Write> JpegWriter<W> {
+    /// Start writing an image `width` by `height` pixels to `writer`, at a `quality` from 1 to
+    /// 100.
+    pub fn new(mut writer: W, width: usize, height: usize, quality: u8) -> io::Result<Self> {
+        let (Ok(width16), Ok(height16)) = (u16::try_from(width), u16::try_from(height)) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "image too large for JPEG",
+            ));
+        };
+        // The IJG library's scaling of the tables by quality
+        let quality = u32::from(quality.clamp(1, 100));
+        let scale = if quality < 50 {
+            5000 / quality
+        } else {
+            200 - 2 * quality
+        };
+        let scaled = |table: [u16; 64]| {
+            table.map(|step| ((u32::from(step) * scale + 50) / 100).clamp(1, 255) as u16)
+        };
+        let luma = Channel {
+            quantization: scaled(LUMA_QUANTIZATION),
+            dc: HuffmanTable::new(&LUMA_DC_LENGTHS, &DC_VALUES),
+            ac: HuffmanTable::new(&LUMA_AC_LENGTHS, &LUMA_AC_VALUES),
+        };
+        let chroma = Channel {
+            quantization: scaled(CHROMA_QUANTIZATION),
+            dc: HuffmanTable::new(&CHROMA_DC_LENGTHS, &DC_VALUES),
+            ac: HuffmanTable::new(&CHROMA_AC_LENGTHS, &CHROMA_AC_VALUES),
+        };
+
+        writer.write_all(&[0xff, 0xd8])?;
+        // JFIF, with square pixels and no thumbnail
+        write_segment(&mut writer, 0xe0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0")?;
+        let mut tables = Vec::new();
+        for (id, channel) in [&luma, &chroma].into_iter().enumerate() {
+            tables.push(id as u8);
+            tables.extend(ZIGZAG.map(|index| channel.quantization[index] as u8));
+        }
+        write_segment(&mut writer, 0xdb, &tables)?;
+        // 8 bit precision, and three channels at full resolution with their quantization tables
+        let mut frame = vec![8];
+        frame.extend(height16.to_be_bytes());
+        frame.extend(width16.to_be_bytes());
+        frame.extend([3, 1, 0x11, 0, 2, 0x11, 1, 3, 0x11, 1]);
+        write_segment(&mut writer, 0xc0, &frame)?;
+        let mut huffman = Vec::new();
+        for (class_and_id, lengths, values) in [
+            (0x00, &LUMA_DC_LENGTHS, &DC_VALUES[..]),
+            (0x10, &LUMA_AC_LENGTHS, &LUMA_AC_VALUES[..]),
+            (0x01, &CHROMA_DC_LENGTHS, &DC_VALUES[..]),
+            (0x11, &CHROMA_AC_LENGTHS, &CHROMA_AC_VALUES[..]),
+        ] {
+            huffman.push(class_and_id);
+            huffman.extend(lengths);
+            huffman.extend(values);
+        }
+        write_segment(&mut writer, 0xc4, &huffman)?;
+        // Every channel in one scan, with its Huffman tables, and all coefficients in full
+        write_segment(&mut writer, 0xda, &[3, 1, 0x00, 2, 0x11, 3, 0x11, 0, 63, 0])?;
+
+        Ok(JpegWriter {
+            writer,
+            width,
+            rows_left: height,
+            luma,
+            chroma,
+            pending: Vec::with_capacity(BLOCK * width),
+            previous_dc: [0; 3],
+            bits: BitWriter::default(),
+        })
+    }
+
+    /// Compress the pending rows as a row of blocks, repeating the last pixel of each row and
+    /// the last row to fill the blocks which go past the edges of the image.
+    fn write_blocks(&mut self) -> io::Result<()> {
+        let rows = self.pending.len() / self.width;
+        for block_x in (0..self.width).step_by(BLOCK) {
+            for channel in 0..3 {
+                let mut block = [0.0; 64];
+                for (index, value) in block.iter_mut().enumerate() {
+                    let x = (block_x + index % BLOCK).min(self.width - 1);
+                    let y = (index / BLOCK).min(rows - 1);
+                    *value = self.pending[y * self.width + x][channel] - 128.0;
+                }
+                let tables = if channel == 0 {
+                    &self.luma
+                } else {
+                    &self.chroma
+                };
+                let coefficients = quantize(&forward_dct(&block), &tables.quantization);
+                self.previous_dc[channel] = encode_block(
+                    &mut self.bits,
+                    &coefficients,
+                    self.previous_dc[channel],
+                    tables,
+                );
+            }
+        }
+        self.pending.clear();
+        self.writer.write_all(&std::mem::take(&mut self.bits.bytes))
+    }
+}
+
+impl<W: Write> ImageWriter for JpegWriter<W> {
+    fn write_rows(
+        &mut self,
+        framebuffer: &Framebuffer,
+        settings: &RenderSettings,
+    ) -> io::Result<()> {
+        for y in 0..framebuffer.height {
+            for x in 0..framebuffer.width {
+                let [r, g, b] = output::encode(framebuffer.get(x, y), settings).map(f32::from);
+                self.pending.push([
+                    0.299 * r + 0.587 * g + 0.114 * b,
+                    -0.168_736 * r - 0.331_264 * g + 0.5 * b + 128.0,
+                    0.5 * r - 0.418_688 * g - 0.081_312 * b + 128.0,
+                ]);
+            }
+            self.rows_left -= 1;
+            if self.pending.len() == BLOCK * self.width || self.rows_left == 0 {
+                self.write_blocks()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, _render_time: Duration) -> io::Result<()> {
+        self.bits.pad();
+        self.writer.write_all(&self.bits.bytes)?;
+        self.writer.write_all(&[0xff, 0xd9])?;
+        self.writer.flush()
+    }
+}
+
+/// Write a marker segment, with its length.
+fn write_segment(writer: &mut impl Write, marker: u8, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&[0xff, marker])?;
+    writer.write_all(&(data.len() as u16 + 2).to_be_bytes())?;
+    writer.write_all(data)
+}
+
+/// The block's frequencies, in rows from lowest to highest, by the type II DCT.
+fn forward_dct(block: &[f32; 64]) -> [f32; 64] {
+    let basis = |frequency: usize, position: usize| {
+        let scale = if frequency == 0 { 0.5_f32.sqrt() } else { 1.0 };
+        scale * ((2 * position + 1) as f32 * frequency as f32 * PI / 16.0).cos() / 2.0
+    };
+    // Transform the rows, then the columns
+    let mut rows = [0.0; 64];
+    for y in 0..BLOCK {
+        for u in 0..BLOCK {
+            rows[y * BLOCK + u] = (0..BLOCK).map(|x| block[y * BLOCK + x] * basis(u, x)).sum();
+        }
+    }
+    let mut frequencies = [0.0; 64];
+    for v in 0..BLOCK {
+        for u in 0..BLOCK {
+            frequencies[v * BLOCK + u] =
+                (0..BLOCK).map(|y| rows[y * BLOCK + u] * basis(v, y)).sum();
+        }
+    }
+    frequencies
+}
+
+fn quantize(frequencies: &[f32; 64], quantization: &[u16; 64]) -> [i32; 64] {
+    std::array::from_fn(|index| {
+        (frequencies[index] / f32::from(quantization[index])).round() as i32
+    })
+}
+
+/// Huffman code a block's coefficients, the DC coefficient relative to the last block's, and
+/// return its DC coefficient.
+fn encode_block(
+    bits: &mut BitWriter,
+    coefficients: &[i32; 64],
+    previous_dc: i32,
+    tables: &Channel,
+) -> i32 {
+    let dc = coefficients[0];
+    let (size, value) = magnitude(dc - previous_dc);
+    bits.write_code(tables.dc.codes[usize::from(size)]);
+    bits.write_bits(value, size);
+
+    // The AC coefficients as runs of zeros followed by a value, or the end of the block
+    let mut zeros = 0;
+    for &index in &ZIGZAG[1..] {
+        let coefficient = coefficients[index];
+        if coefficient == 0 {
+            zeros += 1;
+            continue;
+        }
+        while zeros >= 16 {
+            bits.write_code(tables.ac.codes[0xf0]);
+            zeros -= 16;
+        }
+        let (size, value) = magnitude(coefficient);
+        bits.write_code(tables.ac.codes[(zeros << 4) | usize::from(size)]);
+        bits.write_bits(value, size);
+        zeros = 0;
+    }
+    if zeros > 0 {
+        bits.write_code(tables.ac.codes[0x00]);
+    }
+    dc
+}
+
+/// Number of bits in the magnitude of `value`, and the bits coding it, which for negative values
+/// are the complement of its magnitude.
+fn magnitude(value: i32) -> (u8, u16) {
+    let size = 32 - value.unsigned_abs().leading_zeros();
+    let bits = if value < 0 {
+        value - 1 + (1 << size)
+    } else {
+        value
+    };
+    (size as u8, bits as u16)
+}
+
+/// Entropy coded bits, packed from the most significant bit of each byte, with a zero byte
+/// stuffed after every 0xff byte so it isn't mistaken for a marker.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u16, count: u8) {
+        for bit in (0..count).rev() {
+            self.buffer = (self.buffer << 1) | u32::from((value >> bit) & 1);
+            self.count += 1;
+            if self.count == 8 {
+                self.bytes.push(self.buffer as u8);
+                if self.buffer as u8 == 0xff {
+                    self.bytes.push(0);
+                }
+                (self.buffer, self.count) = (0, 0);
+            }
+        }
+    }
+
+    fn write_code(&mut self, (code, length): (u16, u8)) {
+        self.write_bits(code, length);
+    }
+
+    /// Fill the last byte with one bits.
+    fn pad(&mut self) {
+        if self.count > 0 {
+            self.write_bits(0x7f, 8 - self.count);
+        }
+    }
+}
@@ -0,0 +1,60 @@
+//! A raytracer rendering scenes of spheres.
+
+pub mod animation;
+pub mod aov;
+pub mod bidirectional;
+pub mod blue_noise;
+pub mod camera;
+pub mod clouds;
+pub mod compare;
+#[cfg(feature = "consistency-check")]
+pub mod consistency;
+pub mod dataset;
+#[cfg(feature = "embree")]
+pub mod embree;
+pub mod environment;
+pub mod exr;
+pub mod filter;
+pub mod framebuffer;
+pub mod gltf;
+pub mod grid;
+pub mod hdr;
+pub mod integrator;
+pub mod irradiance_cache;
+pub mod jpeg;
+mod json;
+pub mod light;
+pub mod lut;
+pub mod material;
+pub mod metadata;
+pub mod metropolis;
+pub mod noise;
+pub mod occlusion;
+pub mod output;
+pub mod path_tracer;
+pub mod photon_map;
+pub mod png;
+pub mod render;
+pub mod rng;
+pub mod sampler;
+pub mod scene;
+pub mod settings;
+pub mod sky;
+pub mod sphere;
+pub mod texture;
+pub mod tiff;
+pub mod tile;
+pub mod tone_map;
+pub mod tracer;
+pub mod vec;
+pub mod wavefront;
+
+pub use sphere::Sphere;
+
+pub type Vec3f = vec::Vec3<f32>;
+
+#[derive(Copy, Clone)]
+pub struct Ray {
+    pub origin: Vec3f,
+    pub direction: Vec3f,
+}
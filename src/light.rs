@@ -0,0 +1,538 @@
+//! Lights, which surfaces sample directly rather than waiting for scattered rays to find them.
+
+use crate::{rng::Rng, Ray, Sphere, Vec3f};
+use std::f32::consts::PI;
+
+/// A source of light which is sampled directly when shading.
+pub trait Light: Send + Sync {
+    /// Pick a direction from `point` towards the light, or `None` if no light reaches it.
+    fn sample(&self, point: Vec3f, rng: &mut Rng) -> Option<LightSample>;
+
+    /// Probability density per unit solid angle of [`Light::sample`] choosing `direction` from
+    /// `point`. Zero for lights emitting from a single point, which rays never hit.
+    fn pdf(&self, point: Vec3f, direction: Vec3f) -> f32;
+
+    /// Rough luminance of the total power emitted, for choosing among many lights.
+    fn power(&self) -> f32;
+
+    /// Number of samples averaged at each shading point.
+    fn samples(&self) -> u32 {
+        1
+    }
+
+    /// Index of the sphere of the scene emitting the light, if any. The sphere isn't lit by its
+    /// own light, and doesn't shadow it.
+    fn sphere(&self) -> Option<usize> {
+        None
+    }
+
+    /// Pick a ray of light leaving the light, for following light out into the scene from its
+    /// source. `None` if the light can't emit rays.
+    fn emit(&self, _rng: &mut Rng) -> Option<Emission> {
+        None
+    }
+
+    /// Probability densities of [`Light::emit`] choosing a ray leaving `point` on the light in
+    /// `direction`: per unit area for the point, and per unit solid angle for the direction.
+    fn emit_pdf(&self, _point: Vec3f, _direction: Vec3f) -> (f32, f32) {
+        (0.0, 0.0)
+    }
+
+    /// Describe the problem with the light, if it can't be rendered.
+    fn validate(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// A direction from a shading point towards a light, chosen by [`Light::sample`].
+pub struct LightSample {
+    /// Unit vector towards the light.
+    pub direction: Vec3f,
+    /// Distance to the light along `direction`, within which anything hit casts a shadow.
+    pub distance: f32,
+    /// Radiance arriving along `direction`, or for lights emitting from a single point, the
+    /// irradiance on a surface facing the light.
+    pub radiance: Vec3f,
+    /// Probability density of the direction per unit solid angle, or 1 for lights emitting from
+    /// a single point.
+    pub pdf: f32,
+}
+
+/// A ray of light leaving a light, chosen by [`Light::emit`].
+pub struct Emission {
+    /// Ray leaving the light, from just above its surface.
+    pub ray: Ray,
+    /// Normal of the light's surface where the ray leaves, or `None` for lights emitting from a
+    /// single point.
+    pub normal: Option<Vec3f>,
+    /// Radiance emitted along the ray, or for lights emitting from a single point, the radiant
+    /// intensity.
+    pub radiance: Vec3f,
+    /// Probability density of the ray's origin per unit area, or 1 for lights emitting from a
+    /// single point.
+    pub pdf_position: f32,
+    /// Probability density of the ray's direction per unit solid angle.
+    pub pdf_direction: f32,
+}
+
+/// A direction around `normal`, chosen with probability density proportional to its cosine to
+/// the normal, with that density.
+fn cosine_direction(normal: Vec3f, rng: &mut Rng) -> Option<(Vec3f, f32)> {
+    let direction = normal + rng.unit_vector();
+    if direction.magnitude() <= 1e-6 {
+        return None;
+    }
+    let direction = direction.normalized();
+    Some((direction, direction.dot_product(normal).max(0.0) / PI))
+}
+
+/// Whether light of this color can be rendered, being finite and not negative.
+pub fn is_valid_emission(color: Vec3f) -> bool {
+    color.is_finite() && color.x >= 0.0 && color.y >= 0.0 && color.z >= 0.0
+}
+
+const INVALID_LIGHT: &str =
+    "lights must have a finite position and a finite, non-negative intensity";
+
+/// An emissive sphere of the scene, sampled uniformly over the cone of directions it covers.
+pub struct SphereLight {
+    /// Index of the sphere in the scene.
+    pub index: usize,
+    pub sphere: Sphere,
+    /// Radiance emitted from the surface.
+    pub emission: Vec3f,
+}
+
+impl SphereLight {
+    pub fn new(index: usize, sphere: Sphere, emission: Vec3f) -> Self {
+        SphereLight {
+            index,
+            sphere,
+            emission,
+        }
+    }
+}
+
+impl Light for SphereLight {
+    fn sample(&self, point: Vec3f, rng: &mut Rng) -> Option<LightSample> {
+        let (direction, pdf) = self.sphere.sample_towards(point, rng)?;
+        let ray = Ray {
+            origin: point,
+            direction,
+        };
+        // Directions at the very edge of the cone can just miss the sphere, where they touch its
+        // edge at the distance of the tangent
+        let distance = self.sphere.intersect(&ray).map_or_else(
+            || {
+                let sqr_distance = (self.sphere.center - point).sqr_magnitude();
+                (sqr_distance - self.sphere.sqr_radius).max(0.0).sqrt()
+            },
+            |(t0, _)| t0,
+        );
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.emission,
+            pdf,
+        })
+    }
+
+    fn pdf(&self, point: Vec3f, direction: Vec3f) -> f32 {
+        self.sphere.pdf_towards(point, direction)
+    }
+
+    fn power(&self) -> f32 {
+        self.emission.luminance() * PI * 4.0 * PI * self.sphere.sqr_radius
+    }
+
+    fn emit(&self, rng: &mut Rng) -> Option<Emission> {
+        let normal = rng.unit_vector();
+        let (direction, pdf_direction) = cosine_direction(normal, rng)?;
+        let bias = 1e-4_f32.max(self.sphere.radius * 1e-6);
+        Some(Emission {
+            ray: Ray {
+                origin: self.sphere.center + normal * (self.sphere.radius + bias),
+                direction,
+            },
+            normal: Some(normal),
+            radiance: self.emission,
+            pdf_position: 1.0 / (4.0 * PI * self.sphere.sqr_radius),
+            pdf_direction,
+        })
+    }
+
+    fn emit_pdf(&self, point: Vec3f, direction: Vec3f) -> (f32, f32) {
+        let normal = (point - self.sphere.center).normalized();
+        (
+            1.0 / (4.0 * PI * self.sphere.sqr_radius),
+            normal.dot_product(direction).max(0.0) / PI,
+        )
+    }
+
+    fn sphere(&self) -> Option<usize> {
+        Some(self.index)
+    }
+}
+
+/// Light emitted equally in all directions from a single point. Being infinitely small, it isn't
+/// seen by rays and casts perfectly sharp shadows.
+#[derive(Copy, Clone)]
+pub struct PointLight {
+    pub position: Vec3f,
+    /// Radiant intensity, the power emitted per unit solid angle.
+    pub intensity: Vec3f,
+    /// Power of the distance which the light falls off with. Physically 2, for the inverse
+    /// square law, while lower values reach further.
+    pub falloff: f32,
+}
+
+impl PointLight {
+    pub fn new(position: Vec3f, intensity: Vec3f) -> Self {
+        PointLight {
+            position,
+            intensity,
+            falloff: 2.0,
+        }
+    }
+
+    pub fn with_falloff(mut self, falloff: f32) -> Self {
+        self.falloff = falloff.max(0.0);
+        self
+    }
+
+    /// Irradiance arriving at a distance, facing the light.
+    pub fn irradiance(&self, distance: f32) -> Vec3f {
+        self.intensity * (1.0 / distance.powf(self.falloff))
+    }
+}
+
+impl Light for PointLight {
+    fn sample(&self, point: Vec3f, _rng: &mut Rng) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance = to_light.magnitude();
+        Some(LightSample {
+            direction: to_light * (1.0 / distance),
+            distance,
+            radiance: self.irradiance(distance),
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf(&self, _point: Vec3f, _direction: Vec3f) -> f32 {
+        0.0
+    }
+
+    fn power(&self) -> f32 {
+        self.intensity.luminance() * 4.0 * PI
+    }
+
+    /// Light followed out from the light falls off with the square of the distance, whatever
+    /// the light's falloff.
+    fn emit(&self, rng: &mut Rng) -> Option<Emission> {
+        Some(Emission {
+            ray: Ray {
+                origin: self.position,
+                direction: rng.unit_vector(),
+            },
+            normal: None,
+            radiance: self.intensity,
+            pdf_position: 1.0,
+            pdf_direction: 1.0 / (4.0 * PI),
+        })
+    }
+
+    fn emit_pdf(&self, _point: Vec3f, _direction: Vec3f) -> (f32, f32) {
+        (1.0, 1.0 / (4.0 * PI))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !self.position.is_finite() || !is_valid_emission(self.intensity) {
+            return Err(INVALID_LIGHT.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// A point light shining in a cone, like a torch or stage light.
+#[derive(Copy, Clone)]
+pub struct SpotLight {
+    pub position: Vec3f,
+    /// Unit vector along the center of the cone.
+    pub direction: Vec3f,
+    /// Radiant intensity along the center of the cone.
+    pub intensity: Vec3f,
+    /// Angle between the center and the edge of the cone, in radians.
+    pub cone_angle: f32,
+    /// Angle inside the edge of the cone over which the light fades out, in radians, softening
+    /// the edge of the pool of light.
+    pub penumbra: f32,
+}
+
+impl SpotLight {
+    pub fn new(position: Vec3f, direction: Vec3f, intensity: Vec3f, cone_angle: f32) -> Self {
+        SpotLight {
+            position,
+            direction: direction.normalized(),
+            intensity,
+            cone_angle,
+            penumbra: 0.0,
+        }
+    }
+
+    pub fn with_penumbra(mut self, penumbra: f32) -> Self {
+        self.penumbra = penumbra.clamp(0.0, self.cone_angle);
+        self
+    }
+
+    /// Irradiance arriving at `point`, facing the light.
+    pub fn irradiance(&self, point: Vec3f) -> Vec3f {
+        let to_point = point - self.position;
+        let sqr_distance = to_point.sqr_magnitude();
+        let cos = to_point.dot_product(self.direction) / sqr_distance.sqrt();
+        let (cos_outer, cos_inner) = (
+            self.cone_angle.cos(),
+            (self.cone_angle - self.penumbra).cos(),
+        );
+        let falloff = if cos >= cos_inner {
+            1.0
+        } else if cos <= cos_outer {
+            return Vec3f::new_uniform(0.0);
+        } else {
+            // Smoothstep across the penumbra
+            let t = (cos - cos_outer) / (cos_inner - cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        };
+        self.intensity * (falloff / sqr_distance)
+    }
+}
+
+impl Light for SpotLight {
+    fn sample(&self, point: Vec3f, _rng: &mut Rng) -> Option<LightSample> {
+        let to_light = self.position - point;
+        let distance = to_light.magnitude();
+        Some(LightSample {
+            direction: to_light * (1.0 / distance),
+            distance,
+            radiance: self.irradiance(point),
+            pdf: 1.0,
+        })
+    }
+
+    fn pdf(&self, _point: Vec3f, _direction: Vec3f) -> f32 {
+        0.0
+    }
+
+    /// As if the light were full strength out to the middle of the penumbra.
+    fn power(&self) -> f32 {
+        let cos = (self.cone_angle - 0.5 * self.penumbra).cos();
+        self.intensity.luminance() * 2.0 * PI * (1.0 - cos)
+    }
+
+    /// Directions are chosen uniformly within the cone.
+    fn emit(&self, rng: &mut Rng) -> Option<Emission> {
+        let cos_outer = self.cone_angle.cos();
+        let cos = 1.0 - rng.next_f32() * (1.0 - cos_outer);
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.next_f32();
+        let (tangent, bitangent) = self.direction.tangent_frame();
+        let direction =
+            self.direction * cos + tangent * (sin * phi.cos()) + bitangent * (sin * phi.sin());
+        Some(Emission {
+            ray: Ray {
+                origin: self.position,
+                direction,
+            },
+            normal: None,
+            // Irradiance at a distance of one is the intensity in that direction
+            radiance: self.irradiance(self.position + direction),
+            pdf_position: 1.0,
+            pdf_direction: 1.0 / (2.0 * PI * (1.0 - cos_outer)),
+        })
+    }
+
+    fn emit_pdf(&self, _point: Vec3f, direction: Vec3f) -> (f32, f32) {
+        let cos_outer = self.cone_angle.cos();
+        if direction.dot_product(self.direction) < cos_outer {
+            return (1.0, 0.0);
+        }
+        (1.0, 1.0 / (2.0 * PI * (1.0 - cos_outer)))
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !self.direction.is_finite() {
+            return Err("spot lights must have a direction".to_string());
+        }
+        if !self.position.is_finite() || !is_valid_emission(self.intensity) {
+            return Err(INVALID_LIGHT.to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Shape of an [`AreaLight`].
+#[derive(Copy, Clone)]
+pub enum AreaShape {
+    /// Parallelogram spanned by two edges from one corner, which is usually a rectangle. Light is
+    /// emitted to the side of their cross product.
+    Rectangle { edge_u: Vec3f, edge_v: Vec3f },
+    /// Disk facing the side its normal points to.
+    Disk { normal: Vec3f, radius: f32 },
+}
+
+/// Flat light emitting from one side, such as a window or a softbox. It's sampled with several
+/// shadow rays at each point, for soft shadows, and isn't seen by rays itself.
+#[derive(Copy, Clone)]
+pub struct AreaLight {
+    /// Corner of a rectangle, or center of a disk.
+    pub origin: Vec3f,
+    pub shape: AreaShape,
+    /// Radiance emitted from the surface.
+    pub emission: Vec3f,
+    /// Number of shadow rays sampling the light at each shading point.
+    pub samples: u32,
+}
+
+impl AreaLight {
+    pub fn rectangle(corner: Vec3f, edge_u: Vec3f, edge_v: Vec3f, emission: Vec3f) -> Self {
+        AreaLight {
+            origin: corner,
+            shape: AreaShape::Rectangle { edge_u, edge_v },
+            emission,
+            samples: 4,
+        }
+    }
+
+    pub fn disk(center: Vec3f, normal: Vec3f, radius: f32, emission: Vec3f) -> Self {
+        AreaLight {
+            origin: center,
+            shape: AreaShape::Disk {
+                normal: normal.normalized(),
+                radius,
+            },
+            emission,
+            samples: 4,
+        }
+    }
+
+    pub fn with_samples(mut self, samples: u32) -> Self {
+        self.samples = samples.max(1);
+        self
+    }
+
+    pub fn area(&self) -> f32 {
+        match self.shape {
+            AreaShape::Rectangle { edge_u, edge_v } => edge_u.cross_product(edge_v).magnitude(),
+            AreaShape::Disk { radius, .. } => PI * radius * radius,
+        }
+    }
+
+    pub fn normal(&self) -> Vec3f {
+        match self.shape {
+            AreaShape::Rectangle { edge_u, edge_v } => edge_u.cross_product(edge_v).normalized(),
+            AreaShape::Disk { normal, .. } => normal,
+        }
+    }
+
+    /// A point on the light, chosen uniformly over its area.
+    pub fn sample_point(&self, rng: &mut Rng) -> Vec3f {
+        match self.shape {
+            AreaShape::Rectangle { edge_u, edge_v } => {
+                self.origin + edge_u * rng.next_f32() + edge_v * rng.next_f32()
+            }
+            AreaShape::Disk { normal, radius } => {
+                let r = radius * rng.next_f32().sqrt();
+                let phi = 2.0 * PI * rng.next_f32();
+                let (tangent, bitangent) = normal.tangent_frame();
+                self.origin + tangent * (r * phi.cos()) + bitangent * (r * phi.sin())
+            }
+        }
+    }
+}
+
+impl Light for AreaLight {
+    fn sample(&self, point: Vec3f, rng: &mut Rng) -> Option<LightSample> {
+        let to_light = self.sample_point(rng) - point;
+        let sqr_distance = to_light.sqr_magnitude();
+        let distance = sqr_distance.sqrt();
+        let direction = to_light * (1.0 / distance);
+        let cos_light = -self.normal().dot_product(direction);
+        if cos_light <= 0.0 {
+            return None;
+        }
+        Some(LightSample {
+            direction,
+            distance,
+            radiance: self.emission,
+            // Converting from density over the area to over solid angle
+            pdf: sqr_distance / (cos_light * self.area()),
+        })
+    }
+
+    fn pdf(&self, point: Vec3f, direction: Vec3f) -> f32 {
+        let normal = self.normal();
+        let cos_light = -normal.dot_product(direction);
+        if cos_light <= 0.0 {
+            return 0.0;
+        }
+        let distance = (point - self.origin).dot_product(normal) / cos_light;
+        if distance <= 0.0 {
+            return 0.0;
+        }
+        let offset = point + direction * distance - self.origin;
+        let inside = match self.shape {
+            AreaShape::Rectangle { edge_u, edge_v } => {
+                // Coordinates of the hit along each edge
+                let cross = edge_u.cross_product(edge_v);
+                let scale = 1.0 / cross.sqr_magnitude();
+                let u = offset.cross_product(edge_v).dot_product(cross) * scale;
+                let v = edge_u.cross_product(offset).dot_product(cross) * scale;
+                (0.0..=1.0).contains(&u) && (0.0..=1.0).contains(&v)
+            }
+            AreaShape::Disk { radius, .. } => offset.sqr_magnitude() <= radius * radius,
+        };
+        if !inside {
+            return 0.0;
+        }
+        distance * distance / (cos_light * self.area())
+    }
+
+    fn power(&self) -> f32 {
+        self.emission.luminance() * PI * self.area()
+    }
+
+    fn emit(&self, rng: &mut Rng) -> Option<Emission> {
+        let normal = self.normal();
+        let (direction, pdf_direction) = cosine_direction(normal, rng)?;
+        Some(Emission {
+            ray: Ray {
+                origin: self.sample_point(rng) + normal * 1e-4,
+                direction,
+            },
+            normal: Some(normal),
+            radiance: self.emission,
+            pdf_position: 1.0 / self.area(),
+            pdf_direction,
+        })
+    }
+
+    fn emit_pdf(&self, _point: Vec3f, direction: Vec3f) -> (f32, f32) {
+        (
+            1.0 / self.area(),
+            self.normal().dot_product(direction).max(0.0) / PI,
+        )
+    }
+
+    fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if !(self.area() > 0.0 && self.normal().is_finite()) {
+            return Err("area lights must have a positive area".to_string());
+        }
+        if !self.origin.is_finite() || !is_valid_emission(self.emission) {
+            return Err(INVALID_LIGHT.to_string());
+        }
+        Ok(())
+    }
+}
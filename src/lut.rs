@@ -0,0 +1,287 @@
+//! Color lookup tables in the `.cube` format, for matching the look of a film stock or camera.
+
+use crate::Vec3f;
+use std::path::Path;
+
+/// A 1D or 3D lookup table which maps colors to new colors.
+pub enum Lut {
+    /// A curve for each channel, `size` entries long.
+    OneD {
+        size: usize,
+        domain: (Vec3f, Vec3f),
+        table: Vec<Vec3f>,
+    },
+    /// A `size` cubed lattice of colors, with red changing fastest.
+    ThreeD {
+        size: usize,
+        domain: (Vec3f, Vec3f),
+        table: Vec<Vec3f>,
+    },
+}
+
+impl Lut {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| format!("Failed to read LUT `{}`: {err}", path.display()))?;
+        Lut::parse(&text).map_err(|err| format!("Invalid LUT `{}`: {err}", path.display()))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut size_1d = None;
+        let mut size_3d = None;
+        let mut domain = (Vec3f::new_uniform(0.0), Vec3f::new_uniform(1.0));
+        let mut table = Vec::new();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: &str| format!("line {}: {message}", number + 1);
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or_default();
+            let size = |words: std::str::SplitWhitespace| -> Result<usize, String> {
+                words
+                    .last()
+                    .and_then(|size| size.parse().ok())
+                    .filter(|&size| size >= 2)
+                    .ok_or_else(|| error("expected a size of at least 2"))
+            };
+            match keyword {
+                "TITLE" => {}
+                "LUT_1D_SIZE" => size_1d = Some(size(words)?),
+                "LUT_3D_SIZE" => size_3d = Some(size(words)?),
+                "DOMAIN_MIN" => domain.0 = parse_color(line.split_whitespace().skip(1), &error)?,
+                "DOMAIN_MAX" => domain.1 = parse_color(line.split_whitespace().skip(1), &error)?,
+                // The older form of the domain, which is the same on every channel
+                "LUT_1D_INPUT_RANGE" | "LUT_3D_INPUT_RANGE" => domain = parse_range(words, &error)?,
+                _ => table.push(parse_color(line.split_whitespace(), &error)?),
+            }
+        }
+
+        let (lo, hi) = domain;
+        if !(lo.x < hi.x && lo.y < hi.y && lo.z < hi.z) {
+            return Err("the domain's minimum must be below its maximum".to_string());
+        }
+        let (expected, lut) = match (size_1d, size_3d) {
+            (Some(size), None) => (
+                size,
+                Lut::OneD {
+                    size,
+                    domain,
+                    table,
+                },
+            ),
+            (None, Some(size)) => (
+                size * size * size,
+                Lut::ThreeD {
+                    size,
+                    domain,
+                    table,
+                },
+            ),
+            (None, None) => return Err("missing LUT_1D_SIZE or LUT_3D_SIZE".to_string()),
+            (Some(_), Some(_)) => {
+                return Err("1D and 3D tables in one file are not supported".to_string())
+            }
+        };
+        let (Lut::OneD { table, .. } | Lut::ThreeD { table, .. }) = &lut;
+        if table.len() != expected {
+            return Err(format!(
+                "expected {expected} table entries, found {}",
+                table.len()
+            ));
+        }
+        Ok(lut)
+    }
+
+    /// Map a color through the table, interpolating between entries. Colors outside the domain
+    /// are clamped to it.
+    pub fn apply(&self, color: Vec3f) -> Vec3f {
+        match self {
+            Lut::OneD {
+                size,
+                domain,
+                table,
+            } => {
+                let position = lattice_position(color, *domain, *size);
+                let channel = |position: f32, get: fn(&Vec3f) -> f32| {
+                    let (i, t) = split(position, *size);
+                    get(&table[i]) * (1.0 - t) + get(&table[i + 1]) * t
+                };
+                Vec3f::new(
+                    channel(position.x, |c| c.x),
+                    channel(position.y, |c| c.y),
+                    channel(position.z, |c| c.z),
+                )
+            }
+            Lut::ThreeD {
+                size,
+                domain,
+                table,
+            } => {
+                let position = lattice_position(color, *domain, *size);
+                let (r, tr) = split(position.x, *size);
+                let (g, tg) = split(position.y, *size);
+                let (b, tb) = split(position.z, *size);
+                let at = |r: usize, g: usize, b: usize| table[r + (g + b * size) * size];
+                // Trilinear interpolation between the eight surrounding entries
+                let lerp = |a: Vec3f, b: Vec3f, t: f32| a * (1.0 - t) + b * t;
+                let plane = |b: usize| {
+                    lerp(
+                        lerp(at(r, g, b), at(r + 1, g, b), tr),
+                        lerp(at(r, g + 1, b), at(r + 1, g + 1, b), tr),
+                        tg,
+                    )
+                };
+                lerp(plane(b), plane(b + 1), tb)
+            }
+        }
+    }
+}
+
+fn parse_color<'a>(
+    mut words: impl Iterator<Item = &'a str>,
+    error: &impl Fn(&str) -> String,
+) -> Result<Vec3f, String> {
+    let mut channel = || {
+        words
+            .next()
+            .and_then(|word| word.parse::<f32>().ok())
+            .ok_or_else(|| error("expected three numbers"))
+    };
+    let color = Vec3f::new(channel()?, channel()?, channel()?);
+    match words.next() {
+        Some(_) => Err(error("expected three numbers")),
+        None => Ok(color),
+    }
+}
+
+/// Parse the minimum and maximum of an input range, as a domain.
+fn parse_range<'a>(
+    mut words: impl Iterator<Item = &'a str>,
+    error: &impl Fn(&str) -> String,
+) -> Result<(Vec3f, Vec3f), String> {
+    let mut bound = || {
+        words
+            .next()
+            .and_then(|word| word.parse::<f32>().ok())
+            .ok_or_else(|| error("expected two numbers"))
+    };
+    let (lo, hi) = (bound()?, bound()?);
+    match words.next() {
+        Some(_) => Err(error("expected two numbers")),
+        None => Ok((Vec3f::new_uniform(lo), Vec3f::new_uniform(hi))),
+    }
+}
+
+/// Position of the color within a lattice of `size` entries per channel, from 0 to `size - 1`.
+fn lattice_position(color: Vec3f, (lo, hi): (Vec3f, Vec3f), size: usize) -> Vec3f {
+    let scale = (size - 1) as f32;
+    let channel = |c: f32, lo: f32, hi: f32| ((c - lo) / (hi - lo)).clamp(0.0, 1.0) * scale;
+    Vec3f::new(
+        channel(color.x, lo.x, hi.x),
+        channel(color.y, lo.y, hi.y),
+        channel(color.z, lo.z, hi.z),
+    )
+}
+
+/// Split a lattice position into the index of the entry below it and the fraction towards the
+/// next, always leaving a next entry.
+fn split(position: f32, size: usize) -> (usize, f32) {
+    let index = (position as usize).min(size - 2);
+    (index, position - index as f32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Vec3f, b: Vec3f) {
+        let show = |c: Vec3f| format!("({}, {}, {})", c.x, c.y, c.z);
+        assert!((a - b).magnitude() < 1e-5, "{} is not {}", show(a), show(b));
+    }
+
+    #[test]
+    fn parses_a_1d_table() {
+        let lut = Lut::parse(
+            "# An inverting curve\nTITLE \"invert\"\nLUT_1D_SIZE 3\n\n1 1 1\n0.5 0.5 0.5\n0 0 0\n",
+        )
+        .unwrap();
+        assert!(matches!(lut, Lut::OneD { size: 3, .. }));
+        assert_close(
+            lut.apply(Vec3f::new(0.0, 0.25, 1.0)),
+            Vec3f::new(1.0, 0.75, 0.0),
+        );
+    }
+
+    #[test]
+    fn parses_a_3d_table_with_red_changing_fastest() {
+        let mut text = "LUT_3D_SIZE 2\n".to_string();
+        for b in 0..2 {
+            for g in 0..2 {
+                for r in 0..2 {
+                    // Swap red and blue
+                    text += &format!("{b} {g} {r}\n");
+                }
+            }
+        }
+        let lut = Lut::parse(&text).unwrap();
+        assert_close(
+            lut.apply(Vec3f::new(1.0, 0.0, 0.0)),
+            Vec3f::new(0.0, 0.0, 1.0),
+        );
+        assert_close(
+            lut.apply(Vec3f::new(0.2, 0.4, 0.6)),
+            Vec3f::new(0.6, 0.4, 0.2),
+        );
+    }
+
+    #[test]
+    fn parses_the_domain() {
+        let lut = Lut::parse("LUT_1D_SIZE 2\nDOMAIN_MIN 0 0 0\nDOMAIN_MAX 2 4 8\n0 0 0\n1 1 1\n");
+        assert_close(
+            lut.unwrap().apply(Vec3f::new(1.0, 1.0, 1.0)),
+            Vec3f::new(0.5, 0.25, 0.125),
+        );
+
+        let lut = Lut::parse("LUT_3D_INPUT_RANGE -1 1\nLUT_1D_SIZE 2\n0 0 0\n1 1 1\n");
+        assert_close(
+            lut.unwrap().apply(Vec3f::new(0.0, -1.0, 2.0)),
+            Vec3f::new(0.5, 0.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_tables() {
+        let error = |text: &str| Lut::parse(text).err().unwrap();
+        assert_eq!(
+            error("0 0 0\n1 1 1\n"),
+            "missing LUT_1D_SIZE or LUT_3D_SIZE"
+        );
+        assert_eq!(
+            error("LUT_1D_SIZE 1\n0 0 0\n"),
+            "line 1: expected a size of at least 2"
+        );
+        assert_eq!(
+            error("LUT_1D_SIZE 2\n0 0\n1 1 1\n"),
+            "line 2: expected three numbers"
+        );
+        assert_eq!(
+            error("LUT_1D_SIZE 2\nLUT_1D_INPUT_RANGE 0\n0 0 0\n1 1 1\n"),
+            "line 2: expected two numbers"
+        );
+        assert_eq!(
+            error("LUT_1D_SIZE 3\n0 0 0\n1 1 1\n"),
+            "expected 3 table entries, found 2"
+        );
+        assert_eq!(
+            error("LUT_3D_SIZE 2\nLUT_1D_SIZE 2\n"),
+            "1D and 3D tables in one file are not supported"
+        );
+        assert_eq!(
+            error("LUT_1D_INPUT_RANGE 1 0\nLUT_1D_SIZE 2\n0 0 0\n1 1 1\n"),
+            "the domain's minimum must be below its maximum"
+        );
+    }
+}
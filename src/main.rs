@@ -1,200 +1,570 @@
 use std::{
-    f32::consts::PI,
-    fs::File,
-    io::{BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    time::Duration,
 };
 
-mod sphere;
-mod vec;
+#[cfg(feature = "consistency-check")]
+use rayox::consistency;
+#[cfg(feature = "embree")]
+use rayox::embree;
+use rayox::{
+    animation::Animation,
+    aov::Aov,
+    camera::{FisheyeMapping, Projection, Stereo, StereoLayout, ThinLensCamera, DEFAULT_FOV},
+    compare, dataset,
+    environment::EnvironmentMap,
+    filter::Filter,
+    gltf,
+    integrator::IntegratorKind,
+    lut,
+    material::Medium,
+    output, render,
+    sampler::SamplerKind,
+    scene::{Background, Scene},
+    settings::RenderSettings,
+    texture::ImageTexture,
+    tile::TileOrder,
+    tone_map::ToneMap,
+    Vec3f,
+};
 
-type Vec3f = vec::Vec3<f32>;
+/// How often a checkpoint is saved with `--checkpoint`, unless `--checkpoint-interval` says.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
 
-struct Sphere {
-    center: Vec3f,
-    radius: f32,
-    sqr_radius: f32,
-    surface_color: Vec3f,
-    emission: Vec3f,
-    transparency: f32,
-    reflection: f32,
-}
+/// Frames per second of a sequence rendered with `--frames`, unless `--fps` says.
+const FPS: f32 = 24.0;
 
-struct Ray {
-    origin: Vec3f,
-    direction: Vec3f,
-}
+/// Color of the light scattered by the fog added with `--fog`.
+const FOG_COLOR: Vec3f = Vec3f {
+    x: 0.9,
+    y: 0.9,
+    z: 0.9,
+};
+
+fn main() {
+    let mut args = std::env::args().skip(1).peekable();
+    if args.next_if(|arg| arg == "compare").is_some() {
+        let (Some(reference), Some(test), None) = (args.next(), args.next(), args.next()) else {
+            exit_with_usage("compare requires a reference image and a test image");
+        };
+        compare_images(Path::new(&reference), Path::new(&test));
+        return;
+    }
+    let dataset = args.next_if(|arg| arg == "dataset").is_some();
+    let mut dataset_dir = PathBuf::from("dataset");
+    let mut dataset_count = 100;
+    let mut scene_name = String::from("classic");
+    let mut export_path = None;
+    let mut output_path = PathBuf::from("raytraced.ppm");
+    let mut environment_path = None;
+    let mut fog = None;
+    let mut projection = None;
+    let mut fov = None;
+    let mut stereo = None;
+    let mut checkpoint = None;
+    let mut checkpoint_interval = CHECKPOINT_INTERVAL;
+    let mut tiled = false;
+    let mut resume = false;
+    let mut frames = None;
+    let mut fps = FPS;
+    let mut ffmpeg = None;
+    let mut settings = RenderSettings::default();
+    let mut integrators = Vec::new();
+    #[cfg(feature = "consistency-check")]
+    let mut check_primitives = false;
+    #[cfg(feature = "embree")]
+    let mut use_embree = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scene" => match args.next() {
+                Some(name) => scene_name = name,
+                None => exit_with_usage("--scene requires a name"),
+            },
+            "--out" if dataset => match args.next() {
+                Some(dir) => dataset_dir = PathBuf::from(dir),
+                None => exit_with_usage("--out requires a directory"),
+            },
+            "--count" if dataset => match args.next().and_then(|count| count.parse().ok()) {
+                Some(count) => dataset_count = count,
+                None => exit_with_usage("--count requires a number"),
+            },
+            "--seed" => match args.next().and_then(|seed| seed.parse().ok()) {
+                Some(seed) => settings.seed = seed,
+                None => exit_with_usage("--seed requires a number"),
+            },
+            "--export" => match args.next() {
+                Some(path) => export_path = Some(PathBuf::from(path)),
+                None => exit_with_usage("--export requires a .gltf file"),
+            },
+            "--environment" => match args.next() {
+                Some(path) => environment_path = Some(PathBuf::from(path)),
+                None => exit_with_usage("--environment requires an image file"),
+            },
+            "--fog" => match args.next().and_then(|density| density.parse().ok()) {
+                Some(density) if density > 0.0 && f32::is_finite(density) => fog = Some(density),
+                _ => exit_with_usage("--fog requires a positive density"),
+            },
+            "--resolution" => match args.next().as_deref().and_then(parse_resolution) {
+                Some((width, height)) => {
+                    settings.width = width;
+                    settings.height = height;
+                }
+                None => exit_with_usage("--resolution requires a size such as 1920x1080"),
+            },
+            "--fov" => match args.next().and_then(|fov| fov.parse().ok()) {
+                Some(degrees) if degrees > 0.0 && degrees < 180.0 => fov = Some(degrees),
+                _ => exit_with_usage("--fov requires an angle in degrees, below 180"),
+            },
+            "--orthographic" => match args.next().and_then(|height| height.parse().ok()) {
+                Some(height) if height > 0.0 => {
+                    projection = Some(Projection::Orthographic { height })
+                }
+                _ => exit_with_usage("--orthographic requires a positive view height"),
+            },
+            "--fisheye" | "--equisolid" => match args.next().and_then(|fov| fov.parse().ok()) {
+                Some(fov) if fov > 0.0 && fov <= 360.0 => {
+                    let mapping = if arg == "--fisheye" {
+                        FisheyeMapping::Equidistant
+                    } else {
+                        FisheyeMapping::Equisolid
+                    };
+                    projection = Some(Projection::Fisheye { fov, mapping })
+                }
+                _ => exit_with_usage(&format!("{arg} requires a field of view in degrees")),
+            },
+            "--panorama" => projection = Some(Projection::Equirectangular),
+            "--stereo" | "--over-under" => {
+                match args.next().and_then(|distance| distance.parse().ok()) {
+                    Some(interocular) if interocular > 0.0 => {
+                        let layout = if arg == "--stereo" {
+                            StereoLayout::SideBySide
+                        } else {
+                            StereoLayout::OverUnder
+                        };
+                        stereo = Some(Stereo {
+                            interocular,
+                            convergence: None,
+                            layout,
+                        })
+                    }
+                    _ => exit_with_usage(&format!("{arg} requires an interocular distance")),
+                }
+            }
+            "--convergence" => match (stereo.as_mut(), args.next().and_then(|d| d.parse().ok())) {
+                (Some(stereo), Some(distance)) if distance > 0.0 => {
+                    stereo.convergence = Some(distance)
+                }
+                _ => exit_with_usage("--convergence requires a distance, after --stereo"),
+            },
+            "--wavefront" => settings.wavefront = true,
+            "--integrator" => match args.next().as_deref().and_then(IntegratorKind::from_name) {
+                Some(integrator) => integrators.push(integrator),
+                None => exit_with_usage(
+                    "--integrator requires whitted, direct, path, bidirectional or \
+                     irradiance-cache",
+                ),
+            },
+            "--samples" => match args.next().and_then(|count| count.parse().ok()) {
+                Some(count) if count > 0 => settings.samples_per_pixel = count,
+                _ => exit_with_usage("--samples requires a number of samples per pixel"),
+            },
+            "--noise-threshold" => match args.next().and_then(|t| t.parse().ok()) {
+                Some(threshold) if threshold > 0.0 => settings.noise_threshold = Some(threshold),
+                _ => exit_with_usage("--noise-threshold requires a positive fraction"),
+            },
+            "--filter" => match args.next().as_deref().and_then(Filter::from_name) {
+                Some(filter) => settings.filter = filter,
+                None => exit_with_usage("--filter requires box, tent, gaussian or mitchell"),
+            },
+            "--sampler" => match args.next().as_deref() {
+                Some("independent") => settings.sampler = SamplerKind::Independent,
+                Some("stratified") => settings.sampler = SamplerKind::Stratified,
+                Some("halton") => settings.sampler = SamplerKind::Halton,
+                Some("blue-noise") => settings.sampler = SamplerKind::BlueNoise,
+                _ => exit_with_usage(
+                    "--sampler requires independent, stratified, halton or blue-noise",
+                ),
+            },
+            "--max-depth" => match args.next().and_then(|depth| depth.parse().ok()) {
+                Some(depth) => settings.max_depth = depth,
+                None => exit_with_usage("--max-depth requires a number of bounces"),
+            },
+            "--path-trace" => integrators.push(IntegratorKind::PathTracing),
+            "--clamp-indirect" => match args.next().and_then(|max| max.parse().ok()) {
+                Some(max) if max > 0.0 => settings.max_indirect = Some(max),
+                _ => exit_with_usage("--clamp-indirect requires a positive brightness"),
+            },
+            "--bidirectional" => integrators.push(IntegratorKind::Bidirectional),
+            "--photon-map" => match args.next().and_then(|count| count.parse().ok()) {
+                Some(photons) if photons > 0 => {
+                    integrators.push(IntegratorKind::PhotonMapping { photons })
+                }
+                _ => exit_with_usage("--photon-map requires a number of photons"),
+            },
+            "--metropolis" => match args.next().and_then(|count| count.parse().ok()) {
+                Some(mutations) if mutations > 0 => {
+                    integrators.push(IntegratorKind::Metropolis { mutations })
+                }
+                _ => exit_with_usage("--metropolis requires a number of mutations per pixel"),
+            },
+            "--ambient-occlusion" => match args.next().and_then(|d| d.parse().ok()) {
+                Some(distance) if distance > 0.0 => {
+                    integrators.push(IntegratorKind::AmbientOcclusion { distance })
+                }
+                _ => exit_with_usage("--ambient-occlusion requires a positive distance"),
+            },
+            "--gamma" => match args.next().and_then(|gamma| gamma.parse().ok()) {
+                Some(gamma) if gamma > 0.0 => settings.gamma = Some(gamma),
+                _ => exit_with_usage("--gamma requires a positive number"),
+            },
+            "--exposure" => match args.next().and_then(|stops| stops.parse().ok()) {
+                Some(stops) if f32::is_finite(stops) => settings.exposure = stops,
+                _ => exit_with_usage("--exposure requires a number of stops"),
+            },
+            "--tone-map" => match args.next().as_deref().and_then(ToneMap::from_name) {
+                Some(tone_map) => settings.tone_map = tone_map,
+                None => exit_with_usage("--tone-map requires clamp, reinhard or aces"),
+            },
+            "--output" => match args.next().map(PathBuf::from) {
+                Some(path) if output::Format::from_path(&path).is_some() => output_path = path,
+                _ => {
+                    exit_with_usage("--output requires a .ppm, .png, .tif, .jpg, .exr or .hdr file")
+                }
+            },
+            "--aov" => match args.next().as_deref().and_then(Aov::from_name) {
+                Some(aov) if !settings.aovs.contains(&aov) => settings.aovs.push(aov),
+                Some(_) => {}
+                None => exit_with_usage(
+                    "--aov requires normal, depth, albedo, direct, indirect, alpha, object-id or \
+                     material-id",
+                ),
+            },
+            "--alpha" => settings.alpha = true,
+            "--half" => settings.half_float = true,
+            "--16-bit" => settings.sixteen_bit = true,
+            "--quality" => match args.next().and_then(|quality| quality.parse().ok()) {
+                Some(quality @ 1..=100) => settings.quality = quality,
+                _ => exit_with_usage("--quality requires a number from 1 to 100"),
+            },
+            "--debug-nan" => settings.debug_non_finite = true,
+            "--tile-size" => match args.next().and_then(|size| size.parse().ok()) {
+                Some(size) if size > 0 => {
+                    settings.tile_size = size;
+                    tiled = true;
+                }
+                _ => exit_with_usage("--tile-size requires a number of pixels"),
+            },
+            "--tile-order" => match args.next().as_deref().and_then(TileOrder::from_name) {
+                Some(order) => {
+                    settings.tile_order = order;
+                    tiled = true;
+                }
+                None => exit_with_usage("--tile-order requires scanline or spiral"),
+            },
+            "--threads" => match args.next().and_then(|threads| threads.parse().ok()) {
+                Some(threads) if threads > 0 => {
+                    settings.threads = threads;
+                    tiled = true;
+                }
+                _ => exit_with_usage("--threads requires a positive number of threads"),
+            },
+            "--progress" => settings.report_progress = true,
+            "--checkpoint" => match args.next() {
+                Some(path) => checkpoint = Some(PathBuf::from(path)),
+                None => exit_with_usage("--checkpoint requires a file"),
+            },
+            "--checkpoint-interval" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(seconds) if seconds > 0.0 => match Duration::try_from_secs_f64(seconds) {
+                    Ok(interval) => checkpoint_interval = interval,
+                    Err(_) => exit_with_usage("--checkpoint-interval is too long"),
+                },
+                _ => exit_with_usage("--checkpoint-interval requires a number of seconds"),
+            },
+            "--resume" => resume = true,
+            "--frames" => match args.next().as_deref().and_then(parse_frames) {
+                Some(range) => frames = Some(range),
+                None => exit_with_usage("--frames requires a range of frames, as START..END"),
+            },
+            "--fps" => match args.next().and_then(|fps| fps.parse().ok()) {
+                Some(rate) if rate > 0.0 && f32::is_finite(rate) => fps = rate,
+                _ => exit_with_usage("--fps requires a positive number of frames per second"),
+            },
+            "--ffmpeg" => match args.next() {
+                Some(path) => ffmpeg = Some(PathBuf::from(path)),
+                None => exit_with_usage("--ffmpeg requires a video file"),
+            },
+            "--memory-budget" => match args
+                .next()
+                .and_then(|mib| mib.parse::<usize>().ok())
+                .and_then(|mib| mib.checked_mul(1024 * 1024))
+            {
+                Some(bytes) => settings.memory_budget = Some(bytes),
+                None => exit_with_usage("--memory-budget requires a size in MiB"),
+            },
+            "--lut" => match args.next() {
+                Some(path) => match lut::Lut::load(Path::new(&path)) {
+                    Ok(lut) => settings.lut = Some(lut),
+                    Err(err) => {
+                        eprintln!("{err}");
+                        std::process::exit(1);
+                    }
+                },
+                None => exit_with_usage("--lut requires a .cube file"),
+            },
+            #[cfg(feature = "consistency-check")]
+            "--check-primitives" => check_primitives = true,
+            #[cfg(feature = "embree")]
+            "--embree" => use_embree = true,
+            _ => exit_with_usage(&format!("Unknown argument `{arg}`")),
+        }
+    }
 
-impl Sphere {
-    /// Find the intersection points of the given ray within the sphere.
-    /// Intersection points are given as float, distance along the ray.
-    fn intersect(&self, ray: &Ray) -> Option<(f32, f32)> {
-        // Line from sphere center to ray origin
-        let l: Vec3f = self.center - ray.origin;
-        // Distance from sphere center to ray origin, in direction of ray
-        let tca: f32 = l.dot_product(ray.direction);
-        // If `tca` is negative, sphere center is behind ray origin
-        if tca < 0_f32 {
-            return None;
+    if integrators.len() > 1 {
+        exit_with_usage(
+            "Only one of --integrator, --path-trace, --bidirectional, --photon-map, --metropolis \
+             and --ambient-occlusion can be used",
+        );
+    }
+    if let Some(&integrator) = integrators.first() {
+        settings.integrator = integrator;
+    }
+    if settings.wavefront && settings.integrator != IntegratorKind::Whitted {
+        exit_with_usage("The wavefront renderer only supports the whitted integrator");
+    }
+    if settings.wavefront && settings.noise_threshold.is_some() {
+        exit_with_usage("--noise-threshold isn't supported by the wavefront renderer");
+    }
+    if ffmpeg.is_some() && frames.is_none() {
+        exit_with_usage("--ffmpeg requires --frames");
+    }
+    if frames.is_some() && (checkpoint.is_some() || dataset) {
+        exit_with_usage("--frames isn't supported with --checkpoint or dataset");
+    }
+    #[cfg(feature = "embree")]
+    if frames.is_some() && use_embree {
+        exit_with_usage("--frames isn't supported with --embree");
+    }
+    if resume && checkpoint.is_none() {
+        exit_with_usage("--resume requires --checkpoint");
+    }
+    // Checkpointed renders are rendered progressively on one thread, which leaves these out
+    let needs_full_renderer = settings.wavefront
+        || tiled
+        || settings.debug_non_finite
+        || settings.noise_threshold.is_some()
+        || settings.memory_budget.is_some()
+        || !settings.aovs.is_empty()
+        || settings.alpha
+        || matches!(settings.integrator, IntegratorKind::Metropolis { .. })
+        || dataset;
+    if checkpoint.is_some() && needs_full_renderer {
+        exit_with_usage(
+            "--checkpoint isn't supported with --wavefront, --noise-threshold, --memory-budget, \
+             --aov, --alpha, --metropolis, --threads, --tile-size, --tile-order, --debug-nan or \
+             dataset",
+        );
+    }
+    if !settings.aovs.is_empty() {
+        if settings.wavefront {
+            exit_with_usage("--aov isn't supported by the wavefront renderer");
+        }
+        if let IntegratorKind::Metropolis { .. } = settings.integrator {
+            exit_with_usage("--aov isn't supported by --metropolis");
         }
-        // Square distance from sphere center to ray, perpendicular to ray
-        let d2 = l.dot_product(l) - tca * tca;
-        // If distance > radius, the ray lies outside the sphere
-        if d2 > self.sqr_radius {
-            return None;
+        let format = output::Format::from_path(&output_path);
+        for aov in &settings.aovs {
+            if !format.is_some_and(|format| aov.supports(format)) {
+                exit_with_usage(&format!(
+                    "--aov {} isn't supported by `{}`",
+                    aov.name(),
+                    output_path.display()
+                ));
+            }
         }
-        // Distance from `d` to intersection point
-        let thc: f32 = (self.sqr_radius - d2).sqrt();
-        Some((tca - thc, tca + thc))
     }
-}
 
-fn mix(a: f32, b: f32, mix: f32) -> f32 {
-    b * mix + a * (1_f32 - mix)
-}
+    if settings.alpha {
+        if settings.wavefront {
+            exit_with_usage("--alpha isn't supported by the wavefront renderer");
+        }
+        if let IntegratorKind::Metropolis { .. } = settings.integrator {
+            exit_with_usage("--alpha isn't supported by --metropolis");
+        }
+        if dataset {
+            exit_with_usage("--alpha isn't supported by dataset");
+        }
+        if !output::Format::from_path(&output_path).is_some_and(output::Format::has_alpha) {
+            exit_with_usage(&format!(
+                "--alpha requires a .png, .tif or .exr file, not `{}`",
+                output_path.display()
+            ));
+        }
+    }
 
-const MAX_RAY_DEPTH: usize = 5;
-
-fn trace(ray: Ray, spheres: &[Sphere], depth: usize) -> Vec3f {
-    // Find the first sphere which the ray intersects
-    let mut near: (f32, Option<&Sphere>) = (f32::INFINITY, None);
-    for sphere in spheres {
-        if let Some((mut t0, t1)) = sphere.intersect(&ray) {
-            // If the first intersection point lies behind the ray origin, then the first
-            // intersection is the same as the second.
-            if t0 < 0_f32 {
-                t0 = t1;
-            }
-            if t0 < near.0 {
-                near = (t0, Some(sphere));
-            }
+    if dataset {
+        if let Err(err) = dataset::generate(&dataset_dir, dataset_count, settings.seed, &settings) {
+            eprintln!("Failed to generate dataset: {err}");
+            std::process::exit(1);
         }
+        return;
     }
 
-    // No intersection - return background color
-    let Some(near_sphere) = near.1 else {
-        return Vec3f::new_uniform(2.0);
+    let mut scene = match scene_name.as_str() {
+        "classic" => Scene::classic(),
+        "outdoor" => Scene::outdoor(),
+        _ => exit_with_usage(&format!("Unknown scene `{scene_name}`")),
     };
+    if let Some(path) = environment_path {
+        match EnvironmentMap::load(&path) {
+            Ok(environment) => scene.background = Background::Environment(environment),
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
+            }
+        }
+    }
 
-    // Point of intersection
-    let hit_point: Vec3f = ray.origin + ray.direction * near.0;
-    let mut hit_normal: Vec3f = (hit_point - near_sphere.center).normalized();
+    if let Some(density) = fog {
+        scene.fog = Some(Medium::fog(FOG_COLOR, density));
+    }
 
-    let bias: f32 = 1e-4;
+    // The built in scenes all use the default camera, which these options replace
+    let mut animation = Animation::built_in();
+    if projection.is_some() || stereo.is_some() || fov.is_some() {
+        let camera = ThinLensCamera {
+            fov: fov.unwrap_or(DEFAULT_FOV),
+            projection: projection.unwrap_or(Projection::Perspective),
+            stereo,
+            ..ThinLensCamera::default()
+        };
+        scene.camera = Box::new(camera.clone());
+        animation.camera = animation.camera.map(|path| path.with_camera(camera));
+    }
 
-    let is_inside = if ray.direction.dot_product(hit_normal) > 0.0 {
-        hit_normal = -hit_normal;
-        true
-    } else {
-        false
-    };
+    if let Some(path) = export_path {
+        let aspect_ratio = settings.width as f32 / settings.height as f32;
+        if let Err(err) = gltf::export(&scene, aspect_ratio, &path) {
+            eprintln!("Failed to export scene: {err}");
+            std::process::exit(1);
+        }
+        return;
+    }
 
-    let surface_color = if depth < MAX_RAY_DEPTH
-        && (near_sphere.transparency > 0.0 || near_sphere.reflection > 0.0)
-    {
-        let facing_ratio = -ray.direction.dot_product(hit_normal);
-        let fresnel_effect = mix((1.0 - facing_ratio).powi(3), 1.0, 0.1);
-
-        let reflect_dir = ray.direction - hit_normal * 2.0 * ray.direction.dot_product(hit_normal);
-        let reflect_dir = reflect_dir.normalized();
-        let reflect_origin = hit_point + hit_normal * bias;
-        let reflection = trace(
-            Ray {
-                origin: reflect_origin,
-                direction: reflect_dir,
-            },
-            spheres,
-            depth + 1,
-        );
-        let refraction = if near_sphere.transparency > 0.0 {
-            let ior: f32 = 1.1;
-            let eta: f32 = if is_inside { ior } else { 1.0 / ior };
-            let cosi = -hit_normal.dot_product(ray.direction);
-            let k = 1.0 - eta * eta * (1.0 - cosi * cosi);
-            let refract_dir = ray.direction * eta + hit_normal * (eta * cosi - k.sqrt());
-            let refract_dir = refract_dir.normalized();
-            let refract_origin = hit_point - hit_normal * bias;
-            trace(
-                Ray {
-                    origin: refract_origin,
-                    direction: refract_dir,
-                },
-                spheres,
-                depth + 1,
-            )
-        } else {
-            Vec3f::new_uniform(0.0)
-        };
-        (reflection * fresnel_effect
-            + refraction * (1.0 - fresnel_effect) * near_sphere.transparency)
-            * near_sphere.surface_color
-    } else {
-        let mut surface_color = Vec3f::new_uniform(0.0);
-        for (i, sphere) in spheres.iter().enumerate() {
-            if sphere.emission.x > 0.0 {
-                let mut transmission = Vec3f::new_uniform(1.0);
-                let light_dir = (sphere.center - hit_point).normalized();
-                let light_origin = hit_point + hit_normal * bias;
-                let light_ray = Ray {
-                    origin: light_origin,
-                    direction: light_dir,
-                };
-                for (j, other_sphere) in spheres.iter().enumerate() {
-                    if i == j {
-                        continue;
-                    };
-                    if other_sphere.intersect(&light_ray).is_some() {
-                        transmission = Vec3f::new_uniform(0.0);
-                        break;
-                    }
-                }
-                surface_color += near_sphere.surface_color
-                    * transmission
-                    * 0_f32.max(hit_normal.dot_product(light_dir))
-                    * sphere.emission;
+    #[cfg(feature = "consistency-check")]
+    if check_primitives {
+        let report = consistency::check_primitives(&scene.spheres, 10_000);
+        report.print();
+        std::process::exit(if report.is_ok() { 0 } else { 1 });
+    }
+
+    #[cfg(feature = "embree")]
+    let mut scene = if use_embree {
+        match embree::EmbreeScene::new(&scene.spheres) {
+            Ok(embree) => {
+                let mut scene = scene;
+                scene.accelerator = Some(Box::new(embree));
+                scene
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                std::process::exit(1);
             }
         }
-        surface_color
+    } else {
+        scene
     };
 
-    surface_color + near_sphere.emission
+    let rendered = match (frames, checkpoint) {
+        (Some(frames), _) => render::render_sequence(
+            &mut scene,
+            &animation,
+            &settings,
+            &output_path,
+            frames,
+            fps,
+            ffmpeg.as_deref(),
+        ),
+        (None, Some(checkpoint)) => render::render_checkpointed(
+            &scene,
+            &settings,
+            &output_path,
+            &checkpoint,
+            checkpoint_interval,
+            resume,
+        ),
+        (None, None) => render::render(&scene, &settings, &output_path),
+    };
+    if let Err(err) = rendered {
+        eprintln!("Failed to render: {err}");
+        std::process::exit(1);
+    }
 }
 
-fn render(spheres: &[Sphere]) -> std::io::Result<()> {
-    const WIDTH: usize = 640;
-    const HEIGHT: usize = 480;
-    let inv_width = 1.0 / WIDTH as f32;
-    let inv_height = 1.0 / HEIGHT as f32;
-    const FOV: f32 = 30.0;
-    const ASPECT_RATIO: f32 = WIDTH as f32 / HEIGHT as f32;
-    let angle = f32::tan(PI * 0.5 * FOV / 180.0);
-
-    let mut image = [Vec3f::default(); WIDTH * HEIGHT];
-
-    for y in 0..HEIGHT {
-        for x in 0..HEIGHT {
-            let pixel = &mut image[x + y];
-            let xx = (2.0 * ((x as f32 + 0.5) * inv_width) - 1.0) * angle * ASPECT_RATIO;
-            let yy = (1.0 - 2.0 * ((y as f32 + 0.5) * inv_height)) * angle;
-            let ray_dir = Vec3f {
-                x: xx,
-                y: yy,
-                z: -1.0,
-            }
-            .normalized();
-            let ray = Ray {
-                origin: Vec3f::new_uniform(0.0),
-                direction: ray_dir,
-            };
-            *pixel = trace(ray, spheres, 0);
+/// Print how much the image at `test` differs from the one at `reference`.
+fn compare_images(reference: &Path, test: &Path) {
+    let load = |path: &Path| {
+        ImageTexture::load(path).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            std::process::exit(1);
+        })
+    };
+    match compare::compare(&load(reference), &load(test)) {
+        Ok(differences) => {
+            println!("RMSE {:.6}", differences.rmse);
+            println!("MAE  {:.6}", differences.mae);
+            println!("FLIP {:.6}", differences.flip);
+        }
+        Err(err) => {
+            eprintln!("Failed to compare: {err}");
+            std::process::exit(1);
         }
     }
+}
 
-    let file = File::open("raytraced.ppm")?;
-    let mut buf_writer = BufWriter::new(file);
+/// Parse a range of frames written as `START..END`, up to but not including the end, or
+/// `START..=END`, including it.
+fn parse_frames(range: &str) -> Option<Range<usize>> {
+    let (start, end) = range.split_once("..")?;
+    let start = start.parse().ok()?;
+    let end = match end.strip_prefix('=') {
+        Some(end) => end.parse::<usize>().ok()? + 1,
+        None => end.parse().ok()?,
+    };
+    (start < end).then_some(start..end)
+}
 
-    Ok(())
+/// Parse an image size written as `WIDTHxHEIGHT`.
+fn parse_resolution(size: &str) -> Option<(usize, usize)> {
+    let (width, height) = size.split_once('x')?;
+    let (width, height) = (width.parse().ok()?, height.parse().ok()?);
+    (width > 0 && height > 0).then_some((width, height))
 }
 
-fn main() {
-    println!("Hello, world!");
+fn exit_with_usage(message: &str) -> ! {
+    eprintln!("{message}");
+    eprintln!(
+        "Usage: rayox [--scene classic|outdoor] [--environment FILE] [--fog DENSITY] [OPTIONS]"
+    );
+    eprintln!("       rayox [--scene classic|outdoor] --export FILE.gltf");
+    eprintln!("       rayox dataset [--out DIR] [--count N] [OPTIONS]");
+    eprintln!("       rayox compare REFERENCE TEST, each a .ppm, .png, .exr, .pfm or .hdr image");
+    eprintln!("Options: [--resolution WIDTHxHEIGHT] [--fov DEGREES] [--wavefront]");
+    eprintln!("         [--integrator whitted|direct|path|bidirectional|irradiance-cache]");
+    eprintln!("         [--sampler independent|stratified|halton|blue-noise]");
+    eprintln!("         [--filter box|tent|gaussian|mitchell]");
+    eprintln!("         [--samples PER_PIXEL] [--noise-threshold FRACTION] [--max-depth BOUNCES]");
+    eprintln!("         [--seed N] [--path-trace] [--clamp-indirect BRIGHTNESS]");
+    eprintln!("         [--bidirectional] [--photon-map PHOTONS] [--metropolis MUTATIONS]");
+    eprintln!("         [--ambient-occlusion DISTANCE] [--memory-budget MiB] [--lut FILE]");
+    eprintln!("         [--tile-size PIXELS] [--tile-order scanline|spiral] [--progress]");
+    eprintln!("         [--checkpoint FILE [--checkpoint-interval SECONDS] [--resume]]");
+    eprintln!("         [--frames START..END [--fps FPS] [--ffmpeg VIDEO]]");
+    eprintln!("         [--output FILE.ppm|.png|.tif|.jpg|.exr|.hdr] [--16-bit] [--half]");
+    eprintln!("         [--aov normal|depth|albedo|direct|indirect|alpha|object-id|material-id]");
+    eprintln!("         [--quality 1-100] [--exposure STOPS] [--tone-map clamp|reinhard|aces]");
+    eprintln!("         [--gamma GAMMA] [--alpha] [--debug-nan] [--threads N]");
+    eprintln!("         [--orthographic HEIGHT] [--fisheye DEGREES] [--equisolid DEGREES]");
+    eprintln!("         [--panorama] [--stereo|--over-under DISTANCE [--convergence DISTANCE]]");
+    std::process::exit(2);
 }
@@ -0,0 +1,1883 @@
+use crate::{grid::DensityGrid, rng::Rng, texture::Texture, Ray, Vec3f};
+use std::f32::consts::PI;
+
+/// Handle to a material in the scene.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct MaterialId(pub usize);
+
+/// The local geometry where a ray hit a surface.
+#[derive(Copy, Clone)]
+pub struct Interaction {
+    /// Point of intersection.
+    pub point: Vec3f,
+    /// Surface normal, facing the side of the surface which the ray arrived from.
+    pub normal: Vec3f,
+    /// Unit vector along the surface in the direction of increasing u, which anisotropic
+    /// materials align to.
+    pub tangent: Vec3f,
+    /// Texture coordinates.
+    pub uv: (f32, f32),
+    /// Offset for secondary ray origins, to avoid self-intersection.
+    pub bias: f32,
+    /// Index of refraction of the medium the ray arrived through.
+    pub incident_ior: f32,
+    /// Index of refraction of the medium on the other side of the surface.
+    pub transmitted_ior: f32,
+}
+
+impl Interaction {
+    /// Spawn a ray leaving the surface in `direction`, offset to the side it leaves from.
+    pub fn spawn_ray(&self, direction: Vec3f) -> Ray {
+        let offset = if direction.dot_product(self.normal) >= 0.0 {
+            self.normal * self.bias
+        } else {
+            -self.normal * self.bias
+        };
+        Ray {
+            origin: self.point + offset,
+            direction,
+        }
+    }
+}
+
+/// Participating medium which light scatters and is absorbed within, as it travels through.
+#[derive(Copy, Clone)]
+pub struct Medium {
+    /// Chance per unit distance of light being scattered, for each channel.
+    pub scattering: Vec3f,
+    /// Chance per unit distance of light being absorbed, for each channel.
+    pub absorption: Vec3f,
+    /// Average cosine of the angle light is scattered through, from -1 (scattered back) through
+    /// 0 (scattered evenly) to 1 (scattered onwards), for the Henyey-Greenstein phase function.
+    pub anisotropy: f32,
+}
+
+impl Medium {
+    /// A medium scattering light evenly in every direction.
+    pub fn new(scattering: Vec3f, absorption: Vec3f) -> Self {
+        Medium {
+            scattering,
+            absorption,
+            anisotropy: 0.0,
+        }
+    }
+
+    /// Fog with `density`, the chance per unit distance of light meeting a droplet, which
+    /// scatters `color` of the light and absorbs the rest.
+    pub fn fog(color: Vec3f, density: f32) -> Self {
+        Medium::new(color * density, (Vec3f::new_uniform(1.0) - color) * density)
+    }
+
+    /// Scatter light onwards for positive `anisotropy`, like the water droplets in clouds and
+    /// fog, or back towards where it came from for negative.
+    pub fn with_anisotropy(mut self, anisotropy: f32) -> Self {
+        self.anisotropy = anisotropy.clamp(-0.99, 0.99);
+        self
+    }
+
+    pub fn extinction(&self) -> Vec3f {
+        self.scattering + self.absorption
+    }
+
+    /// Probability density per unit solid angle of light travelling in `direction` being
+    /// scattered into `scattered`, by the Henyey-Greenstein phase function.
+    pub fn phase(&self, direction: Vec3f, scattered: Vec3f) -> f32 {
+        let g = self.anisotropy;
+        let cos = direction.dot_product(scattered);
+        let denominator = 1.0 + g * g - 2.0 * g * cos;
+        (1.0 - g * g) / (4.0 * PI * denominator * denominator.sqrt())
+    }
+
+    /// A direction light travelling in `direction` is scattered into, chosen with probability
+    /// density equal to the phase function, so the phase function and density cancel out.
+    pub fn sample_phase(&self, direction: Vec3f, rng: &mut Rng) -> Vec3f {
+        let g = self.anisotropy;
+        let u = rng.next_f32();
+        let cos = if g.abs() < 1e-3 {
+            1.0 - 2.0 * u
+        } else {
+            let square = (1.0 - g * g) / (1.0 + g - 2.0 * g * u);
+            ((1.0 + g * g - square * square) / (2.0 * g)).clamp(-1.0, 1.0)
+        };
+        let sin = (1.0 - cos * cos).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.next_f32();
+        let (tangent, bitangent) = direction.tangent_frame();
+        (tangent * (sin * phi.cos()) + bitangent * (sin * phi.sin()) + direction * cos).normalized()
+    }
+}
+
+/// The nested media which a ray is travelling through, innermost last. Each is recorded with the
+/// sphere it was entered through, its index of refraction, and whether it scatters light.
+#[derive(Clone, Default)]
+pub struct Media(Vec<(usize, f32, Option<Medium>)>);
+
+impl Media {
+    /// Index of refraction of the innermost medium, or of air outside everything.
+    pub fn ior(&self) -> f32 {
+        self.0.last().map_or(1.0, |&(_, ior, _)| ior)
+    }
+
+    /// Index of refraction of the medium the ray is in after leaving through `sphere`.
+    pub fn ior_outside(&self, sphere: usize) -> f32 {
+        self.0
+            .iter()
+            .rev()
+            .find(|&&(entered, _, _)| entered != sphere)
+            .map_or(1.0, |&(_, ior, _)| ior)
+    }
+
+    /// The innermost medium, if it scatters or absorbs light.
+    pub fn medium(&self) -> Option<Medium> {
+        self.0.last().and_then(|&(_, _, medium)| medium)
+    }
+
+    /// Whether the ray is outside every sphere.
+    pub fn is_outside(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// The sphere the innermost medium was entered through.
+    pub fn sphere(&self) -> Option<usize> {
+        self.0.last().map(|&(sphere, _, _)| sphere)
+    }
+
+    pub fn enter(&self, sphere: usize, ior: f32, medium: Option<Medium>) -> Media {
+        let mut media = self.clone();
+        media.0.push((sphere, ior, medium));
+        media
+    }
+
+    pub fn exit(&self, sphere: usize) -> Media {
+        let mut media = self.clone();
+        if let Some(index) = media
+            .0
+            .iter()
+            .rposition(|&(entered, _, _)| entered == sphere)
+        {
+            media.0.remove(index);
+        }
+        media
+    }
+}
+
+/// Approximation of a material in the metallic-roughness model used by glTF and most modelling
+/// tools.
+pub struct Pbr {
+    pub base_color: Vec3f,
+    pub metallic: f32,
+    pub roughness: f32,
+    pub emission: Vec3f,
+    /// Fraction of light which passes through the surface rather than being diffusely reflected.
+    pub transmission: f32,
+    pub ior: f32,
+    /// Strength of a clear coat over the material.
+    pub clearcoat: f32,
+    pub clearcoat_roughness: f32,
+}
+
+impl Pbr {
+    pub fn new(base_color: Vec3f, metallic: f32, roughness: f32) -> Self {
+        Pbr {
+            base_color,
+            metallic,
+            roughness,
+            emission: Vec3f::new_uniform(0.0),
+            transmission: 0.0,
+            ior: 1.5,
+            clearcoat: 0.0,
+            clearcoat_roughness: 0.0,
+        }
+    }
+}
+
+/// How a surface interacts with light.
+pub trait Material: Send + Sync {
+    /// Name of the kind of material, for reporting.
+    fn name(&self) -> &'static str;
+
+    /// Closest equivalent of the material in the metallic-roughness model, for exporting.
+    fn pbr(&self) -> Pbr;
+
+    /// Index of refraction of the medium enclosed by the surface, for materials which let light
+    /// through.
+    fn ior(&self) -> Option<f32> {
+        None
+    }
+
+    /// Medium enclosed by the surface, for materials which scatter or absorb the light passing
+    /// through them.
+    fn medium(&self) -> Option<Medium> {
+        None
+    }
+
+    /// Density of the enclosed medium at `point`, which its scattering and absorption are scaled
+    /// by, for media which vary from place to place like smoke.
+    fn density(&self, _point: Vec3f) -> f32 {
+        1.0
+    }
+
+    /// Greatest density of the enclosed medium anywhere, or `None` where it's the same
+    /// throughout.
+    fn max_density(&self) -> Option<f32> {
+        None
+    }
+
+    /// Normal which the surface is shaded with, where the material perturbs it to add detail.
+    fn shading_normal(&self, interaction: &Interaction) -> Vec3f {
+        interaction.normal
+    }
+
+    /// Light emitted by the surface.
+    fn emitted(&self) -> Vec3f {
+        Vec3f::new_uniform(0.0)
+    }
+
+    /// Fraction of the light which shadow rays carry straight through the surface, tinting the
+    /// shadows of transparent objects. Zero for opaque surfaces.
+    fn transparency(&self) -> Vec3f {
+        Vec3f::new_uniform(0.0)
+    }
+
+    /// Secondary rays leaving the surface after `ray` hits it, each with the weight of the light
+    /// it brings back. `None` if the surface is only lit directly by lights.
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        _interaction: &Interaction,
+        _rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        None
+    }
+
+    /// Whether lights are sampled directly at the surface, as well as through the scattered rays.
+    /// The light found each way is then weighted, so it isn't counted twice.
+    fn samples_lights(&self) -> bool {
+        false
+    }
+
+    /// Probability density per unit solid angle of the scattered rays leaving in `direction`,
+    /// for weighing them against lights sampled directly. The default of zero leaves lights
+    /// entirely to the direct samples.
+    fn pdf(&self, _ray: &Ray, _interaction: &Interaction, _direction: Vec3f) -> f32 {
+        0.0
+    }
+
+    /// Fraction of light arriving from `light_dir` which the surface reflects back along `ray`,
+    /// which is the BRDF scaled by pi, so a white diffuse surface gives one. Doesn't include the
+    /// cosine term.
+    fn eval(&self, ray: &Ray, interaction: &Interaction, light_dir: Vec3f) -> Vec3f;
+}
+
+/// Matte surface, only lit directly.
+pub struct Diffuse<T = Vec3f> {
+    pub color: T,
+}
+
+impl<T: Texture> Diffuse<T> {
+    pub fn new(color: T) -> Self {
+        Diffuse { color }
+    }
+}
+
+impl<T: Texture> Material for Diffuse<T> {
+    fn name(&self) -> &'static str {
+        "diffuse"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr::new(self.color.average(), 0.0, 1.0)
+    }
+
+    fn eval(&self, _ray: &Ray, interaction: &Interaction, _light_dir: Vec3f) -> Vec3f {
+        self.color.value(interaction.uv, interaction.point)
+    }
+}
+
+/// Matte surface which scatters light equally in all directions, so it is lit indirectly by
+/// other surfaces as well as directly by lights.
+pub struct Lambertian<T = Vec3f> {
+    pub albedo: T,
+}
+
+impl<T: Texture> Lambertian<T> {
+    pub fn new(albedo: T) -> Self {
+        Lambertian { albedo }
+    }
+}
+
+impl Lambertian {
+    /// Start building a white matte material.
+    pub fn builder() -> LambertianBuilder {
+        LambertianBuilder {
+            albedo: Vec3f::new_uniform(1.0),
+        }
+    }
+}
+
+/// Builds a [`Lambertian`] material, checking it makes sense once it is finished.
+pub struct LambertianBuilder {
+    albedo: Vec3f,
+}
+
+impl LambertianBuilder {
+    pub fn albedo(mut self, albedo: Vec3f) -> Self {
+        self.albedo = albedo;
+        self
+    }
+
+    /// Finish the material, or describe the first problem with it.
+    pub fn build(self) -> Result<Lambertian, String> {
+        check_reflectance("lambertian albedo", self.albedo)?;
+        Ok(Lambertian::new(self.albedo))
+    }
+}
+
+impl<T: Texture> Material for Lambertian<T> {
+    fn name(&self) -> &'static str {
+        "lambertian"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr::new(self.albedo.average(), 0.0, 1.0)
+    }
+
+    /// Scatters a single ray, with cosine weighted directions. The cosine term and the sampling
+    /// density cancel out, leaving the albedo as the weight.
+    fn scatter(
+        &self,
+        _ray: &Ray,
+        interaction: &Interaction,
+        rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        // Offsetting the normal by a uniformly random unit vector gives cosine weighted directions
+        let direction = interaction.normal + rng.unit_vector();
+        let direction = if direction.magnitude() > 1e-6 {
+            direction.normalized()
+        } else {
+            interaction.normal
+        };
+        Some(vec![(
+            interaction.spawn_ray(direction),
+            self.albedo.value(interaction.uv, interaction.point),
+        )])
+    }
+
+    fn samples_lights(&self) -> bool {
+        true
+    }
+
+    fn pdf(&self, _ray: &Ray, interaction: &Interaction, direction: Vec3f) -> f32 {
+        direction.dot_product(interaction.normal).max(0.0) / PI
+    }
+
+    fn eval(&self, _ray: &Ray, interaction: &Interaction, _light_dir: Vec3f) -> Vec3f {
+        self.albedo.value(interaction.uv, interaction.point)
+    }
+}
+
+/// Rough matte surface such as clay or concrete, using the Oren-Nayar model of many tiny
+/// Lambertian facets. Unlike a Lambertian surface it looks flatter, and brightens towards the
+/// light at grazing angles.
+pub struct OrenNayar<T = Vec3f> {
+    pub albedo: T,
+    /// Standard deviation of the facets' slope angles in radians, where 0 is Lambertian.
+    pub sigma: f32,
+}
+
+impl<T: Texture> OrenNayar<T> {
+    pub fn new(albedo: T, sigma: f32) -> Self {
+        OrenNayar {
+            albedo,
+            sigma: sigma.max(0.0),
+        }
+    }
+}
+
+impl OrenNayar {
+    /// Start building a white matte material with smooth facets, which is Lambertian.
+    pub fn builder() -> OrenNayarBuilder {
+        OrenNayarBuilder {
+            albedo: Vec3f::new_uniform(1.0),
+            sigma: 0.0,
+        }
+    }
+}
+
+/// Builds an [`OrenNayar`] material, checking it makes sense once it is finished.
+pub struct OrenNayarBuilder {
+    albedo: Vec3f,
+    sigma: f32,
+}
+
+impl OrenNayarBuilder {
+    pub fn albedo(mut self, albedo: Vec3f) -> Self {
+        self.albedo = albedo;
+        self
+    }
+
+    /// Standard deviation of the facets' slope angles in radians, where 0 is Lambertian.
+    pub fn sigma(mut self, sigma: f32) -> Self {
+        self.sigma = sigma;
+        self
+    }
+
+    /// Finish the material, or describe the first problem with it.
+    pub fn build(self) -> Result<OrenNayar, String> {
+        check_reflectance("oren-nayar albedo", self.albedo)?;
+        if !(self.sigma >= 0.0 && self.sigma.is_finite()) {
+            return Err(format!(
+                "oren-nayar slope deviation is {}, which must not be negative",
+                self.sigma
+            ));
+        }
+        Ok(OrenNayar::new(self.albedo, self.sigma))
+    }
+}
+
+impl<T: Texture> Material for OrenNayar<T> {
+    fn name(&self) -> &'static str {
+        "oren-nayar"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr::new(self.albedo.average(), 0.0, 1.0)
+    }
+
+    /// Scatters a single ray with cosine weighted directions, as for a Lambertian surface, so
+    /// the weight is the facets' reflectance relative to Lambertian.
+    fn scatter(
+        &self,
+        ray: &Ray,
+        interaction: &Interaction,
+        rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        let direction = interaction.normal + rng.unit_vector();
+        let direction = if direction.magnitude() > 1e-6 {
+            direction.normalized()
+        } else {
+            interaction.normal
+        };
+        Some(vec![(
+            interaction.spawn_ray(direction),
+            self.eval(ray, interaction, direction),
+        )])
+    }
+
+    fn samples_lights(&self) -> bool {
+        true
+    }
+
+    fn pdf(&self, _ray: &Ray, interaction: &Interaction, direction: Vec3f) -> f32 {
+        direction.dot_product(interaction.normal).max(0.0) / PI
+    }
+
+    fn eval(&self, ray: &Ray, interaction: &Interaction, light_dir: Vec3f) -> Vec3f {
+        let normal = interaction.normal;
+        let cos_o = (-ray.direction.dot_product(normal)).clamp(0.0, 1.0);
+        let cos_i = light_dir.dot_product(normal).clamp(0.0, 1.0);
+        let sigma2 = self.sigma * self.sigma;
+        let a = 1.0 - 0.5 * sigma2 / (sigma2 + 0.33);
+        let b = 0.45 * sigma2 / (sigma2 + 0.09);
+
+        // Cosine of the azimuthal angle between the directions, around the normal
+        let tangential_o = -ray.direction - normal * cos_o;
+        let tangential_i = light_dir - normal * cos_i;
+        let lengths = tangential_o.magnitude() * tangential_i.magnitude();
+        let cos_phi = if lengths > 1e-6 {
+            (tangential_o.dot_product(tangential_i) / lengths).max(0.0)
+        } else {
+            0.0
+        };
+        // Sine of the larger angle to the normal, and tangent of the smaller
+        let sin = |cos: f32| (1.0 - cos * cos).max(0.0).sqrt();
+        let (cos_alpha, cos_beta) = (cos_o.min(cos_i), cos_o.max(cos_i));
+        let sin_alpha_tan_beta = sin(cos_alpha) * sin(cos_beta) / cos_beta.max(1e-6);
+        self.albedo.value(interaction.uv, interaction.point)
+            * (a + b * cos_phi * sin_alpha_tan_beta)
+    }
+}
+
+/// Classic non-physical shading, with Blinn-Phong highlights on a diffuse base. Only lit directly,
+/// which makes it fast.
+pub struct BlinnPhong {
+    pub diffuse: Vec3f,
+    pub specular: Vec3f,
+    /// Specular exponent, where higher values give smaller, sharper highlights.
+    pub shininess: f32,
+}
+
+impl BlinnPhong {
+    pub fn new(diffuse: Vec3f, specular: Vec3f, shininess: f32) -> Self {
+        BlinnPhong {
+            diffuse,
+            specular,
+            shininess,
+        }
+    }
+
+    /// Start building a white material without highlights.
+    pub fn builder() -> BlinnPhongBuilder {
+        BlinnPhongBuilder {
+            diffuse: Vec3f::new_uniform(1.0),
+            specular: Vec3f::new_uniform(0.0),
+            shininess: 1.0,
+        }
+    }
+}
+
+/// Builds a [`BlinnPhong`] material, checking it makes sense once it is finished.
+pub struct BlinnPhongBuilder {
+    diffuse: Vec3f,
+    specular: Vec3f,
+    shininess: f32,
+}
+
+impl BlinnPhongBuilder {
+    pub fn diffuse(mut self, diffuse: Vec3f) -> Self {
+        self.diffuse = diffuse;
+        self
+    }
+
+    pub fn specular(mut self, specular: Vec3f) -> Self {
+        self.specular = specular;
+        self
+    }
+
+    /// Specular exponent, where higher values give smaller, sharper highlights.
+    pub fn shininess(mut self, shininess: f32) -> Self {
+        self.shininess = shininess;
+        self
+    }
+
+    /// Finish the material, or describe the first problem with it.
+    pub fn build(self) -> Result<BlinnPhong, String> {
+        check_reflectance("blinn-phong diffuse color", self.diffuse)?;
+        check_reflectance("blinn-phong specular color", self.specular)?;
+        if !(self.shininess >= 0.0 && self.shininess.is_finite()) {
+            return Err(format!(
+                "blinn-phong shininess is {}, which must not be negative",
+                self.shininess
+            ));
+        }
+        Ok(BlinnPhong::new(self.diffuse, self.specular, self.shininess))
+    }
+}
+
+impl Material for BlinnPhong {
+    fn name(&self) -> &'static str {
+        "blinn-phong"
+    }
+
+    fn pbr(&self) -> Pbr {
+        // The usual mapping from exponent to GGX width, alpha = sqrt(2 / (n + 2))
+        let alpha = (2.0 / (self.shininess + 2.0)).sqrt();
+        Pbr::new(self.diffuse, 0.0, alpha.sqrt())
+    }
+
+    fn eval(&self, ray: &Ray, interaction: &Interaction, light_dir: Vec3f) -> Vec3f {
+        let half = (light_dir - ray.direction).normalized();
+        let highlight = half
+            .dot_product(interaction.normal)
+            .max(0.0)
+            .powf(self.shininess);
+        self.diffuse + self.specular * highlight
+    }
+}
+
+/// Surface which only emits light.
+pub struct Emissive {
+    pub emission: Vec3f,
+}
+
+impl Emissive {
+    pub fn new(emission: Vec3f) -> Self {
+        Emissive { emission }
+    }
+}
+
+impl Material for Emissive {
+    fn name(&self) -> &'static str {
+        "emissive"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr {
+            emission: self.emission,
+            ..Pbr::new(Vec3f::new_uniform(0.0), 0.0, 1.0)
+        }
+    }
+
+    fn emitted(&self) -> Vec3f {
+        self.emission
+    }
+
+    fn eval(&self, _ray: &Ray, _interaction: &Interaction, _light_dir: Vec3f) -> Vec3f {
+        Vec3f::new_uniform(0.0)
+    }
+}
+
+/// Reflective surface, using an approximation of the Fresnel effect.
+pub struct Specular {
+    pub color: Vec3f,
+}
+
+impl Specular {
+    pub fn new(color: Vec3f) -> Self {
+        Specular { color }
+    }
+}
+
+/// Mirror direction of `direction` about `normal`.
+fn reflect(direction: Vec3f, normal: Vec3f) -> Vec3f {
+    direction - normal * 2.0 * direction.dot_product(normal)
+}
+
+fn mix(a: f32, b: f32, mix: f32) -> f32 {
+    b * mix + a * (1_f32 - mix)
+}
+
+impl Material for Specular {
+    fn name(&self) -> &'static str {
+        "specular"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr::new(self.color, 1.0, 0.0)
+    }
+
+    fn scatter(
+        &self,
+        ray: &Ray,
+        interaction: &Interaction,
+        _rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        let normal = interaction.normal;
+        let facing_ratio = -ray.direction.dot_product(normal);
+        let fresnel_effect = mix((1.0 - facing_ratio).powi(3), 1.0, 0.1);
+
+        let reflect_dir = reflect(ray.direction, normal);
+        Some(vec![(
+            interaction.spawn_ray(reflect_dir.normalized()),
+            self.color * fresnel_effect,
+        )])
+    }
+
+    /// Once rays are too deep to be scattered, the surface is lit as if it were diffuse.
+    fn eval(&self, _ray: &Ray, _interaction: &Interaction, _light_dir: Vec3f) -> Vec3f {
+        self.color
+    }
+}
+
+/// A thin transparent coating, whose reflections interfere with each other to give iridescent
+/// colors, like those of soap bubbles and oil slicks.
+#[derive(Copy, Clone)]
+pub struct ThinFilm {
+    /// Thickness of the film, in nanometres.
+    pub thickness: f32,
+    pub ior: f32,
+}
+
+impl ThinFilm {
+    /// Wavelengths taken to represent the red, green and blue channels, in nanometres.
+    const WAVELENGTHS: [f32; 3] = [630.0, 532.0, 465.0];
+
+    pub fn new(thickness: f32, ior: f32) -> Self {
+        ThinFilm { thickness, ior }
+    }
+
+    /// Fraction of unpolarized light reflected by the film and the base beneath it, for light
+    /// arriving at `cos_i` to the normal from a medium with index of refraction `eta_i`. The base
+    /// has an index of refraction for each channel.
+    pub fn reflectance(&self, cos_i: f32, eta_i: f32, base_ior: Vec3f) -> Vec3f {
+        let (n1, n2) = (eta_i, self.ior);
+        let sin2_i = 1.0 - cos_i * cos_i;
+        let sin2_film = sin2_i * (n1 / n2) * (n1 / n2);
+        if sin2_film >= 1.0 {
+            return Vec3f::new_uniform(1.0);
+        }
+        let cos_film = (1.0 - sin2_film).sqrt();
+
+        let channel = |wavelength: f32, n3: f32| {
+            let sin2_base = sin2_i * (n1 / n3) * (n1 / n3);
+            if sin2_base >= 1.0 {
+                return 1.0;
+            }
+            let cos_base = (1.0 - sin2_base).sqrt();
+            // Phase difference between light reflected off the top and bottom of the film
+            let phase = 4.0 * PI * n2 * self.thickness * cos_film / wavelength;
+            // Airy's formula, summing every reflection within the film
+            let airy = |r12: f32, r23: f32| {
+                let cross = 2.0 * r12 * r23 * phase.cos();
+                (r12 * r12 + r23 * r23 + cross) / (1.0 + r12 * r12 * r23 * r23 + cross)
+            };
+            let perpendicular = airy(
+                (n1 * cos_i - n2 * cos_film) / (n1 * cos_i + n2 * cos_film),
+                (n2 * cos_film - n3 * cos_base) / (n2 * cos_film + n3 * cos_base),
+            );
+            let parallel = airy(
+                (n2 * cos_i - n1 * cos_film) / (n2 * cos_i + n1 * cos_film),
+                (n3 * cos_film - n2 * cos_base) / (n3 * cos_film + n2 * cos_base),
+            );
+            (perpendicular + parallel) * 0.5
+        };
+        let [r, g, b] = Self::WAVELENGTHS;
+        Vec3f::new(
+            channel(r, base_ior.x),
+            channel(g, base_ior.y),
+            channel(b, base_ior.z),
+        )
+    }
+}
+
+/// Index of refraction giving the reflectance `f0` at normal incidence, for each channel. Used to
+/// treat metals as a base beneath a thin film.
+fn ior_from_reflectance(f0: Vec3f) -> Vec3f {
+    let ior = |f0: f32| {
+        let r = f0.clamp(0.0, 0.99).sqrt();
+        (1.0 + r) / (1.0 - r)
+    };
+    Vec3f::new(ior(f0.x), ior(f0.y), ior(f0.z))
+}
+
+/// Transparent material such as glass or water, which both reflects and refracts light in the
+/// proportions given by the Fresnel equations.
+pub struct Dielectric {
+    /// Tint of the light refracted through the surface.
+    pub color: Vec3f,
+    /// Index of refraction of the material.
+    pub ior: f32,
+    pub film: Option<ThinFilm>,
+    /// Fraction of light absorbed per unit distance travelled inside, for each channel.
+    pub absorption: Vec3f,
+}
+
+impl Dielectric {
+    pub const GLASS: f32 = 1.5;
+    pub const WATER: f32 = 1.33;
+    pub const DIAMOND: f32 = 2.42;
+
+    pub fn new(color: Vec3f, ior: f32) -> Self {
+        Dielectric {
+            color,
+            ior,
+            film: None,
+            absorption: Vec3f::new_uniform(0.0),
+        }
+    }
+
+    pub fn with_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    /// Absorb light travelling inside, following the Beer-Lambert law, so that `color` is what
+    /// remains of white light after `distance`. Thick parts then look more deeply tinted than
+    /// thin ones.
+    pub fn with_absorption(mut self, color: Vec3f, distance: f32) -> Self {
+        let absorption = |remaining: f32| -remaining.clamp(1e-6, 1.0).ln() / distance;
+        self.absorption = Vec3f::new(
+            absorption(color.x),
+            absorption(color.y),
+            absorption(color.z),
+        );
+        self
+    }
+
+    /// Start building clear glass.
+    pub fn builder() -> DielectricBuilder {
+        DielectricBuilder {
+            color: Vec3f::new_uniform(1.0),
+            ior: Dielectric::GLASS,
+            film: None,
+            absorption: None,
+        }
+    }
+}
+
+/// Builds a [`Dielectric`] material, checking it makes sense once it is finished.
+pub struct DielectricBuilder {
+    color: Vec3f,
+    ior: f32,
+    film: Option<ThinFilm>,
+    absorption: Option<(Vec3f, f32)>,
+}
+
+impl DielectricBuilder {
+    /// Tint of the light refracted through the surface.
+    pub fn color(mut self, color: Vec3f) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn ior(mut self, ior: f32) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    pub fn film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    /// Absorb light travelling inside, see [`Dielectric::with_absorption`].
+    pub fn absorption(mut self, color: Vec3f, distance: f32) -> Self {
+        self.absorption = Some((color, distance));
+        self
+    }
+
+    /// Finish the material, or describe the first problem with it.
+    pub fn build(self) -> Result<Dielectric, String> {
+        check_reflectance("dielectric color", self.color)?;
+        check_ior("dielectric", self.ior)?;
+        let mut dielectric = Dielectric::new(self.color, self.ior);
+        if let Some(film) = self.film {
+            check_film(film)?;
+            dielectric = dielectric.with_film(film);
+        }
+        if let Some((color, distance)) = self.absorption {
+            check_reflectance("absorption color", color)?;
+            if !(distance > 0.0 && distance.is_finite()) {
+                return Err(format!(
+                    "absorption distance is {distance}, which must be positive"
+                ));
+            }
+            dielectric = dielectric.with_absorption(color, distance);
+        }
+        Ok(dielectric)
+    }
+}
+
+/// Fraction of unpolarized light which is reflected at an interface between media with indices
+/// of refraction `eta_i` and `eta_t`, given the cosines of the incident and transmitted angles.
+fn fresnel(cos_i: f32, cos_t: f32, eta_i: f32, eta_t: f32) -> f32 {
+    let parallel = (eta_t * cos_i - eta_i * cos_t) / (eta_t * cos_i + eta_i * cos_t);
+    let perpendicular = (eta_i * cos_i - eta_t * cos_t) / (eta_i * cos_i + eta_t * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) * 0.5
+}
+
+/// Split `ray` into reflected and refracted rays at a smooth interface between two media, with
+/// the refracted light tinted by `tint`, and the interface optionally coated by a thin film.
+fn reflect_or_refract(
+    ray: &Ray,
+    interaction: &Interaction,
+    tint: Vec3f,
+    film: Option<ThinFilm>,
+) -> Vec<(Ray, Vec3f)> {
+    let normal = interaction.normal;
+    let (eta_i, eta_t) = (interaction.incident_ior, interaction.transmitted_ior);
+    let eta = eta_i / eta_t;
+    let cos_i = (-ray.direction.dot_product(normal)).clamp(0.0, 1.0);
+    let sin2_t = eta * eta * (1.0 - cos_i * cos_i);
+    let reflected = interaction.spawn_ray(reflect(ray.direction, normal).normalized());
+
+    // Total internal reflection, where no light is refracted
+    if sin2_t >= 1.0 {
+        return vec![(reflected, Vec3f::new_uniform(1.0))];
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let reflectance = match film {
+        Some(film) => film.reflectance(cos_i, eta_i, Vec3f::new_uniform(eta_t)),
+        None => Vec3f::new_uniform(fresnel(cos_i, cos_t, eta_i, eta_t)),
+    };
+    let refract_dir = ray.direction * eta + normal * (eta * cos_i - cos_t);
+    vec![
+        (reflected, reflectance),
+        (
+            interaction.spawn_ray(refract_dir.normalized()),
+            tint * (Vec3f::new_uniform(1.0) - reflectance),
+        ),
+    ]
+}
+
+impl Material for Dielectric {
+    fn name(&self) -> &'static str {
+        "dielectric"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr {
+            transmission: 1.0,
+            ior: self.ior,
+            ..Pbr::new(self.color, 0.0, 0.0)
+        }
+    }
+
+    fn ior(&self) -> Option<f32> {
+        Some(self.ior)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.absorption
+            .is_positive()
+            .then_some(Medium::new(Vec3f::new_uniform(0.0), self.absorption))
+    }
+
+    fn scatter(
+        &self,
+        ray: &Ray,
+        interaction: &Interaction,
+        _rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        Some(reflect_or_refract(ray, interaction, self.color, self.film))
+    }
+
+    /// The tint of refracted light, less what is reflected head on.
+    fn transparency(&self) -> Vec3f {
+        let r0 = ((self.ior - 1.0) / (self.ior + 1.0)).powi(2);
+        self.color * (1.0 - r0)
+    }
+
+    fn eval(&self, _ray: &Ray, _interaction: &Interaction, _light_dir: Vec3f) -> Vec3f {
+        Vec3f::new_uniform(0.0)
+    }
+}
+
+/// Invisible boundary of a participating medium such as smoke or cloud, which light passes
+/// straight through, to be scattered and absorbed inside. The boundary doesn't bend light, so
+/// the medium is taken to be in air.
+pub struct Volume {
+    pub medium: Medium,
+    /// Density of the medium within a box, from its lower to its upper corner, where it varies.
+    pub density: Option<(DensityGrid, Vec3f, Vec3f)>,
+}
+
+impl Volume {
+    pub fn new(medium: Medium) -> Self {
+        Volume {
+            medium,
+            density: None,
+        }
+    }
+
+    /// Shape the medium by `grid`, stretched over the box from `min` to `max`, scaling the
+    /// medium's scattering and absorption. There's no medium outside the box.
+    pub fn with_density(mut self, grid: DensityGrid, min: Vec3f, max: Vec3f) -> Self {
+        self.density = Some((grid, min, max));
+        self
+    }
+}
+
+impl Material for Volume {
+    fn name(&self) -> &'static str {
+        "volume"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr {
+            transmission: 1.0,
+            ior: 1.0,
+            ..Pbr::new(Vec3f::new_uniform(1.0), 0.0, 0.0)
+        }
+    }
+
+    fn ior(&self) -> Option<f32> {
+        Some(1.0)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        Some(self.medium)
+    }
+
+    fn density(&self, point: Vec3f) -> f32 {
+        let Some((grid, min, max)) = &self.density else {
+            return 1.0;
+        };
+        let size = *max - *min;
+        let local = point - *min;
+        grid.density(Vec3f::new(
+            local.x / size.x,
+            local.y / size.y,
+            local.z / size.z,
+        ))
+    }
+
+    fn max_density(&self) -> Option<f32> {
+        self.density.as_ref().map(|(grid, _, _)| grid.max())
+    }
+
+    fn scatter(
+        &self,
+        ray: &Ray,
+        interaction: &Interaction,
+        _rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        Some(vec![(
+            interaction.spawn_ray(ray.direction),
+            Vec3f::new_uniform(1.0),
+        )])
+    }
+
+    /// Shadows are cast by the medium inside, not the boundary.
+    fn transparency(&self) -> Vec3f {
+        Vec3f::new_uniform(1.0)
+    }
+
+    fn eval(&self, _ray: &Ray, _interaction: &Interaction, _light_dir: Vec3f) -> Vec3f {
+        Vec3f::new_uniform(0.0)
+    }
+}
+
+/// Metal, whose reflections are blurred by its roughness.
+pub struct Metal {
+    pub color: Vec3f,
+    /// How far reflections stray from the mirror direction, from 0 (polished) to 1 (brushed).
+    pub roughness: f32,
+    pub film: Option<ThinFilm>,
+}
+
+impl Metal {
+    pub fn new(color: Vec3f, roughness: f32) -> Self {
+        Metal {
+            color,
+            roughness: roughness.clamp(0.0, 1.0),
+            film: None,
+        }
+    }
+
+    pub fn with_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    /// Start building a polished white metal.
+    pub fn builder() -> MetalBuilder {
+        MetalBuilder {
+            color: Vec3f::new_uniform(1.0),
+            roughness: 0.0,
+            film: None,
+        }
+    }
+}
+
+/// Builds a [`Metal`] material, checking it makes sense once it is finished.
+pub struct MetalBuilder {
+    color: Vec3f,
+    roughness: f32,
+    film: Option<ThinFilm>,
+}
+
+impl MetalBuilder {
+    pub fn color(mut self, color: Vec3f) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// How far reflections stray from the mirror direction, from 0 (polished) to 1 (brushed).
+    pub fn roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    pub fn film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    /// Finish the material, or describe the first problem with it.
+    pub fn build(self) -> Result<Metal, String> {
+        check_reflectance("metal color", self.color)?;
+        check_fraction("metal roughness", self.roughness)?;
+        let mut metal = Metal::new(self.color, self.roughness);
+        if let Some(film) = self.film {
+            check_film(film)?;
+            metal = metal.with_film(film);
+        }
+        Ok(metal)
+    }
+}
+
+impl Material for Metal {
+    fn name(&self) -> &'static str {
+        "metal"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr::new(self.color, 1.0, self.roughness)
+    }
+
+    fn scatter(
+        &self,
+        ray: &Ray,
+        interaction: &Interaction,
+        rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        let mut direction = reflect(ray.direction, interaction.normal);
+        if self.roughness > 0.0 {
+            direction += rng.unit_vector() * self.roughness;
+        }
+        // Reflections perturbed into the surface are absorbed
+        if direction.dot_product(interaction.normal) <= 0.0 {
+            return Some(Vec::new());
+        }
+        let weight = match self.film {
+            Some(film) => film.reflectance(
+                -ray.direction.dot_product(interaction.normal),
+                interaction.incident_ior,
+                ior_from_reflectance(self.color),
+            ),
+            None => self.color,
+        };
+        Some(vec![(
+            interaction.spawn_ray(direction.normalized()),
+            weight,
+        )])
+    }
+
+    /// Once rays are too deep to be scattered, the surface is lit as if it were diffuse.
+    fn eval(&self, _ray: &Ray, _interaction: &Interaction, _light_dir: Vec3f) -> Vec3f {
+        self.color
+    }
+}
+
+/// Glossy reflector using the GGX microfacet distribution, treating the surface as many tiny
+/// mirrors whose orientations spread out with roughness.
+pub struct Microfacet {
+    /// Reflectance at normal incidence, rising to white at grazing angles.
+    pub color: Vec3f,
+    /// Perceptual roughness, from 0 (mirror) to 1.
+    pub roughness: f32,
+    /// How much rougher the surface is along the tangent than across it, from 0 (the same) to 1,
+    /// stretching highlights like those on brushed metal or hair.
+    pub anisotropy: f32,
+    pub film: Option<ThinFilm>,
+}
+
+impl Microfacet {
+    pub fn new(color: Vec3f, roughness: f32) -> Self {
+        Microfacet {
+            color,
+            // Perfectly smooth surfaces make the distribution infinitely narrow
+            roughness: roughness.clamp(0.01, 1.0),
+            anisotropy: 0.0,
+            film: None,
+        }
+    }
+
+    pub fn with_anisotropy(mut self, anisotropy: f32) -> Self {
+        self.anisotropy = anisotropy.clamp(0.0, 1.0);
+        self
+    }
+
+    pub fn with_film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    /// Start building a white microfacet reflector of medium roughness.
+    pub fn builder() -> MicrofacetBuilder {
+        MicrofacetBuilder {
+            color: Vec3f::new_uniform(1.0),
+            roughness: 0.5,
+            anisotropy: 0.0,
+            film: None,
+        }
+    }
+
+    /// GGX width parameters along the tangent and bitangent.
+    fn alpha(&self) -> (f32, f32) {
+        let alpha = self.roughness * self.roughness;
+        // Burley's mapping, which keeps the narrower width from collapsing to nothing
+        let aspect = (1.0 - 0.9 * self.anisotropy).sqrt();
+        (alpha / aspect, alpha * aspect)
+    }
+
+    /// Density of microfacets facing the half vector, given in the local frame.
+    fn distribution(&self, half: Vec3f) -> f32 {
+        let (alpha_x, alpha_y) = self.alpha();
+        let denominator = (half.x / alpha_x).powi(2) + (half.y / alpha_y).powi(2) + half.z * half.z;
+        1.0 / (PI * alpha_x * alpha_y * denominator * denominator)
+    }
+
+    /// Fraction of microfacets visible from a direction, given in the local frame.
+    fn masking(&self, direction: Vec3f) -> f32 {
+        let (alpha_x, alpha_y) = self.alpha();
+        let tan2 = ((alpha_x * direction.x).powi(2) + (alpha_y * direction.y).powi(2))
+            / (direction.z * direction.z);
+        2.0 / (1.0 + (1.0 + tan2).sqrt())
+    }
+
+    /// Reflectance of a microfacet seen at `cos` to its normal. Schlick's approximation of the
+    /// Fresnel reflectance, unless coated by a thin film.
+    fn fresnel(&self, cos: f32) -> Vec3f {
+        match self.film {
+            Some(film) => film.reflectance(cos, 1.0, ior_from_reflectance(self.color)),
+            None => self.color + (Vec3f::new_uniform(1.0) - self.color) * (1.0 - cos).powi(5),
+        }
+    }
+}
+
+/// Builds a [`Microfacet`] material, checking it makes sense once it is finished.
+pub struct MicrofacetBuilder {
+    color: Vec3f,
+    roughness: f32,
+    anisotropy: f32,
+    film: Option<ThinFilm>,
+}
+
+impl MicrofacetBuilder {
+    /// Reflectance at normal incidence, rising to white at grazing angles.
+    pub fn color(mut self, color: Vec3f) -> Self {
+        self.color = color;
+        self
+    }
+
+    /// Perceptual roughness, from 0 (mirror) to 1. Roughness below 0.01 is rendered as 0.01.
+    pub fn roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    /// How much rougher the surface is along the tangent than across it, from 0 to 1.
+    pub fn anisotropy(mut self, anisotropy: f32) -> Self {
+        self.anisotropy = anisotropy;
+        self
+    }
+
+    pub fn film(mut self, film: ThinFilm) -> Self {
+        self.film = Some(film);
+        self
+    }
+
+    /// Finish the material, or describe the first problem with it.
+    pub fn build(self) -> Result<Microfacet, String> {
+        check_reflectance("microfacet color", self.color)?;
+        check_fraction("microfacet roughness", self.roughness)?;
+        check_fraction("microfacet anisotropy", self.anisotropy)?;
+        let mut microfacet =
+            Microfacet::new(self.color, self.roughness).with_anisotropy(self.anisotropy);
+        if let Some(film) = self.film {
+            check_film(film)?;
+            microfacet = microfacet.with_film(film);
+        }
+        Ok(microfacet)
+    }
+}
+
+/// Check that `color`, named `what` in the error, is finite and from 0 to 1 in every channel.
+fn check_reflectance(what: &str, color: Vec3f) -> Result<(), String> {
+    let valid = |c: f32| (0.0..=1.0).contains(&c);
+    if !(valid(color.x) && valid(color.y) && valid(color.z)) {
+        return Err(format!("{what} must be from 0 to 1 in each channel"));
+    }
+    Ok(())
+}
+
+/// Check that `value`, named `what` in the error, is from 0 to 1.
+fn check_fraction(what: &str, value: f32) -> Result<(), String> {
+    if !(0.0..=1.0).contains(&value) {
+        return Err(format!("{what} is {value}, which must be from 0 to 1"));
+    }
+    Ok(())
+}
+
+/// Check that `ior`, the index of refraction of the `what`, is positive.
+fn check_ior(what: &str, ior: f32) -> Result<(), String> {
+    if !(ior > 0.0 && ior.is_finite()) {
+        return Err(format!(
+            "{what} index of refraction is {ior}, which must be positive"
+        ));
+    }
+    Ok(())
+}
+
+fn check_film(film: ThinFilm) -> Result<(), String> {
+    if !(film.thickness >= 0.0 && film.thickness.is_finite()) {
+        return Err(format!(
+            "thin film is {} nm thick, which must not be negative",
+            film.thickness
+        ));
+    }
+    check_ior("thin film", film.ior)
+}
+
+/// Tangent, bitangent and normal at the interaction, as an orthonormal frame.
+fn local_frame(interaction: &Interaction) -> (Vec3f, Vec3f, Vec3f) {
+    let normal = interaction.normal;
+    let tangent = interaction.tangent;
+    let tangent = (tangent - normal * tangent.dot_product(normal)).normalized();
+    (tangent, normal.cross_product(tangent), normal)
+}
+
+/// `direction` in the coordinates of a local frame.
+fn to_local((tangent, bitangent, normal): (Vec3f, Vec3f, Vec3f), direction: Vec3f) -> Vec3f {
+    Vec3f::new(
+        direction.dot_product(tangent),
+        direction.dot_product(bitangent),
+        direction.dot_product(normal),
+    )
+}
+
+impl Material for Microfacet {
+    fn name(&self) -> &'static str {
+        "microfacet"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr::new(self.color, 1.0, self.roughness)
+    }
+
+    /// Samples microfacet normals in proportion to the GGX distribution, and reflects off them.
+    fn scatter(
+        &self,
+        ray: &Ray,
+        interaction: &Interaction,
+        rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        let frame = local_frame(interaction);
+        let (tangent, bitangent, normal) = frame;
+        let (alpha_x, alpha_y) = self.alpha();
+        // The slopes of GGX microfacets are those of a distribution of unit width, stretched by
+        // the width along each axis.
+        let u = rng.next_f32();
+        let phi = 2.0 * PI * rng.next_f32();
+        let slope = (u / (1.0 - u)).sqrt();
+        let half = (tangent * (alpha_x * slope * phi.cos())
+            + bitangent * (alpha_y * slope * phi.sin())
+            + normal)
+            .normalized();
+
+        let direction = reflect(ray.direction, half);
+        let cos_o = -ray.direction.dot_product(normal);
+        let cos_i = direction.dot_product(normal);
+        let cos_oh = -ray.direction.dot_product(half);
+        // Reflections into the surface are absorbed
+        if cos_i <= 0.0 || cos_o <= 0.0 || cos_oh <= 0.0 {
+            return Some(Vec::new());
+        }
+        // The distribution cancels with the sampling density, leaving the Fresnel and masking
+        // terms.
+        let masking = self.masking(to_local(frame, -ray.direction))
+            * self.masking(to_local(frame, direction));
+        let weight = self.fresnel(cos_oh) * (masking * cos_oh / (cos_o * half.dot_product(normal)));
+        Some(vec![(
+            interaction.spawn_ray(direction.normalized()),
+            weight,
+        )])
+    }
+
+    fn eval(&self, ray: &Ray, interaction: &Interaction, light_dir: Vec3f) -> Vec3f {
+        let frame = local_frame(interaction);
+        let outgoing = to_local(frame, -ray.direction);
+        let incoming = to_local(frame, light_dir);
+        let (cos_o, cos_i) = (outgoing.z, incoming.z);
+        if cos_i <= 0.0 || cos_o <= 0.0 {
+            return Vec3f::new_uniform(0.0);
+        }
+        let half = (incoming + outgoing).normalized();
+        let cos_ih = incoming.dot_product(half);
+        // Scaled by pi to match the albedo convention of the diffuse materials
+        self.fresnel(cos_ih)
+            * (PI * self.distribution(half) * self.masking(outgoing) * self.masking(incoming)
+                / (4.0 * cos_o * cos_i))
+    }
+}
+
+/// Translucent material such as wax, marble or skin, where light enters the surface, scatters
+/// around beneath it, and leaves somewhere else.
+pub struct Subsurface {
+    /// Fraction of light surviving each scattering event beneath the surface.
+    pub albedo: Vec3f,
+    /// Average distance light travels between scattering events, for each channel.
+    pub mean_free_path: Vec3f,
+    pub ior: f32,
+}
+
+impl Subsurface {
+    pub fn new(albedo: Vec3f, mean_free_path: Vec3f, ior: f32) -> Self {
+        Subsurface {
+            albedo,
+            mean_free_path,
+            ior,
+        }
+    }
+
+    /// Start building a white material, scattering light beneath it a unit apart on average,
+    /// with the index of refraction of glass.
+    pub fn builder() -> SubsurfaceBuilder {
+        SubsurfaceBuilder {
+            albedo: Vec3f::new_uniform(1.0),
+            mean_free_path: Vec3f::new_uniform(1.0),
+            ior: 1.5,
+        }
+    }
+}
+
+/// Builds a [`Subsurface`] material, checking it makes sense once it is finished.
+pub struct SubsurfaceBuilder {
+    albedo: Vec3f,
+    mean_free_path: Vec3f,
+    ior: f32,
+}
+
+impl SubsurfaceBuilder {
+    /// Fraction of light surviving each scattering event beneath the surface.
+    pub fn albedo(mut self, albedo: Vec3f) -> Self {
+        self.albedo = albedo;
+        self
+    }
+
+    /// Average distance light travels between scattering events, for each channel.
+    pub fn mean_free_path(mut self, mean_free_path: Vec3f) -> Self {
+        self.mean_free_path = mean_free_path;
+        self
+    }
+
+    pub fn ior(mut self, ior: f32) -> Self {
+        self.ior = ior;
+        self
+    }
+
+    /// Finish the material, or describe the first problem with it.
+    pub fn build(self) -> Result<Subsurface, String> {
+        check_reflectance("subsurface albedo", self.albedo)?;
+        let path = self.mean_free_path;
+        let positive = |d: f32| d > 0.0 && d.is_finite();
+        if !(positive(path.x) && positive(path.y) && positive(path.z)) {
+            return Err("subsurface mean free path must be positive in each channel".to_string());
+        }
+        check_ior("subsurface", self.ior)?;
+        Ok(Subsurface::new(self.albedo, path, self.ior))
+    }
+}
+
+impl Material for Subsurface {
+    fn name(&self) -> &'static str {
+        "subsurface"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr {
+            ior: self.ior,
+            ..Pbr::new(self.albedo, 0.0, 0.3)
+        }
+    }
+
+    fn ior(&self) -> Option<f32> {
+        Some(self.ior)
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        let path = self.mean_free_path;
+        let extinction = Vec3f::new(1.0 / path.x, 1.0 / path.y, 1.0 / path.z);
+        Some(Medium::new(
+            extinction * self.albedo,
+            extinction * (Vec3f::new_uniform(1.0) - self.albedo),
+        ))
+    }
+
+    /// The surface itself is smooth, and refracts light into and out of the medium beneath it.
+    fn scatter(
+        &self,
+        ray: &Ray,
+        interaction: &Interaction,
+        _rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        Some(reflect_or_refract(
+            ray,
+            interaction,
+            Vec3f::new_uniform(1.0),
+            None,
+        ))
+    }
+
+    /// Once rays are too deep to be scattered, the surface is lit as if it were diffuse.
+    fn eval(&self, _ray: &Ray, _interaction: &Interaction, _light_dir: Vec3f) -> Vec3f {
+        self.albedo
+    }
+}
+
+/// A smooth transparent layer over another material, like the lacquer on car paint or varnished
+/// wood, adding a white reflection which strengthens at grazing angles.
+pub struct Clearcoat<M> {
+    pub base: M,
+    /// How much of the coat's reflection is seen, from 0 (uncoated) to 1.
+    pub strength: f32,
+    /// The coat's reflections, from a dielectric with an index of refraction of 1.5.
+    coat: Microfacet,
+}
+
+impl<M: Material> Clearcoat<M> {
+    pub fn new(base: M, strength: f32, roughness: f32) -> Self {
+        Clearcoat {
+            base,
+            strength: strength.clamp(0.0, 1.0),
+            coat: Microfacet::new(Vec3f::new_uniform(0.04), roughness),
+        }
+    }
+
+    /// Fraction of light passing through the coat to or from the base, at `cos` to the normal
+    /// on either side.
+    fn transmittance(&self, cos: f32) -> Vec3f {
+        Vec3f::new_uniform(1.0) - self.coat.fresnel(cos.abs()) * self.strength
+    }
+
+    /// Start building a full strength, smooth coat over `base`.
+    pub fn builder(base: M) -> ClearcoatBuilder<M> {
+        ClearcoatBuilder {
+            base,
+            strength: 1.0,
+            roughness: 0.0,
+        }
+    }
+}
+
+/// Builds a [`Clearcoat`] material, checking it makes sense once it is finished.
+pub struct ClearcoatBuilder<M> {
+    base: M,
+    strength: f32,
+    roughness: f32,
+}
+
+impl<M: Material> ClearcoatBuilder<M> {
+    /// How much of the coat's reflection is seen, from 0 (uncoated) to 1.
+    pub fn strength(mut self, strength: f32) -> Self {
+        self.strength = strength;
+        self
+    }
+
+    /// How far the coat's reflections stray from the mirror direction, from 0 to 1.
+    pub fn roughness(mut self, roughness: f32) -> Self {
+        self.roughness = roughness;
+        self
+    }
+
+    /// Finish the material, or describe the first problem with it.
+    pub fn build(self) -> Result<Clearcoat<M>, String> {
+        check_fraction("clearcoat strength", self.strength)?;
+        check_fraction("clearcoat roughness", self.roughness)?;
+        Ok(Clearcoat::new(self.base, self.strength, self.roughness))
+    }
+}
+
+impl<M: Material> Material for Clearcoat<M> {
+    fn name(&self) -> &'static str {
+        "clearcoat"
+    }
+
+    fn pbr(&self) -> Pbr {
+        Pbr {
+            clearcoat: self.strength,
+            clearcoat_roughness: self.coat.roughness,
+            ..self.base.pbr()
+        }
+    }
+
+    fn ior(&self) -> Option<f32> {
+        self.base.ior()
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.base.medium()
+    }
+
+    fn shading_normal(&self, interaction: &Interaction) -> Vec3f {
+        self.base.shading_normal(interaction)
+    }
+
+    fn emitted(&self) -> Vec3f {
+        self.base.emitted() * self.transmittance(1.0)
+    }
+
+    fn transparency(&self) -> Vec3f {
+        self.base.transparency() * self.transmittance(1.0)
+    }
+
+    /// Scatters the base's rays, dimmed by the light reflected off the coat, along with a ray
+    /// reflected off the coat.
+    fn scatter(
+        &self,
+        ray: &Ray,
+        interaction: &Interaction,
+        rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        let mut rays = self.base.scatter(ray, interaction, rng)?;
+        let transmittance = self.transmittance(-ray.direction.dot_product(interaction.normal));
+        for (scattered, weight) in &mut rays {
+            *weight *= transmittance
+                * self.transmittance(scattered.direction.dot_product(interaction.normal));
+        }
+        if let Some(coat) = self.coat.scatter(ray, interaction, rng) {
+            rays.extend(
+                coat.into_iter()
+                    .map(|(ray, weight)| (ray, weight * self.strength)),
+            );
+        }
+        Some(rays)
+    }
+
+    fn samples_lights(&self) -> bool {
+        self.base.samples_lights()
+    }
+
+    fn pdf(&self, ray: &Ray, interaction: &Interaction, direction: Vec3f) -> f32 {
+        self.base.pdf(ray, interaction, direction)
+    }
+
+    fn eval(&self, ray: &Ray, interaction: &Interaction, light_dir: Vec3f) -> Vec3f {
+        let cos_o = -ray.direction.dot_product(interaction.normal);
+        let cos_i = light_dir.dot_product(interaction.normal);
+        self.base.eval(ray, interaction, light_dir)
+            * self.transmittance(cos_o)
+            * self.transmittance(cos_i)
+            + self.coat.eval(ray, interaction, light_dir) * self.strength
+    }
+}
+
+/// Another material with its normals perturbed by a tangent space normal map, adding the shading
+/// of fine bumps and grooves without changing the geometry.
+pub struct NormalMapped<M, T> {
+    pub base: M,
+    /// Normals in the frame of the tangent, bitangent and normal, mapped from `[-1, 1]` to
+    /// `[0, 1]` as normal map images store them.
+    pub normals: T,
+    /// How strongly the normals are perturbed, where 1 follows the map exactly.
+    pub strength: f32,
+}
+
+impl<M: Material, T: Texture> NormalMapped<M, T> {
+    pub fn new(base: M, normals: T) -> Self {
+        NormalMapped {
+            base,
+            normals,
+            strength: 1.0,
+        }
+    }
+
+    pub fn with_strength(mut self, strength: f32) -> Self {
+        self.strength = strength.max(0.0);
+        self
+    }
+}
+
+impl<M: Material, T: Texture> Material for NormalMapped<M, T> {
+    fn name(&self) -> &'static str {
+        self.base.name()
+    }
+
+    fn pbr(&self) -> Pbr {
+        self.base.pbr()
+    }
+
+    fn ior(&self) -> Option<f32> {
+        self.base.ior()
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.base.medium()
+    }
+
+    fn shading_normal(&self, interaction: &Interaction) -> Vec3f {
+        let normal = self.base.shading_normal(interaction);
+        let (tangent, bitangent, normal) = local_frame(&Interaction {
+            normal,
+            ..*interaction
+        });
+        let mapped =
+            self.normals.value(interaction.uv, interaction.point) * 2.0 - Vec3f::new_uniform(1.0);
+        let perturbed = tangent * (mapped.x * self.strength)
+            + bitangent * (mapped.y * self.strength)
+            + normal * mapped.z.max(1e-3);
+        perturbed.normalized()
+    }
+
+    fn emitted(&self) -> Vec3f {
+        self.base.emitted()
+    }
+
+    fn transparency(&self) -> Vec3f {
+        self.base.transparency()
+    }
+
+    fn scatter(
+        &self,
+        ray: &Ray,
+        interaction: &Interaction,
+        rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        self.base.scatter(ray, interaction, rng)
+    }
+
+    fn samples_lights(&self) -> bool {
+        self.base.samples_lights()
+    }
+
+    fn pdf(&self, ray: &Ray, interaction: &Interaction, direction: Vec3f) -> f32 {
+        self.base.pdf(ray, interaction, direction)
+    }
+
+    fn eval(&self, ray: &Ray, interaction: &Interaction, light_dir: Vec3f) -> Vec3f {
+        self.base.eval(ray, interaction, light_dir)
+    }
+}
+
+/// Mix of two materials, by a constant factor or by a mask texture, for effects like rust
+/// spreading over metal or paint worn through to the wood beneath.
+pub struct Blend<A, B, T = Vec3f> {
+    pub first: A,
+    pub second: B,
+    /// Fraction of the second material at each point, from the mean of the texture's channels.
+    pub mask: T,
+}
+
+impl<A: Material, B: Material> Blend<A, B> {
+    /// Mix the materials evenly over the surface, where a factor of 1 is all `second`.
+    pub fn new(first: A, second: B, factor: f32) -> Self {
+        Blend::masked(first, second, Vec3f::new_uniform(factor.clamp(0.0, 1.0)))
+    }
+
+    /// Start building an even mix of half of each material.
+    pub fn builder(first: A, second: B) -> BlendBuilder<A, B> {
+        BlendBuilder {
+            first,
+            second,
+            mask: Vec3f::new_uniform(0.5),
+            factor: Some(0.5),
+        }
+    }
+}
+
+/// Builds a [`Blend`] material, checking it makes sense once it is finished.
+pub struct BlendBuilder<A, B, T = Vec3f> {
+    first: A,
+    second: B,
+    mask: T,
+    /// The factor the materials are mixed by evenly, if they aren't masked by a texture.
+    factor: Option<f32>,
+}
+
+impl<A: Material, B: Material, T: Texture> BlendBuilder<A, B, T> {
+    /// Mix the materials evenly over the surface, where a factor of 1 is all `second`.
+    pub fn factor(self, factor: f32) -> BlendBuilder<A, B> {
+        BlendBuilder {
+            first: self.first,
+            second: self.second,
+            mask: Vec3f::new_uniform(factor),
+            factor: Some(factor),
+        }
+    }
+
+    /// Mix the materials by a texture, which is all `first` where black and all `second` where
+    /// white.
+    pub fn mask<U: Texture>(self, mask: U) -> BlendBuilder<A, B, U> {
+        BlendBuilder {
+            first: self.first,
+            second: self.second,
+            mask,
+            factor: None,
+        }
+    }
+
+    /// Finish the material, or describe the first problem with it.
+    pub fn build(self) -> Result<Blend<A, B, T>, String> {
+        if let Some(factor) = self.factor {
+            check_fraction("blend factor", factor)?;
+        }
+        Ok(Blend::masked(self.first, self.second, self.mask))
+    }
+}
+
+impl<A: Material, B: Material, T: Texture> Blend<A, B, T> {
+    /// Mix the materials by a texture, which is all `first` where black and all `second` where
+    /// white.
+    pub fn masked(first: A, second: B, mask: T) -> Self {
+        Blend {
+            first,
+            second,
+            mask,
+        }
+    }
+
+    /// Fraction of the second material at the interaction.
+    fn factor(&self, interaction: &Interaction) -> f32 {
+        mask_factor(self.mask.value(interaction.uv, interaction.point))
+    }
+
+    /// Fraction of the second material averaged over the surface.
+    fn average_factor(&self) -> f32 {
+        mask_factor(self.mask.average())
+    }
+}
+
+fn mask_factor(mask: Vec3f) -> f32 {
+    ((mask.x + mask.y + mask.z) / 3.0).clamp(0.0, 1.0)
+}
+
+impl<A: Material, B: Material, T: Texture> Material for Blend<A, B, T> {
+    fn name(&self) -> &'static str {
+        "blend"
+    }
+
+    fn pbr(&self) -> Pbr {
+        let (a, b) = (self.first.pbr(), self.second.pbr());
+        let t = self.average_factor();
+        let lerp = |a: f32, b: f32| a * (1.0 - t) + b * t;
+        Pbr {
+            base_color: a.base_color * (1.0 - t) + b.base_color * t,
+            metallic: lerp(a.metallic, b.metallic),
+            roughness: lerp(a.roughness, b.roughness),
+            emission: a.emission * (1.0 - t) + b.emission * t,
+            transmission: lerp(a.transmission, b.transmission),
+            ior: lerp(a.ior, b.ior),
+            clearcoat: lerp(a.clearcoat, b.clearcoat),
+            clearcoat_roughness: lerp(a.clearcoat_roughness, b.clearcoat_roughness),
+        }
+    }
+
+    fn ior(&self) -> Option<f32> {
+        self.first.ior().or(self.second.ior())
+    }
+
+    fn medium(&self) -> Option<Medium> {
+        self.first.medium().or(self.second.medium())
+    }
+
+    fn shading_normal(&self, interaction: &Interaction) -> Vec3f {
+        let t = self.factor(interaction);
+        (self.first.shading_normal(interaction) * (1.0 - t)
+            + self.second.shading_normal(interaction) * t)
+            .normalized()
+    }
+
+    fn emitted(&self) -> Vec3f {
+        let t = self.average_factor();
+        self.first.emitted() * (1.0 - t) + self.second.emitted() * t
+    }
+
+    fn transparency(&self) -> Vec3f {
+        let t = self.average_factor();
+        self.first.transparency() * (1.0 - t) + self.second.transparency() * t
+    }
+
+    /// Scatters as one material or the other, chosen with the probability of its share of the
+    /// mix, so the weights need no adjusting.
+    fn scatter(
+        &self,
+        ray: &Ray,
+        interaction: &Interaction,
+        rng: &mut Rng,
+    ) -> Option<Vec<(Ray, Vec3f)>> {
+        if rng.next_f32() < self.factor(interaction) {
+            self.second.scatter(ray, interaction, rng)
+        } else {
+            self.first.scatter(ray, interaction, rng)
+        }
+    }
+
+    /// Only when both materials sample lights, since otherwise the scattered rays of the one
+    /// which doesn't would miss the lights.
+    fn samples_lights(&self) -> bool {
+        self.first.samples_lights() && self.second.samples_lights()
+    }
+
+    fn pdf(&self, ray: &Ray, interaction: &Interaction, direction: Vec3f) -> f32 {
+        let t = self.factor(interaction);
+        self.first.pdf(ray, interaction, direction) * (1.0 - t)
+            + self.second.pdf(ray, interaction, direction) * t
+    }
+
+    fn eval(&self, ray: &Ray, interaction: &Interaction, light_dir: Vec3f) -> Vec3f {
+        let t = self.factor(interaction);
+        self.first.eval(ray, interaction, light_dir) * (1.0 - t)
+            + self.second.eval(ray, interaction, light_dir) * t
+    }
+}
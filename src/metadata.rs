@@ -0,0 +1,171 @@
+//! What an image was rendered from and with, written into it so every render describes itself:
+//! its resolution, samples, integrator, seed and a hash of the scene, along with how long it took.
+
+use crate::{
+    integrator::IntegratorKind,
+    scene::{Background, Scene},
+    settings::RenderSettings,
+    Vec3f,
+};
+use std::time::Duration;
+
+/// What an image was rendered from and with, as far as is known before it's rendered.
+pub struct Metadata {
+    pub width: usize,
+    pub height: usize,
+    pub samples_per_pixel: usize,
+    /// The integrator and its setting, if it has one, as given on the command line, such as
+    /// `path` or `photon-map 100000`.
+    pub integrator: String,
+    pub seed: u64,
+    /// Hash of the scene, see [`scene_hash`].
+    pub scene_hash: u64,
+}
+
+impl Metadata {
+    pub fn new(scene: &Scene, settings: &RenderSettings) -> Self {
+        Metadata {
+            width: settings.width,
+            height: settings.height,
+            samples_per_pixel: settings.samples_per_pixel,
+            integrator: integrator(settings.integrator),
+            seed: settings.seed,
+            scene_hash: scene_hash(scene),
+        }
+    }
+
+    /// Names and values of the metadata as text, as image formats store it.
+    pub fn entries(&self) -> Vec<(&'static str, String)> {
+        vec![
+            ("software", "rayox".to_string()),
+            ("resolution", format!("{}x{}", self.width, self.height)),
+            ("samples", self.samples_per_pixel.to_string()),
+            ("integrator", self.integrator.clone()),
+            ("seed", self.seed.to_string()),
+            ("sceneHash", format!("{:016x}", self.scene_hash)),
+        ]
+    }
+}
+
+/// The integrator's name, followed by its setting for those with one.
+fn integrator(kind: IntegratorKind) -> String {
+    let name = kind.name();
+    match kind {
+        IntegratorKind::PhotonMapping { photons } => format!("{name} {photons}"),
+        IntegratorKind::Metropolis { mutations } => format!("{name} {mutations}"),
+        IntegratorKind::AmbientOcclusion { distance } => format!("{name} {distance}"),
+        _ => name.to_string(),
+    }
+}
+
+/// How long a render took, as text, for formats storing their metadata as text.
+pub fn render_time(duration: Duration) -> String {
+    format!("{:.3} s", duration.as_secs_f64())
+}
+
+/// A 64 bit FNV-1a hash of the scene's spheres, materials, camera, background and fog, which
+/// tells renders of different scenes apart. Environment maps are hashed by their intensity and
+/// number of portals, not their pixels.
+pub fn scene_hash(scene: &Scene) -> u64 {
+    let mut hash = Fnv::default();
+    for sphere in &scene.spheres {
+        hash.vec(sphere.center);
+        hash.float(sphere.radius);
+        hash.bytes(&(sphere.material.0 as u64).to_le_bytes());
+    }
+    for material in &scene.materials {
+        hash.bytes(material.name().as_bytes());
+        let pbr = material.pbr();
+        hash.vec(pbr.base_color);
+        hash.vec(pbr.emission);
+        for value in [
+            pbr.metallic,
+            pbr.roughness,
+            pbr.transmission,
+            pbr.ior,
+            pbr.clearcoat,
+            pbr.clearcoat_roughness,
+        ] {
+            hash.float(value);
+        }
+    }
+    let view = scene.camera.view();
+    for vector in [view.eye, view.right, view.up, view.forward] {
+        hash.vec(vector);
+    }
+    hash.float(view.fov);
+    hash.bytes(format!("{:?}", view.projection).as_bytes());
+    match &scene.background {
+        Background::Uniform(color) => {
+            hash.bytes(b"uniform");
+            hash.vec(*color);
+        }
+        Background::Sky(sky) => {
+            hash.bytes(b"sky");
+            for color in [
+                sky.sun_direction,
+                sky.sun_color,
+                sky.zenith_color,
+                sky.horizon_color,
+                sky.ground_color,
+            ] {
+                hash.vec(color);
+            }
+        }
+        Background::Environment(environment) => {
+            hash.bytes(b"environment");
+            hash.float(environment.intensity);
+            hash.bytes(&(environment.portals.len() as u64).to_le_bytes());
+        }
+    }
+    if let Some(fog) = &scene.fog {
+        hash.bytes(b"fog");
+        hash.vec(fog.scattering);
+        hash.vec(fog.absorption);
+        hash.float(fog.anisotropy);
+    }
+    hash.0
+}
+
+/// A 64 bit FNV-1a hash of the scene, see [`scene_hash`], and of the settings which make each
+/// of its samples what it is: the integrator, sampler, filter, depth and clamping. Samples of
+/// renders with the same hash, size and seed can be accumulated into one image.
+pub fn render_hash(scene: &Scene, settings: &RenderSettings) -> u64 {
+    let mut hash = Fnv::default();
+    hash.bytes(&scene_hash(scene).to_le_bytes());
+    hash.bytes(integrator(settings.integrator).as_bytes());
+    hash.bytes(format!("{:?} {:?}", settings.sampler, settings.filter).as_bytes());
+    hash.bytes(&(settings.max_depth as u64).to_le_bytes());
+    match settings.max_indirect {
+        Some(max) => hash.float(max),
+        None => hash.bytes(b"unclamped"),
+    }
+    hash.0
+}
+
+/// State of a 64 bit FNV-1a hash, which is simple and the same on every platform and release.
+struct Fnv(u64);
+
+impl Default for Fnv {
+    fn default() -> Self {
+        Fnv(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv {
+    fn bytes(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn float(&mut self, value: f32) {
+        self.bytes(&value.to_bits().to_le_bytes());
+    }
+
+    fn vec(&mut self, vector: Vec3f) {
+        for value in [vector.x, vector.y, vector.z] {
+            self.float(value);
+        }
+    }
+}
@@ -0,0 +1,233 @@
+//! Metropolis light transport in primary sample space, after Kelemen et al. Rather than tracing
+//! each pixel independently, Markov chains wander over the random numbers which an integrator,
+//! usually the path tracer, makes paths from, perturbing the numbers of a path slightly to find
+//! paths nearby, or replacing them all now and then to jump somewhere new. Paths are visited in
+//! proportion to the light they carry, so once a chain finds light which is hard to reach, such
+//! as light squeezing through a gap or focused through glass, it explores the paths around it
+//! rather than losing it again.
+//!
+//! The image is brightest where chains spend the most time, scaled to the scene's overall
+//! brightness, which is first estimated from independent paths.
+
+use crate::{
+    framebuffer::{Framebuffer, PixelFormat},
+    integrator::Integrator,
+    rng::{stream_seed, Rng},
+    scene::Scene,
+    Vec3f,
+};
+
+/// Independent paths traced to estimate the scene's overall brightness, and to start the
+/// chains from.
+pub const BOOTSTRAP_PATHS: usize = 100_000;
+
+/// Markov chains the mutations are shared among, so the image doesn't depend on where a single
+/// chain happened to start.
+pub const CHAINS: usize = 256;
+
+/// Probability of a mutation replacing every number, rather than perturbing them.
+const LARGE_STEP_PROBABILITY: f32 = 0.3;
+
+/// Standard deviation of the perturbation of each number by a small step.
+const SMALL_STEP_SIGMA: f32 = 0.01;
+
+/// A number of the path being explored, with the value it had before the current mutation.
+#[derive(Copy, Clone, Default)]
+struct PrimarySample {
+    value: f32,
+    /// Iteration at which the value was last changed.
+    modified: usize,
+    backup: f32,
+    backup_modified: usize,
+}
+
+/// The random numbers of the path a Markov chain is at, which are mutated as they're used so
+/// only the numbers a path actually uses are ever changed.
+pub struct PrimarySamples {
+    /// Generates the mutations.
+    rng: Rng,
+    samples: Vec<PrimarySample>,
+    /// Index of the next number handed out.
+    index: usize,
+    iteration: usize,
+    large_step: bool,
+    /// Iteration of the last large step which was accepted.
+    last_large_step: usize,
+}
+
+impl PrimarySamples {
+    /// Numbers for a path from `seed`, which are the same whenever the seed is.
+    pub fn new(seed: u64) -> Self {
+        PrimarySamples {
+            rng: Rng::new(seed),
+            samples: Vec::new(),
+            index: 0,
+            iteration: 0,
+            large_step: true,
+            last_large_step: 0,
+        }
+    }
+
+    /// Start a mutation, handing out the numbers from the beginning.
+    pub fn start_iteration(&mut self) {
+        self.iteration += 1;
+        self.large_step = self.rng.next_f32() < LARGE_STEP_PROBABILITY;
+        self.index = 0;
+    }
+
+    /// Keep the numbers of the mutated path.
+    pub fn accept(&mut self) {
+        if self.large_step {
+            self.last_large_step = self.iteration;
+        }
+    }
+
+    /// Go back to the numbers from before the mutation.
+    pub fn reject(&mut self) {
+        for sample in &mut self.samples {
+            if sample.modified == self.iteration {
+                sample.value = sample.backup;
+                sample.modified = sample.backup_modified;
+            }
+        }
+        self.iteration -= 1;
+    }
+
+    /// The next number of the path, in `[0, 1)`, mutated for the current iteration.
+    pub fn next_sample(&mut self) -> f32 {
+        if self.index >= self.samples.len() {
+            self.samples.push(PrimarySample::default());
+        }
+        let sample = &mut self.samples[self.index];
+        self.index += 1;
+
+        // Numbers not used since a large step have to catch up with it
+        if sample.modified < self.last_large_step {
+            sample.value = self.rng.next_f32();
+            sample.modified = self.last_large_step;
+        }
+        sample.backup = sample.value;
+        sample.backup_modified = sample.modified;
+        if self.large_step {
+            sample.value = self.rng.next_f32();
+        } else {
+            // Every small step the number missed is made at once
+            let steps = (self.iteration - sample.modified) as f32;
+            sample.value += self.rng.normal() * SMALL_STEP_SIGMA * steps.sqrt();
+            sample.value -= sample.value.floor();
+            // A tiny negative value rounds up to exactly one
+            if sample.value >= 1.0 {
+                sample.value = 0.0;
+            }
+        }
+        sample.modified = self.iteration;
+        sample.value
+    }
+}
+
+/// A path made by `integrator` from the numbers handed out by `rng`: the pixel it lands on, and
+/// the light it carries there. Paths which produce a NaN or infinite value carry none.
+fn trace_path(
+    scene: &Scene,
+    integrator: &dyn Integrator,
+    width: usize,
+    height: usize,
+    rng: &mut Rng,
+) -> (usize, usize, Vec3f) {
+    let (px, py) = (rng.next_f32(), rng.next_f32());
+    let x = ((px * width as f32) as usize).min(width - 1);
+    let y = ((py * height as f32) as usize).min(height - 1);
+    let aspect_ratio = width as f32 / height as f32;
+    let radiance = scene
+        .camera
+        .generate_ray(px, py, aspect_ratio, rng)
+        .and_then(|ray| integrator.trace(ray, scene, rng).ok())
+        .unwrap_or_default();
+    (x, y, radiance)
+}
+
+/// Render an image `width` by `height` pixels, with an average of `mutations_per_pixel`
+/// mutations of `integrator`'s paths for each pixel, made from the render's `seed`.
+pub fn render(
+    scene: &Scene,
+    integrator: &dyn Integrator,
+    width: usize,
+    height: usize,
+    mutations_per_pixel: usize,
+    format: PixelFormat,
+    seed: u64,
+) -> Framebuffer {
+    let mut framebuffer = Framebuffer::new(width, height, format);
+
+    // Brightness of independent paths, from which the chains start in proportion
+    let mut brightness = Vec::with_capacity(BOOTSTRAP_PATHS);
+    let mut total = 0.0;
+    for path in 0..BOOTSTRAP_PATHS {
+        let mut rng = Rng::replaying(PrimarySamples::new(stream_seed(seed, path as u64)));
+        let (_, _, radiance) = trace_path(scene, integrator, width, height, &mut rng);
+        total += radiance.luminance();
+        brightness.push(total);
+    }
+    if total <= 0.0 {
+        return framebuffer;
+    }
+    // Each visit to a path counts for the scene's average brightness, spread over the mutations
+    let scale = total / BOOTSTRAP_PATHS as f32 / mutations_per_pixel as f32;
+
+    let mutations = mutations_per_pixel * width * height;
+    let mut choose = Rng::new(stream_seed(seed, u64::MAX));
+    for chain in 0..CHAINS {
+        let target = choose.next_f32() * total;
+        let path = brightness
+            .partition_point(|&sum| sum <= target)
+            .min(BOOTSTRAP_PATHS - 1);
+        let mut rng = Rng::replaying(PrimarySamples::new(stream_seed(seed, path as u64)));
+        let mut current = trace_path(scene, integrator, width, height, &mut rng);
+        // The mutations are shared out as evenly as they go
+        let chain_mutations = mutations / CHAINS + usize::from(chain < mutations % CHAINS);
+        for _ in 0..chain_mutations {
+            let samples = rng.primary_samples().expect("chain replays its numbers");
+            samples.start_iteration();
+            let proposed = trace_path(scene, integrator, width, height, &mut rng);
+            let (current_luminance, proposed_luminance) =
+                (current.2.luminance(), proposed.2.luminance());
+            let acceptance = if current_luminance > 0.0 {
+                (proposed_luminance / current_luminance).min(1.0)
+            } else {
+                1.0
+            };
+
+            // Both paths are counted, by how likely the chain is to move to each, so the
+            // rejected paths still add to the image
+            if proposed_luminance > 0.0 {
+                splat(
+                    &mut framebuffer,
+                    &proposed,
+                    acceptance * scale / proposed_luminance,
+                );
+            }
+            if current_luminance > 0.0 {
+                splat(
+                    &mut framebuffer,
+                    &current,
+                    (1.0 - acceptance) * scale / current_luminance,
+                );
+            }
+
+            let samples = rng.primary_samples().expect("chain replays its numbers");
+            if choose.next_f32() < acceptance {
+                samples.accept();
+                current = proposed;
+            } else {
+                samples.reject();
+            }
+        }
+    }
+    framebuffer
+}
+
+/// Add `weight` of a path's light to the pixel it landed on.
+fn splat(framebuffer: &mut Framebuffer, (x, y, radiance): &(usize, usize, Vec3f), weight: f32) {
+    let color = framebuffer.get(*x, *y) + *radiance * weight;
+    framebuffer.set(*x, *y, color);
+}
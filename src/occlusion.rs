@@ -0,0 +1,36 @@
+//! Ambient occlusion, a quick preview of a scene's shape which shades each point by how much of
+//! the sky above it is hidden by nearby surfaces, ignoring lights and materials.
+
+use crate::{rng::Rng, scene::Scene, Ray, Vec3f};
+
+/// Rays cast over the hemisphere above each point.
+pub const AMBIENT_OCCLUSION_SAMPLES: usize = 16;
+
+/// Brightness where the ray hits the scene, from black where fully occluded to white where
+/// nothing lies within `distance` above the surface. Rays which miss are white.
+pub fn trace(ray: Ray, scene: &Scene, distance: f32, rng: &mut Rng) -> Vec3f {
+    let Some(hit) = scene.intersect(&ray) else {
+        return Vec3f::new_uniform(1.0);
+    };
+    let sphere = &scene.spheres[hit.sphere];
+    let point = ray.origin + ray.direction * hit.t;
+    let mut normal = (point - sphere.center).normalized();
+    if ray.direction.dot_product(normal) > 0.0 {
+        normal = -normal;
+    }
+    let bias = 1e-4_f32.max(sphere.radius * 1e-6);
+    let origin = point + normal * bias;
+
+    // Cosine weighted rays, so occluders overhead count for more than those near the horizon
+    let mut open = 0;
+    for _ in 0..AMBIENT_OCCLUSION_SAMPLES {
+        let direction = (normal + rng.unit_vector()).normalized();
+        let occluded = scene
+            .intersect(&Ray { origin, direction })
+            .is_some_and(|hit| hit.t < distance);
+        if !occluded {
+            open += 1;
+        }
+    }
+    Vec3f::new_uniform(open as f32 / AMBIENT_OCCLUSION_SAMPLES as f32)
+}
@@ -0,0 +1,277 @@
+//! Writing rendered images to files, in the format their extension names.
+
+use crate::{
+    exr::ExrWriter, framebuffer::Framebuffer, hdr::HdrWriter, jpeg::JpegWriter, metadata::Metadata,
+    png::PngWriter, settings::RenderSettings, tiff::TiffWriter, Vec3f,
+};
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, Write},
+    path::Path,
+    time::Duration,
+};
+
+/// Writes an image in rows from the top, which can be written a few rows at a time as they're
+/// rendered.
+pub trait ImageWriter {
+    /// Write the rows of `framebuffer`, below any written already, encoded as `settings` say.
+    fn write_rows(
+        &mut self,
+        framebuffer: &Framebuffer,
+        settings: &RenderSettings,
+    ) -> io::Result<()>;
+
+    /// Finish writing the image, once all of its rows have been written, recording that it took
+    /// `render_time` to render in formats with metadata.
+    fn finish(self: Box<Self>, render_time: Duration) -> io::Result<()>;
+}
+
+/// Image file formats which can be written.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Binary PPM, which is simple but which few programs open.
+    Ppm,
+    /// PNG, in 8 or 16 bits per channel.
+    Png,
+    /// Uncompressed TIFF, in 8 or 16 bits per channel.
+    Tiff,
+    /// Lossy JPEG, at the settings' quality, for sharing previews.
+    Jpeg,
+    /// OpenEXR, keeping the light as it was rendered, in floats.
+    Exr,
+    /// Radiance RGBE, keeping the light as it was rendered, in a quarter of the space of EXR.
+    Hdr,
+}
+
+impl Format {
+    /// The format named by the extension of `path`.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "ppm" => Some(Format::Ppm),
+            "png" => Some(Format::Png),
+            "tif" | "tiff" => Some(Format::Tiff),
+            "jpg" | "jpeg" => Some(Format::Jpeg),
+            "exr" => Some(Format::Exr),
+            "hdr" => Some(Format::Hdr),
+            _ => None,
+        }
+    }
+
+    /// Whether images in this format can have an alpha channel.
+    pub fn has_alpha(self) -> bool {
+        matches!(self, Format::Png | Format::Tiff | Format::Exr)
+    }
+
+    /// Start writing an image in this format to `writer`, the size, depth, precision and quality
+    /// `settings` say, with an alpha channel if they ask for one and the format has one. PNG and
+    /// EXR images keep `metadata`.
+    pub fn writer<'a>(
+        self,
+        writer: impl Write + Seek + 'a,
+        settings: &RenderSettings,
+        metadata: &Metadata,
+    ) -> io::Result<Box<dyn ImageWriter + 'a>> {
+        let (width, height) = (settings.width, settings.height);
+        let (sixteen_bit, alpha) = (settings.sixteen_bit, settings.alpha);
+        Ok(match self {
+            Format::Ppm => Box::new(PpmWriter::new(writer, width, height)?),
+            Format::Png => Box::new(PngWriter::new(
+                writer,
+                width,
+                height,
+                sixteen_bit,
+                alpha,
+                metadata,
+            )?),
+            Format::Tiff => Box::new(TiffWriter::new(writer, width, height, sixteen_bit, alpha)?),
+            Format::Jpeg => Box::new(JpegWriter::new(writer, width, height, settings.quality)?),
+            Format::Exr => Box::new(ExrWriter::new(
+                writer,
+                width,
+                height,
+                settings.half_float,
+                alpha,
+                metadata,
+            )?),
+            Format::Hdr => Box::new(HdrWriter::new(writer, width, height)?),
+        })
+    }
+}
+
+/// Create the file at `path` for an image the size, depth, precision and quality `settings` say,
+/// in the format its extension names, keeping `metadata` if the format can.
+pub fn create(
+    path: &Path,
+    settings: &RenderSettings,
+    metadata: &Metadata,
+) -> io::Result<Box<dyn ImageWriter>> {
+    let format = Format::from_path(path).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Unknown image format `{}`, expected .ppm, .png, .tif, .jpg, .exr or .hdr",
+                path.display()
+            ),
+        )
+    })?;
+    format.writer(BufWriter::new(File::create(path)?), settings, metadata)
+}
+
+/// Writes the same image with several writers at once.
+pub struct TeeWriter<'a>(pub Vec<Box<dyn ImageWriter + 'a>>);
+
+impl ImageWriter for TeeWriter<'_> {
+    fn write_rows(
+        &mut self,
+        framebuffer: &Framebuffer,
+        settings: &RenderSettings,
+    ) -> io::Result<()> {
+        for writer in &mut self.0 {
+            writer.write_rows(framebuffer, settings)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>, render_time: Duration) -> io::Result<()> {
+        for writer in self.0 {
+            writer.finish(render_time)?;
+        }
+        Ok(())
+    }
+}
+
+/// Writes an image as a binary PPM.
+pub struct PpmWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PpmWriter<W> {
+    /// Start writing an image `width` by `height` pixels to `writer`.
+    pub fn new(mut writer: W, width: usize, height: usize) -> io::Result<Self> {
+        write!(writer, "P6\n{} {}\n255\n", width, height)?;
+        Ok(PpmWriter { writer })
+    }
+}
+
+impl<W: Write> ImageWriter for PpmWriter<W> {
+    fn write_rows(
+        &mut self,
+        framebuffer: &Framebuffer,
+        settings: &RenderSettings,
+    ) -> io::Result<()> {
+        for pixel in framebuffer.pixels() {
+            self.writer.write_all(&encode(pixel, settings))?;
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, _render_time: Duration) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Pixel (`x`, `y`) of `framebuffer` and its alpha, with the color divided by the alpha, as PNG
+/// and TIFF store it, rather than multiplied by it, as it's rendered. Fully transparent pixels
+/// are black.
+pub fn straight_alpha(framebuffer: &Framebuffer, x: usize, y: usize) -> (Vec3f, f32) {
+    let (color, alpha) = (framebuffer.get(x, y), framebuffer.alpha(x, y));
+    if alpha > 0.0 {
+        (color * (1.0 / alpha), alpha.min(1.0))
+    } else {
+        (Vec3f::default(), 0.0)
+    }
+}
+
+/// A color as 8 bits per channel, after applying the settings' exposure, tone mapping, either
+/// their gamma or the sRGB curve, and LUT. Colors still outside `[0, 1]` are clamped.
+pub fn encode(color: Vec3f, settings: &RenderSettings) -> [u8; 3] {
+    display(color, settings).map(|channel| (channel * 255.0 + 0.5) as u8)
+}
+
+/// A color as 16 bits per channel, encoded as [`encode`] does.
+pub fn encode_16(color: Vec3f, settings: &RenderSettings) -> [u16; 3] {
+    display(color, settings).map(|channel| (channel * 65535.0 + 0.5) as u16)
+}
+
+/// A color as it's displayed, in `[0, 1]`, after the settings' exposure, tone mapping, curve and
+/// LUT. LUTs are made for colors encoded for display, so it's applied after the curve.
+fn display(color: Vec3f, settings: &RenderSettings) -> [f32; 3] {
+    let color = settings.tone_map.apply(color, settings.exposure);
+    let [r, g, b] = [color.x, color.y, color.z].map(|channel| {
+        let channel = channel.clamp(0.0, 1.0);
+        match settings.gamma {
+            Some(gamma) => channel.powf(1.0 / gamma),
+            None => linear_to_srgb(channel),
+        }
+    });
+    let mut color = Vec3f::new(r, g, b);
+    if let Some(lut) = &settings.lut {
+        color = lut.apply(color);
+    }
+    [color.x, color.y, color.z].map(|channel| channel.clamp(0.0, 1.0))
+}
+
+/// A linear light value in `[0, 1]` encoded with the sRGB curve, as 8 bit images are expected to
+/// be, which spends more of their levels on dark values, where the eye tells them apart.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// A value in `[0, 1]` encoded with the sRGB curve, decoded to linear light.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn srgb_curve_round_trips() {
+        for i in 0..=1000 {
+            let value = i as f32 / 1000.0;
+            let round_trip = srgb_to_linear(linear_to_srgb(value));
+            assert!(
+                (round_trip - value).abs() < 1e-5,
+                "{value} became {round_trip}"
+            );
+        }
+    }
+
+    #[test]
+    fn srgb_curve_matches_the_standard() {
+        assert_eq!(linear_to_srgb(0.0), 0.0);
+        assert!((linear_to_srgb(1.0) - 1.0).abs() < 1e-6);
+        assert!((linear_to_srgb(0.5) - 0.735_357).abs() < 1e-5);
+        assert!((srgb_to_linear(0.5) - 0.214_041).abs() < 1e-5);
+        // The linear segment at the bottom meets the power curve
+        let knee = 0.003_130_8;
+        assert!((linear_to_srgb(knee) - linear_to_srgb(knee + 1e-7)).abs() < 1e-5);
+        assert!((srgb_to_linear(0.040_45) - knee).abs() < 1e-6);
+    }
+
+    #[test]
+    fn encoding_clamps_and_applies_the_curve() {
+        let settings = RenderSettings::default();
+        assert_eq!(encode(Vec3f::new(-1.0, 0.5, 2.0), &settings), [0, 188, 255]);
+        assert_eq!(
+            encode_16(Vec3f::new(0.0, 1.0, 0.0), &settings),
+            [0, 65535, 0]
+        );
+
+        let settings = RenderSettings {
+            gamma: Some(1.0),
+            ..RenderSettings::default()
+        };
+        assert_eq!(encode(Vec3f::new_uniform(0.5), &settings), [128; 3]);
+    }
+}
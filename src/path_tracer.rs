@@ -0,0 +1,99 @@
+//! Unbiased path tracing, which follows a single path of light from the camera through as many
+//! bounces as it takes, for global illumination: soft shadows, and light bouncing between
+//! surfaces and bleeding their colors onto each other.
+
+use crate::{
+    material::Media,
+    rng::Rng,
+    scene::Scene,
+    tracer::{self, Bounces, NonFinite, SampledLights},
+    Ray, Vec3f,
+};
+
+/// Most bounces followed, as a backstop for paths which Russian roulette keeps alive.
+pub const MAX_PATH_DEPTH: usize = 64;
+
+/// Bounces after which paths may be ended at random by Russian roulette.
+const ROULETTE_DEPTH: usize = 3;
+
+/// Light arriving along the ray, or what produced a NaN or infinite value along the way. Lights
+/// are sampled directly at each bounce, weighed against the scattered rays with multiple
+/// importance sampling, while the path carries on in one scattered direction. Light found after
+/// the first bounce is clamped to `max_indirect`, if given, in its brightest component.
+pub fn trace(
+    mut ray: Ray,
+    scene: &Scene,
+    max_indirect: Option<f32>,
+    rng: &mut Rng,
+) -> Result<Vec3f, NonFinite> {
+    let mut radiance = Vec3f::new_uniform(0.0);
+    let mut throughput = Vec3f::new_uniform(1.0);
+    let mut media = Media::default();
+    let mut sampled_lights: Option<SampledLights> = None;
+    let mut bounces = Bounces {
+        depth: 0,
+        max_depth: MAX_PATH_DEPTH,
+        gather_indirect: true,
+        transparent_shadows: true,
+    };
+    loop {
+        let hit = scene.intersect(&ray);
+        let walked = tracer::walk_medium(ray, hit, &media, sampled_lights, scene, rng);
+        // Rare paths which find a bright light after bouncing carry huge values, which would
+        // leave single blown out pixels
+        let clamp = |light: Vec3f| match max_indirect {
+            Some(max) if bounces.depth > 0 && light.max_component() > max => {
+                light * (max / light.max_component())
+            }
+            _ => light,
+        };
+        radiance += clamp(walked.in_scattered * throughput);
+        ray = walked.ray;
+        throughput *= walked.throughput;
+        let lights = walked.sampled_lights;
+        let Some(hit) = walked.hit else {
+            return Ok(radiance + clamp(tracer::escaped(&ray, scene, lights) * throughput));
+        };
+
+        let shaded = tracer::shade(&ray, &hit, scene, bounces, lights, &media, rng);
+        shaded.check(&hit, scene, bounces.depth)?;
+        radiance += clamp(shaded.radiance * throughput);
+
+        // Surfaces which split the light, like glass reflecting and refracting it, continue the
+        // path along one of the rays, chosen in proportion to the light it carries
+        let mut secondary = shaded.secondary;
+        let total: f32 = secondary
+            .iter()
+            .map(|(_, weight, ..)| weight.average())
+            .sum();
+        if total <= 0.0 {
+            return Ok(radiance);
+        }
+        let mut target = rng.next_f32() * total;
+        let index = secondary
+            .iter()
+            .position(|(_, weight, ..)| {
+                target -= weight.average();
+                target < 0.0
+            })
+            .unwrap_or(secondary.len() - 1);
+        let (next, weight, next_media, next_lights) = secondary.swap_remove(index);
+        if weight.average() <= 0.0 {
+            return Ok(radiance);
+        }
+        throughput *= weight * (total / weight.average());
+
+        bounces = bounces.next();
+        if bounces.depth >= ROULETTE_DEPTH {
+            // Paths carrying little light are ended early, and the survivors make up for them
+            let survival = throughput.max_component().min(0.95);
+            if rng.next_f32() >= survival {
+                return Ok(radiance);
+            }
+            throughput = throughput * (1.0 / survival);
+        }
+        ray = next;
+        media = next_media;
+        sampled_lights = next_lights;
+    }
+}
@@ -0,0 +1,420 @@
+//! Photon mapping, which first follows photons out from the lights, storing where they land on
+//! surfaces, then renders by gathering the photons near each point the camera sees. Light
+//! focused through glass or off mirrors into caustics, which camera paths rarely find, is read
+//! straight from the photons which followed it there.
+//!
+//! Photons are stored in two maps: the caustic map, of photons which only passed through glass
+//! or off mirrors on their way from the light, and the global map, of every photon landing on a
+//! surface which isn't a mirror or glass. Points seen by the camera are lit directly by sampling
+//! the lights, as the tracer does, with shadows blocked by glass as the caustic map carries the
+//! light it lets through, and indirectly by a final gather: rays cast from the point, which read
+//! the global map where they land.
+
+use crate::{
+    material::{Material, Media},
+    rng::Rng,
+    scene::Scene,
+    tracer::{self, Bounces, NonFinite, SampledLights, Surface, Walked, MAX_RAY_DEPTH},
+    Ray, Vec3f,
+};
+use std::{collections::BinaryHeap, f32::consts::PI};
+
+/// Most surfaces a photon bounces off before it's given up on.
+pub const MAX_PHOTON_BOUNCES: usize = 16;
+
+/// Photons gathered to estimate the light arriving at a point.
+pub const GATHER_PHOTONS: usize = 64;
+
+/// Furthest photons are gathered from, so sparse photons don't light distant surfaces.
+pub const MAX_GATHER_RADIUS: f32 = 1.0;
+
+/// Rays cast from each point the camera sees, gathering the light bouncing onto it.
+pub const FINAL_GATHER_RAYS: usize = 8;
+
+/// Light carried by a photon to the surface where it landed.
+struct Photon {
+    point: Vec3f,
+    /// Direction the photon arrived in.
+    direction: Vec3f,
+    /// Flux carried by the photon.
+    power: Vec3f,
+    /// Axis the photon splits its subtree of the kd-tree on.
+    axis: u8,
+}
+
+/// Photons stored as a balanced kd-tree, laid out in an array with each subtree's root in the
+/// middle of its range.
+pub struct PhotonMap {
+    photons: Vec<Photon>,
+}
+
+/// The photons traced for a render.
+pub struct PhotonMaps {
+    /// Photons which only passed through glass or off mirrors before landing.
+    pub caustic: PhotonMap,
+    /// Every photon which landed on a surface which isn't a mirror or glass.
+    pub global: PhotonMap,
+}
+
+impl PhotonMaps {
+    /// Follow `count` photons out from the scene's lights, each light sending photons in
+    /// proportion to its power.
+    pub fn trace(scene: &Scene, count: usize, rng: &mut Rng) -> Self {
+        let (mut caustic, mut global) = (Vec::new(), Vec::new());
+        for _ in 0..count {
+            let Some((index, probability)) = scene.choose_light(rng) else {
+                break;
+            };
+            let Some(emission) = scene.lights()[index].emit(rng) else {
+                continue;
+            };
+            let cos = emission
+                .normal
+                .map_or(1.0, |normal| normal.dot_product(emission.ray.direction));
+            let pdf = probability * emission.pdf_position * emission.pdf_direction;
+            let power = emission.radiance * (cos / (pdf * count as f32));
+            trace_photon(scene, emission.ray, power, &mut caustic, &mut global, rng);
+        }
+        PhotonMaps {
+            caustic: PhotonMap::new(caustic),
+            global: PhotonMap::new(global),
+        }
+    }
+}
+
+/// Follow a photon carrying `power` along `ray` until it's absorbed, storing it wherever it
+/// lands on a surface which isn't a mirror or glass.
+fn trace_photon(
+    scene: &Scene,
+    mut ray: Ray,
+    mut power: Vec3f,
+    caustic: &mut Vec<Photon>,
+    global: &mut Vec<Photon>,
+    rng: &mut Rng,
+) {
+    let mut media = Media::default();
+    let mut specular = true;
+    for bounce in 0..MAX_PHOTON_BOUNCES {
+        let Some(hit) = scene.intersect(&ray) else {
+            return;
+        };
+        // Media only absorb photons
+        power *= tracer::transmittance(scene, &media, &ray, hit.t);
+        let material = scene.material(scene.spheres[hit.sphere].material);
+        let surface = Surface::new(&ray, &hit, scene, &media);
+        let interaction = surface.interaction;
+
+        let scattered = material.scatter(&ray, &interaction, rng);
+        let delta = scattered.is_some() && !material.samples_lights();
+        if !delta {
+            let photon = || Photon {
+                point: interaction.point,
+                direction: ray.direction,
+                power,
+                axis: 0,
+            };
+            // Photons straight from the light are its direct light, which is sampled instead
+            if specular && bounce > 0 {
+                caustic.push(photon());
+            }
+            global.push(photon());
+        }
+        specular &= delta;
+
+        // Photons leave surfaces which split the light, like glass reflecting and refracting it,
+        // along one of the rays, chosen in proportion to the light it carries. Surfaces
+        // otherwise only lit directly scatter them in cosine weighted directions.
+        let next = match scattered {
+            Some(mut secondary) => {
+                let total: f32 = secondary.iter().map(|(_, weight)| weight.average()).sum();
+                let mut target = rng.next_f32() * total;
+                let index = secondary
+                    .iter()
+                    .position(|(_, weight)| {
+                        target -= weight.average();
+                        target < 0.0
+                    })
+                    .unwrap_or(secondary.len().saturating_sub(1));
+                (total > 0.0)
+                    .then(|| secondary.swap_remove(index))
+                    .map(|(next, weight)| (next, weight * (total / weight.average())))
+            }
+            None => {
+                let direction = interaction.normal + rng.unit_vector();
+                (direction.magnitude() > 1e-6).then(|| {
+                    let direction = direction.normalized();
+                    let weight = material.eval(&ray, &interaction, direction);
+                    (interaction.spawn_ray(direction), weight)
+                })
+            }
+        };
+        let Some((next, weight)) = next.filter(|(_, weight)| weight.is_positive()) else {
+            return;
+        };
+        // Photons are absorbed at random rather than dimmed, so they keep similar powers
+        let survival = weight.max_component().min(1.0);
+        if rng.next_f32() >= survival {
+            return;
+        }
+        power *= weight * (1.0 / survival);
+        media = surface.media_towards(next.direction, material, &media);
+        ray = next;
+    }
+}
+
+impl PhotonMap {
+    fn new(mut photons: Vec<Photon>) -> Self {
+        balance(&mut photons);
+        PhotonMap { photons }
+    }
+
+    /// Number of photons in the map.
+    pub fn len(&self) -> usize {
+        self.photons.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.photons.is_empty()
+    }
+
+    /// Light reflected back along `ray` by the surface it hit, from the photons landing nearby.
+    fn radiance(&self, ray: &Ray, surface: &Surface, material: &dyn Material) -> Vec3f {
+        let nearest = self.nearest(surface.interaction.point);
+        // Where too few photons landed nearby, they're taken as spread over the furthest they're
+        // gathered from
+        let sqr_radius = match nearest.peek() {
+            None => return Vec3f::new_uniform(0.0),
+            Some(&(furthest, _)) if nearest.len() == GATHER_PHOTONS => f32::from_bits(furthest),
+            Some(_) => MAX_GATHER_RADIUS * MAX_GATHER_RADIUS,
+        };
+        let mut flux = Vec3f::new_uniform(0.0);
+        for (_, index) in nearest {
+            let photon = &self.photons[index];
+            // Only photons arriving on the side the ray sees
+            if photon.direction.dot_product(surface.normal) < 0.0 {
+                flux += material.eval(ray, &surface.interaction, -photon.direction) * photon.power;
+            }
+        }
+        // eval is scaled by pi, relative to the BRDF, and the photons are spread over a disc
+        flux * (1.0 / (PI * PI * sqr_radius))
+    }
+
+    /// The photons nearest `point`, up to [`GATHER_PHOTONS`] within [`MAX_GATHER_RADIUS`], as
+    /// squared distances in their bit patterns, which order the same as the distances, with
+    /// indices. The furthest is at the top.
+    fn nearest(&self, point: Vec3f) -> BinaryHeap<(u32, usize)> {
+        let mut nearest = BinaryHeap::with_capacity(GATHER_PHOTONS + 1);
+        self.search(
+            point,
+            0..self.photons.len(),
+            MAX_GATHER_RADIUS * MAX_GATHER_RADIUS,
+            &mut nearest,
+        );
+        nearest
+    }
+
+    fn search(
+        &self,
+        point: Vec3f,
+        range: std::ops::Range<usize>,
+        max_sqr_distance: f32,
+        nearest: &mut BinaryHeap<(u32, usize)>,
+    ) {
+        if range.is_empty() {
+            return;
+        }
+        let middle = range.start + range.len() / 2;
+        let photon = &self.photons[middle];
+        let offset = component(point, photon.axis) - component(photon.point, photon.axis);
+        let (near, far) = if offset < 0.0 {
+            (range.start..middle, middle + 1..range.end)
+        } else {
+            (middle + 1..range.end, range.start..middle)
+        };
+        self.search(point, near, max_sqr_distance, nearest);
+
+        let bound = |nearest: &BinaryHeap<(u32, usize)>| match nearest.peek() {
+            Some(&(furthest, _)) if nearest.len() == GATHER_PHOTONS => f32::from_bits(furthest),
+            _ => max_sqr_distance,
+        };
+        let sqr_distance = (photon.point - point).sqr_magnitude();
+        if sqr_distance < bound(nearest) {
+            nearest.push((sqr_distance.to_bits(), middle));
+            if nearest.len() > GATHER_PHOTONS {
+                nearest.pop();
+            }
+        }
+        if offset * offset < bound(nearest) {
+            self.search(point, far, max_sqr_distance, nearest);
+        }
+    }
+}
+
+/// Arrange photons into a balanced kd-tree, splitting each subtree at its median along the axis
+/// its photons spread furthest over.
+fn balance(photons: &mut [Photon]) {
+    if photons.len() <= 1 {
+        return;
+    }
+    let (mut min, mut max) = (photons[0].point, photons[0].point);
+    for photon in photons.iter() {
+        let point = photon.point;
+        min = Vec3f::new(min.x.min(point.x), min.y.min(point.y), min.z.min(point.z));
+        max = Vec3f::new(max.x.max(point.x), max.y.max(point.y), max.z.max(point.z));
+    }
+    let extent = max - min;
+    let axis = if extent.x >= extent.y && extent.x >= extent.z {
+        0
+    } else if extent.y >= extent.z {
+        1
+    } else {
+        2
+    };
+    let middle = photons.len() / 2;
+    photons.select_nth_unstable_by(middle, |a, b| {
+        component(a.point, axis).total_cmp(&component(b.point, axis))
+    });
+    photons[middle].axis = axis;
+    let (before, after) = photons.split_at_mut(middle);
+    balance(before);
+    balance(&mut after[1..]);
+}
+
+fn component(v: Vec3f, axis: u8) -> f32 {
+    match axis {
+        0 => v.x,
+        1 => v.y,
+        _ => v.z,
+    }
+}
+
+/// Light arriving along the camera ray, or what produced a NaN or infinite value along the way.
+pub fn trace(
+    ray: Ray,
+    scene: &Scene,
+    photons: &PhotonMaps,
+    rng: &mut Rng,
+) -> Result<Vec3f, NonFinite> {
+    trace_path(ray, scene, photons, 0, None, &Media::default(), rng)
+}
+
+/// Follow a camera ray off mirrors and through glass, until it reaches a surface to gather the
+/// light at.
+fn trace_path(
+    ray: Ray,
+    scene: &Scene,
+    photons: &PhotonMaps,
+    depth: usize,
+    sampled_lights: Option<SampledLights>,
+    media: &Media,
+    rng: &mut Rng,
+) -> Result<Vec3f, NonFinite> {
+    let hit = scene.intersect(&ray);
+    let Walked {
+        ray,
+        hit,
+        throughput,
+        in_scattered,
+        sampled_lights,
+    } = tracer::walk_medium(ray, hit, media, sampled_lights, scene, rng);
+    let Some(hit) = hit else {
+        return Ok(tracer::escaped(&ray, scene, sampled_lights) * throughput + in_scattered);
+    };
+
+    // Surfaces which aren't mirrors or glass gather, scattering a ray which samples the lights
+    let bounces = Bounces {
+        depth,
+        max_depth: MAX_RAY_DEPTH,
+        gather_indirect: true,
+        transparent_shadows: false,
+    };
+    let shaded = tracer::shade(&ray, &hit, scene, bounces, sampled_lights, media, rng);
+    shaded.check(&hit, scene, depth)?;
+    let gathers = shaded
+        .secondary
+        .iter()
+        .any(|(.., sampled_lights)| sampled_lights.is_some());
+    if !gathers {
+        let radiance = shaded.secondary.into_iter().try_fold(
+            shaded.radiance,
+            |radiance, (ray, weight, media, sampled_lights)| {
+                let incoming =
+                    trace_path(ray, scene, photons, depth + 1, sampled_lights, &media, rng)?;
+                Ok(radiance + incoming * weight)
+            },
+        )?;
+        return Ok(radiance * throughput + in_scattered);
+    }
+
+    // Each gather ray comes with its own direct light samples, weighed against it
+    let material = scene.material(scene.spheres[hit.sphere].material);
+    let surface = Surface::new(&ray, &hit, scene, media);
+    let mut gathered = Vec3f::new_uniform(0.0);
+    let mut shaded = Some(shaded);
+    for _ in 0..FINAL_GATHER_RAYS {
+        let shaded = match shaded.take() {
+            Some(shaded) => shaded,
+            None => {
+                let shaded = tracer::shade(&ray, &hit, scene, bounces, sampled_lights, media, rng);
+                shaded.check(&hit, scene, depth)?;
+                shaded
+            }
+        };
+        gathered += shaded.radiance;
+        for (ray, weight, media, sampled_lights) in shaded.secondary {
+            gathered +=
+                gather(ray, scene, photons, depth + 1, sampled_lights, &media, rng)? * weight;
+        }
+    }
+    let caustics = photons.caustic.radiance(&ray, &surface, material);
+    Ok((gathered * (1.0 / FINAL_GATHER_RAYS as f32) + caustics) * throughput + in_scattered)
+}
+
+/// Light arriving along a gather ray, read from the global photon map where it lands, having
+/// followed it off any mirrors and through any glass on the way.
+fn gather(
+    ray: Ray,
+    scene: &Scene,
+    photons: &PhotonMaps,
+    depth: usize,
+    sampled_lights: Option<SampledLights>,
+    media: &Media,
+    rng: &mut Rng,
+) -> Result<Vec3f, NonFinite> {
+    let hit = scene.intersect(&ray);
+    let Walked {
+        ray,
+        hit,
+        throughput,
+        in_scattered,
+        sampled_lights,
+    } = tracer::walk_medium(ray, hit, media, sampled_lights, scene, rng);
+    let Some(hit) = hit else {
+        return Ok(tracer::escaped(&ray, scene, sampled_lights) * throughput + in_scattered);
+    };
+
+    let material = scene.material(scene.spheres[hit.sphere].material);
+    let surface = Surface::new(&ray, &hit, scene, media);
+    let mut radiance = tracer::emitted(&ray, &hit, scene, sampled_lights);
+    match material.scatter(&ray, &surface.interaction, rng) {
+        Some(secondary) if !material.samples_lights() => {
+            if depth < MAX_RAY_DEPTH {
+                for (next, weight) in secondary {
+                    let media = surface.media_towards(next.direction, material, media);
+                    radiance +=
+                        gather(next, scene, photons, depth + 1, None, &media, rng)? * weight;
+                }
+            }
+        }
+        _ => radiance += photons.global.radiance(&ray, &surface, material),
+    }
+    if !radiance.is_finite() {
+        return Err(NonFinite {
+            sphere: hit.sphere,
+            material: material.name(),
+            depth,
+            quantity: "radiance",
+        });
+    }
+    Ok(radiance * throughput + in_scattered)
+}
@@ -0,0 +1,846 @@
+//! PNG encoding. Rows are filtered as the PNG specification recommends, then compressed with
+//! deflate, matching repeated runs of bytes and coding them with the fixed Huffman codes, which
+//! is simple and gets most of the way to a full encoder on rendered images.
+//!
+//! PNGs can be decoded too, for comparing renders and loading textures.
+
+use crate::{
+    framebuffer::Framebuffer,
+    metadata::{self, Metadata},
+    output::{self, ImageWriter},
+    settings::RenderSettings,
+    texture::ImageTexture,
+    Vec3f,
+};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// Writes an image as a PNG, in rows from the top, which can be written a few rows at a time as
+/// they're rendered. Each batch of rows is compressed as a block of the deflate stream.
+pub struct PngWriter<W: Write> {
+    writer: W,
+    /// Write 16 bits per channel rather than 8.
+    sixteen_bit: bool,
+    /// Write an alpha channel after the color.
+    alpha: bool,
+    /// The last row written, which the next is filtered against.
+    previous_row: Vec<u8>,
+    /// The deflate stream, whose whole bytes are written out after each batch of rows.
+    bits: BitWriter,
+    /// Adler-32 checksum of the uncompressed data, as its two sums.
+    adler: (u32, u32),
+}
+
+impl<W: Write> PngWriter<W> {
+    /// Start writing an image `width` by `height` pixels to `writer`, with 16 bits per channel if
+    /// `sixteen_bit`, otherwise 8, and an alpha channel if `alpha`, keeping `metadata` in text
+    /// chunks.
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        sixteen_bit: bool,
+        alpha: bool,
+        metadata: &Metadata,
+    ) -> io::Result<Self> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "image too large for PNG");
+        let mut header = Vec::with_capacity(13);
+        header.extend(u32::try_from(width).map_err(|_| too_large())?.to_be_bytes());
+        header.extend(
+            u32::try_from(height)
+                .map_err(|_| too_large())?
+                .to_be_bytes(),
+        );
+        // RGB or RGBA, deflate compressed, with adaptive filtering and no interlacing
+        let bit_depth = if sixteen_bit { 16 } else { 8 };
+        let color_type = if alpha { 6 } else { 2 };
+        header.extend([bit_depth, color_type, 0, 0, 0]);
+        writer.write_all(&SIGNATURE)?;
+        write_chunk(&mut writer, b"IHDR", &header)?;
+        for (keyword, text) in metadata.entries() {
+            write_text(&mut writer, keyword, &text)?;
+        }
+
+        let mut bits = BitWriter::default();
+        // zlib header, for a 32 KiB window and no preset dictionary
+        bits.bytes.extend([0x78, 0x01]);
+        Ok(PngWriter {
+            writer,
+            sixteen_bit,
+            alpha,
+            previous_row: vec![0; width * bytes_per_pixel(sixteen_bit, alpha)],
+            bits,
+            adler: (1, 0),
+        })
+    }
+}
+
+impl<W: Write> ImageWriter for PngWriter<W> {
+    fn write_rows(
+        &mut self,
+        framebuffer: &Framebuffer,
+        settings: &RenderSettings,
+    ) -> io::Result<()> {
+        let mut data = Vec::with_capacity(framebuffer.height * (1 + self.previous_row.len()));
+        let mut row = Vec::with_capacity(self.previous_row.len());
+        for y in 0..framebuffer.height {
+            row.clear();
+            for x in 0..framebuffer.width {
+                let (color, alpha) = output::straight_alpha(framebuffer, x, y);
+                if self.sixteen_bit {
+                    let channels = output::encode_16(color, settings);
+                    row.extend(channels.iter().flat_map(|channel| channel.to_be_bytes()));
+                    if self.alpha {
+                        row.extend(((alpha * 65535.0 + 0.5) as u16).to_be_bytes());
+                    }
+                } else {
+                    row.extend(output::encode(color, settings));
+                    if self.alpha {
+                        row.push((alpha * 255.0 + 0.5) as u8);
+                    }
+                }
+            }
+            filter_row(
+                &row,
+                &self.previous_row,
+                bytes_per_pixel(self.sixteen_bit, self.alpha),
+                &mut data,
+            );
+            std::mem::swap(&mut row, &mut self.previous_row);
+        }
+        for &byte in &data {
+            self.adler.0 = (self.adler.0 + u32::from(byte)) % 65521;
+            self.adler.1 = (self.adler.1 + self.adler.0) % 65521;
+        }
+
+        // A block with fixed Huffman codes, which isn't the last
+        self.bits.write_bits(0, 1);
+        self.bits.write_bits(1, 2);
+        compress(&data, &mut self.bits);
+        write_literal(&mut self.bits, END_OF_BLOCK);
+        let bytes = std::mem::take(&mut self.bits.bytes);
+        write_chunk(&mut self.writer, b"IDAT", &bytes)
+    }
+
+    fn finish(mut self: Box<Self>, render_time: Duration) -> io::Result<()> {
+        // An empty last block ends the deflate stream
+        self.bits.write_bits(1, 1);
+        self.bits.write_bits(1, 2);
+        write_literal(&mut self.bits, END_OF_BLOCK);
+        self.bits.align();
+        let (a, b) = self.adler;
+        self.bits.bytes.extend(((b << 16) | a).to_be_bytes());
+        let bytes = std::mem::take(&mut self.bits.bytes);
+        write_chunk(&mut self.writer, b"IDAT", &bytes)?;
+        // Text chunks may come after the image, which is where the time it took is known
+        write_text(
+            &mut self.writer,
+            "renderTime",
+            &metadata::render_time(render_time),
+        )?;
+        write_chunk(&mut self.writer, b"IEND", &[])?;
+        self.writer.flush()
+    }
+}
+
+/// Bytes per pixel, with 16 bits per channel if `sixteen_bit`, otherwise 8, and an alpha channel
+/// if `alpha`.
+fn bytes_per_pixel(sixteen_bit: bool, alpha: bool) -> usize {
+    let channels = if alpha { 4 } else { 3 };
+    if sixteen_bit {
+        2 * channels
+    } else {
+        channels
+    }
+}
+
+/// Append `row`, filtered against the row above it, to `data`, with whichever of the filters
+/// leaves the smallest differences, as they compress best. Bytes are predicted from the same
+/// byte of the pixel to the left, `bytes_per_pixel` before.
+fn filter_row(row: &[u8], above: &[u8], bytes_per_pixel: usize, data: &mut Vec<u8>) {
+    let mut best = Vec::new();
+    let mut best_cost = u64::MAX;
+    let mut filtered = Vec::with_capacity(row.len() + 1);
+    for filter in 0..5u8 {
+        filtered.clear();
+        filtered.push(filter);
+        for (i, &value) in row.iter().enumerate() {
+            let (left, upper_left) = match i.checked_sub(bytes_per_pixel) {
+                Some(left) => (row[left], above[left]),
+                None => (0, 0),
+            };
+            let prediction = match filter {
+                0 => 0,
+                1 => left,
+                2 => above[i],
+                3 => ((u16::from(left) + u16::from(above[i])) / 2) as u8,
+                _ => paeth(left, above[i], upper_left),
+            };
+            filtered.push(value.wrapping_sub(prediction));
+        }
+        let cost = filtered[1..]
+            .iter()
+            .map(|&byte| u64::from((byte as i8).unsigned_abs()))
+            .sum();
+        if cost < best_cost {
+            best_cost = cost;
+            std::mem::swap(&mut best, &mut filtered);
+        }
+    }
+    data.extend(best);
+}
+
+/// Whichever of the left, upper and upper left bytes is closest to the gradient through them.
+fn paeth(left: u8, above: u8, upper_left: u8) -> u8 {
+    let estimate = i16::from(left) + i16::from(above) - i16::from(upper_left);
+    let distance = |byte: u8| (estimate - i16::from(byte)).abs();
+    if distance(left) <= distance(above) && distance(left) <= distance(upper_left) {
+        left
+    } else if distance(above) <= distance(upper_left) {
+        above
+    } else {
+        upper_left
+    }
+}
+
+/// Write a chunk, with its length and checksum.
+fn write_chunk(writer: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    let length = u32::try_from(data.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "PNG chunk too large"))?;
+    writer.write_all(&length.to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+    writer.write_all(&(!crc32(crc32(!0, kind), data)).to_be_bytes())
+}
+
+/// Write a text chunk, of `text` under `keyword`, which is written in title case as PNG's
+/// registered keywords are, such as `Software`, so `sceneHash` becomes `Scene Hash`.
+fn write_text(writer: &mut impl Write, keyword: &str, text: &str) -> io::Result<()> {
+    let mut data = Vec::with_capacity(keyword.len() + 4 + text.len());
+    for (i, letter) in keyword.bytes().enumerate() {
+        if i == 0 {
+            data.push(letter.to_ascii_uppercase());
+        } else {
+            if letter.is_ascii_uppercase() {
+                data.push(b' ');
+            }
+            data.push(letter);
+        }
+    }
+    data.push(0);
+    data.extend(text.as_bytes());
+    write_chunk(writer, b"tEXt", &data)
+}
+
+/// Update a CRC-32, as PNG uses, with `bytes`.
+fn crc32(mut crc: u32, bytes: &[u8]) -> u32 {
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xedb8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+/// Deflate's bits, packed from the least significant bit of each byte.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is packed from its most significant bit.
+    fn write_code(&mut self, code: u32, length: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - length), length);
+    }
+
+    /// Pad to a whole byte.
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.write_bits(0, 8 - self.count);
+        }
+    }
+}
+
+const END_OF_BLOCK: u32 = 256;
+
+/// Shortest and longest runs of bytes deflate matches, and how far back it can look for them.
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const WINDOW: usize = 32 * 1024;
+
+/// Earlier runs tried for each match, trading compression for speed.
+const MAX_CHAIN: usize = 64;
+
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Compress `data` into the body of a block with fixed Huffman codes, replacing runs of bytes
+/// seen earlier with how long they are and how far back, found through chains of earlier
+/// positions with the same next three bytes.
+fn compress(data: &[u8], bits: &mut BitWriter) {
+    let mask = (1 << HASH_BITS) - 1;
+    let hash = |i: usize| {
+        ((usize::from(data[i]) << 10) ^ (usize::from(data[i + 1]) << 5) ^ usize::from(data[i + 2]))
+            & mask
+    };
+    let mut head = vec![usize::MAX; 1 << HASH_BITS];
+    let mut previous = vec![usize::MAX; data.len()];
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut length, mut distance) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != usize::MAX && i - candidate <= WINDOW && chain < MAX_CHAIN {
+                let matched = data[candidate..]
+                    .iter()
+                    .zip(&data[i..])
+                    .take(MAX_MATCH)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if matched > length {
+                    (length, distance) = (matched, i - candidate);
+                    if matched == MAX_MATCH {
+                        break;
+                    }
+                }
+                candidate = previous[candidate];
+                chain += 1;
+            }
+        }
+
+        let step = if length >= MIN_MATCH {
+            write_match(bits, length, distance);
+            length
+        } else {
+            write_literal(bits, u32::from(data[i]));
+            1
+        };
+        // Every position passed over can start a later match
+        let end = (i + step).min(data.len().saturating_sub(MIN_MATCH - 1));
+        for (j, previous) in previous.iter_mut().enumerate().take(end).skip(i) {
+            let h = hash(j);
+            *previous = head[h];
+            head[h] = j;
+        }
+        i += step;
+    }
+}
+
+/// Write a literal byte, or the end of block or a match length's symbol, with its fixed code.
+fn write_literal(bits: &mut BitWriter, symbol: u32) {
+    match symbol {
+        0..=143 => bits.write_code(0x30 + symbol, 8),
+        144..=255 => bits.write_code(0x190 + symbol - 144, 9),
+        256..=279 => bits.write_code(symbol - 256, 7),
+        _ => bits.write_code(0xc0 + symbol - 280, 8),
+    }
+}
+
+fn write_match(bits: &mut BitWriter, length: usize, distance: usize) {
+    let code = LENGTH_BASES.partition_point(|&base| usize::from(base) <= length) - 1;
+    write_literal(bits, 257 + code as u32);
+    let extra = length - usize::from(LENGTH_BASES[code]);
+    bits.write_bits(extra as u32, u32::from(LENGTH_EXTRA_BITS[code]));
+
+    let code = DISTANCE_BASES.partition_point(|&base| usize::from(base) <= distance) - 1;
+    bits.write_code(code as u32, 5);
+    let extra = distance - usize::from(DISTANCE_BASES[code]);
+    bits.write_bits(extra as u32, u32::from(DISTANCE_EXTRA_BITS[code]));
+}
+
+/// Decode a PNG with 8 or 16 bits per channel, in gray or RGB with or without alpha, as the
+/// renderer and most other software write them. Colors are taken to be sRGB encoded, as the
+/// renderer writes them, and are decoded to linear light, multiplied by the alpha, as they're
+/// rendered.
+pub fn decode(bytes: &[u8]) -> Result<ImageTexture, String> {
+    let mut chunks = bytes
+        .strip_prefix(&SIGNATURE)
+        .ok_or("expected a PNG signature")?;
+    let mut header = None;
+    let mut compressed = Vec::new();
+    loop {
+        if chunks.len() < 12 {
+            return Err("truncated chunk".to_string());
+        }
+        let length = u32::from_be_bytes([chunks[0], chunks[1], chunks[2], chunks[3]]) as usize;
+        let kind = &chunks[4..8];
+        let data = chunks.get(8..8 + length).ok_or("truncated chunk")?;
+        chunks = chunks.get(12 + length..).ok_or("truncated chunk")?;
+        match kind {
+            b"IHDR" if data.len() == 13 => header = Some(data),
+            b"IDAT" => compressed.extend(data),
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    let header = header.ok_or("missing header")?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (bit_depth, color_type, interlace) = (header[8], header[9], header[12]);
+    if width == 0 || height == 0 {
+        return Err("invalid resolution".to_string());
+    }
+    let channels = match color_type {
+        0 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(format!("unsupported color type {color_type}")),
+    };
+    if bit_depth != 8 && bit_depth != 16 {
+        return Err(format!("unsupported bit depth {bit_depth}"));
+    }
+    if interlace != 0 {
+        return Err("interlaced images aren't supported".to_string());
+    }
+
+    let bytes_per_channel = usize::from(bit_depth / 8);
+    let bytes_per_pixel = channels * bytes_per_channel;
+    let data = inflate(&compressed)?;
+    let row_length = width * bytes_per_pixel;
+    if data.len() < height * (1 + row_length) {
+        return Err("truncated image data".to_string());
+    }
+    let mut pixels = Vec::with_capacity(width * height);
+    let mut above = vec![0; row_length];
+    let mut row = vec![0; row_length];
+    for filtered in data.chunks_exact(1 + row_length).take(height) {
+        unfilter_row(filtered, &above, bytes_per_pixel, &mut row)?;
+        let max = if bit_depth == 16 { 65535.0 } else { 255.0 };
+        for pixel in row.chunks_exact(bytes_per_pixel) {
+            let value = |channel: usize| {
+                let at = channel * bytes_per_channel;
+                let value = if bit_depth == 16 {
+                    u16::from_be_bytes([pixel[at], pixel[at + 1]])
+                } else {
+                    u16::from(pixel[at])
+                };
+                f32::from(value) / max
+            };
+            let (color, alpha) = match channels {
+                1 => (Vec3f::new_uniform(value(0)), 1.0),
+                2 => (Vec3f::new_uniform(value(0)), value(1)),
+                3 => (Vec3f::new(value(0), value(1), value(2)), 1.0),
+                _ => (Vec3f::new(value(0), value(1), value(2)), value(3)),
+            };
+            let decode = output::srgb_to_linear;
+            pixels.push(Vec3f::new(decode(color.x), decode(color.y), decode(color.z)) * alpha);
+        }
+        std::mem::swap(&mut row, &mut above);
+    }
+    Ok(ImageTexture::new(width, height, pixels))
+}
+
+/// Undo the filter of `filtered`, whose first byte names it, against the row `above`, into
+/// `row`, as [`filter_row`] does it.
+fn unfilter_row(
+    filtered: &[u8],
+    above: &[u8],
+    bytes_per_pixel: usize,
+    row: &mut [u8],
+) -> Result<(), String> {
+    let filter = filtered[0];
+    for (i, &value) in filtered[1..].iter().enumerate() {
+        let (left, upper_left) = match i.checked_sub(bytes_per_pixel) {
+            Some(left) => (row[left], above[left]),
+            None => (0, 0),
+        };
+        let prediction = match filter {
+            0 => 0,
+            1 => left,
+            2 => above[i],
+            3 => ((u16::from(left) + u16::from(above[i])) / 2) as u8,
+            4 => paeth(left, above[i], upper_left),
+            _ => return Err(format!("invalid filter {filter}")),
+        };
+        row[i] = value.wrapping_add(prediction);
+    }
+    Ok(())
+}
+
+/// Decompress a zlib stream, with deflate blocks of any kind, checking its Adler-32 checksum.
+pub fn inflate(stream: &[u8]) -> Result<Vec<u8>, String> {
+    let [method, flags, ..] = *stream else {
+        return Err("truncated zlib stream".to_string());
+    };
+    if method & 0x0f != 8 || (u16::from(method) << 8 | u16::from(flags)) % 31 != 0 {
+        return Err("invalid zlib header".to_string());
+    }
+    if flags & 0x20 != 0 {
+        return Err("preset dictionaries aren't supported".to_string());
+    }
+    let mut bits = BitReader {
+        bytes: &stream[2..],
+        position: 0,
+    };
+    let mut data = Vec::new();
+    loop {
+        let last = bits.read(1)? == 1;
+        match bits.read(2)? {
+            0 => {
+                // Stored, as its length, the length's complement and the bytes as they are
+                bits.align();
+                let (length, complement) = (bits.read(16)?, bits.read(16)?);
+                if length != !complement & 0xffff {
+                    return Err("invalid stored block length".to_string());
+                }
+                for _ in 0..length {
+                    data.push(bits.read(8)? as u8);
+                }
+            }
+            1 => {
+                let mut lengths = [0; 288 + 32];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..288].fill(8);
+                lengths[288..].fill(5);
+                let literals = Huffman::new(&lengths[..288]);
+                let distances = Huffman::new(&lengths[288..]);
+                inflate_block(&mut bits, &literals, &distances, &mut data)?;
+            }
+            2 => {
+                let (literals, distances) = read_codes(&mut bits)?;
+                inflate_block(&mut bits, &literals, &distances, &mut data)?;
+            }
+            _ => return Err("invalid block type".to_string()),
+        }
+        if last {
+            break;
+        }
+    }
+    bits.align();
+    let checksum = (0..4).try_fold(0, |checksum, _| {
+        Ok::<_, String>(checksum << 8 | bits.read(8)?)
+    })?;
+    let mut adler = (1, 0);
+    for &byte in &data {
+        adler.0 = (adler.0 + u32::from(byte)) % 65521;
+        adler.1 = (adler.1 + adler.0) % 65521;
+    }
+    if checksum != (adler.1 << 16 | adler.0) {
+        return Err("checksum mismatch".to_string());
+    }
+    Ok(data)
+}
+
+/// Read the code lengths of a block with its own Huffman codes, themselves Huffman coded, and
+/// make its literal and length codes, and distance codes.
+fn read_codes(bits: &mut BitReader) -> Result<(Huffman, Huffman), String> {
+    const ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+    let literal_count = bits.read(5)? as usize + 257;
+    let distance_count = bits.read(5)? as usize + 1;
+    let code_length_count = bits.read(4)? as usize + 4;
+    let mut code_lengths = [0; 19];
+    for &symbol in &ORDER[..code_length_count] {
+        code_lengths[symbol] = bits.read(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let (value, repeat) = match code_lengths.decode(bits)? {
+            symbol @ 0..=15 => (symbol as u8, 1),
+            16 => {
+                let &previous = lengths.last().ok_or("repeat with no previous length")?;
+                (previous, 3 + bits.read(2)?)
+            }
+            17 => (0, 3 + bits.read(3)?),
+            _ => (0, 11 + bits.read(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > literal_count + distance_count {
+        return Err("code lengths overrun".to_string());
+    }
+    let (literals, distances) = lengths.split_at(literal_count);
+    Ok((Huffman::new(literals), Huffman::new(distances)))
+}
+
+/// Decode the symbols of a block up to its end, copying matches from the data decoded so far.
+fn inflate_block(
+    bits: &mut BitReader,
+    literals: &Huffman,
+    distances: &Huffman,
+    data: &mut Vec<u8>,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(bits)?;
+        match symbol {
+            0..=255 => data.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let code = usize::from(symbol - 257);
+                let (&base, &extra) = LENGTH_BASES
+                    .get(code)
+                    .zip(LENGTH_EXTRA_BITS.get(code))
+                    .ok_or("invalid length")?;
+                let length = usize::from(base) + bits.read(u32::from(extra))? as usize;
+                let code = usize::from(distances.decode(bits)?);
+                let (&base, &extra) = DISTANCE_BASES
+                    .get(code)
+                    .zip(DISTANCE_EXTRA_BITS.get(code))
+                    .ok_or("invalid distance")?;
+                let distance = usize::from(base) + bits.read(u32::from(extra))? as usize;
+                let start = data
+                    .len()
+                    .checked_sub(distance)
+                    .ok_or("distance too far back")?;
+                // Matches may overlap the bytes they produce, so they're copied a byte at a time
+                for i in start..start + length {
+                    data.push(data[i]);
+                }
+            }
+        }
+    }
+}
+
+/// Deflate's bits, read from the least significant bit of each byte.
+struct BitReader<'a> {
+    bytes: &'a [u8],
+    /// Index of the next bit.
+    position: usize,
+}
+
+impl BitReader<'_> {
+    fn read(&mut self, count: u32) -> Result<u32, String> {
+        let mut value = 0;
+        for i in 0..count {
+            let byte = self
+                .bytes
+                .get(self.position / 8)
+                .ok_or("truncated deflate stream")?;
+            value |= u32::from(byte >> (self.position % 8) & 1) << i;
+            self.position += 1;
+        }
+        Ok(value)
+    }
+
+    /// Skip to the start of the next whole byte.
+    fn align(&mut self) {
+        self.position = self.position.div_ceil(8) * 8;
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the symbols in the order
+/// of their codes.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// The code giving each symbol a code `lengths[symbol]` bits long, or none if it's zero.
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0; 16];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        counts[0] = 0;
+        let mut symbols: Vec<u16> = (0..lengths.len() as u16)
+            .filter(|&symbol| lengths[usize::from(symbol)] > 0)
+            .collect();
+        symbols.sort_by_key(|&symbol| lengths[usize::from(symbol)]);
+        Huffman { counts, symbols }
+    }
+
+    /// Read a code a bit at a time, from its most significant bit, until it's one of the codes of
+    /// its length.
+    fn decode(&self, bits: &mut BitReader) -> Result<u16, String> {
+        let (mut code, mut first, mut index) = (0, 0, 0);
+        for &count in &self.counts[1..] {
+            code |= bits.read(1)? as usize;
+            let count = usize::from(count);
+            if code < first + count {
+                return Ok(self.symbols[index + code - first]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err("invalid Huffman code".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::framebuffer::PixelFormat;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            width: 24,
+            height: 8,
+            samples_per_pixel: 1,
+            integrator: "whitted".to_string(),
+            seed: 0,
+            scene_hash: 0,
+        }
+    }
+
+    /// A render in two strips of rows, with runs of repeated pixels for deflate to match.
+    fn strips(alpha: bool) -> Vec<Framebuffer> {
+        (0..2)
+            .map(|strip| {
+                let mut framebuffer = Framebuffer::new(24, 4, PixelFormat::F32);
+                if alpha {
+                    framebuffer = framebuffer.with_alpha();
+                }
+                for (x, y) in (0..4).flat_map(|y| (0..24).map(move |x| (x, y))) {
+                    let row = strip * 4 + y;
+                    let color = Vec3f::new(x as f32 / 23.0, row as f32 / 7.0, (x / 8) as f32 * 0.5);
+                    framebuffer.set(x, y, color);
+                    if alpha {
+                        framebuffer.set_alpha(x, y, if x < 12 { 1.0 } else { 0.5 });
+                    }
+                }
+                framebuffer
+            })
+            .collect()
+    }
+
+    fn write(sixteen_bit: bool, alpha: bool) -> Vec<u8> {
+        let settings = RenderSettings::default();
+        let mut bytes = Vec::new();
+        let mut writer =
+            Box::new(PngWriter::new(&mut bytes, 24, 8, sixteen_bit, alpha, &metadata()).unwrap());
+        for framebuffer in strips(alpha) {
+            writer.write_rows(&framebuffer, &settings).unwrap();
+        }
+        writer.finish(Duration::from_secs(1)).unwrap();
+        bytes
+    }
+
+    /// Check the decoded image against the rendered colors, once encoded as the writer does.
+    fn assert_round_trips(sixteen_bit: bool, alpha: bool) {
+        let settings = RenderSettings::default();
+        let image = decode(&write(sixteen_bit, alpha)).unwrap();
+        assert_eq!((image.width(), image.height()), (24, 8));
+        for (strip, framebuffer) in strips(alpha).iter().enumerate() {
+            for (x, y) in (0..4).flat_map(|y| (0..24).map(move |x| (x, y))) {
+                let (color, coverage) = output::straight_alpha(framebuffer, x, y);
+                let encoded = if sixteen_bit {
+                    output::encode_16(color, &settings).map(|c| f32::from(c) / 65535.0)
+                } else {
+                    output::encode(color, &settings).map(|c| f32::from(c) / 255.0)
+                };
+                // Colors are decoded multiplied by the alpha, as it was stored
+                let coverage = if sixteen_bit {
+                    f32::from((coverage * 65535.0 + 0.5) as u16) / 65535.0
+                } else {
+                    f32::from((coverage * 255.0 + 0.5) as u8) / 255.0
+                };
+                let expected = encoded.map(|c| output::srgb_to_linear(c) * coverage);
+                let decoded = image.pixel(x, strip * 4 + y);
+                for (a, b) in [decoded.x, decoded.y, decoded.z].into_iter().zip(expected) {
+                    assert!((a - b).abs() < 1e-6, "({x}, {y}) is {a}, not {b}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_8_bit() {
+        assert_round_trips(false, false);
+    }
+
+    #[test]
+    fn round_trips_16_bit_with_alpha() {
+        assert_round_trips(true, true);
+        assert_round_trips(false, true);
+    }
+
+    #[test]
+    fn writes_chunks_with_checksums() {
+        let bytes = write(false, false);
+        assert!(bytes.starts_with(&SIGNATURE));
+        // IHDR, 24 by 8 pixels of 8 bit RGB
+        assert_eq!(&bytes[8..16], &[0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+        assert_eq!(&bytes[16..29], &[0, 0, 0, 24, 0, 0, 0, 8, 8, 2, 0, 0, 0]);
+        // An empty IEND chunk's checksum is well known
+        assert!(bytes.ends_with(&[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]));
+    }
+
+    #[test]
+    fn writes_keywords_in_title_case() {
+        let bytes = write(false, false);
+        let contains = |text: &[u8]| bytes.windows(text.len()).any(|window| window == text);
+        assert!(contains(b"tEXtSoftware\0rayox"));
+        assert!(contains(b"tEXtScene Hash\x000000000000000000"));
+        assert!(contains(b"tEXtRender Time\0"));
+    }
+
+    #[test]
+    fn inflates_stored_and_compressed_blocks() {
+        // A stored block, then a last block with fixed codes holding a literal and the end
+        let mut stream = vec![0x78, 0x01, 0x00, 3, 0, !3, !0, b'a', b'b', b'c'];
+        let mut bits = BitWriter::default();
+        bits.write_bits(1, 1);
+        bits.write_bits(1, 2);
+        write_literal(&mut bits, u32::from(b'd'));
+        write_literal(&mut bits, END_OF_BLOCK);
+        bits.align();
+        stream.extend(&bits.bytes);
+        let adler = |data: &[u8]| {
+            let (a, b) = data.iter().fold((1_u32, 0_u32), |(a, b), &byte| {
+                let a = (a + u32::from(byte)) % 65521;
+                (a, (b + a) % 65521)
+            });
+            (b << 16) | a
+        };
+        stream.extend(adler(b"abcd").to_be_bytes());
+        assert_eq!(inflate(&stream).unwrap(), b"abcd");
+
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        assert!(inflate(&stream).is_err());
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        assert!(decode(b"P6\n1 1\n255\n\0\0\0").is_err());
+        let bytes = write(false, false);
+        assert!(decode(&bytes[..bytes.len() / 2]).is_err());
+    }
+}
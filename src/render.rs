@@ -0,0 +1,756 @@
+use crate::{
+    animation::Animation,
+    aov::{Aov, PassSums},
+    framebuffer::{Framebuffer, MemoryPlan, PixelFormat},
+    integrator::{Integrator, IntegratorKind},
+    metadata::{self, Metadata},
+    metropolis,
+    output::{self, ImageWriter, PpmWriter, TeeWriter},
+    rng::Rng,
+    sampler::SamplerKind,
+    scene::Scene,
+    settings::RenderSettings,
+    tile::{self, Tile},
+    tracer::NonFinite,
+    wavefront, Ray, Vec3f,
+};
+use std::{
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Samples every pixel takes before adaptive sampling judges whether it needs more.
+pub const MIN_ADAPTIVE_SAMPLES: usize = 8;
+
+/// Start of every checkpoint file, followed by the version of its layout.
+const CHECKPOINT_MAGIC: &[u8; 8] = b"rayoxckp";
+const CHECKPOINT_VERSION: u64 = 3;
+
+/// Color marking pixels where a NaN or infinite value was produced, when debugging.
+const MARKER: Vec3f = Vec3f {
+    x: 1.0,
+    y: 0.0,
+    z: 1.0,
+};
+
+/// Render the scene, writing the image to `path` in the format its extension names, along with
+/// any passes the settings ask for beside it.
+pub fn render(scene: &Scene, settings: &RenderSettings, path: &Path) -> std::io::Result<()> {
+    let output = output::create(path, settings, &Metadata::new(scene, settings))?;
+    render_to(scene, settings, path, output)
+}
+
+/// Render the scene, writing the image with `output`, and any passes the settings ask for beside
+/// `path`.
+fn render_to(
+    scene: &Scene,
+    settings: &RenderSettings,
+    path: &Path,
+    mut output: Box<dyn ImageWriter + '_>,
+) -> std::io::Result<()> {
+    let start = Instant::now();
+    let (width, height) = (settings.width, settings.height);
+    // Under a memory budget, the wavefront renderer may use at most a quarter of it for rays in
+    // flight. Each pixel of a batch has a camera ray in flight for every one of its samples.
+    let samples = settings.samples_per_pixel;
+    let batch_size = match settings.memory_budget {
+        Some(budget) if settings.wavefront => {
+            let rays = (budget / 4 / wavefront::BYTES_PER_RAY).min(wavefront::BATCH_SIZE);
+            if rays < samples {
+                return Err(std::io::Error::other(format!(
+                    "Memory budget leaves room for {rays} rays in flight, fewer than the \
+                     {samples} samples of a pixel"
+                )));
+            }
+            rays / samples
+        }
+        _ => (wavefront::BATCH_SIZE / samples).max(1),
+    };
+    let overhead = if settings.wavefront {
+        batch_size * samples * wavefront::BYTES_PER_RAY
+    } else {
+        0
+    };
+    // An alpha channel is counted as a whole image, to leave room for it
+    let images = 1 + settings.aovs.len() + usize::from(settings.alpha);
+    let plan = MemoryPlan::new(width, height, images, settings.memory_budget, overhead)
+        .map_err(std::io::Error::other)?;
+    if plan.is_degraded(height) {
+        eprintln!(
+            "Render exceeds the memory budget, using {:?} pixels in strips of {} rows",
+            plan.format, plan.rows_per_strip
+        );
+    }
+
+    let integrator = settings.integrator.create(scene, settings);
+
+    // Markov chains wander over the whole image, so it's rendered all at once
+    if let IntegratorKind::Metropolis { mutations } = settings.integrator {
+        let image = metropolis::render(
+            scene,
+            &*integrator,
+            width,
+            height,
+            mutations,
+            plan.format,
+            settings.seed,
+        );
+        output.write_rows(&image, settings)?;
+        return output.finish(start.elapsed());
+    }
+
+    // Data passes are written as they are, apart from the render's look
+    let data_settings = RenderSettings {
+        width,
+        height,
+        quality: settings.quality,
+        sixteen_bit: settings.sixteen_bit,
+        half_float: settings.half_float,
+        gamma: Some(1.0),
+        ..RenderSettings::default()
+    };
+    let pass_settings = |aov: Aov| {
+        if aov.is_data() {
+            &data_settings
+        } else {
+            settings
+        }
+    };
+    let metadata = Metadata::new(scene, settings);
+    let mut pass_outputs = settings
+        .aovs
+        .iter()
+        .map(|&aov| output::create(&aov.path(path), pass_settings(aov), &metadata))
+        .collect::<std::io::Result<Vec<_>>>()?;
+
+    let strips = (0..height)
+        .step_by(plan.rows_per_strip)
+        .map(|first_row| first_row..(first_row + plan.rows_per_strip).min(height));
+    let tile_count: usize = strips
+        .clone()
+        .map(|strip| tile::count(width, strip, settings.tile_size))
+        .sum();
+    let mut tiles_done = 0;
+    for strip_rows in strips {
+        let (first_row, rows) = (strip_rows.start, strip_rows.len());
+        let mut strip = Framebuffer::new(width, rows, plan.format);
+        if settings.alpha {
+            strip = strip.with_alpha();
+        }
+        let mut pass_strips: Vec<_> = (0..settings.aovs.len())
+            .map(|_| Framebuffer::new(width, rows, plan.format))
+            .collect();
+        if settings.wavefront {
+            let quarantined = wavefront::render(
+                scene,
+                &mut strip,
+                first_row,
+                batch_size,
+                settings,
+                |x, y, rng| sample_ray(scene, settings, x, y, rng),
+            );
+            if settings.debug_non_finite {
+                for (x, y, non_finite) in quarantined {
+                    strip.set(x, y - first_row, marker(x, y, non_finite));
+                }
+            }
+        } else {
+            let tiles = tile::tiles(width, strip_rows, settings.tile_size, settings.tile_order);
+            render_tiles(scene, &*integrator, settings, &tiles, |tile, pixels| {
+                for ((x, y), pixel) in tile.pixels().zip(pixels) {
+                    strip.set(x, y - first_row, pixel.color);
+                    strip.set_alpha(x, y - first_row, pixel.alpha);
+                    for (pass_strip, pass) in pass_strips.iter_mut().zip(pixel.passes) {
+                        pass_strip.set(x, y - first_row, pass);
+                    }
+                }
+                tiles_done += 1;
+                if settings.report_progress {
+                    eprint!(
+                        "\rRendered {tiles_done} of {tile_count} tiles ({:.0}%)",
+                        100.0 * tiles_done as f32 / tile_count as f32
+                    );
+                }
+            });
+        }
+
+        output.write_rows(&strip, settings)?;
+        for ((pass_output, pass_strip), &aov) in pass_outputs
+            .iter_mut()
+            .zip(&pass_strips)
+            .zip(&settings.aovs)
+        {
+            pass_output.write_rows(pass_strip, pass_settings(aov))?;
+        }
+    }
+    if settings.report_progress && tiles_done > 0 {
+        eprintln!();
+    }
+    let render_time = start.elapsed();
+    for pass_output in pass_outputs {
+        pass_output.finish(render_time)?;
+    }
+    output.finish(render_time)
+}
+
+/// A rendered pixel.
+struct Pixel {
+    /// Average of the pixel's samples.
+    color: Vec3f,
+    /// Fraction of the samples which hit something.
+    alpha: f32,
+    passes: Vec<Vec3f>,
+}
+
+/// Render `tiles` on the settings' number of threads, each taking the next tile no other has
+/// started, and hand each tile to `finished` on this thread as it's done, with its pixels in the
+/// order of [`Tile::pixels`]. Every tile has its own pixels, and every sample its own random
+/// numbers, so the image is the same however many threads render it, in whatever order tiles
+/// finish.
+fn render_tiles(
+    scene: &Scene,
+    integrator: &dyn Integrator,
+    settings: &RenderSettings,
+    tiles: &[Tile],
+    mut finished: impl FnMut(Tile, Vec<Pixel>),
+) {
+    let next = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        for _ in 0..settings.threads.min(tiles.len()) {
+            let (next, sender) = (&next, sender.clone());
+            scope.spawn(move || {
+                while let Some(&tile) = tiles.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    let pixels = render_tile(scene, integrator, settings, tile);
+                    if sender.send((tile, pixels)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // Finished tiles stop arriving once every thread has run out of them
+        drop(sender);
+        for (tile, pixels) in receiver {
+            finished(tile, pixels);
+        }
+    });
+}
+
+/// Render the pixels of `tile`, in the order of [`Tile::pixels`].
+fn render_tile(
+    scene: &Scene,
+    integrator: &dyn Integrator,
+    settings: &RenderSettings,
+    tile: Tile,
+) -> Vec<Pixel> {
+    tile.pixels()
+        .map(|(x, y)| {
+            render_pixel(scene, integrator, settings, x, y).unwrap_or_else(|non_finite| Pixel {
+                color: marker(x, y, non_finite),
+                alpha: 1.0,
+                passes: vec![Vec3f::default(); settings.aovs.len()],
+            })
+        })
+        .collect()
+}
+
+/// Average of the samples of pixel (`x`, `y`), weighted by the settings' filter, along with the
+/// pixel's passes. Samples producing a NaN or infinite value are dropped, see [`quarantine`], or
+/// when debugging, what produced it is returned. With a noise threshold, the pixel stops taking
+/// samples once its average is estimated to be within the threshold.
+///
+/// The fraction of the samples which hit something comes with the average, as the pixel's alpha.
+/// With an alpha channel, samples which miss everything are left black rather than showing the
+/// background.
+fn render_pixel(
+    scene: &Scene,
+    integrator: &dyn Integrator,
+    settings: &RenderSettings,
+    x: usize,
+    y: usize,
+) -> Result<Pixel, NonFinite> {
+    let samples = settings.samples_per_pixel;
+    let mut sum = Vec3f::default();
+    let (mut total_weight, mut covered_weight) = (0.0, 0.0);
+    let mut passes = PassSums::new(&settings.aovs);
+    // Running mean and sum of squared differences of the samples' brightness, by Welford's method
+    let (mut mean, mut squares) = (0.0, 0.0);
+    let (mut taken, mut kept) = (0, 0);
+    while taken < samples {
+        // Each sample has its own random numbers, so it renders the same whatever order pixels
+        // are rendered in.
+        let mut rng = Rng::for_sample(
+            settings.sampler,
+            settings.seed,
+            (x, y),
+            settings.width,
+            taken,
+            samples,
+        );
+        let (ray, weight) = sample_ray(scene, settings, x, y, &mut rng);
+        let ray = ray.filter(|ray| !settings.alpha || scene.intersect(ray).is_some());
+        let radiance = match ray {
+            Some(ray) => integrator.trace(ray, scene, &mut rng),
+            None => Ok(Vec3f::default()),
+        };
+        let sample = radiance.and_then(|radiance| {
+            if !settings.aovs.is_empty() {
+                // Passes have their own random numbers, so asking for them doesn't change the
+                // render
+                let mut rng = Rng::for_sample(
+                    SamplerKind::Independent,
+                    !settings.seed,
+                    (x, y),
+                    settings.width,
+                    taken,
+                    samples,
+                );
+                passes.add(ray.as_ref(), radiance, weight, scene, &mut rng)?;
+            }
+            Ok(radiance)
+        });
+        taken += 1;
+        let Some(radiance) = quarantine(sample, settings)? else {
+            continue;
+        };
+        if ray.is_some() {
+            covered_weight += weight;
+        }
+        sum += radiance * weight;
+        total_weight += weight;
+        kept += 1;
+        let brightness = radiance.luminance();
+        let delta = brightness - mean;
+        mean += delta / kept as f32;
+        squares += delta * (brightness - mean);
+
+        if let Some(threshold) = settings.noise_threshold {
+            if kept >= MIN_ADAPTIVE_SAMPLES {
+                // Standard error of the mean, relative to it, with pixels darker than a step of
+                // 8 bit color held to the same error as one
+                let error = (squares / ((kept - 1) * kept) as f32).sqrt();
+                if error <= threshold * mean.max(1.0 / 255.0) {
+                    break;
+                }
+            }
+        }
+    }
+    let alpha = if total_weight > 0.0 {
+        covered_weight / total_weight
+    } else {
+        0.0
+    };
+    Ok(Pixel {
+        color: weighted_average(sum, total_weight),
+        alpha,
+        passes: passes.average(total_weight).collect(),
+    })
+}
+
+/// `sum` of weighted samples divided by their `total_weight`, or black if the weights, which may
+/// be negative, cancel out.
+fn weighted_average(sum: Vec3f, total_weight: f32) -> Vec3f {
+    if total_weight > 0.0 {
+        sum * (1.0 / total_weight)
+    } else {
+        Vec3f::default()
+    }
+}
+
+/// The camera ray for one of the samples of pixel (`x`, `y`), if there is one, with the weight
+/// the settings' filter gives the sample. A single sample goes through the middle of the pixel,
+/// while more are spread over the filter's footprint as the sampler and filter place them, so
+/// averaging them smooths the edges of objects.
+fn sample_ray(
+    scene: &Scene,
+    settings: &RenderSettings,
+    x: usize,
+    y: usize,
+    rng: &mut Rng,
+) -> (Option<Ray>, f32) {
+    let ((dx, dy), weight) = if settings.samples_per_pixel > 1 {
+        settings.filter.sample(rng.next_2d())
+    } else {
+        ((0.0, 0.0), 1.0)
+    };
+    let ray = scene.camera.pixel_sample_ray(
+        x,
+        y,
+        (0.5 + dx, 0.5 + dy),
+        settings.width,
+        settings.height,
+        rng,
+    );
+    (ray, weight)
+}
+
+/// A sample's value, or nothing if it produced a NaN or infinite value, which is dropped so it
+/// can't spread any further and its pixel is made from its other samples. When debugging, what
+/// produced it is passed on instead, for the pixel to be marked, see [`marker`].
+fn quarantine<T>(
+    sample: Result<T, NonFinite>,
+    settings: &RenderSettings,
+) -> Result<Option<T>, NonFinite> {
+    match sample {
+        Ok(value) => Ok(Some(value)),
+        Err(non_finite) if settings.debug_non_finite => Err(non_finite),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Color of pixel (`x`, `y`) when debugging and a NaN or infinite value was produced in it,
+/// which is magenta, after reporting what produced it.
+fn marker(x: usize, y: usize, non_finite: NonFinite) -> Vec3f {
+    eprintln!("Pixel ({x}, {y}): {non_finite}");
+    MARKER
+}
+
+/// Renders an image progressively, a slice of time at a time, so an application can keep its
+/// event loop responsive while rendering on the same thread. Each pass takes one more sample in
+/// every pixel, accumulated into a float framebuffer, so the image can be shown or saved after
+/// any step and sharpens as passes are added, until `samples_per_pixel` of them are done.
+pub struct Renderer<'a> {
+    scene: &'a Scene,
+    settings: &'a RenderSettings,
+    integrator: Box<dyn Integrator>,
+    /// Sum of the samples taken in each pixel so far, weighted by the settings' filter.
+    accumulated: Framebuffer,
+    /// Sum of the weights of the samples taken in each pixel so far.
+    weights: Vec<f32>,
+    /// Whether each pixel has produced a NaN or infinite value, when debugging, to be marked.
+    marked: Vec<bool>,
+    /// Passes finished over the whole image.
+    passes: usize,
+    /// Index of the next pixel to render in the current pass, in rows from the top.
+    next_pixel: usize,
+    /// Time spent rendering so far, over every step.
+    render_time: Duration,
+}
+
+impl<'a> Renderer<'a> {
+    /// Start rendering an image of the scene as `settings` describe, on this thread, a pixel at a
+    /// time in rows from the top, so the settings' threads and tiles are unused. The wavefront
+    /// renderer, Metropolis light transport, adaptive sampling and the memory budget aren't
+    /// supported, so the image is rendered as if they weren't set, in a full size float
+    /// framebuffer.
+    pub fn new(scene: &'a Scene, settings: &'a RenderSettings) -> Self {
+        Renderer {
+            scene,
+            settings,
+            integrator: settings.integrator.create(scene, settings),
+            accumulated: Framebuffer::new(settings.width, settings.height, PixelFormat::F32),
+            weights: vec![0.0; settings.width * settings.height],
+            marked: vec![false; settings.width * settings.height],
+            passes: 0,
+            next_pixel: 0,
+            render_time: Duration::ZERO,
+        }
+    }
+
+    /// Render as many samples as fit in `budget`, returning whether the image is finished. At
+    /// least one sample is rendered per step, so the render always progresses.
+    pub fn step(&mut self, budget: Duration) -> bool {
+        let start = Instant::now();
+        let (width, samples) = (self.settings.width, self.settings.samples_per_pixel);
+        while !self.is_finished() {
+            let (x, y) = (self.next_pixel % width, self.next_pixel / width);
+            let mut rng = Rng::for_sample(
+                self.settings.sampler,
+                self.settings.seed,
+                (x, y),
+                width,
+                self.passes,
+                samples,
+            );
+            let (ray, weight) = sample_ray(self.scene, self.settings, x, y, &mut rng);
+            let color = match ray {
+                Some(ray) => self.integrator.trace(ray, self.scene, &mut rng),
+                None => Ok(Vec3f::default()),
+            };
+            match quarantine(color, self.settings) {
+                Ok(Some(color)) => {
+                    let sum = self.accumulated.get(x, y) + color * weight;
+                    self.accumulated.set(x, y, sum);
+                    self.weights[self.next_pixel] += weight;
+                }
+                Ok(None) => {}
+                Err(non_finite) => {
+                    // Each pixel is only reported once
+                    if !self.marked[self.next_pixel] {
+                        marker(x, y, non_finite);
+                    }
+                    self.marked[self.next_pixel] = true;
+                }
+            }
+            self.next_pixel += 1;
+            if self.next_pixel == width * self.settings.height {
+                self.passes += 1;
+                self.next_pixel = 0;
+            }
+            if start.elapsed() >= budget {
+                break;
+            }
+        }
+        self.render_time += start.elapsed();
+        self.is_finished()
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.passes == self.settings.samples_per_pixel
+    }
+
+    /// Passes finished over the whole image so far.
+    pub fn passes(&self) -> usize {
+        self.passes
+    }
+
+    /// Fraction of the image rendered so far, from 0 to 1.
+    pub fn progress(&self) -> f32 {
+        let pixels = self.settings.width * self.settings.height;
+        (self.passes * pixels + self.next_pixel) as f32
+            / (self.settings.samples_per_pixel * pixels) as f32
+    }
+
+    /// The image so far, averaging the samples taken in each pixel, weighted by the settings'
+    /// filter. Pixels not yet rendered are black, and those marked when debugging magenta.
+    pub fn image(&self) -> Framebuffer {
+        let (width, height) = (self.settings.width, self.settings.height);
+        let mut image = Framebuffer::new(width, height, PixelFormat::F32);
+        for y in 0..height {
+            for x in 0..width {
+                let color = if self.marked[y * width + x] {
+                    MARKER
+                } else {
+                    weighted_average(self.accumulated.get(x, y), self.weights[y * width + x])
+                };
+                image.set(x, y, color);
+            }
+        }
+        image
+    }
+
+    /// Write the image so far to `path`, in the format its extension names.
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        let metadata = Metadata::new(self.scene, self.settings);
+        let mut output = output::create(path, self.settings, &metadata)?;
+        output.write_rows(&self.image(), self.settings)?;
+        output.finish(self.render_time)
+    }
+
+    /// Write everything needed to carry on rendering later to `path`: the samples accumulated so
+    /// far, how far through its passes the render is and how long it's taken, along with what it's
+    /// a render of, see [`metadata::render_hash`], so it isn't resumed as another. Each sample's
+    /// random numbers are made from the seed, pixel and sample, so that's all the state of the
+    /// random numbers too. The checkpoint is written beside `path` and then moved over it, so a
+    /// crash while writing leaves the previous one whole.
+    pub fn save_checkpoint(&self, path: &Path) -> io::Result<()> {
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        writer.write_all(CHECKPOINT_MAGIC)?;
+        let settings = self.settings;
+        for value in [
+            CHECKPOINT_VERSION,
+            settings.width as u64,
+            settings.height as u64,
+            settings.samples_per_pixel as u64,
+            settings.seed,
+            metadata::render_hash(self.scene, settings),
+            self.passes as u64,
+            self.next_pixel as u64,
+            self.render_time.as_millis() as u64,
+        ] {
+            writer.write_all(&value.to_le_bytes())?;
+        }
+        for (color, weight) in self.accumulated.pixels().zip(&self.weights) {
+            for value in [color.x, color.y, color.z, *weight] {
+                writer.write_all(&value.to_le_bytes())?;
+            }
+        }
+        writer.flush()?;
+        drop(writer);
+        fs::rename(&temporary, path)
+    }
+
+    /// Carry on rendering from the checkpoint at `path`, which must have been saved by a render
+    /// of the same size, samples per pixel and seed, of the same scene with the same settings for
+    /// its samples.
+    pub fn resume(scene: &'a Scene, settings: &'a RenderSettings, path: &Path) -> io::Result<Self> {
+        let invalid = |message: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid checkpoint `{}`: {message}", path.display()),
+            )
+        };
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        let mut next = || -> io::Result<u64> {
+            let mut bytes = [0; 8];
+            reader.read_exact(&mut bytes)?;
+            Ok(u64::from_le_bytes(bytes))
+        };
+        if &magic != CHECKPOINT_MAGIC || next()? != CHECKPOINT_VERSION {
+            return Err(invalid("not a checkpoint of this version"));
+        }
+        let saved = [next()?, next()?, next()?, next()?];
+        let expected = [
+            settings.width as u64,
+            settings.height as u64,
+            settings.samples_per_pixel as u64,
+            settings.seed,
+        ];
+        if saved != expected {
+            return Err(invalid(
+                "saved by a render of a different size, samples per pixel or seed",
+            ));
+        }
+        if next()? != metadata::render_hash(scene, settings) {
+            return Err(invalid(
+                "saved by a render of a different scene or camera, or with a different \
+                 integrator, sampler, filter, max depth or indirect clamp",
+            ));
+        }
+        let (passes, next_pixel) = (next()? as usize, next()? as usize);
+        if passes > settings.samples_per_pixel || next_pixel >= settings.width * settings.height {
+            return Err(invalid("progress out of range"));
+        }
+
+        let render_time = Duration::from_millis(next()?);
+
+        let mut renderer = Renderer::new(scene, settings);
+        renderer.passes = passes;
+        renderer.next_pixel = next_pixel;
+        renderer.render_time = render_time;
+        let mut bytes = [0; 16];
+        for y in 0..settings.height {
+            for x in 0..settings.width {
+                reader.read_exact(&mut bytes)?;
+                let [r, g, b, weight] = [0, 4, 8, 12].map(|i| {
+                    f32::from_le_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]])
+                });
+                renderer.accumulated.set(x, y, Vec3f::new(r, g, b));
+                renderer.weights[y * settings.width + x] = weight;
+            }
+        }
+        Ok(renderer)
+    }
+}
+
+/// Render frames `frames` of the scene moving as `animation` says, at `fps` frames per second,
+/// each to a file beside `path` numbered with its frame, such as `raytraced.0001.ppm`. With
+/// `ffmpeg`, the frames are piped to ffmpeg as well, which encodes them into a video there.
+pub fn render_sequence(
+    scene: &mut Scene,
+    animation: &Animation,
+    settings: &RenderSettings,
+    path: &Path,
+    frames: Range<usize>,
+    fps: f32,
+    ffmpeg: Option<&Path>,
+) -> io::Result<()> {
+    let mut encoder = match ffmpeg {
+        Some(video) => Some(
+            Command::new("ffmpeg")
+                .args([
+                    "-y",
+                    "-loglevel",
+                    "error",
+                    "-f",
+                    "image2pipe",
+                    "-c:v",
+                    "ppm",
+                ])
+                .arg("-framerate")
+                .arg(fps.to_string())
+                .args(["-i", "-"])
+                .arg(video)
+                .stdin(Stdio::piped())
+                .spawn()
+                .map_err(|err| {
+                    io::Error::new(err.kind(), format!("Failed to start ffmpeg: {err}"))
+                })?,
+        ),
+        None => None,
+    };
+    let count = frames.len();
+    for (done, frame) in frames.enumerate() {
+        if settings.report_progress {
+            eprintln!("Rendering frame {frame} ({} of {count})", done + 1);
+        }
+        animation.apply(scene, frame as f32 / fps, 1.0 / fps);
+        let frame_path = frame_path(path, frame);
+        let mut writers = vec![output::create(
+            &frame_path,
+            settings,
+            &Metadata::new(scene, settings),
+        )?];
+        if let Some(stdin) = encoder.as_mut().and_then(|encoder| encoder.stdin.as_mut()) {
+            let (width, height) = (settings.width, settings.height);
+            let stdin = BufWriter::new(stdin);
+            writers.push(Box::new(PpmWriter::new(stdin, width, height)?));
+        }
+        render_to(scene, settings, &frame_path, Box::new(TeeWriter(writers)))?;
+    }
+    if let Some(mut encoder) = encoder {
+        // Closing its input tells ffmpeg the video is finished
+        drop(encoder.stdin.take());
+        let status = encoder.wait()?;
+        if !status.success() {
+            return Err(io::Error::other(format!("ffmpeg failed, {status}")));
+        }
+    }
+    Ok(())
+}
+
+/// Where frame `frame` of a sequence written to `path` goes, which is beside it with the frame's
+/// number before the extension.
+fn frame_path(path: &Path, frame: usize) -> PathBuf {
+    let extension = path.extension().and_then(|extension| extension.to_str());
+    path.with_extension(match extension {
+        Some(extension) => format!("{frame:04}.{extension}"),
+        None => format!("{frame:04}"),
+    })
+}
+
+/// Render the scene progressively, writing the image to `path` once it's finished, and a
+/// checkpoint to `checkpoint` every `interval` along the way, so a render which is stopped can
+/// carry on from there. With `resume`, the render carries on from the checkpoint already there.
+/// Only supports what [`Renderer`] does.
+pub fn render_checkpointed(
+    scene: &Scene,
+    settings: &RenderSettings,
+    path: &Path,
+    checkpoint: &Path,
+    interval: Duration,
+    resume: bool,
+) -> io::Result<()> {
+    let mut renderer = if resume {
+        Renderer::resume(scene, settings, checkpoint)?
+    } else {
+        Renderer::new(scene, settings)
+    };
+    while !renderer.step(interval) {
+        renderer.save_checkpoint(checkpoint)?;
+        if settings.report_progress {
+            eprint!(
+                "\rRendered {:.1}%, checkpoint saved",
+                100.0 * renderer.progress()
+            );
+        }
+    }
+    if settings.report_progress {
+        eprintln!();
+    }
+    renderer.save_checkpoint(checkpoint)?;
+    renderer.save(path)
+}
@@ -0,0 +1,137 @@
+use crate::{
+    metropolis::PrimarySamples,
+    sampler::{Sampler, SamplerKind},
+    Vec3f,
+};
+use std::f32::consts::PI;
+
+/// Small, fast pseudo-random number generator (SplitMix64).
+pub struct Rng {
+    state: u64,
+    /// Numbers handed out in place of generated ones, for Metropolis light transport to perturb.
+    primary: Option<Box<PrimarySamples>>,
+    /// Places the numbers handed out in place of generated ones, for one of a pixel's samples.
+    sampler: Option<Box<dyn Sampler>>,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng {
+            state: seed,
+            primary: None,
+            sampler: None,
+        }
+    }
+
+    /// Numbers for sample `sample` of the `samples` taken in pixel (`x`, `y`) of an image `width`
+    /// pixels wide, placed as `kind` places them and made from the render's `seed`. The numbers
+    /// are the same whenever the seed, pixel and sample are.
+    pub fn for_sample(
+        kind: SamplerKind,
+        seed: u64,
+        (x, y): (usize, usize),
+        width: usize,
+        sample: usize,
+        samples: usize,
+    ) -> Self {
+        let pixel = (y * width + x) as u64;
+        let mut rng = Rng::new(stream_seed(seed, pixel * samples as u64 + sample as u64));
+        // Independent numbers are generated just as well without a sampler
+        if kind != SamplerKind::Independent {
+            rng.sampler = Some(kind.create(seed, (x, y), width, sample, samples));
+        }
+        rng
+    }
+
+    /// Hand out the numbers `sampler` places rather than generating them.
+    pub fn sampling(sampler: Box<dyn Sampler>) -> Self {
+        Rng {
+            state: 0,
+            primary: None,
+            sampler: Some(sampler),
+        }
+    }
+
+    /// Hand out the numbers from `samples` rather than generating them.
+    pub fn replaying(samples: PrimarySamples) -> Self {
+        Rng {
+            state: 0,
+            primary: Some(Box::new(samples)),
+            sampler: None,
+        }
+    }
+
+    /// The numbers being handed out, if replaying them.
+    pub fn primary_samples(&mut self) -> Option<&mut PrimarySamples> {
+        self.primary.as_deref_mut()
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        if let Some(primary) = &mut self.primary {
+            // Only the top bits are used to make floats
+            return to_bits(primary.next_sample());
+        }
+        match &mut self.sampler {
+            Some(sampler) => to_bits(sampler.get_1d()),
+            None => self.generate(),
+        }
+    }
+
+    fn generate(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    /// Uniform float in `[0, 1)`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Two uniform floats in `[0, 1)`, which a sampler places as a pair.
+    pub fn next_2d(&mut self) -> (f32, f32) {
+        if self.primary.is_none() {
+            if let Some(sampler) = &mut self.sampler {
+                return sampler.get_2d();
+            }
+        }
+        (self.next_f32(), self.next_f32())
+    }
+
+    /// Uniform float in `[min, max)`.
+    pub fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+
+    /// Normally distributed float, with a mean of zero and a standard deviation of one.
+    pub fn normal(&mut self) -> f32 {
+        // Box-Muller transform
+        let radius = (-2.0 * (1.0 - self.next_f32()).ln()).sqrt();
+        radius * (2.0 * PI * self.next_f32()).cos()
+    }
+
+    /// Uniformly distributed direction on the unit sphere.
+    pub fn unit_vector(&mut self) -> Vec3f {
+        let z = 1.0 - 2.0 * self.next_f32();
+        let r = (1.0 - z * z).max(0.0).sqrt();
+        let phi = 2.0 * PI * self.next_f32();
+        Vec3f::new(r * phi.cos(), r * phi.sin(), z)
+    }
+}
+
+/// Seed of stream `stream` of the numbers made from the render's `seed`, each stream apart from
+/// the others. With a seed of zero the streams are seeded by their number alone.
+pub fn stream_seed(seed: u64, stream: u64) -> u64 {
+    stream ^ mix(seed)
+}
+
+/// SplitMix64's finalizer, which scrambles the bits of `z`.
+pub(crate) fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A float in `[0, 1)` as the top bits of a number, as only the top bits are used to make floats.
+fn to_bits(value: f32) -> u64 {
+    ((value * (1u64 << 24) as f32) as u64) << 40
+}
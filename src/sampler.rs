@@ -0,0 +1,232 @@
+//! Samplers, which place the random numbers of each of a pixel's samples. The numbers drawn for a
+//! sample, whether they place it in the pixel, on the lens or on a light, are its dimensions, and
+//! a sampler can spread each dimension evenly over a pixel's samples where independent numbers
+//! would clump.
+
+use crate::{
+    blue_noise::{self, MASK_SIZE},
+    rng::{mix, stream_seed, Rng},
+};
+
+/// How the random numbers of a pixel's samples are placed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SamplerKind {
+    /// Every number independently at random.
+    Independent,
+    /// The samples of a pixel split the range of each number between them, one in each equal
+    /// stratum, jittered at random within it. Each number drawn, whether it places the sample in
+    /// the pixel, on the lens or on a light, is stratified in a different random order, so the
+    /// samples cover every domain evenly.
+    Stratified,
+    /// The samples of a pixel follow the Halton sequence, a quasi-random sequence which fills
+    /// each number's range more and more evenly as samples are added, however many there are.
+    /// Each number drawn takes the next dimension of the sequence, with its digits scrambled
+    /// differently in each pixel. Numbers beyond the last dimension are independent.
+    Halton,
+    /// Every pixel follows the same Halton sequence, offset in each dimension by a blue noise
+    /// mask across the image, so neighbouring pixels draw numbers which are far apart. The noise
+    /// left at low sample counts is then fine grained rather than clumped.
+    BlueNoise,
+}
+
+impl SamplerKind {
+    /// The sampler for sample `sample` of the `samples` taken in pixel (`x`, `y`) of an image
+    /// `width` pixels wide, made from the render's `seed`. Its numbers are the same whenever the
+    /// seed, pixel and sample are.
+    pub fn create(
+        self,
+        seed: u64,
+        (x, y): (usize, usize),
+        width: usize,
+        sample: usize,
+        samples: usize,
+    ) -> Box<dyn Sampler> {
+        let pixel = (y * width + x) as u64;
+        // Numbers the sampler doesn't place are independent
+        let rng = Rng::new(stream_seed(seed, pixel * samples as u64 + sample as u64));
+        let seed = mix(seed);
+        let sample = sample as u32;
+        match self {
+            SamplerKind::Independent => Box::new(rng),
+            SamplerKind::Stratified => Box::new(Stratified {
+                pixel,
+                seed,
+                sample,
+                samples: samples as u32,
+                dimension: 0,
+                rng,
+            }),
+            SamplerKind::Halton => Box::new(Halton {
+                pixel,
+                seed,
+                sample,
+                dimension: 0,
+                rng,
+            }),
+            SamplerKind::BlueNoise => Box::new(BlueNoise {
+                x,
+                y,
+                seed,
+                sample,
+                dimension: 0,
+                rng,
+            }),
+        }
+    }
+}
+
+/// Places the random numbers of one of a pixel's samples.
+pub trait Sampler: Send {
+    /// The next dimension's number, in `[0, 1)`.
+    fn get_1d(&mut self) -> f32;
+
+    /// The next two dimensions' numbers, for samplers which place them as a pair.
+    fn get_2d(&mut self) -> (f32, f32) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+/// Every number independently at random.
+impl Sampler for Rng {
+    fn get_1d(&mut self) -> f32 {
+        self.next_f32()
+    }
+}
+
+/// Bases of the Halton sequence's dimensions, the first primes.
+const HALTON_BASES: [u32; 64] = [
+    2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37, 41, 43, 47, 53, 59, 61, 67, 71, 73, 79, 83, 89, 97,
+    101, 103, 107, 109, 113, 127, 131, 137, 139, 149, 151, 157, 163, 167, 173, 179, 181, 191, 193,
+    197, 199, 211, 223, 227, 229, 233, 239, 241, 251, 257, 263, 269, 271, 277, 281, 283, 293, 307,
+    311,
+];
+
+/// Stratifies each dimension over the pixel's samples. See [`SamplerKind::Stratified`].
+pub struct Stratified {
+    /// Identifies the pixel, which the orders of the strata are made from.
+    pixel: u64,
+    /// The render's seed, mixed, which the orders are varied by.
+    seed: u64,
+    sample: u32,
+    samples: u32,
+    dimension: u32,
+    rng: Rng,
+}
+
+impl Sampler for Stratified {
+    fn get_1d(&mut self) -> f32 {
+        // Each dimension orders the strata differently, so they aren't correlated
+        let seed = mix(self.pixel ^ (u64::from(self.dimension) << 32) ^ self.seed);
+        self.dimension += 1;
+        let stratum = permute(self.sample, self.samples, seed as u32);
+        let jitter = self.rng.next_u64() >> 40;
+        let value = ((u64::from(stratum) << 24) + jitter) / u64::from(self.samples);
+        to_f32(value)
+    }
+}
+
+/// Follows the Halton sequence, scrambled in each pixel. See [`SamplerKind::Halton`].
+pub struct Halton {
+    /// Identifies the pixel, which the scrambling is made from.
+    pixel: u64,
+    /// The render's seed, mixed, which the scrambling is varied by.
+    seed: u64,
+    sample: u32,
+    dimension: u32,
+    rng: Rng,
+}
+
+impl Sampler for Halton {
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let Some(&base) = HALTON_BASES.get(dimension as usize) else {
+            return self.rng.next_f32();
+        };
+        // Each dimension scrambles the digits differently, so they aren't correlated
+        let seed = mix(self.pixel ^ (u64::from(dimension) << 32) ^ self.seed);
+        to_f32(scrambled_radical_inverse(self.sample, base, seed))
+    }
+}
+
+/// Follows the same Halton sequence in every pixel, offset by a blue noise mask. See
+/// [`SamplerKind::BlueNoise`].
+pub struct BlueNoise {
+    x: usize,
+    y: usize,
+    /// The render's seed, mixed, which the scrambling and the mask's offsets are varied by.
+    seed: u64,
+    sample: u32,
+    dimension: u32,
+    rng: Rng,
+}
+
+impl Sampler for BlueNoise {
+    fn get_1d(&mut self) -> f32 {
+        let dimension = self.dimension;
+        self.dimension += 1;
+        let Some(&base) = HALTON_BASES.get(dimension as usize) else {
+            return self.rng.next_f32();
+        };
+        // Every dimension reads the mask from a different place
+        let seed = mix(u64::from(dimension) ^ self.seed);
+        let value = scrambled_radical_inverse(self.sample, base, seed);
+        let x = self.x + (seed >> 32) as usize % MASK_SIZE;
+        let y = self.y + (seed >> 48) as usize % MASK_SIZE;
+        let offset = (blue_noise::value(x, y) * (1u64 << 24) as f32) as u64;
+        to_f32((value + offset) % (1 << 24))
+    }
+}
+
+/// A 24 bit fraction as a float.
+fn to_f32(value: u64) -> f32 {
+    value as f32 / (1u64 << 24) as f32
+}
+
+/// The digits of `index` in `base`, mirrored about the point and scrambled by a random
+/// permutation of the digits for each position, chosen by `seed`, as a 24 bit fraction. The
+/// zeros beyond the index's leading digit are scrambled too, to the full precision.
+fn scrambled_radical_inverse(mut index: u32, base: u32, seed: u64) -> u64 {
+    let mut value = 0.0_f64;
+    let mut scale = 1.0 / f64::from(base);
+    let mut position = 0_u64;
+    while scale * (1u64 << 24) as f64 >= 1.0 {
+        let order = mix(seed ^ position) as u32;
+        let digit = permute(index % base, base, order);
+        value += f64::from(digit) * scale;
+        index /= base;
+        scale /= f64::from(base);
+        position += 1;
+    }
+    ((value * (1u64 << 24) as f64) as u64).min((1 << 24) - 1)
+}
+
+/// Position of `index` in a random ordering of `0..length`, chosen by `seed`, after Kensler's
+/// correlated multi-jittered sampling. The hash is invertible over the smallest power of two
+/// covering the length, and is repeated until it lands within it.
+fn permute(mut index: u32, length: u32, seed: u32) -> u32 {
+    let mask = u32::MAX >> (length - 1).max(1).leading_zeros();
+    loop {
+        index ^= seed;
+        index = index.wrapping_mul(0xe170_893d);
+        index ^= seed >> 16;
+        index ^= (index & mask) >> 4;
+        index ^= seed >> 8;
+        index = index.wrapping_mul(0x0929_eb3f);
+        index ^= seed >> 23;
+        index ^= (index & mask) >> 1;
+        index = index.wrapping_mul(1 | seed >> 27);
+        index = index.wrapping_mul(0x6935_fa69);
+        index ^= (index & mask) >> 11;
+        index = index.wrapping_mul(0x74dc_b303);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0x9e50_1cc3);
+        index ^= (index & mask) >> 2;
+        index = index.wrapping_mul(0xc860_a3df);
+        index &= mask;
+        index ^= index >> 5;
+        if index < length {
+            return (index + seed % length) % length;
+        }
+    }
+}
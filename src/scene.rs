@@ -0,0 +1,331 @@
+use crate::{
+    camera::{Camera, ThinLensCamera},
+    clouds::CloudLayer,
+    environment::EnvironmentMap,
+    light::{is_valid_emission, AreaLight, Light, PointLight, SphereLight, SpotLight},
+    material::{
+        Dielectric, Diffuse, Emissive, Lambertian, Material, MaterialId, Media, Medium, Specular,
+    },
+    rng::Rng,
+    sky::Sky,
+    Ray, Sphere, Vec3f,
+};
+use std::ops::Range;
+
+/// Most lights which are all sampled at each shading point. Scenes with more have one chosen at
+/// random instead.
+pub const LIGHTS_SAMPLED_IN_FULL: usize = 8;
+
+pub struct Scene {
+    pub spheres: Vec<Sphere>,
+    pub materials: Vec<Box<dyn Material>>,
+    /// Lights sampled directly when shading, including the emissive spheres added with
+    /// [`Scene::add_sphere`].
+    lights: Vec<Box<dyn Light>>,
+    /// Running total of the lights' power, for choosing among them.
+    light_power: Vec<f32>,
+    pub background: Background,
+    /// Medium filling the space between the spheres, such as fog or haze. It only affects light
+    /// travelling between surfaces, as the background lies beyond it.
+    pub fog: Option<Medium>,
+    pub camera: Box<dyn Camera>,
+    /// Finds ray intersections in place of the built in sphere intersection, when set.
+    pub accelerator: Option<Box<dyn Intersector>>,
+}
+
+/// An alternative backend for finding the nearest intersection of a ray with the scene.
+pub trait Intersector: Send + Sync {
+    fn intersect(&self, ray: &Ray) -> Option<Hit>;
+}
+
+/// Where a ray hit the scene.
+pub struct Hit {
+    /// Distance along the ray to the hit.
+    pub t: f32,
+    /// Index of the sphere which was hit.
+    pub sphere: usize,
+}
+
+/// What is seen by rays which don't hit anything in the scene.
+pub enum Background {
+    Uniform(Vec3f),
+    Sky(Sky),
+    Environment(EnvironmentMap),
+}
+
+impl Background {
+    pub fn radiance(&self, ray: &Ray) -> Vec3f {
+        match self {
+            Background::Uniform(color) => *color,
+            Background::Sky(sky) => sky.radiance(ray),
+            Background::Environment(environment) => environment.radiance(ray.direction),
+        }
+    }
+
+    /// Radiance along the ray, leaving out the light which surfaces sample directly: the sun, or
+    /// the whole of an environment map.
+    pub fn indirect_radiance(&self, ray: &Ray) -> Vec3f {
+        match self {
+            Background::Uniform(color) => *color,
+            Background::Sky(sky) => sky.indirect_radiance(ray),
+            Background::Environment(_) => Vec3f::new_uniform(0.0),
+        }
+    }
+}
+
+impl Scene {
+    /// Find the nearest sphere which the ray intersects.
+    pub fn intersect(&self, ray: &Ray) -> Option<Hit> {
+        if let Some(accelerator) = &self.accelerator {
+            return accelerator.intersect(ray);
+        }
+        let mut near: Option<Hit> = None;
+        for (index, sphere) in self.spheres.iter().enumerate() {
+            if let Some((mut t0, t1)) = sphere.intersect(ray) {
+                // If the first intersection point lies behind the ray origin, then the first
+                // intersection is the same as the second.
+                if t0 < 0_f32 {
+                    t0 = t1;
+                }
+                if near.as_ref().is_none_or(|near| t0 < near.t) {
+                    near = Some(Hit {
+                        t: t0,
+                        sphere: index,
+                    });
+                }
+            }
+        }
+        near
+    }
+
+    pub fn new(background: Background) -> Self {
+        Scene {
+            spheres: Vec::new(),
+            materials: Vec::new(),
+            lights: Vec::new(),
+            light_power: Vec::new(),
+            background,
+            fog: None,
+            camera: Box::new(ThinLensCamera::default()),
+            accelerator: None,
+        }
+    }
+
+    /// The medium which a ray travelling through `media` is in: the innermost, or the fog when
+    /// outside every sphere.
+    pub fn medium(&self, media: &Media) -> Option<Medium> {
+        if media.is_outside() {
+            self.fog
+        } else {
+            media.medium()
+        }
+    }
+
+    /// Start building a scene, which is empty with a black background until added to.
+    pub fn builder() -> SceneBuilder {
+        SceneBuilder {
+            scene: Scene::new(Background::Uniform(Vec3f::new_uniform(0.0))),
+        }
+    }
+
+    pub fn add_material(&mut self, material: impl Material + 'static) -> MaterialId {
+        self.materials.push(Box::new(material));
+        MaterialId(self.materials.len() - 1)
+    }
+
+    pub fn material(&self, id: MaterialId) -> &dyn Material {
+        self.materials[id.0].as_ref()
+    }
+
+    /// Add a sphere, which becomes a light if its material emits light.
+    pub fn add_sphere(&mut self, sphere: Sphere) {
+        let emission = self.material(sphere.material).emitted();
+        if emission.is_positive() {
+            self.add_light(SphereLight::new(self.spheres.len(), sphere, emission));
+        }
+        self.spheres.push(sphere);
+    }
+
+    pub fn add_light(&mut self, light: impl Light + 'static) {
+        let total = self.light_power.last().copied().unwrap_or(0.0);
+        self.light_power.push(total + light.power().max(0.0));
+        self.lights.push(Box::new(light));
+    }
+
+    pub fn lights(&self) -> &[Box<dyn Light>] {
+        &self.lights
+    }
+
+    /// Indices of the lights to sample at a shading point, with the probability that each was
+    /// chosen. A few lights are all sampled, while from many, one is chosen in proportion to its
+    /// power.
+    pub fn choose_lights(&self, rng: &mut Rng) -> (Range<usize>, f32) {
+        if self.lights.len() <= LIGHTS_SAMPLED_IN_FULL {
+            return (0..self.lights.len(), 1.0);
+        }
+        match self.choose_light(rng) {
+            Some((index, probability)) => (index..index + 1, probability),
+            None => (0..0, 1.0),
+        }
+    }
+
+    /// Probability of [`Scene::choose_lights`] choosing the light with the given index.
+    pub fn light_probability(&self, index: usize) -> f32 {
+        if self.lights.len() <= LIGHTS_SAMPLED_IN_FULL {
+            return 1.0;
+        }
+        self.power_fraction(index)
+    }
+
+    /// Index of a single light chosen in proportion to its power, with the probability that it
+    /// was chosen, or `None` if no light emits anything.
+    pub fn choose_light(&self, rng: &mut Rng) -> Option<(usize, f32)> {
+        let total = self.light_power.last().copied().unwrap_or(0.0);
+        if total <= 0.0 {
+            return None;
+        }
+        let target = rng.next_f32() * total;
+        let index = self.light_power.partition_point(|&power| power <= target);
+        let index = index.min(self.lights.len() - 1);
+        Some((index, self.power_fraction(index)))
+    }
+
+    /// Fraction of the scene's light emitted by the light with the given index, which is the
+    /// probability of [`Scene::choose_light`] choosing it.
+    pub fn power_fraction(&self, index: usize) -> f32 {
+        let total = self.light_power.last().copied().unwrap_or(0.0);
+        let before = if index > 0 {
+            self.light_power[index - 1]
+        } else {
+            0.0
+        };
+        (self.light_power[index] - before) / total
+    }
+
+    /// Spheres on a ground plane, lit by a spherical light against a bright background.
+    pub fn classic() -> Self {
+        let mut scene = Scene::new(Background::Uniform(Vec3f::new_uniform(2.0)));
+        let ground = scene.add_material(Diffuse::new(Vec3f::new(0.2, 0.2, 0.2)));
+        add_spheres(&mut scene, ground);
+        // Light
+        let light = scene.add_material(Emissive::new(Vec3f::new_uniform(225.0)));
+        scene.add_sphere(Sphere::new(Vec3f::new(0.0, 20.0, -30.0), 3.0, light));
+        scene
+    }
+
+    /// The same spheres outside, lit by the sun under a cloudy sky, on ground which bounces light
+    /// between them.
+    pub fn outdoor() -> Self {
+        let sky = Sky::new(Vec3f::new(-0.4, 0.5, 0.35)).with_clouds(CloudLayer::new(150.0, 300.0));
+        let mut scene = Scene::new(Background::Sky(sky));
+        let ground = scene.add_material(Lambertian::new(Vec3f::new(0.2, 0.2, 0.2)));
+        add_spheres(&mut scene, ground);
+        scene
+    }
+}
+
+/// Builds a scene up one sphere at a time, checking it makes sense once it is finished.
+pub struct SceneBuilder {
+    scene: Scene,
+}
+
+impl SceneBuilder {
+    pub fn background(mut self, background: Background) -> Self {
+        self.scene.background = background;
+        self
+    }
+
+    pub fn camera(mut self, camera: impl Camera + 'static) -> Self {
+        self.scene.camera = Box::new(camera);
+        self
+    }
+
+    pub fn fog(mut self, fog: Medium) -> Self {
+        self.scene.fog = Some(fog);
+        self
+    }
+
+    /// Add a sphere with a material of its own.
+    pub fn add_sphere(
+        mut self,
+        center: Vec3f,
+        radius: f32,
+        material: impl Material + 'static,
+    ) -> Self {
+        let material = self.scene.add_material(material);
+        self.scene.add_sphere(Sphere::new(center, radius, material));
+        self
+    }
+
+    /// Add a spherical light, emitting `emission` radiance from its surface.
+    pub fn add_light(self, center: Vec3f, radius: f32, emission: Vec3f) -> Self {
+        self.add_sphere(center, radius, Emissive::new(emission))
+    }
+
+    pub fn add_point_light(mut self, light: PointLight) -> Self {
+        self.scene.add_light(light);
+        self
+    }
+
+    pub fn add_spot_light(mut self, light: SpotLight) -> Self {
+        self.scene.add_light(light);
+        self
+    }
+
+    pub fn add_area_light(mut self, light: AreaLight) -> Self {
+        self.scene.add_light(light);
+        self
+    }
+
+    /// Finish the scene, or describe the first problem with it.
+    pub fn build(self) -> Result<Scene, String> {
+        let scene = self.scene;
+        if let Background::Uniform(color) = scene.background {
+            if !is_valid_emission(color) {
+                return Err("background color must be finite and not negative".to_string());
+            }
+        }
+        if let Some(fog) = scene.fog {
+            if !is_valid_emission(fog.scattering) || !is_valid_emission(fog.absorption) {
+                return Err("fog must have a finite, non-negative density".to_string());
+            }
+        }
+        for (index, sphere) in scene.spheres.iter().enumerate() {
+            if !sphere.center.is_finite() {
+                return Err(format!("sphere {index} has a non-finite center"));
+            }
+            if !(sphere.radius > 0.0 && sphere.radius.is_finite()) {
+                return Err(format!(
+                    "sphere {index} has radius {}, which must be positive",
+                    sphere.radius
+                ));
+            }
+            if !is_valid_emission(scene.material(sphere.material).emitted()) {
+                return Err(format!(
+                    "sphere {index} emits light which must be finite and not negative"
+                ));
+            }
+        }
+        for light in scene.lights() {
+            light.validate()?;
+        }
+        Ok(scene)
+    }
+}
+
+fn add_spheres(scene: &mut Scene, ground: MaterialId) {
+    let glass = scene.add_material(Dielectric::new(
+        Vec3f::new(1.0, 0.32, 0.36),
+        Dielectric::GLASS,
+    ));
+    let gold = scene.add_material(Specular::new(Vec3f::new(0.9, 0.76, 0.46)));
+    let blue = scene.add_material(Specular::new(Vec3f::new(0.65, 0.77, 0.97)));
+    let silver = scene.add_material(Specular::new(Vec3f::new(0.9, 0.9, 0.9)));
+    scene.spheres.extend([
+        Sphere::new(Vec3f::new(0.0, -10004.0, -20.0), 10000.0, ground),
+        Sphere::new(Vec3f::new(0.0, 0.0, -20.0), 4.0, glass),
+        Sphere::new(Vec3f::new(5.0, -1.0, -15.0), 2.0, gold),
+        Sphere::new(Vec3f::new(5.0, 0.0, -25.0), 3.0, blue),
+        Sphere::new(Vec3f::new(-5.5, 0.0, -15.0), 3.0, silver),
+    ]);
+}
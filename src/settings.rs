@@ -0,0 +1,111 @@
+use crate::{
+    aov::Aov, filter::Filter, integrator::IntegratorKind, lut::Lut, sampler::SamplerKind,
+    tile::TileOrder, tone_map::ToneMap, tracer::MAX_RAY_DEPTH,
+};
+use std::{num::NonZeroUsize, thread};
+
+/// Options controlling how a render is carried out.
+pub struct RenderSettings {
+    /// Size of the image, in pixels.
+    pub width: usize,
+    pub height: usize,
+    /// Use the wavefront renderer rather than recursive tracing.
+    pub wavefront: bool,
+    /// Integrator finding the light along each camera ray. The wavefront renderer only supports
+    /// Whitted ray tracing.
+    pub integrator: IntegratorKind,
+    /// Camera rays traced through each pixel and averaged, to smooth jagged edges and noise.
+    pub samples_per_pixel: usize,
+    /// Stop taking samples in a pixel once the standard error of its brightness falls to this
+    /// fraction of it, so converged areas finish early, leaving `samples_per_pixel` as the most
+    /// taken where it's noisy. Not supported by the wavefront renderer.
+    pub noise_threshold: Option<f32>,
+    /// Filter weighing the samples around each pixel, which are spread over its footprint.
+    pub filter: Filter,
+    /// How the random numbers of each pixel's samples are placed.
+    pub sampler: SamplerKind,
+    /// Brightest the light a path traced sample finds after its first bounce may be, in its
+    /// brightest component. Rare paths which find a bright light by chance otherwise leave
+    /// single blown out pixels, fireflies, which clamping removes at the cost of a little of
+    /// the light.
+    pub max_indirect: Option<f32>,
+    /// Seed every random number of the render is made from, so it's reproduced exactly whenever
+    /// the settings and seed are, whatever order its pixels are rendered in.
+    pub seed: u64,
+    /// Most reflections and refractions followed by Whitted ray tracing, in either renderer, and
+    /// by irradiance caching.
+    pub max_depth: usize,
+    /// Size of the square tiles the image is rendered in, in pixels, and the order they're
+    /// rendered in. The wavefront renderer renders whole strips of the image at once instead.
+    pub tile_size: usize,
+    pub tile_order: TileOrder,
+    /// Threads tiles are rendered on, each taking the next tile to render as it finishes one.
+    /// The wavefront renderer, Metropolis light transport and progressive rendering use only the
+    /// thread they're called on.
+    pub threads: usize,
+    /// Report each tile as it's finished.
+    pub report_progress: bool,
+    /// Maximum memory to use for image buffers, in bytes. When the render wouldn't fit, quality
+    /// is gradually traded for memory rather than running out.
+    pub memory_budget: Option<usize>,
+    /// Passes written alongside the render, each beside it with its name before the extension.
+    /// Not supported by the wavefront renderer or Metropolis light transport.
+    pub aovs: Vec<Aov>,
+    /// Give the render an alpha channel, the fraction of each pixel's camera rays which hit
+    /// something, and leave the background transparent rather than rendering it, so the render
+    /// can be composited over another. Only PNG, TIFF and EXR images have one.
+    pub alpha: bool,
+    /// Quality of JPEG images, from 1 to 100, trading size for fidelity.
+    pub quality: u8,
+    /// Write PNG and TIFF images with 16 bits per channel rather than 8, for renders which will
+    /// be graded further.
+    pub sixteen_bit: bool,
+    /// Write EXR images in half precision floats, at half the size, rather than full.
+    pub half_float: bool,
+    /// Stops the light is brightened by, or darkened if negative, before tone mapping.
+    pub exposure: f32,
+    /// Curve fitting the light into the range of 8 bit images, before the LUT.
+    pub tone_map: ToneMap,
+    /// Gamma the colors of 8 bit images are encoded with, before the LUT, instead of the sRGB
+    /// curve. One leaves them linear.
+    pub gamma: Option<f32>,
+    /// Lookup table applied to the final colors, once encoded for display, to give the render
+    /// the look of a particular film or camera.
+    pub lut: Option<Lut>,
+    /// Report where NaN or infinite values are produced, and mark their pixels in magenta, rather
+    /// than dropping the samples producing them.
+    pub debug_non_finite: bool,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width: 640,
+            height: 480,
+            wavefront: false,
+            integrator: IntegratorKind::Whitted,
+            samples_per_pixel: 1,
+            noise_threshold: None,
+            filter: Filter::Box,
+            sampler: SamplerKind::Independent,
+            max_indirect: None,
+            seed: 0,
+            max_depth: MAX_RAY_DEPTH,
+            tile_size: 32,
+            tile_order: TileOrder::Scanline,
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            report_progress: false,
+            memory_budget: None,
+            aovs: Vec::new(),
+            alpha: false,
+            quality: 90,
+            sixteen_bit: false,
+            half_float: false,
+            exposure: 0.0,
+            tone_map: ToneMap::Clamp,
+            gamma: None,
+            lut: None,
+            debug_non_finite: false,
+        }
+    }
+}
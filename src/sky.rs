@@ -0,0 +1,204 @@
+use crate::{clouds::CloudLayer, Ray, Vec3f};
+use std::f32::consts::PI;
+
+/// Scale from the luminance of the Preetham model, in kcd/m², to the renderer's radiance.
+const PREETHAM_SCALE: f32 = 0.06;
+
+/// Procedural daylight sky, with a sun and optional layer of clouds. The dome is either a simple
+/// gradient between two colors, or the analytic Preetham model.
+pub struct Sky {
+    /// Direction towards the sun.
+    pub sun_direction: Vec3f,
+    /// Light arriving from the sun, on a surface facing it.
+    pub sun_color: Vec3f,
+    pub zenith_color: Vec3f,
+    pub horizon_color: Vec3f,
+    /// Color seen below the horizon, when nothing else is hit.
+    pub ground_color: Vec3f,
+    pub clouds: Option<CloudLayer>,
+    /// Haziness of the atmosphere for the Preetham model, from about 2 (very clear) to 10
+    /// (hazy), or `None` for the gradient.
+    pub turbidity: Option<f32>,
+}
+
+impl Sky {
+    pub fn new(sun_direction: Vec3f) -> Self {
+        Sky {
+            sun_direction: sun_direction.normalized(),
+            sun_color: Vec3f::new(1.6, 1.5, 1.3),
+            zenith_color: Vec3f::new(0.25, 0.45, 0.9),
+            horizon_color: Vec3f::new(0.75, 0.85, 1.0),
+            ground_color: Vec3f::new(0.35, 0.33, 0.3),
+            clouds: None,
+            turbidity: None,
+        }
+    }
+
+    /// A sky following the Preetham model of daylight, "A Practical Analytic Model for Daylight",
+    /// for the sun in `sun_direction` and the atmosphere's turbidity. The sun's color comes from
+    /// the light it loses passing through the atmosphere, so it reddens towards the horizon.
+    pub fn preetham(sun_direction: Vec3f, turbidity: f32) -> Self {
+        let turbidity = turbidity.clamp(1.7, 10.0);
+        let mut sky = Sky {
+            turbidity: Some(turbidity),
+            ..Sky::new(sun_direction)
+        };
+        sky.sun_color = sun_transmittance(sky.sun_direction, turbidity) * 1.8;
+        // The gradient colors are kept as a summary of the dome, for ambient light
+        sky.zenith_color = sky.dome_radiance(Vec3f::new(0.0, 1.0, 0.0));
+        let away = Vec3f::new(-sky.sun_direction.x, 0.05, -sky.sun_direction.z);
+        sky.horizon_color = sky.dome_radiance(away.normalized());
+        sky
+    }
+
+    pub fn with_clouds(mut self, clouds: CloudLayer) -> Self {
+        self.clouds = Some(clouds);
+        self
+    }
+
+    /// Radiance arriving along the ray from the sky, through any clouds.
+    pub fn radiance(&self, ray: &Ray) -> Vec3f {
+        self.through_clouds(ray, self.clear_radiance(ray.direction))
+    }
+
+    /// Radiance arriving along the ray from the sky without the sun disc, for rays from surfaces
+    /// which are lit by the sun directly.
+    pub fn indirect_radiance(&self, ray: &Ray) -> Vec3f {
+        self.through_clouds(ray, self.dome_radiance(ray.direction))
+    }
+
+    fn through_clouds(&self, ray: &Ray, background: Vec3f) -> Vec3f {
+        match &self.clouds {
+            Some(clouds) => {
+                let (in_scattered, transmittance) = clouds.march(ray, self);
+                background * transmittance + in_scattered
+            }
+            None => background,
+        }
+    }
+
+    /// Radiance of the sky in the given direction, ignoring clouds.
+    pub fn clear_radiance(&self, direction: Vec3f) -> Vec3f {
+        // Angular radius of the sun disc is about a quarter of a degree
+        const SUN_COS_RADIUS: f32 = 0.99999;
+
+        let sky = self.dome_radiance(direction);
+        if direction.y >= 0.0 && direction.dot_product(self.sun_direction) > SUN_COS_RADIUS {
+            sky + self.sun_color * 20.0
+        } else {
+            sky
+        }
+    }
+
+    /// Radiance of the sky in the given direction, ignoring clouds and the sun disc.
+    fn dome_radiance(&self, direction: Vec3f) -> Vec3f {
+        if direction.y < 0.0 {
+            return self.ground_color;
+        }
+        if let Some(turbidity) = self.turbidity {
+            return preetham_radiance(direction, self.sun_direction, turbidity);
+        }
+        let gradient = direction.y.sqrt();
+        let sky = self.horizon_color * (1.0 - gradient) + self.zenith_color * gradient;
+        let cos_sun = direction.dot_product(self.sun_direction);
+        // Haze around the sun
+        sky + self.sun_color * (0.3 * cos_sun.max(0.0).powi(32))
+    }
+
+    /// Rough average of the light arriving from the sky dome on a surface with the given normal.
+    pub fn ambient(&self, normal: Vec3f) -> Vec3f {
+        let facing_up = 0.5 + 0.5 * normal.y;
+        (self.zenith_color * 0.5 + self.horizon_color * 0.5) * facing_up
+            + self.ground_color * (1.0 - facing_up)
+    }
+
+    /// Light arriving from the sun at `point`, after passing through the clouds.
+    pub fn sun_light(&self, point: Vec3f) -> Vec3f {
+        match &self.clouds {
+            Some(clouds) => self.sun_color * clouds.transmittance(point, self.sun_direction),
+            None => self.sun_color,
+        }
+    }
+}
+
+/// Radiance of the clear sky in `direction` by the Preetham model.
+fn preetham_radiance(direction: Vec3f, sun_direction: Vec3f, turbidity: f32) -> Vec3f {
+    let t = turbidity;
+    let theta_sun = sun_direction.y.clamp(0.0, 1.0).acos();
+    let cos_theta = direction.y.max(0.01);
+    let gamma = direction.dot_product(sun_direction).clamp(-1.0, 1.0).acos();
+
+    // Perez distribution coefficients for luminance and the two chromaticities
+    let perez = [
+        [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ],
+        [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ],
+        [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ],
+    ];
+    let distribution = |[a, b, c, d, e]: [f32; 5], cos_theta: f32, gamma: f32| {
+        (1.0 + a * (b / cos_theta).exp()) * (1.0 + c * (d * gamma).exp() + e * gamma.cos().powi(2))
+    };
+
+    // Values at the zenith, which the distribution is relative to
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_sun);
+    let zenith_luminance = (4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192;
+    let polynomial =
+        |[a, b, c, d]: [f32; 4]| a * theta_sun.powi(3) + b * theta_sun.powi(2) + c * theta_sun + d;
+    let zenith_x = t * t * polynomial([0.00166, -0.00375, 0.00209, 0.0])
+        + t * polynomial([-0.02903, 0.06377, -0.03202, 0.00394])
+        + polynomial([0.11693, -0.21196, 0.06052, 0.25886]);
+    let zenith_y = t * t * polynomial([0.00275, -0.00610, 0.00317, 0.0])
+        + t * polynomial([-0.04214, 0.08970, -0.04153, 0.00516])
+        + polynomial([0.15346, -0.26756, 0.06670, 0.26688]);
+
+    let relative = |zenith: f32, coefficients: [f32; 5]| {
+        zenith * distribution(coefficients, cos_theta, gamma)
+            / distribution(coefficients, 1.0, theta_sun)
+    };
+    let luminance = relative(zenith_luminance, perez[0]);
+    let x = relative(zenith_x, perez[1]);
+    let y = relative(zenith_y, perez[2]);
+
+    // xyY to XYZ, and on to linear sRGB
+    let luminance = luminance.max(0.0) * PREETHAM_SCALE;
+    let big_x = x / y * luminance;
+    let big_z = (1.0 - x - y) / y * luminance;
+    Vec3f::new(
+        3.2406 * big_x - 1.5372 * luminance - 0.4986 * big_z,
+        -0.9689 * big_x + 1.8758 * luminance + 0.0415 * big_z,
+        0.0557 * big_x - 0.2040 * luminance + 1.0570 * big_z,
+    )
+}
+
+/// Fraction of the sun's light in each channel which passes through the atmosphere, from
+/// Rayleigh scattering by air and scattering by aerosols, which grows with turbidity.
+fn sun_transmittance(sun_direction: Vec3f, turbidity: f32) -> Vec3f {
+    let theta = sun_direction.y.clamp(0.0, 1.0).acos();
+    // Relative optical mass of air, rising steeply towards the horizon
+    let air_mass = 1.0 / (theta.cos() + 0.15 * (93.885 - theta.to_degrees()).max(0.1).powf(-1.253));
+    let beta = 0.04608 * turbidity - 0.04586;
+    // Representative wavelengths of each channel, in micrometres
+    let channel = |wavelength: f32| {
+        let rayleigh = 0.008735 * wavelength.powf(-4.08);
+        let aerosol = beta * wavelength.powf(-1.3);
+        (-air_mass * (rayleigh + aerosol)).exp()
+    };
+    Vec3f::new(channel(0.65), channel(0.57), channel(0.475))
+}
@@ -1 +1,111 @@
-use crate::Vec3f;
+use crate::{material::MaterialId, rng::Rng, Ray, Vec3f};
+use std::f32::consts::PI;
+
+#[derive(Copy, Clone)]
+pub struct Sphere {
+    pub center: Vec3f,
+    pub radius: f32,
+    pub sqr_radius: f32,
+    pub material: MaterialId,
+}
+
+impl Sphere {
+    pub fn new(center: Vec3f, radius: f32, material: MaterialId) -> Self {
+        Sphere {
+            center,
+            radius,
+            sqr_radius: radius * radius,
+            material,
+        }
+    }
+
+    /// Find the intersection points of the given ray within the sphere.
+    /// Intersection points are given as float, distance along the ray.
+    pub fn intersect(&self, ray: &Ray) -> Option<(f32, f32)> {
+        // Line from sphere center to ray origin
+        let l: Vec3f = self.center - ray.origin;
+        // Distance from sphere center to ray origin, in direction of ray
+        let tca: f32 = l.dot_product(ray.direction);
+        // Square distance from ray origin to sphere center
+        let sqr_l = l.dot_product(l);
+        // If `tca` is negative, sphere center is behind ray origin. The ray can only hit the
+        // sphere if it starts inside of it.
+        if tca < 0_f32 && sqr_l > self.sqr_radius {
+            return None;
+        }
+        // Square distance from sphere center to ray, perpendicular to ray. Computed from the
+        // perpendicular vector itself rather than `sqr_l - tca * tca`, which loses all precision
+        // when the ray origin is far from the sphere.
+        let d = l - ray.direction * tca;
+        let d2 = d.dot_product(d);
+        // If distance > radius, the ray lies outside the sphere
+        if d2 > self.sqr_radius {
+            return None;
+        }
+        // Distance from `d` to intersection point
+        let thc: f32 = (self.sqr_radius - d2).sqrt();
+        Some((tca - thc, tca + thc))
+    }
+
+    /// Texture coordinates of the point on the surface with the given outward normal. u runs
+    /// around the vertical axis, starting and ending at -X, and v from 0 at the bottom to 1 at
+    /// the top.
+    pub fn uv(&self, normal: Vec3f) -> (f32, f32) {
+        let u = ((-normal.z).atan2(normal.x) + PI) / (2.0 * PI);
+        let v = (-normal.y).clamp(-1.0, 1.0).acos() / PI;
+        (u, v)
+    }
+
+    /// Tangent at the point on the surface with the given normal, in the direction of increasing
+    /// u, which runs around the sphere's vertical axis like a line of latitude.
+    pub fn tangent(&self, normal: Vec3f) -> Vec3f {
+        let tangent = Vec3f::new(normal.z, 0.0, -normal.x);
+        if tangent.sqr_magnitude() > 1e-12 {
+            tangent.normalized()
+        } else {
+            // Lines of latitude vanish at the poles
+            normal.tangent_frame().0
+        }
+    }
+
+    /// Sample a direction from `point` towards the sphere, uniformly over the cone of directions
+    /// it covers, with the probability density of the direction per unit solid angle. `None`
+    /// when the point is inside the sphere.
+    pub fn sample_towards(&self, point: Vec3f, rng: &mut Rng) -> Option<(Vec3f, f32)> {
+        let to_center = self.center - point;
+        let sqr_distance = to_center.sqr_magnitude();
+        if sqr_distance <= self.sqr_radius {
+            return None;
+        }
+        let axis = to_center * (1.0 / sqr_distance.sqrt());
+        // One minus the cosine of the cone's half angle, rearranged to stay precise for small,
+        // distant spheres.
+        let sin2_max = self.sqr_radius / sqr_distance;
+        let one_minus_cos_max = sin2_max / (1.0 + (1.0 - sin2_max).sqrt());
+        let cos_theta = 1.0 - rng.next_f32() * one_minus_cos_max;
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * PI * rng.next_f32();
+        let (tangent, bitangent) = axis.tangent_frame();
+        let direction = tangent * (sin_theta * phi.cos())
+            + bitangent * (sin_theta * phi.sin())
+            + axis * cos_theta;
+        Some((direction.normalized(), 1.0 / (2.0 * PI * one_minus_cos_max)))
+    }
+
+    /// Probability density per unit solid angle of [`Sphere::sample_towards`] choosing
+    /// `direction` from `point`.
+    pub fn pdf_towards(&self, point: Vec3f, direction: Vec3f) -> f32 {
+        let to_center = self.center - point;
+        let sqr_distance = to_center.sqr_magnitude();
+        if sqr_distance <= self.sqr_radius {
+            return 0.0;
+        }
+        let sin2_max = self.sqr_radius / sqr_distance;
+        let one_minus_cos_max = sin2_max / (1.0 + (1.0 - sin2_max).sqrt());
+        let cos_theta = to_center.dot_product(direction) / sqr_distance.sqrt();
+        if 1.0 - cos_theta > one_minus_cos_max {
+            return 0.0;
+        }
+        1.0 / (2.0 * PI * one_minus_cos_max)
+    }
+}
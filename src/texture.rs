@@ -0,0 +1,449 @@
+//! Textures, which vary a color over a surface.
+
+use crate::{exr, noise, output::srgb_to_linear, png, Vec3f};
+use std::path::Path;
+
+/// A color which varies over a surface.
+pub trait Texture: Send + Sync {
+    /// Color at `point`, where the surface has texture coordinates `uv`.
+    fn value(&self, uv: (f32, f32), point: Vec3f) -> Vec3f;
+
+    /// Average color over the surface, for exporting to formats which can't express the texture.
+    fn average(&self) -> Vec3f;
+}
+
+/// A flat color is the simplest texture.
+impl Texture for Vec3f {
+    fn value(&self, _uv: (f32, f32), _point: Vec3f) -> Vec3f {
+        *self
+    }
+
+    fn average(&self) -> Vec3f {
+        *self
+    }
+}
+
+impl Texture for Box<dyn Texture> {
+    fn value(&self, uv: (f32, f32), point: Vec3f) -> Vec3f {
+        self.as_ref().value(uv, point)
+    }
+
+    fn average(&self) -> Vec3f {
+        self.as_ref().average()
+    }
+}
+
+/// Checkerboard alternating between two textures.
+pub struct Checker<A, B> {
+    pub even: A,
+    pub odd: B,
+    /// Number of squares per unit of texture coordinates, or of cubes per unit of distance for
+    /// solid checkerboards.
+    pub scale: f32,
+    /// Whether the checkerboard fills space as cubes, rather than covering the texture
+    /// coordinates as squares.
+    pub solid: bool,
+}
+
+impl<A: Texture, B: Texture> Checker<A, B> {
+    /// Checkerboard over the texture coordinates, which wraps around the surface.
+    pub fn new(even: A, odd: B, scale: f32) -> Self {
+        Checker {
+            even,
+            odd,
+            scale,
+            solid: false,
+        }
+    }
+
+    /// Checkerboard filling space, which surfaces cut through without depending on their texture
+    /// coordinates.
+    pub fn solid(even: A, odd: B, scale: f32) -> Self {
+        Checker {
+            solid: true,
+            ..Checker::new(even, odd, scale)
+        }
+    }
+}
+
+impl<A: Texture, B: Texture> Texture for Checker<A, B> {
+    fn value(&self, uv: (f32, f32), point: Vec3f) -> Vec3f {
+        let cell = |x: f32| (x * self.scale).floor() as i64;
+        let parity = if self.solid {
+            cell(point.x) + cell(point.y) + cell(point.z)
+        } else {
+            cell(uv.0) + cell(uv.1)
+        };
+        if parity.rem_euclid(2) == 0 {
+            self.even.value(uv, point)
+        } else {
+            self.odd.value(uv, point)
+        }
+    }
+
+    fn average(&self) -> Vec3f {
+        (self.even.average() + self.odd.average()) * 0.5
+    }
+}
+
+/// How a [`NoiseTexture`] shapes its noise.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum NoisePattern {
+    /// Soft, cloudy blotches.
+    Smooth,
+    /// Wispy noise with sharp creases.
+    Turbulence,
+    /// Veins running along X, distorted by turbulence.
+    Marble,
+    /// Rings around the vertical axis, distorted by noise.
+    Wood,
+}
+
+/// Procedural texture blending between two textures by noise through space, needing no images.
+pub struct NoiseTexture<A, B> {
+    pub pattern: NoisePattern,
+    /// Texture where the noise is lowest.
+    pub low: A,
+    /// Texture where the noise is highest.
+    pub high: B,
+    /// Frequency of the noise, so larger values give smaller features.
+    pub scale: f32,
+}
+
+impl<A: Texture, B: Texture> NoiseTexture<A, B> {
+    pub fn new(pattern: NoisePattern, low: A, high: B, scale: f32) -> Self {
+        NoiseTexture {
+            pattern,
+            low,
+            high,
+            scale,
+        }
+    }
+
+    /// Noise at `point`, from 0 to 1.
+    fn noise(&self, point: Vec3f) -> f32 {
+        let p = point * self.scale;
+        let t = match self.pattern {
+            NoisePattern::Smooth => noise::fbm(p, 5) * 0.5 + 0.5,
+            NoisePattern::Turbulence => noise::turbulence(p, 7),
+            NoisePattern::Marble => 0.5 + 0.5 * (p.x + 4.0 * noise::turbulence(p, 7)).sin(),
+            NoisePattern::Wood => {
+                let rings = (p.x * p.x + p.z * p.z).sqrt() + 0.3 * noise::fbm(p, 3);
+                rings.rem_euclid(1.0)
+            }
+        };
+        t.clamp(0.0, 1.0)
+    }
+}
+
+impl<A: Texture, B: Texture> Texture for NoiseTexture<A, B> {
+    fn value(&self, uv: (f32, f32), point: Vec3f) -> Vec3f {
+        let t = self.noise(point);
+        self.low.value(uv, point) * (1.0 - t) + self.high.value(uv, point) * t
+    }
+
+    fn average(&self) -> Vec3f {
+        (self.low.average() + self.high.average()) * 0.5
+    }
+}
+
+/// A bitmap, repeating outside of texture coordinates from 0 to 1, with the first row at the top
+/// where v is 1.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    pixels: Vec<Vec3f>,
+}
+
+impl ImageTexture {
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3f>) -> Self {
+        assert_eq!(pixels.len(), width * height, "wrong number of pixels");
+        ImageTexture {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Load a Radiance `.hdr`, `.pfm`, `.png`, `.exr`, or binary or plain text PPM image, going
+    /// by the extension. PPM and PNG values are taken to be sRGB encoded, as the renderer writes
+    /// them, and are decoded to linear light; the others are linear already. TIFF and JPEG images
+    /// are only written, not read.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let extension = path.extension().and_then(|extension| extension.to_str());
+        let extension = extension.map(str::to_ascii_lowercase);
+        if let Some("tif" | "tiff" | "jpg" | "jpeg") = extension.as_deref() {
+            return Err(format!(
+                "Can't read `{}`, TIFF and JPEG images can only be written",
+                path.display()
+            ));
+        }
+        let bytes = std::fs::read(path)
+            .map_err(|err| format!("Failed to read image `{}`: {err}", path.display()))?;
+        match extension.as_deref() {
+            Some("hdr") => ImageTexture::parse_hdr(&bytes),
+            Some("pfm") => ImageTexture::parse_pfm(&bytes),
+            Some("png") => png::decode(&bytes),
+            Some("exr") => exr::decode(&bytes),
+            _ => ImageTexture::parse_ppm(&bytes),
+        }
+        .map_err(|err| format!("Invalid image `{}`: {err}", path.display()))
+    }
+
+    /// Parse a Radiance RGBE image, with or without run length encoding.
+    pub fn parse_hdr(bytes: &[u8]) -> Result<Self, String> {
+        let mut position = 0;
+        let mut line = || {
+            let start = position;
+            let end = bytes[start..].iter().position(|&b| b == b'\n')? + start;
+            position = end + 1;
+            std::str::from_utf8(&bytes[start..end]).ok()
+        };
+        if !line().is_some_and(|magic| magic.starts_with("#?")) {
+            return Err("expected a Radiance header".to_string());
+        }
+        // Header variables end at an empty line
+        loop {
+            match line() {
+                Some("") => break,
+                Some(variable) if variable.starts_with("FORMAT=") => {
+                    if variable != "FORMAT=32-bit_rle_rgbe" {
+                        return Err(format!("unsupported {variable}"));
+                    }
+                }
+                Some(_) => {}
+                None => return Err("unterminated header".to_string()),
+            }
+        }
+        let resolution = line().ok_or("missing resolution")?;
+        let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+            ["-Y", height, "+X", width] => (height.parse().ok(), width.parse().ok()),
+            _ => return Err(format!("unsupported orientation `{resolution}`")),
+        };
+        let (Some(height), Some(width)) = (height, width) else {
+            return Err("invalid resolution".to_string());
+        };
+        let (width, height): (usize, usize) = (width, height);
+        if width == 0 || height == 0 {
+            return Err("invalid resolution".to_string());
+        }
+
+        let mut data = bytes[position..].iter().copied();
+        let mut next = || data.next().ok_or("truncated pixel data");
+        let mut rgbe = vec![[0_u8; 4]; width * height];
+        for row in rgbe.chunks_mut(width) {
+            let first = [next()?, next()?, next()?, next()?];
+            let encoded = (8..0x8000).contains(&width)
+                && first[0] == 2
+                && first[1] == 2
+                && (first[2] as usize) << 8 | first[3] as usize == width;
+            if !encoded {
+                // Flat pixels, without run length encoding
+                row[0] = first;
+                for pixel in &mut row[1..] {
+                    *pixel = [next()?, next()?, next()?, next()?];
+                }
+                continue;
+            }
+            // Each channel of the row is encoded in turn, as runs and literal spans
+            for channel in 0..4 {
+                let mut x = 0;
+                while x < width {
+                    let count = next()? as usize;
+                    if count > 128 {
+                        let count = count - 128;
+                        let value = next()?;
+                        for pixel in row.iter_mut().skip(x).take(count) {
+                            pixel[channel] = value;
+                        }
+                        x += count;
+                    } else {
+                        if count == 0 {
+                            return Err("invalid run length".to_string());
+                        }
+                        for pixel in row.iter_mut().skip(x).take(count) {
+                            pixel[channel] = next()?;
+                        }
+                        x += count;
+                    }
+                }
+            }
+        }
+
+        let pixels = rgbe
+            .into_iter()
+            .map(|[r, g, b, e]| {
+                if e == 0 {
+                    return Vec3f::new_uniform(0.0);
+                }
+                let scale = 2_f32.powi(e as i32 - 136);
+                Vec3f::new(r as f32, g as f32, b as f32) * scale
+            })
+            .collect();
+        Ok(ImageTexture::new(width, height, pixels))
+    }
+
+    /// Parse a color PFM image, which stores rows from bottom to top.
+    pub fn parse_pfm(bytes: &[u8]) -> Result<Self, String> {
+        let mut position = 0;
+        let mut token =
+            || next_token(bytes, &mut position).and_then(|token| std::str::from_utf8(token).ok());
+        if token() != Some("PF") {
+            return Err("expected a color PFM".to_string());
+        }
+        let width: usize = token()
+            .and_then(|width| width.parse().ok())
+            .ok_or("expected the width")?;
+        let height: usize = token()
+            .and_then(|height| height.parse().ok())
+            .ok_or("expected the height")?;
+        let scale: f32 = token()
+            .and_then(|scale| scale.parse().ok())
+            .ok_or("expected the scale")?;
+        if width == 0 || height == 0 {
+            return Err("invalid resolution".to_string());
+        }
+        let data = bytes.get(position + 1..).unwrap_or_default();
+        let count = width * height * 3;
+        if data.len() < count * 4 {
+            return Err("truncated pixel data".to_string());
+        }
+        // A negative scale means little endian
+        let values: Vec<f32> = data
+            .chunks_exact(4)
+            .take(count)
+            .map(|v| {
+                let v = [v[0], v[1], v[2], v[3]];
+                if scale < 0.0 {
+                    f32::from_le_bytes(v)
+                } else {
+                    f32::from_be_bytes(v)
+                }
+            })
+            .collect();
+        let pixels = values
+            .chunks_exact(3 * width)
+            .rev()
+            .flat_map(|row| row.chunks_exact(3).map(|v| Vec3f::new(v[0], v[1], v[2])))
+            .collect();
+        Ok(ImageTexture::new(width, height, pixels))
+    }
+
+    pub fn parse_ppm(bytes: &[u8]) -> Result<Self, String> {
+        let mut position = 0;
+        let magic = next_token(bytes, &mut position).ok_or("missing header")?;
+        let binary = match magic {
+            b"P6" => true,
+            b"P3" => false,
+            _ => return Err("expected a P3 or P6 PPM".to_string()),
+        };
+        let mut number = |name: &str| {
+            next_token(bytes, &mut position)
+                .and_then(|token| std::str::from_utf8(token).ok()?.parse::<usize>().ok())
+                .ok_or_else(|| format!("expected the {name}"))
+        };
+        let width = number("width")?;
+        let height = number("height")?;
+        let max = number("maximum value")?;
+        if width == 0 || height == 0 || !(1..=65535).contains(&max) {
+            return Err("invalid header".to_string());
+        }
+
+        let count = width * height * 3;
+        let values: Vec<usize> = if binary {
+            // A single whitespace byte separates the header from the pixels
+            let data = bytes.get(position + 1..).unwrap_or_default();
+            if max < 256 {
+                data.iter().take(count).map(|&v| v as usize).collect()
+            } else {
+                data.chunks_exact(2)
+                    .take(count)
+                    .map(|v| u16::from_be_bytes([v[0], v[1]]) as usize)
+                    .collect()
+            }
+        } else {
+            let mut values = Vec::with_capacity(count);
+            for _ in 0..count {
+                values.push(number("pixel values")?);
+            }
+            values
+        };
+        if values.len() < count {
+            return Err(format!(
+                "expected {count} pixel values, found {}",
+                values.len()
+            ));
+        }
+
+        let scale = 1.0 / max as f32;
+        let decode = |value: usize| srgb_to_linear(value as f32 * scale);
+        let pixels = values
+            .chunks_exact(3)
+            .map(|v| Vec3f::new(decode(v[0]), decode(v[1]), decode(v[2])))
+            .collect();
+        Ok(ImageTexture::new(width, height, pixels))
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// The pixel in column `x` of row `y`, counting rows from the top.
+    pub fn pixel(&self, x: usize, y: usize) -> Vec3f {
+        self.pixels[x + y * self.width]
+    }
+}
+
+impl Texture for ImageTexture {
+    /// Bilinearly filtered between the four nearest pixels.
+    fn value(&self, (u, v): (f32, f32), _point: Vec3f) -> Vec3f {
+        let x = u.rem_euclid(1.0) * self.width as f32 - 0.5;
+        let y = (1.0 - v).rem_euclid(1.0) * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let wrap = |i: f32, size: usize| (i as isize).rem_euclid(size as isize) as usize;
+        let (x0, x1) = (wrap(x0, self.width), wrap(x0 + 1.0, self.width));
+        let (y0, y1) = (wrap(y0, self.height), wrap(y0 + 1.0, self.height));
+        let lerp = |a: Vec3f, b: Vec3f, t: f32| a * (1.0 - t) + b * t;
+        lerp(
+            lerp(self.pixel(x0, y0), self.pixel(x1, y0), tx),
+            lerp(self.pixel(x0, y1), self.pixel(x1, y1), tx),
+            ty,
+        )
+    }
+
+    fn average(&self) -> Vec3f {
+        let sum = self
+            .pixels
+            .iter()
+            .fold(Vec3f::new_uniform(0.0), |sum, &pixel| sum + pixel);
+        sum * (1.0 / self.pixels.len() as f32)
+    }
+}
+
+/// The next whitespace separated token of a PPM header, skipping `#` comments.
+fn next_token<'a>(bytes: &'a [u8], position: &mut usize) -> Option<&'a [u8]> {
+    loop {
+        match bytes.get(*position)? {
+            b'#' => {
+                while bytes.get(*position).is_some_and(|&b| b != b'\n') {
+                    *position += 1;
+                }
+            }
+            b if b.is_ascii_whitespace() => *position += 1,
+            _ => break,
+        }
+    }
+    let start = *position;
+    while bytes
+        .get(*position)
+        .is_some_and(|b| !b.is_ascii_whitespace())
+    {
+        *position += 1;
+    }
+    Some(&bytes[start..*position])
+}
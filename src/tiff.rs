@@ -0,0 +1,244 @@
+//! TIFF encoding, as uncompressed RGB or RGBA in a single strip, which every reader of baseline
+//! TIFF opens. Nothing is compressed, so where everything goes is known up front and rows can be
+//! written as they're rendered.
+
+use crate::{
+    framebuffer::Framebuffer,
+    output::{self, ImageWriter},
+    settings::RenderSettings,
+};
+use std::{
+    io::{self, Write},
+    time::Duration,
+};
+
+/// Little endian byte order, the version and where the directory of tags starts, just after.
+const HEADER: [u8; 8] = [b'I', b'I', 42, 0, 8, 0, 0, 0];
+
+/// Types of tag values.
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+
+/// Writes an image as a TIFF, in rows from the top, which can be written a few rows at a time as
+/// they're rendered.
+pub struct TiffWriter<W: Write> {
+    writer: W,
+    /// Write 16 bits per channel rather than 8.
+    sixteen_bit: bool,
+    /// Write an alpha channel after the color.
+    alpha: bool,
+}
+
+impl<W: Write> TiffWriter<W> {
+    /// Start writing an image `width` by `height` pixels to `writer`, with 16 bits per channel if
+    /// `sixteen_bit`, otherwise 8, and an alpha channel if `alpha`.
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        sixteen_bit: bool,
+        alpha: bool,
+    ) -> io::Result<Self> {
+        let too_large = || io::Error::new(io::ErrorKind::InvalidInput, "image too large for TIFF");
+        let bits: u16 = if sixteen_bit { 16 } else { 8 };
+        let channels: u16 = if alpha { 4 } else { 3 };
+        let bytes = u32::try_from(width * height * usize::from(channels * bits / 8))
+            .map_err(|_| too_large())?;
+        let (width, height) = (
+            u32::try_from(width).map_err(|_| too_large())?,
+            u32::try_from(height).map_err(|_| too_large())?,
+        );
+
+        // Tags in increasing order, each with its type, count and value, or where the value is
+        // when it doesn't fit in four bytes
+        let mut tags: Vec<(u16, u16, u32, u32)> = vec![
+            (256, LONG, 1, width),
+            (257, LONG, 1, height),
+            (258, SHORT, u32::from(channels), 0),
+            // Uncompressed
+            (259, SHORT, 1, 1),
+            // RGB
+            (262, SHORT, 1, 2),
+            (273, LONG, 1, 0),
+            (277, SHORT, 1, u32::from(channels)),
+            (278, LONG, 1, height),
+            (279, LONG, 1, bytes),
+            (282, RATIONAL, 1, 0),
+            (283, RATIONAL, 1, 0),
+            // Channels interleaved
+            (284, SHORT, 1, 1),
+            // Resolution in inches
+            (296, SHORT, 1, 2),
+        ];
+        if alpha {
+            // Alpha which the color isn't multiplied by
+            tags.push((338, SHORT, 1, 2));
+        }
+        let directory_size = 2 + 12 * tags.len() + 4;
+        // Values which don't fit in their tag follow the directory: the bits per sample and the
+        // resolutions, then the pixels.
+        let bits_offset = (HEADER.len() + directory_size) as u32;
+        let resolution_offset = bits_offset + 2 * u32::from(channels);
+        let pixels_offset = resolution_offset + 16;
+
+        let mut header = Vec::with_capacity(pixels_offset as usize);
+        header.extend(HEADER);
+        header.extend((tags.len() as u16).to_le_bytes());
+        for (tag, kind, count, value) in tags {
+            let value = match tag {
+                258 => bits_offset,
+                273 => pixels_offset,
+                282 => resolution_offset,
+                283 => resolution_offset + 8,
+                _ => value,
+            };
+            header.extend(tag.to_le_bytes());
+            header.extend(kind.to_le_bytes());
+            header.extend(count.to_le_bytes());
+            // Short values sit at the start of the four bytes
+            if kind == SHORT && count == 1 {
+                header.extend((value as u16).to_le_bytes());
+                header.extend([0; 2]);
+            } else {
+                header.extend(value.to_le_bytes());
+            }
+        }
+        // No further directories
+        header.extend([0; 4]);
+        for _ in 0..channels {
+            header.extend(bits.to_le_bytes());
+        }
+        // 72 pixels per inch across and down
+        for _ in 0..2 {
+            header.extend(72_u32.to_le_bytes());
+            header.extend(1_u32.to_le_bytes());
+        }
+        writer.write_all(&header)?;
+        Ok(TiffWriter {
+            writer,
+            sixteen_bit,
+            alpha,
+        })
+    }
+}
+
+impl<W: Write> ImageWriter for TiffWriter<W> {
+    fn write_rows(
+        &mut self,
+        framebuffer: &Framebuffer,
+        settings: &RenderSettings,
+    ) -> io::Result<()> {
+        for (x, y) in
+            (0..framebuffer.height).flat_map(|y| (0..framebuffer.width).map(move |x| (x, y)))
+        {
+            let (color, alpha) = output::straight_alpha(framebuffer, x, y);
+            if self.sixteen_bit {
+                for channel in output::encode_16(color, settings) {
+                    self.writer.write_all(&channel.to_le_bytes())?;
+                }
+                if self.alpha {
+                    let alpha = (alpha * 65535.0 + 0.5) as u16;
+                    self.writer.write_all(&alpha.to_le_bytes())?;
+                }
+            } else {
+                self.writer.write_all(&output::encode(color, settings))?;
+                if self.alpha {
+                    self.writer.write_all(&[(alpha * 255.0 + 0.5) as u8])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(mut self: Box<Self>, _render_time: Duration) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{framebuffer::PixelFormat, Vec3f};
+
+    fn write(sixteen_bit: bool, alpha: bool) -> Vec<u8> {
+        let mut framebuffer = Framebuffer::new(3, 2, PixelFormat::F32);
+        if alpha {
+            framebuffer = framebuffer.with_alpha();
+        }
+        for (x, y) in (0..2).flat_map(|y| (0..3).map(move |x| (x, y))) {
+            framebuffer.set(x, y, Vec3f::new(x as f32 * 0.5, y as f32, 0.0));
+            if alpha {
+                framebuffer.set_alpha(x, y, 1.0);
+            }
+        }
+        let mut bytes = Vec::new();
+        let mut writer = Box::new(TiffWriter::new(&mut bytes, 3, 2, sixteen_bit, alpha).unwrap());
+        writer
+            .write_rows(&framebuffer, &RenderSettings::default())
+            .unwrap();
+        writer.finish(Duration::ZERO).unwrap();
+        bytes
+    }
+
+    /// The value of each tag in the directory, with values which don't fit in four bytes
+    /// replaced by where they are.
+    fn tags(bytes: &[u8]) -> Vec<(u16, u32)> {
+        let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+        let count = usize::from(u16_at(8));
+        (0..count)
+            .map(|i| {
+                let entry = 10 + 12 * i;
+                let value = match (u16_at(entry + 2), u16_at(entry + 4)) {
+                    (SHORT, 1) => u32::from(u16_at(entry + 8)),
+                    _ => u32::from_le_bytes(bytes[entry + 8..entry + 12].try_into().unwrap()),
+                };
+                (u16_at(entry), value)
+            })
+            .collect()
+    }
+
+    fn tag(tags: &[(u16, u32)], tag: u16) -> usize {
+        tags.iter().find(|&&(t, _)| t == tag).unwrap().1 as usize
+    }
+
+    #[test]
+    fn writes_an_uncompressed_rgb_strip() {
+        let bytes = write(false, false);
+        assert_eq!(&bytes[..8], &HEADER);
+        let tags = tags(&bytes);
+        assert!(tags.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert_eq!((tag(&tags, 256), tag(&tags, 257)), (3, 2));
+        assert_eq!(
+            (tag(&tags, 259), tag(&tags, 262), tag(&tags, 277)),
+            (1, 2, 3)
+        );
+        let bits = tag(&tags, 258);
+        assert_eq!(&bytes[bits..bits + 6], &[8, 0, 8, 0, 8, 0]);
+
+        let (offset, length) = (tag(&tags, 273), tag(&tags, 279));
+        assert_eq!((length, bytes.len()), (18, offset + 18));
+        // sRGB encoded, from the top row
+        assert_eq!(
+            &bytes[offset..],
+            &[0, 0, 0, 188, 0, 0, 255, 0, 0, 0, 255, 0, 188, 255, 0, 255, 255, 0]
+        );
+    }
+
+    #[test]
+    fn writes_16_bit_with_alpha() {
+        let bytes = write(true, true);
+        let tags = tags(&bytes);
+        assert_eq!((tag(&tags, 277), tag(&tags, 338)), (4, 2));
+        let bits = tag(&tags, 258);
+        assert_eq!(&bytes[bits..bits + 8], &[16, 0, 16, 0, 16, 0, 16, 0]);
+
+        let (offset, length) = (tag(&tags, 273), tag(&tags, 279));
+        assert_eq!((length, bytes.len()), (48, offset + 48));
+        // The last pixel is yellow and opaque
+        assert_eq!(
+            &bytes[bytes.len() - 8..],
+            &[0xff, 0xff, 0xff, 0xff, 0, 0, 0xff, 0xff]
+        );
+    }
+}
@@ -0,0 +1,73 @@
+//! Tiles, or buckets, which the image is split into and rendered one at a time. Each tile only
+//! touches its own pixels, so they can be rendered in any order, to report progress as they
+//! finish, and apart from one another.
+
+use std::ops::Range;
+
+/// A rectangle of pixels, `width` by `height` from (`x`, `y`) at its top left.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Tile {
+    /// The tile's pixels, in rows from the top.
+    pub fn pixels(self) -> impl Iterator<Item = (usize, usize)> {
+        (self.y..self.y + self.height)
+            .flat_map(move |y| (self.x..self.x + self.width).map(move |x| (x, y)))
+    }
+}
+
+/// Order tiles are rendered in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TileOrder {
+    /// Rows of tiles from the top, each from the left.
+    Scanline,
+    /// Outwards from the middle, where the subject usually is, in rings.
+    Spiral,
+}
+
+impl TileOrder {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "scanline" => Some(TileOrder::Scanline),
+            "spiral" => Some(TileOrder::Spiral),
+            _ => None,
+        }
+    }
+}
+
+/// The `rows` of an image `width` pixels wide, split into tiles `size` pixels square, smaller
+/// where they're cut off at the edges, in `order`.
+pub fn tiles(width: usize, rows: Range<usize>, size: usize, order: TileOrder) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in rows.clone().step_by(size) {
+        for x in (0..width).step_by(size) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: size.min(width - x),
+                height: size.min(rows.end - y),
+            });
+        }
+    }
+    if order == TileOrder::Spiral {
+        let middle = (width as f32 / 2.0, (rows.start + rows.end) as f32 / 2.0);
+        // Which ring around the middle each tile is in, then its angle around it
+        let key = |tile: &Tile| {
+            let dx = (tile.x as f32 + tile.width as f32 / 2.0 - middle.0) / size as f32;
+            let dy = (tile.y as f32 + tile.height as f32 / 2.0 - middle.1) / size as f32;
+            (dx.abs().max(dy.abs()).round(), dy.atan2(dx))
+        };
+        tiles.sort_by(|a, b| key(a).partial_cmp(&key(b)).unwrap());
+    }
+    tiles
+}
+
+/// Number of tiles `tiles` splits the `rows` of an image `width` pixels wide into.
+pub fn count(width: usize, rows: Range<usize>, size: usize) -> usize {
+    width.div_ceil(size) * rows.len().div_ceil(size)
+}
@@ -0,0 +1,52 @@
+//! Tone mapping, which squeezes the range of light rendered into the range an 8 bit image can
+//! show. Without it everything brighter than one, such as the sky, clips to white and loses its
+//! color.
+
+use crate::Vec3f;
+
+/// A curve mapping rendered light to displayable colors, in `[0, 1]`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ToneMap {
+    /// Colors are left as they are, so anything brighter than one clips.
+    Clamp,
+    /// Reinhard's operator on luminance, which compresses bright colors smoothly towards white
+    /// without shifting their hue, leaving dark colors nearly untouched.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve, with a toe darkening the shadows and a shoulder
+    /// desaturating highlights as film does, for more contrast than Reinhard.
+    Aces,
+}
+
+impl ToneMap {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "clamp" => Some(ToneMap::Clamp),
+            "reinhard" => Some(ToneMap::Reinhard),
+            "aces" => Some(ToneMap::Aces),
+            _ => None,
+        }
+    }
+
+    /// `color`, after scaling by `exposure` stops, mapped by the curve.
+    pub fn apply(self, color: Vec3f, exposure: f32) -> Vec3f {
+        let color = if exposure == 0.0 {
+            color
+        } else {
+            color * exposure.exp2()
+        };
+        match self {
+            ToneMap::Clamp => color,
+            ToneMap::Reinhard => {
+                let luminance = color.luminance().max(0.0);
+                color * (1.0 / (1.0 + luminance))
+            }
+            ToneMap::Aces => {
+                let curve = |x: f32| {
+                    let x = x.max(0.0);
+                    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+                };
+                Vec3f::new(curve(color.x), curve(color.y), curve(color.z))
+            }
+        }
+    }
+}
@@ -0,0 +1,860 @@
+use crate::{
+    material::{Interaction, Material, Media, Medium},
+    rng::Rng,
+    scene::{Background, Hit, Scene},
+    Ray, Vec3f,
+};
+use std::{f32::consts::PI, fmt};
+
+/// Default for the most reflections and refractions followed by Whitted ray tracing.
+pub const MAX_RAY_DEPTH: usize = 5;
+
+/// Most scattering events followed within a medium before a path is given up on.
+const MAX_MEDIUM_EVENTS: usize = 1024;
+
+/// Most transparent surfaces a shadow ray passes through before the light is taken as blocked.
+const MAX_SHADOW_SURFACES: usize = 16;
+
+/// Steps taken through media whose density varies, to find how much light gets through them.
+const TRANSMITTANCE_STEPS: usize = 64;
+
+/// How far along its path from the camera a ray is, and how far paths are followed.
+#[derive(Copy, Clone)]
+pub struct Bounces {
+    /// Number of surfaces the path has scattered off so far.
+    pub depth: usize,
+    /// Depth at which surfaces stop scattering rays.
+    pub max_depth: usize,
+    /// Whether surfaces which are otherwise only lit directly scatter rays too, gathering the
+    /// light bouncing onto them from the rest of the scene.
+    pub gather_indirect: bool,
+    /// Whether shadow rays pass through transparent surfaces, tinted by them, in place of the
+    /// light the surfaces focus. Off where that light is found some other way.
+    pub transparent_shadows: bool,
+}
+
+impl Bounces {
+    /// Following reflections and refractions until `max_depth`, with matte surfaces only lit
+    /// directly.
+    pub fn whitted(depth: usize, max_depth: usize) -> Self {
+        Bounces {
+            depth,
+            max_depth,
+            gather_indirect: false,
+            transparent_shadows: true,
+        }
+    }
+
+    /// The bounce after this one.
+    pub fn next(self) -> Self {
+        Bounces {
+            depth: self.depth + 1,
+            ..self
+        }
+    }
+}
+
+/// The result of shading a single ray hit.
+pub struct Shaded {
+    /// Light leaving the surface towards the ray origin, not counting any secondary rays.
+    pub radiance: Vec3f,
+    /// Secondary rays to be traced, each with the weight of the light it brings back, the media
+    /// it travels through, and the lights sampled directly at its origin, if any.
+    pub secondary: Vec<(Ray, Vec3f, Media, Option<SampledLights>)>,
+}
+
+/// Where lights were sampled directly, at the origin of a secondary ray. Light the ray finds is
+/// weighed against the direct samples which could have found it too, with multiple importance
+/// sampling, so it isn't counted twice.
+#[derive(Copy, Clone)]
+pub struct SampledLights {
+    /// Shading point the lights were sampled from.
+    pub point: Vec3f,
+    /// Probability density per unit solid angle of the surface scattering the ray in its
+    /// direction.
+    pub pdf: f32,
+}
+
+/// Where a ray hit a sphere, as seen by the material there.
+pub struct Surface {
+    pub interaction: Interaction,
+    /// Normal of the sphere, facing the side the ray arrived from. The interaction's normal is
+    /// the shading normal, which the material may have perturbed.
+    pub normal: Vec3f,
+    /// Index of the sphere which was hit.
+    pub sphere: usize,
+    /// Whether the ray hit the sphere from inside.
+    pub inside: bool,
+}
+
+impl Surface {
+    /// The surface where `ray` hit the scene, having travelled through `media`.
+    pub fn new(ray: &Ray, hit: &Hit, scene: &Scene, media: &Media) -> Self {
+        let near_sphere = &scene.spheres[hit.sphere];
+        let material = scene.material(near_sphere.material);
+
+        // Point of intersection
+        let hit_point: Vec3f = ray.origin + ray.direction * hit.t;
+        let mut hit_normal: Vec3f = (hit_point - near_sphere.center).normalized();
+        let uv = near_sphere.uv(hit_normal);
+
+        let is_inside = if ray.direction.dot_product(hit_normal) > 0.0 {
+            hit_normal = -hit_normal;
+            true
+        } else {
+            false
+        };
+
+        let interaction = Interaction {
+            point: hit_point,
+            normal: hit_normal,
+            tangent: near_sphere.tangent(hit_normal),
+            uv,
+            // Hit points on large spheres are less precise, so scale with the sphere.
+            bias: 1e-4_f32.max(near_sphere.radius * 1e-6),
+            incident_ior: if is_inside {
+                material.ior().unwrap_or(media.ior())
+            } else {
+                media.ior()
+            },
+            transmitted_ior: if is_inside {
+                media.ior_outside(hit.sphere)
+            } else {
+                material.ior().unwrap_or(media.ior())
+            },
+        };
+        Surface {
+            interaction: Interaction {
+                normal: material.shading_normal(&interaction),
+                ..interaction
+            },
+            normal: hit_normal,
+            sphere: hit.sphere,
+            inside: is_inside,
+        }
+    }
+
+    /// Media which a ray leaving the surface in `direction` travels through, after `media`. Rays
+    /// passing through the surface enter or leave the medium inside it.
+    pub fn media_towards(&self, direction: Vec3f, material: &dyn Material, media: &Media) -> Media {
+        match material.ior() {
+            Some(ior) if direction.dot_product(self.normal) < 0.0 => {
+                if self.inside {
+                    media.exit(self.sphere)
+                } else {
+                    media.enter(self.sphere, ior, material.medium())
+                }
+            }
+            _ => media.clone(),
+        }
+    }
+}
+
+/// A NaN or infinite value produced while shading, and where it came from.
+#[derive(Debug)]
+pub struct NonFinite {
+    /// Index of the sphere being shaded.
+    pub sphere: usize,
+    /// Name of the sphere's material.
+    pub material: &'static str,
+    pub depth: usize,
+    /// Which quantity wasn't finite.
+    pub quantity: &'static str,
+}
+
+impl fmt::Display for NonFinite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "non-finite {} from sphere {} ({}) at depth {}",
+            self.quantity, self.sphere, self.material, self.depth
+        )
+    }
+}
+
+impl Shaded {
+    /// Check that the radiance and secondary ray weights are all finite, so a bad value is
+    /// caught where it's produced rather than spreading into the image.
+    pub fn check(&self, hit: &Hit, scene: &Scene, depth: usize) -> Result<(), NonFinite> {
+        let quantity = if !self.radiance.is_finite() {
+            "radiance"
+        } else if self
+            .secondary
+            .iter()
+            .any(|(ray, weight, ..)| !weight.is_finite() || !ray.direction.is_finite())
+        {
+            "secondary ray"
+        } else {
+            return Ok(());
+        };
+        let sphere = &scene.spheres[hit.sphere];
+        Err(NonFinite {
+            sphere: hit.sphere,
+            material: scene.material(sphere.material).name(),
+            depth,
+            quantity,
+        })
+    }
+}
+
+/// Light arriving along the ray, following reflections and refractions up to `max_depth` deep,
+/// or what produced a NaN or infinite value along the way.
+pub fn trace(ray: Ray, scene: &Scene, max_depth: usize, rng: &mut Rng) -> Result<Vec3f, NonFinite> {
+    let mut radiance = Vec3f::new_uniform(0.0);
+    // Rays still to be traced, with the fraction of their light which reaches the camera. The
+    // last added is traced first, so the tree of rays is followed depth first, and the stack
+    // only grows with the rays branching off along the way.
+    let mut pending = vec![(ray, Vec3f::new_uniform(1.0), 0, Media::default(), None)];
+    while let Some((ray, throughput, depth, media, sampled_lights)) = pending.pop() {
+        let hit = scene.intersect(&ray);
+        let walked = walk_medium(ray, hit, &media, sampled_lights, scene, rng);
+        radiance += walked.in_scattered * throughput;
+        let throughput = throughput * walked.throughput;
+        let (ray, sampled_lights) = (walked.ray, walked.sampled_lights);
+        // No intersection - add the background color
+        let Some(hit) = walked.hit else {
+            radiance += escaped(&ray, scene, sampled_lights) * throughput;
+            continue;
+        };
+
+        let bounces = Bounces::whitted(depth, max_depth);
+        let shaded = shade(&ray, &hit, scene, bounces, sampled_lights, &media, rng);
+        shaded.check(&hit, scene, depth)?;
+        radiance += shaded.radiance * throughput;
+        pending.extend(shaded.secondary.into_iter().rev().map(
+            |(ray, weight, media, sampled_lights)| {
+                (ray, throughput * weight, depth + 1, media, sampled_lights)
+            },
+        ));
+    }
+    Ok(radiance)
+}
+
+/// Light from the background along a ray which hit nothing, weighed against the lights sampled
+/// at its origin, if any.
+pub fn escaped(ray: &Ray, scene: &Scene, sampled_lights: Option<SampledLights>) -> Vec3f {
+    let Some(sampled_lights) = sampled_lights else {
+        return scene.background.radiance(ray);
+    };
+    match &scene.background {
+        Background::Environment(environment) => {
+            environment.radiance(ray.direction)
+                * power_heuristic(
+                    sampled_lights.pdf,
+                    environment.pdf(sampled_lights.point, ray.direction),
+                )
+        }
+        background => background.indirect_radiance(ray),
+    }
+}
+
+/// Weight of a sample drawn with probability density `pdf`, where another technique draws the
+/// same sample with density `other`, by the power heuristic of Veach. A sample which nothing
+/// else can draw has all the weight.
+pub fn power_heuristic(pdf: f32, other: f32) -> f32 {
+    if other <= 0.0 {
+        return 1.0;
+    }
+    let (pdf, other) = (pdf * pdf, other * other);
+    pdf / (pdf + other)
+}
+
+/// A ray followed through the medium it was travelling in, to the surface it reaches.
+pub struct Walked {
+    /// The ray leaving the last point it scattered at, or the original ray if it didn't scatter.
+    pub ray: Ray,
+    /// Where the ray hits the scene.
+    pub hit: Option<Hit>,
+    /// Weight of the light the ray brings back, which is zero if the light was lost.
+    pub throughput: Vec3f,
+    /// Light scattered back along the way by the medium, straight from the lights.
+    pub in_scattered: Vec3f,
+    /// Where lights were sampled directly for the ray, which changes when it scatters.
+    pub sampled_lights: Option<SampledLights>,
+}
+
+/// Follow a ray through the scattering medium it's travelling in, if any, scattering it at random
+/// points until it reaches a surface. `hit` is where the ray hits the scene, and lights were
+/// sampled directly for it at `sampled_lights`. Lights are sampled at each point the ray
+/// scatters.
+pub fn walk_medium(
+    mut ray: Ray,
+    mut hit: Option<Hit>,
+    media: &Media,
+    mut sampled_lights: Option<SampledLights>,
+    scene: &Scene,
+    rng: &mut Rng,
+) -> Walked {
+    let walked = |ray, hit, throughput, in_scattered, sampled_lights| Walked {
+        ray,
+        hit,
+        throughput,
+        in_scattered,
+        sampled_lights,
+    };
+    let none = Vec3f::new_uniform(0.0);
+    // Fog only lies between surfaces, as the background is beyond it
+    let medium = if media.is_outside() && hit.is_none() {
+        None
+    } else {
+        scene.medium(media)
+    };
+    let Some(medium) = medium else {
+        return walked(ray, hit, Vec3f::new_uniform(1.0), none, sampled_lights);
+    };
+    if let Some(material) = varying_density(scene, media) {
+        return delta_track(ray, hit, &medium, material, sampled_lights, scene, rng);
+    }
+    let extinction = medium.extinction();
+    let transmittance = |t: f32| {
+        Vec3f::new(
+            (-extinction.x * t).exp(),
+            (-extinction.y * t).exp(),
+            (-extinction.z * t).exp(),
+        )
+    };
+    let average = |v: Vec3f| (v.x + v.y + v.z) / 3.0;
+    // Media which only absorb attenuate the light all the way to the surface, by Beer-Lambert's
+    // law, with no need to sample events.
+    if !medium.scattering.is_positive() {
+        let surface_t = hit.as_ref().map_or(f32::INFINITY, |hit| hit.t);
+        return walked(ray, hit, transmittance(surface_t), none, sampled_lights);
+    }
+    // Fog is thin enough that light scattered more than once is left out. The ray carries on to
+    // the surface, attenuated, gathering the light scattered towards it at one point along the
+    // way, chosen in proportion to the light reaching back from there.
+    if let (true, Some(surface)) = (media.is_outside(), &hit) {
+        let sigma = average(extinction);
+        let reached = -(-sigma * surface.t).exp_m1();
+        let t = -(-rng.next_f32() * reached).ln_1p() / sigma;
+        let pdf = sigma * (-sigma * t).exp() / reached;
+        let point = ray.origin + ray.direction * t;
+        // The background all around lights the fog too, taken as scattered evenly. Environment
+        // maps are sampled directly instead.
+        let surrounding = match &scene.background {
+            Background::Uniform(color) => *color,
+            Background::Sky(sky) => {
+                let up = Vec3f::new(0.0, 1.0, 0.0);
+                (sky.ambient(up) + sky.ambient(-up)) * 0.5
+            }
+            Background::Environment(_) => Vec3f::new_uniform(0.0),
+        };
+        let in_scattered = (in_scatter(point, ray.direction, &medium, scene, rng) + surrounding)
+            * medium.scattering
+            * transmittance(t)
+            * (1.0 / pdf);
+        let throughput = transmittance(surface.t);
+        return walked(ray, hit, throughput, in_scattered, sampled_lights);
+    }
+
+    let mut throughput = Vec3f::new_uniform(1.0);
+    let mut in_scattered = none;
+    for _ in 0..MAX_MEDIUM_EVENTS {
+        // The distance to the next scattering event is sampled for one channel picked at random,
+        // and weighted by the average density over all of them.
+        let sigma = match rng.next_u64() % 3 {
+            0 => extinction.x,
+            1 => extinction.y,
+            _ => extinction.z,
+        };
+        let t = -(1.0 - rng.next_f32()).ln() / sigma;
+        let surface_t = hit.as_ref().map_or(f32::INFINITY, |hit| hit.t);
+        if t >= surface_t {
+            let transmittance = transmittance(surface_t);
+            let probability = average(transmittance);
+            if probability <= 0.0 {
+                break;
+            }
+            let throughput = throughput * transmittance * (1.0 / probability);
+            return walked(ray, hit, throughput, in_scattered, sampled_lights);
+        }
+        let transmittance = transmittance(t);
+        let density = average(extinction * transmittance);
+        if density <= 0.0 {
+            break;
+        }
+        throughput = throughput * medium.scattering * transmittance * (1.0 / density);
+        let point = ray.origin + ray.direction * t;
+        in_scattered += in_scatter(point, ray.direction, &medium, scene, rng) * throughput;
+        // Scatter by the medium's phase function, which is sampled exactly
+        let direction = medium.sample_phase(ray.direction, rng);
+        sampled_lights = Some(SampledLights {
+            point,
+            pdf: medium.phase(ray.direction, direction),
+        });
+        ray = Ray {
+            origin: point,
+            direction,
+        };
+        hit = scene.intersect(&ray);
+    }
+    walked(ray, None, none, in_scattered, None)
+}
+
+/// Material enclosing the innermost medium, where the medium's density varies.
+fn varying_density<'a>(scene: &'a Scene, media: &Media) -> Option<&'a dyn Material> {
+    let sphere = media.sphere()?;
+    let material = scene.material(scene.spheres[sphere].material);
+    material.max_density().is_some().then_some(material)
+}
+
+/// Walk through a medium whose density varies by delta tracking. Collisions are sampled as if the
+/// medium were as dense as it gets everywhere, and each is then taken as a real scattering event
+/// in proportion to the actual density there, or as a null collision which the ray carries
+/// straight on through. The weights make up for the colors of the medium not being tracked apart.
+fn delta_track(
+    mut ray: Ray,
+    mut hit: Option<Hit>,
+    medium: &Medium,
+    material: &dyn Material,
+    mut sampled_lights: Option<SampledLights>,
+    scene: &Scene,
+    rng: &mut Rng,
+) -> Walked {
+    let max_density = material.max_density().unwrap_or(1.0);
+    let majorant = medium.extinction().max_component() * max_density;
+    let mut throughput = Vec3f::new_uniform(1.0);
+    let mut in_scattered = Vec3f::new_uniform(0.0);
+    if majorant <= 0.0 {
+        return Walked {
+            ray,
+            hit,
+            throughput,
+            in_scattered,
+            sampled_lights,
+        };
+    }
+    let mut t = 0.0;
+    let mut events = 0;
+    while events < MAX_MEDIUM_EVENTS {
+        t -= (1.0 - rng.next_f32()).ln() / majorant;
+        let Some(surface) = &hit else {
+            break;
+        };
+        if t >= surface.t {
+            return Walked {
+                ray,
+                hit,
+                throughput,
+                in_scattered,
+                sampled_lights,
+            };
+        }
+        let point = ray.origin + ray.direction * t;
+        let density = material.density(point);
+        let extinction = medium.extinction() * density;
+        let real = extinction.average() / majorant;
+        if rng.next_f32() >= real {
+            throughput = throughput
+                * (Vec3f::new_uniform(majorant) - extinction)
+                * (1.0 / (majorant * (1.0 - real)));
+            continue;
+        }
+        events += 1;
+        throughput = throughput * medium.scattering * (density / extinction.average());
+        in_scattered += in_scatter(point, ray.direction, medium, scene, rng) * throughput;
+        let direction = medium.sample_phase(ray.direction, rng);
+        sampled_lights = Some(SampledLights {
+            point,
+            pdf: medium.phase(ray.direction, direction),
+        });
+        ray = Ray {
+            origin: point,
+            direction,
+        };
+        hit = scene.intersect(&ray);
+        t = 0.0;
+    }
+    Walked {
+        ray,
+        hit: None,
+        throughput: Vec3f::new_uniform(0.0),
+        in_scattered,
+        sampled_lights: None,
+    }
+}
+
+/// Fraction of the light from `distance` along `ray` which reaches its origin through the media
+/// it's travelling in, with no surfaces in between.
+pub fn transmittance(scene: &Scene, media: &Media, ray: &Ray, distance: f32) -> Vec3f {
+    match scene.medium(media) {
+        Some(medium) => medium_transmittance(&medium, varying_density(scene, media), ray, distance),
+        None => Vec3f::new_uniform(1.0),
+    }
+}
+
+/// Fraction of the light from `distance` along `ray` which makes it through `medium`. Where the
+/// medium's density varies, as given by `material`, it's sampled in the middle of even steps.
+fn medium_transmittance(
+    medium: &Medium,
+    material: Option<&dyn Material>,
+    ray: &Ray,
+    distance: f32,
+) -> Vec3f {
+    let depth = match material {
+        Some(material) => {
+            let step = distance / TRANSMITTANCE_STEPS as f32;
+            let density: f32 = (0..TRANSMITTANCE_STEPS)
+                .map(|i| material.density(ray.origin + ray.direction * ((i as f32 + 0.5) * step)))
+                .sum();
+            density * step
+        }
+        None => distance,
+    };
+    let extinction = medium.extinction() * -depth;
+    Vec3f::new(extinction.x.exp(), extinction.y.exp(), extinction.z.exp())
+}
+
+/// Light scattered by a medium at `point` back along a ray travelling in `direction`, straight
+/// from the lights, the sun and any environment map. Lights which scattered rays can also find
+/// are weighed against them.
+fn in_scatter(
+    point: Vec3f,
+    direction: Vec3f,
+    medium: &Medium,
+    scene: &Scene,
+    rng: &mut Rng,
+) -> Vec3f {
+    let mut radiance = Vec3f::new_uniform(0.0);
+    let (chosen, probability) = scene.choose_lights(rng);
+    for light in &scene.lights()[chosen] {
+        let samples = light.samples();
+        let mut sum = Vec3f::new_uniform(0.0);
+        for _ in 0..samples {
+            let Some(sample) = light.sample(point, rng) else {
+                continue;
+            };
+            if !sample.radiance.is_positive() {
+                continue;
+            }
+            let phase = medium.phase(direction, sample.direction);
+            let light_ray = Ray {
+                origin: point,
+                direction: sample.direction,
+            };
+            let transmittance =
+                shadow_transmittance(scene, light_ray, sample.distance, light.sphere(), true);
+            // Only lights emitted by spheres are found by scattered rays
+            let weight = if light.sphere().is_some() {
+                power_heuristic(probability * samples as f32 * sample.pdf, phase)
+            } else {
+                1.0
+            };
+            sum += sample.radiance * transmittance * (weight * phase / sample.pdf);
+        }
+        radiance += sum * (1.0 / (probability * samples as f32));
+    }
+    match &scene.background {
+        Background::Sky(sky) => {
+            let sun_ray = Ray {
+                origin: point,
+                direction: sky.sun_direction,
+            };
+            let transmittance = shadow_transmittance(scene, sun_ray, f32::INFINITY, None, true);
+            radiance +=
+                sky.sun_light(point) * transmittance * medium.phase(direction, sky.sun_direction);
+        }
+        Background::Environment(environment) => {
+            if let Some((sample, pdf)) = environment.sample(point, rng) {
+                let phase = medium.phase(direction, sample);
+                let environment_ray = Ray {
+                    origin: point,
+                    direction: sample,
+                };
+                let transmittance =
+                    shadow_transmittance(scene, environment_ray, f32::INFINITY, None, true);
+                radiance += environment.radiance(sample)
+                    * transmittance
+                    * (power_heuristic(pdf, phase) * phase / pdf);
+            }
+        }
+        Background::Uniform(_) => {}
+    }
+    radiance
+}
+
+/// Fraction of the light from `distance` along a shadow ray which reaches its origin, after
+/// passing through any transparent surfaces in between and absorbed by the media inside them.
+/// Shadow rays aren't refracted, so light is tinted by stained glass but not focused by it.
+/// Anything hit on the sphere `light`, which emits the light, doesn't block it. Unless
+/// `transparent`, every surface blocks the light. The scene's fog dims the light on its way
+/// between surfaces, except from infinitely far away, as the background is beyond it.
+pub fn shadow_transmittance(
+    scene: &Scene,
+    mut ray: Ray,
+    distance: f32,
+    light: Option<usize>,
+    transparent: bool,
+) -> Vec3f {
+    let fog = |length: f32| match scene.fog {
+        Some(fog) if length.is_finite() => {
+            let extinction = fog.extinction() * -length;
+            Vec3f::new(extinction.x.exp(), extinction.y.exp(), extinction.z.exp())
+        }
+        _ => Vec3f::new_uniform(1.0),
+    };
+    let mut transmittance = Vec3f::new_uniform(1.0);
+    let mut travelled = 0.0;
+    for _ in 0..MAX_SHADOW_SURFACES {
+        let Some(hit) = scene.intersect(&ray) else {
+            return transmittance * fog(distance - travelled);
+        };
+        if light == Some(hit.sphere) || travelled + hit.t >= distance {
+            return transmittance * fog((distance - travelled).min(hit.t));
+        }
+        let sphere = &scene.spheres[hit.sphere];
+        let material = scene.material(sphere.material);
+        if !transparent {
+            break;
+        }
+        transmittance *= material.transparency();
+        if !transmittance.is_positive() {
+            break;
+        }
+        let hit_point = ray.origin + ray.direction * hit.t;
+        // Leaving the sphere, the ray has crossed the medium inside it, and entering it, the fog
+        // outside
+        if (hit_point - sphere.center).dot_product(ray.direction) > 0.0 {
+            if let Some(medium) = material.medium() {
+                let varying = material.max_density().is_some().then_some(material);
+                transmittance *= medium_transmittance(&medium, varying, &ray, hit.t);
+            }
+        } else {
+            transmittance *= fog(hit.t);
+        }
+        let bias = 1e-4_f32.max(sphere.radius * 1e-6);
+        ray.origin = hit_point + ray.direction * bias;
+        travelled += hit.t + bias;
+    }
+    Vec3f::new_uniform(0.0)
+}
+
+/// Shade the point where `ray` hit the scene. When lights were sampled directly at the ray's
+/// origin, light emitted by the surface is weighed against them. `media` are the media which the
+/// ray travelled through.
+pub fn shade(
+    ray: &Ray,
+    hit: &Hit,
+    scene: &Scene,
+    bounces: Bounces,
+    sampled_lights: Option<SampledLights>,
+    media: &Media,
+    rng: &mut Rng,
+) -> Shaded {
+    let material = scene.material(scene.spheres[hit.sphere].material);
+    let surface = Surface::new(ray, hit, scene, media);
+    let interaction = surface.interaction;
+    let hit_point = interaction.point;
+    // Lighting follows the shading normal, while the geometric normal decides which side of the
+    // surface rays are on
+    let shading_normal = interaction.normal;
+
+    let emitted = emitted(ray, hit, scene, sampled_lights);
+    // Surfaces only lit directly gather indirect light with cosine weighted rays, when asked to.
+    // The cosine term and the sampling density cancel out, leaving the reflectance as the weight.
+    let gather = |rng: &mut Rng| {
+        let direction = shading_normal + rng.unit_vector();
+        if direction.magnitude() <= 1e-6 {
+            return None;
+        }
+        let direction = direction.normalized();
+        let weight = material.eval(ray, &interaction, direction);
+        weight
+            .is_positive()
+            .then(|| vec![(interaction.spawn_ray(direction), weight)])
+    };
+    let (scattered, gathers) = if bounces.depth < bounces.max_depth {
+        match material.scatter(ray, &interaction, rng) {
+            Some(scattered) => (Some(scattered), false),
+            None if bounces.gather_indirect => (gather(rng), true),
+            None => (None, false),
+        }
+    } else {
+        (None, false)
+    };
+    let scatter_pdf = |direction: Vec3f| {
+        if gathers {
+            shading_normal.dot_product(direction).max(0.0) / PI
+        } else {
+            material.pdf(ray, &interaction, direction)
+        }
+    };
+    let samples_lights = material.samples_lights() || gathers;
+    let secondary = scattered.map(|secondary| {
+        secondary
+            .into_iter()
+            .map(|(scattered, weight)| {
+                let media = surface.media_towards(scattered.direction, material, media);
+                let sampled_lights = samples_lights.then(|| SampledLights {
+                    point: hit_point,
+                    pdf: scatter_pdf(scattered.direction),
+                });
+                (scattered, weight, media, sampled_lights)
+            })
+            .collect()
+    });
+    let scatters = match secondary {
+        Some(secondary) if !samples_lights => {
+            return Shaded {
+                radiance: emitted,
+                secondary,
+            };
+        }
+        Some(_) => true,
+        None => false,
+    };
+    // Weight of a direct sample of a light which scattered rays can also find
+    let direct_weight = |pdf: f32, direction: Vec3f| {
+        if scatters {
+            power_heuristic(pdf, scatter_pdf(direction))
+        } else {
+            1.0
+        }
+    };
+
+    // Each light is sampled with its own number of shadow rays, which are averaged, and divided
+    // by the probability of choosing it
+    let mut surface_color = Vec3f::new_uniform(0.0);
+    let (chosen, probability) = scene.choose_lights(rng);
+    for light in &scene.lights()[chosen] {
+        if light.sphere() == Some(hit.sphere) {
+            continue;
+        }
+        let samples = light.samples();
+        let mut sum = Vec3f::new_uniform(0.0);
+        for _ in 0..samples {
+            let Some(sample) = light.sample(hit_point, rng) else {
+                continue;
+            };
+            let cos = shading_normal.dot_product(sample.direction);
+            if cos <= 0.0 || !sample.radiance.is_positive() {
+                continue;
+            }
+            let light_ray = interaction.spawn_ray(sample.direction);
+            let transmittance = shadow_transmittance(
+                scene,
+                light_ray,
+                sample.distance,
+                light.sphere(),
+                bounces.transparent_shadows,
+            );
+            if transmittance.is_positive() {
+                // Only lights emitted by spheres are found by scattered rays
+                let weight = if light.sphere().is_some() {
+                    direct_weight(probability * samples as f32 * sample.pdf, sample.direction)
+                } else {
+                    1.0
+                };
+                // eval is scaled by pi, relative to the BRDF
+                sum += material.eval(ray, &interaction, sample.direction)
+                    * sample.radiance
+                    * transmittance
+                    * (weight * cos / (PI * sample.pdf));
+            }
+        }
+        surface_color += sum * (1.0 / (probability * samples as f32));
+    }
+    surface_color += sample_background(
+        ray,
+        &interaction,
+        material,
+        scene,
+        direct_weight,
+        bounces.transparent_shadows,
+        rng,
+    );
+    if let Background::Sky(sky) = &scene.background {
+        // Scattered rays gather the light from the sky themselves, and paths gathering indirect
+        // light don't approximate it
+        if !scatters && !bounces.gather_indirect {
+            surface_color +=
+                material.eval(ray, &interaction, shading_normal) * sky.ambient(shading_normal);
+        }
+    }
+
+    Shaded {
+        radiance: surface_color + emitted,
+        secondary: secondary.unwrap_or_default(),
+    }
+}
+
+/// Light emitted by the surface where `ray` hit the scene, weighed against the lights sampled at
+/// the ray's origin, if any.
+pub fn emitted(
+    ray: &Ray,
+    hit: &Hit,
+    scene: &Scene,
+    sampled_lights: Option<SampledLights>,
+) -> Vec3f {
+    let material = scene.material(scene.spheres[hit.sphere].material);
+    let Some(sampled_lights) = sampled_lights else {
+        return material.emitted();
+    };
+    // Only the lights emitted by this sphere could have been sampled in this direction
+    let light_pdf: f32 = scene
+        .lights()
+        .iter()
+        .enumerate()
+        .filter(|(_, light)| light.sphere() == Some(hit.sphere))
+        .map(|(index, light)| {
+            scene.light_probability(index)
+                * light.samples() as f32
+                * light.pdf(sampled_lights.point, ray.direction)
+        })
+        .sum();
+    material.emitted() * power_heuristic(sampled_lights.pdf, light_pdf)
+}
+
+/// Light from the sun or an environment map reaching the surface directly, reflected back along
+/// `ray`. Environment samples which scattered rays could also find are weighed by
+/// `direct_weight`, from their probability density and direction. Shadows are cast through
+/// transparent surfaces if `transparent_shadows`.
+pub fn sample_background(
+    ray: &Ray,
+    interaction: &Interaction,
+    material: &dyn Material,
+    scene: &Scene,
+    direct_weight: impl Fn(f32, Vec3f) -> f32,
+    transparent_shadows: bool,
+    rng: &mut Rng,
+) -> Vec3f {
+    let shading_normal = interaction.normal;
+    let mut radiance = Vec3f::new_uniform(0.0);
+    if let Background::Sky(sky) = &scene.background {
+        let sun_ray = interaction.spawn_ray(sky.sun_direction);
+        let origin = sun_ray.origin;
+        let transmittance =
+            shadow_transmittance(scene, sun_ray, f32::INFINITY, None, transparent_shadows);
+        if transmittance.is_positive() {
+            radiance += material.eval(ray, interaction, sky.sun_direction)
+                * sky.sun_light(origin)
+                * transmittance
+                * 0_f32.max(shading_normal.dot_product(sky.sun_direction));
+        }
+    }
+    if let Background::Environment(environment) = &scene.background {
+        // The environment is sampled towards its bright parts
+        if let Some((direction, pdf)) = environment.sample(interaction.point, rng) {
+            let cos = shading_normal.dot_product(direction);
+            let environment_ray = interaction.spawn_ray(direction);
+            let transmittance = if cos > 0.0 {
+                shadow_transmittance(
+                    scene,
+                    environment_ray,
+                    f32::INFINITY,
+                    None,
+                    transparent_shadows,
+                )
+            } else {
+                Vec3f::new_uniform(0.0)
+            };
+            if transmittance.is_positive() {
+                radiance += material.eval(ray, interaction, direction)
+                    * environment.radiance(direction)
+                    * transmittance
+                    * (direct_weight(pdf, direction) * cos / (PI * pdf));
+            }
+        }
+    }
+    radiance
+}
@@ -1,6 +1,6 @@
 use std::ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign};
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, PartialEq)]
 pub struct Vec3<T>
 where
     T: Copy,
@@ -14,6 +14,10 @@ impl<T> Vec3<T>
 where
     T: Copy,
 {
+    pub fn new(x: T, y: T, z: T) -> Self {
+        Vec3 { x, y, z }
+    }
+
     pub fn new_uniform(a: T) -> Self {
         Vec3 { x: a, y: a, z: a }
     }
@@ -32,6 +36,19 @@ where
     }
 }
 
+impl<T> Vec3<T>
+where
+    T: Copy + Mul<Output = T> + Sub<Output = T>,
+{
+    pub fn cross_product(self, rhs: Self) -> Self {
+        Vec3 {
+            x: self.y * rhs.z - self.z * rhs.y,
+            y: self.z * rhs.x - self.x * rhs.z,
+            z: self.x * rhs.y - self.y * rhs.x,
+        }
+    }
+}
+
 impl Vec3<f32> {
     pub fn magnitude(&self) -> f32 {
         self.sqr_magnitude().sqrt()
@@ -50,6 +67,41 @@ impl Vec3<f32> {
             self
         }
     }
+
+    /// Two unit vectors perpendicular to this unit vector and each other.
+    pub fn tangent_frame(self) -> (Self, Self) {
+        // Duff et al., "Building an Orthonormal Basis, Revisited"
+        let sign = 1_f32.copysign(self.z);
+        let a = -1.0 / (sign + self.z);
+        let b = self.x * self.y * a;
+        (
+            Vec3::new(1.0 + sign * self.x * self.x * a, sign * b, -sign * self.x),
+            Vec3::new(b, sign + self.y * self.y * a, -self.y),
+        )
+    }
+
+    /// Whether any component is greater than zero.
+    pub fn is_positive(&self) -> bool {
+        self.x > 0.0 || self.y > 0.0 || self.z > 0.0
+    }
+
+    /// Whether every component is neither infinite nor NaN.
+    pub fn is_finite(&self) -> bool {
+        self.x.is_finite() && self.y.is_finite() && self.z.is_finite()
+    }
+
+    pub fn average(&self) -> f32 {
+        (self.x + self.y + self.z) / 3.0
+    }
+
+    pub fn max_component(&self) -> f32 {
+        self.x.max(self.y).max(self.z)
+    }
+
+    /// Perceived brightness of a linear sRGB color.
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.x + 0.7152 * self.y + 0.0722 * self.z
+    }
 }
 
 impl Vec3<f64> {
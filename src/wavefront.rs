@@ -0,0 +1,214 @@
+//! Wavefront rendering.
+//!
+//! Rather than following each camera ray recursively to completion, rays are processed in large
+//! batches. Every ray in the wavefront is intersected with the scene, the hits are sorted by the
+//! material they hit, and shading them produces the next wavefront of secondary rays. Intersection
+//! and shading each run over many coherent rays at once, which is what SIMD and GPU backends need.
+//! Secondary rays take random numbers of their own, seeded from their parent's, so the image
+//! converges to the same result as [`tracer::trace`] without matching it sample for sample.
+
+use crate::{
+    framebuffer::Framebuffer,
+    material::Media,
+    rng::Rng,
+    scene::{Hit, Scene},
+    settings::RenderSettings,
+    tracer::{self, Bounces, NonFinite, SampledLights},
+    Ray, Vec3f,
+};
+
+/// Default number of camera rays in flight at once, between the samples of every pixel in a
+/// batch.
+pub const BATCH_SIZE: usize = 64 * 1024;
+
+/// Memory used per camera ray in flight, in bytes. Each ray has up to two secondary rays.
+pub const BYTES_PER_RAY: usize = std::mem::size_of::<(Vec3f, f32, Option<NonFinite>)>()
+    + 3 * std::mem::size_of::<(Option<Hit>, PathRay)>();
+
+/// A ray in the wavefront, along with what its light contributes to.
+struct PathRay {
+    ray: Ray,
+    /// Index of the sample within the batch which the ray contributes to.
+    sample: usize,
+    /// Weight of the light the ray brings back, in the sample.
+    weight: Vec3f,
+    depth: usize,
+    /// Lights sampled directly at the ray's origin, which the light it finds is weighed against.
+    sampled_lights: Option<SampledLights>,
+    media: Media,
+    rng: Rng,
+}
+
+/// Render into `framebuffer`, which holds the rows of the image starting at `first_row`, with
+/// the camera rays of `batch_size` pixels in flight at once, following rays as deep and taking
+/// as many samples per pixel as `settings` say. Camera rays are made by `primary_ray` with the
+/// random numbers of their sample, along with the weight the pixel's filter gives the sample.
+/// Samples producing a NaN or infinite value are dropped, and their pixels made from their other
+/// samples. Returns the pixels where that happened, with what produced the first such value.
+pub fn render(
+    scene: &Scene,
+    framebuffer: &mut Framebuffer,
+    first_row: usize,
+    batch_size: usize,
+    settings: &RenderSettings,
+    primary_ray: impl Fn(usize, usize, &mut Rng) -> (Option<Ray>, f32),
+) -> Vec<(usize, usize, NonFinite)> {
+    let width = framebuffer.width;
+    let pixels = width * framebuffer.height;
+    let samples_per_pixel = settings.samples_per_pixel;
+    // Light is accumulated in full precision for each sample of the batch, before being stored
+    // in the framebuffer which may be lower precision, so a sample can be dropped on its own.
+    let mut quarantined = Vec::new();
+
+    for batch_start in (0..pixels).step_by(batch_size) {
+        let batch_end = (batch_start + batch_size).min(pixels);
+        let samples = (batch_end - batch_start) * samples_per_pixel;
+        let mut accumulated = vec![Vec3f::default(); samples];
+        let mut non_finite: Vec<Option<NonFinite>> = Vec::new();
+        non_finite.resize_with(samples, || None);
+        let mut weights = vec![0.0; samples];
+        let mut wavefront: Vec<PathRay> = (batch_start..batch_end)
+            .flat_map(|pixel| (0..samples_per_pixel).map(move |sample| (pixel, sample)))
+            .enumerate()
+            .filter_map(|(index, (pixel, sample))| {
+                let mut rng = Rng::for_sample(
+                    settings.sampler,
+                    settings.seed,
+                    (pixel % width, first_row + pixel / width),
+                    width,
+                    sample,
+                    samples_per_pixel,
+                );
+                let (ray, weight) = primary_ray(pixel % width, first_row + pixel / width, &mut rng);
+                weights[index] = weight;
+                Some(PathRay {
+                    ray: ray?,
+                    sample: index,
+                    weight: Vec3f::new_uniform(weight),
+                    depth: 0,
+                    sampled_lights: None,
+                    media: Media::default(),
+                    rng,
+                })
+            })
+            .collect();
+        while !wavefront.is_empty() {
+            wavefront = extend(
+                scene,
+                wavefront,
+                settings.max_depth,
+                &mut accumulated,
+                &mut non_finite,
+            );
+        }
+        let mut non_finite = non_finite.into_iter();
+        for pixel in batch_start..batch_end {
+            let (x, y) = (pixel % width, pixel / width);
+            let first = (pixel - batch_start) * samples_per_pixel;
+            let (mut sum, mut total_weight) = (Vec3f::default(), 0.0);
+            let mut dropped = None;
+            for sample in first..first + samples_per_pixel {
+                match non_finite.next().flatten() {
+                    Some(err) => {
+                        dropped.get_or_insert(err);
+                    }
+                    None => {
+                        sum += accumulated[sample];
+                        total_weight += weights[sample];
+                    }
+                }
+            }
+            if total_weight > 0.0 {
+                framebuffer.set(x, y, sum * (1.0 / total_weight));
+            } else {
+                framebuffer.set(x, y, Vec3f::default());
+            }
+            if let Some(err) = dropped {
+                quarantined.push((x, first_row + y, err));
+            }
+        }
+    }
+    quarantined
+}
+
+/// Intersect and shade every ray of the wavefront, up to `max_depth` bounces deep, accumulating
+/// their light into `image`, and return the next wavefront. The first NaN or infinite value
+/// produced for each sample is recorded in `non_finite`, and the sample's remaining rays are
+/// dropped.
+fn extend(
+    scene: &Scene,
+    wavefront: Vec<PathRay>,
+    max_depth: usize,
+    image: &mut [Vec3f],
+    non_finite: &mut [Option<NonFinite>],
+) -> Vec<PathRay> {
+    // Rays travelling through a scattering medium are followed through it to the next surface
+    let mut hits: Vec<(Option<Hit>, PathRay)> = wavefront
+        .into_iter()
+        .filter_map(|mut path| {
+            let hit = scene.intersect(&path.ray);
+            let walked = tracer::walk_medium(
+                path.ray,
+                hit,
+                &path.media,
+                path.sampled_lights,
+                scene,
+                &mut path.rng,
+            );
+            image[path.sample] += walked.in_scattered * path.weight;
+            if !walked.throughput.is_positive() {
+                return None;
+            }
+            path.ray = walked.ray;
+            path.weight *= walked.throughput;
+            path.sampled_lights = walked.sampled_lights;
+            Some((walked.hit, path))
+        })
+        .collect();
+
+    // Group the hits by material, so rays hitting the same material are shaded together. Misses
+    // come first.
+    hits.sort_by_key(|(hit, _)| hit.as_ref().map(|hit| scene.spheres[hit.sphere].material));
+
+    let mut next = Vec::with_capacity(hits.len());
+    for (hit, mut path) in hits {
+        if non_finite[path.sample].is_some() {
+            continue;
+        }
+        let Some(hit) = hit else {
+            image[path.sample] +=
+                tracer::escaped(&path.ray, scene, path.sampled_lights) * path.weight;
+            continue;
+        };
+        let shaded = tracer::shade(
+            &path.ray,
+            &hit,
+            scene,
+            Bounces::whitted(path.depth, max_depth),
+            path.sampled_lights,
+            &path.media,
+            &mut path.rng,
+        );
+        if let Err(err) = shaded.check(&hit, scene, path.depth) {
+            non_finite[path.sample] = Some(err);
+            continue;
+        }
+        image[path.sample] += shaded.radiance * path.weight;
+        next.extend(
+            shaded
+                .secondary
+                .into_iter()
+                .map(|(ray, weight, media, sampled_lights)| PathRay {
+                    ray,
+                    sample: path.sample,
+                    weight: path.weight * weight,
+                    depth: path.depth + 1,
+                    sampled_lights,
+                    media,
+                    // Secondary rays branch off with their own random numbers
+                    rng: Rng::new(path.rng.next_u64()),
+                }),
+        );
+    }
+    next
+}